@@ -0,0 +1,261 @@
+//! NFSv4 ACLs: the entries an object may carry beyond its mode bits, how
+//! they pass from a directory to what is made in it, and how they are kept
+//! in step with the mode (RFC 8881 sections 6.2 to 6.4). Evaluation against
+//! a caller lives with the other permission checks in `perm`.
+//!
+//! AUDIT and ALARM entries are kept and handed back as set, but raise
+//! nothing; only ALLOW and DENY entries decide access.
+
+use crate::error::{NfsError, NfsResult};
+use crate::proto::nfs4::*;
+use crate::vfs::parse_owner;
+use bytes::Bytes;
+
+/// Whom an entry is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Who {
+    /// OWNER@
+    Owner,
+    /// GROUP@, the owning group
+    Group,
+    /// EVERYONE@
+    Everyone,
+    User(u32),
+    /// A group named with ACE4_IDENTIFIER_GROUP
+    GroupId(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ace {
+    /// ACE4_*_ACE_TYPE
+    pub kind: u32,
+    /// ACE4_* flags but ACE4_IDENTIFIER_GROUP, which `who` carries
+    pub flags: u32,
+    pub mask: u32,
+    pub who: Who,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Acl {
+    /// ACL4_* flags of the dacl attribute
+    pub flags: u32,
+    pub aces: Vec<Ace>,
+}
+
+// Flags that say how an entry is passed down, as opposed to how it applies
+const INHERIT_FLAGS: u32 = ACE4_FILE_INHERIT_ACE | ACE4_DIRECTORY_INHERIT_ACE | ACE4_NO_PROPAGATE_INHERIT_ACE | ACE4_INHERIT_ONLY_ACE;
+
+// What an object's mode lets everyone do regardless of its bits, and what
+// its owner may do besides
+const ALWAYS: u32 = ACE4_READ_ATTRIBUTES | ACE4_READ_ACL | ACE4_SYNCHRONIZE;
+const OWNER_ALWAYS: u32 = ACE4_WRITE_ATTRIBUTES | ACE4_WRITE_ACL | ACE4_WRITE_OWNER;
+
+// Every bit some mode bit stands for
+const MODE_MASK: u32 = ACE4_READ_DATA | ACE4_WRITE_DATA | ACE4_APPEND_DATA | ACE4_EXECUTE | ACE4_DELETE_CHILD;
+
+/// The entry bits one class of mode bits (`rwx` in the low three) stands for
+pub fn rwx_mask(rwx: u32, dir: bool) -> u32 {
+    let mut mask = 0;
+    if rwx & 0o4 != 0 {
+        mask |= ACE4_READ_DATA;
+    }
+    if rwx & 0o2 != 0 {
+        mask |= ACE4_WRITE_DATA | ACE4_APPEND_DATA;
+        if dir {
+            mask |= ACE4_DELETE_CHILD;
+        }
+    }
+    if rwx & 0o1 != 0 {
+        mask |= ACE4_EXECUTE;
+    }
+    mask
+}
+
+impl Ace {
+    fn new(kind: u32, mask: u32, who: Who) -> Self {
+        Ace { kind, flags: 0, mask, who }
+    }
+
+    /// An entry as a client sent it. Users and groups are numeric ids,
+    /// optionally "@domain", as owners are.
+    pub fn from_wire(ace: &Nfsace4) -> NfsResult<Self> {
+        if ace.acetype > ACE4_SYSTEM_ALARM_ACE_TYPE {
+            return Err(NfsError::InvalidArgument(format!("bad acetype4 {}", ace.acetype)));
+        }
+        let who = match &ace.who[..] {
+            b"OWNER@" => Who::Owner,
+            b"GROUP@" => Who::Group,
+            b"EVERYONE@" => Who::Everyone,
+            id if ace.flag & ACE4_IDENTIFIER_GROUP != 0 => Who::GroupId(parse_owner(id)?),
+            id => Who::User(parse_owner(id)?),
+        };
+        Ok(Ace { kind: ace.acetype, flags: ace.flag & !ACE4_IDENTIFIER_GROUP, mask: ace.access_mask, who })
+    }
+
+    pub fn to_wire(&self) -> Nfsace4 {
+        let (who, group) = match self.who {
+            Who::Owner => ("OWNER@".to_string(), false),
+            Who::Group => ("GROUP@".to_string(), true),
+            Who::Everyone => ("EVERYONE@".to_string(), false),
+            Who::User(uid) => (uid.to_string(), false),
+            Who::GroupId(gid) => (gid.to_string(), true),
+        };
+        let flag = if group { self.flags | ACE4_IDENTIFIER_GROUP } else { self.flags };
+        Nfsace4 { acetype: self.kind, flag, access_mask: self.mask, who: Bytes::from(who) }
+    }
+
+    /// ALLOW or DENY, which decide access
+    pub fn decides(&self) -> bool {
+        matches!(self.kind, ACE4_ACCESS_ALLOWED_ACE_TYPE | ACE4_ACCESS_DENIED_ACE_TYPE)
+    }
+
+    /// Whether the entry applies to the object that holds it, rather than
+    /// only being passed down
+    pub fn effective(&self) -> bool {
+        self.flags & ACE4_INHERIT_ONLY_ACE == 0
+    }
+
+    fn special(&self) -> bool {
+        matches!(self.who, Who::Owner | Who::Group | Who::Everyone)
+    }
+}
+
+impl Acl {
+    pub fn from_wire(aces: &[Nfsace4]) -> NfsResult<Self> {
+        Ok(Acl { flags: 0, aces: aces.iter().map(Ace::from_wire).collect::<NfsResult<_>>()? })
+    }
+
+    pub fn to_wire(&self) -> Vec<Nfsace4> {
+        self.aces.iter().map(Ace::to_wire).collect()
+    }
+
+    /// The ACL that says what `mode` says: the owner and the owning group
+    /// get their bits and are denied the rest, everyone else gets theirs
+    pub fn from_mode(mode: u32, dir: bool) -> Self {
+        let mut aces = Vec::with_capacity(5);
+        Self::push_mode(&mut aces, Who::Owner, mode >> 6 & 0o7, ALWAYS | OWNER_ALWAYS, dir);
+        Self::push_mode(&mut aces, Who::Group, mode >> 3 & 0o7, ALWAYS, dir);
+        aces.push(Ace::new(ACE4_ACCESS_ALLOWED_ACE_TYPE, rwx_mask(mode & 0o7, dir) | ALWAYS, Who::Everyone));
+        Acl { flags: 0, aces }
+    }
+
+    fn push_mode(aces: &mut Vec<Ace>, who: Who, rwx: u32, always: u32, dir: bool) {
+        let allow = rwx_mask(rwx, dir);
+        aces.push(Ace::new(ACE4_ACCESS_ALLOWED_ACE_TYPE, allow | always, who));
+        let deny = rwx_mask(0o7, dir) & !allow;
+        if deny != 0 {
+            aces.push(Ace::new(ACE4_ACCESS_DENIED_ACE_TYPE, deny, who));
+        }
+    }
+
+    /// The nine permission bits the ACL amounts to: for each class, what
+    /// the entries for OWNER@, GROUP@ and EVERYONE@ that reach it allow
+    pub fn mode(&self) -> u32 {
+        let classes: [&[Who]; 3] = [&[Who::Owner, Who::Everyone], &[Who::Group, Who::Everyone], &[Who::Everyone]];
+        classes.iter().fold(0, |mode, class| {
+            let (mut allowed, mut denied) = (0, 0);
+            for ace in self.aces.iter().filter(|a| a.decides() && a.effective() && class.contains(&a.who)) {
+                let undecided = ace.mask & !(allowed | denied);
+                match ace.kind {
+                    ACE4_ACCESS_ALLOWED_ACE_TYPE => allowed |= undecided,
+                    _ => denied |= undecided,
+                }
+            }
+            let has = |mask: u32| allowed & mask == mask;
+            let rwx = (has(ACE4_READ_DATA) as u32) << 2 | (has(ACE4_WRITE_DATA | ACE4_APPEND_DATA) as u32) << 1 | has(ACE4_EXECUTE) as u32;
+            mode << 3 | rwx
+        })
+    }
+
+    /// The ACL after a chmod to `mode` (RFC 8881 6.4.1.1). The entries for
+    /// OWNER@, GROUP@ and EVERYONE@ are replaced by ones that say what the
+    /// mode says; what other users and groups were allowed is cut to the
+    /// group bits, as a POSIX ACL mask would. Entries that are only passed
+    /// down are left as they are.
+    pub fn chmod(&self, mode: u32, dir: bool) -> Self {
+        let from_mode = Self::from_mode(mode, dir).aces;
+        let (owner, rest) = from_mode.split_at(from_mode.iter().position(|a| a.who != Who::Owner).unwrap_or(from_mode.len()));
+        let group_mask = rwx_mask(mode >> 3 & 0o7, dir);
+        let mut aces = owner.to_vec();
+        for ace in &self.aces {
+            if !ace.decides() || !ace.effective() {
+                aces.push(ace.clone());
+                continue;
+            }
+            // an entry a directory passes on keeps doing so unchanged
+            if dir && ace.flags & INHERIT_FLAGS != 0 {
+                aces.push(Ace { flags: ace.flags | ACE4_INHERIT_ONLY_ACE, ..ace.clone() });
+            }
+            if ace.special() {
+                continue;
+            }
+            let mut ace = Ace { flags: ace.flags & !INHERIT_FLAGS, ..ace.clone() };
+            if ace.kind == ACE4_ACCESS_ALLOWED_ACE_TYPE {
+                ace.mask &= !(MODE_MASK & !group_mask);
+            }
+            aces.push(ace);
+        }
+        aces.extend_from_slice(rest);
+        Acl { flags: self.flags, aces }
+    }
+
+    /// What an object made in a directory with this ACL starts with, if
+    /// anything (RFC 8881 6.4.3). A directory keeps entries for files as
+    /// inherit-only so it can pass them on in turn.
+    pub fn inherit(&self, dir: bool) -> Option<Self> {
+        let mut aces = Vec::new();
+        for ace in &self.aces {
+            let to_files = ace.flags & ACE4_FILE_INHERIT_ACE != 0;
+            let to_dirs = ace.flags & ACE4_DIRECTORY_INHERIT_ACE != 0;
+            let stop = ace.flags & ACE4_NO_PROPAGATE_INHERIT_ACE != 0;
+            let flags = match (dir, to_files, to_dirs, stop) {
+                (false, true, _, _) | (true, _, true, true) => ace.flags & !INHERIT_FLAGS,
+                (true, _, true, false) => ace.flags & !ACE4_INHERIT_ONLY_ACE,
+                (true, true, false, false) => ace.flags | ACE4_INHERIT_ONLY_ACE,
+                _ => continue,
+            };
+            aces.push(Ace { flags: flags | ACE4_INHERITED_ACE, ..ace.clone() });
+        }
+        (!aces.is_empty()).then_some(Acl { flags: self.flags & ACL4_AUTO_INHERIT, aces })
+    }
+
+    /// The dacl attribute: the ALLOW and DENY entries
+    pub fn dacl(&self) -> Nfsacl41 {
+        Nfsacl41 { flag: self.flags, aces: self.aces.iter().filter(|a| a.decides()).map(Ace::to_wire).collect() }
+    }
+
+    /// This ACL with its ALLOW and DENY entries replaced by those of `dacl`
+    pub fn with_dacl(&self, dacl: &Acl) -> Self {
+        let mut aces = dacl.aces.clone();
+        aces.extend(self.aces.iter().filter(|a| !a.decides()).cloned());
+        Acl { flags: dacl.flags, aces }
+    }
+}
+
+/// The mode and ACL an object ends up with when it has `mode` and `acl`
+/// and is asked to take `set_mode` and `set_acl` (RFC 8881 6.4.1): the mode
+/// is set first, then the ACL, which decides the nine permission bits. A
+/// new mode alone rewrites an ACL to agree with it.
+pub fn settle(mode: u32, acl: Option<&Acl>, set_mode: Option<u32>, set_acl: Option<&Acl>, dir: bool) -> (u32, Option<Acl>) {
+    let mode = set_mode.map_or(mode, |m| m & 0o7777);
+    match (set_acl, acl) {
+        (Some(new), _) => (mode & !0o777 | new.mode(), Some(new.clone())),
+        (None, Some(old)) if set_mode.is_some() => (mode, Some(old.chmod(mode, dir))),
+        (None, old) => (mode, old.cloned()),
+    }
+}
+
+/// The mode and ACL of a new object made in a directory with `parent`: an
+/// ACL it was given, or else what it inherits, which a mode it was given
+/// then rewrites and which otherwise decides its permission bits
+pub fn settle_new(default_mode: u32, parent: Option<&Acl>, set_mode: Option<u32>, set_acl: Option<&Acl>, dir: bool) -> (u32, Option<Acl>) {
+    let inherited = match set_acl {
+        Some(_) => None,
+        None => parent.and_then(|p| p.inherit(dir)),
+    };
+    match inherited {
+        Some(acl) if set_mode.is_none() => settle(default_mode, None, None, Some(&acl), dir),
+        Some(acl) => settle(default_mode, Some(&acl), set_mode, None, dir),
+        None => settle(default_mode, None, set_mode, set_acl, dir),
+    }
+}
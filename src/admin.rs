@@ -0,0 +1,317 @@
+//! Local control interface for operators. Each request is one JSON object
+//! on a line, e.g. `{"op": "expire_client", "client": "10.0.0.7"}`, and is
+//! answered by one line `{"ok": true, "result": ...}` or
+//! `{"ok": false, "error": "..."}`. The Unix socket is only accessible to
+//! the server's user; `nfs-rs-admin` is the command line client.
+
+use crate::audit::hex;
+use crate::error::{NfsError, NfsResult};
+use crate::server::{ConfigHandle, Dispatcher, ShutdownHandle};
+use crate::vfs::Vfs;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AdminRequest {
+    /// Clients known through connections, mounts or lock monitoring
+    Clients,
+    /// Byte-range locks, by file
+    Locks,
+    /// Exports with their usage
+    Exports,
+    /// Close a client's connections and drop its mounts, locks and monitoring
+    ExpireClient { client: String },
+    /// Recall a delegation by stateid
+    RevokeDelegation { stateid: String },
+    /// Stop waiting for reclaims and grant new locks
+    EndGrace,
+    /// Re-read the configuration, as SIGHUP does
+    Reload,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminReply {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientInfo {
+    /// Address, or for NLM the name the client gave itself
+    pub client: String,
+    pub connections: Vec<ConnectionInfo>,
+    /// Paths mounted over MOUNT
+    pub mounts: Vec<String>,
+    /// Monitored by NSM on behalf of NLM
+    pub monitored: bool,
+    /// Byte-range locks held under this name
+    pub locks: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    pub peer: String,
+    pub connected_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileLocks {
+    /// File handle in hex
+    pub fh: String,
+    pub locks: Vec<LockInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockInfo {
+    pub owner: String,
+    pub svid: i32,
+    pub offset: u64,
+    /// 0 means to end of file
+    pub length: u64,
+    pub exclusive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportInfo {
+    pub path: String,
+    pub fsid: u64,
+    /// MOUNT entries at or below the export
+    pub mounts: usize,
+    pub total_bytes: Option<u64>,
+    pub free_bytes: Option<u64>,
+    pub total_files: Option<u64>,
+    pub free_files: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Expired {
+    pub connections: usize,
+    pub mounts: usize,
+    pub locks: usize,
+    pub monitored: bool,
+}
+
+fn entry(clients: &mut BTreeMap<String, ClientInfo>, name: String) -> &mut ClientInfo {
+    clients.entry(name.clone()).or_insert_with(|| ClientInfo { client: name, ..Default::default() })
+}
+
+fn to_value(v: impl Serialize) -> NfsResult<Value> {
+    Ok(serde_json::to_value(v)?)
+}
+
+pub struct AdminService {
+    dispatcher: Arc<Dispatcher>,
+    config: Arc<ConfigHandle>,
+}
+
+impl AdminService {
+    pub fn new(dispatcher: Arc<Dispatcher>, config: Arc<ConfigHandle>) -> Self {
+        Self { dispatcher, config }
+    }
+
+    pub async fn handle(&self, req: AdminRequest) -> NfsResult<Value> {
+        match req {
+            AdminRequest::Clients => to_value(self.clients()),
+            AdminRequest::Locks => to_value(self.locks()),
+            AdminRequest::Exports => to_value(self.exports().await),
+            AdminRequest::ExpireClient { client } => to_value(self.expire(&client)),
+            // nothing hands out delegations, so no stateid can name one
+            AdminRequest::RevokeDelegation { stateid } => {
+                Err(NfsError::InvalidArgument(format!("no delegation {}: this server grants none", stateid)))
+            }
+            AdminRequest::EndGrace => {
+                let ended = self.dispatcher.locks().end_grace();
+                if ended {
+                    info!("grace period ended by operator");
+                }
+                to_value(serde_json::json!({ "was_in_grace": ended }))
+            }
+            AdminRequest::Reload => {
+                self.config.reload_from_source()?;
+                to_value(serde_json::json!({ "exports": self.config.current().exports.len() }))
+            }
+        }
+    }
+
+    fn clients(&self) -> Vec<ClientInfo> {
+        let mut clients = BTreeMap::new();
+        let now = SystemTime::now();
+        for c in self.dispatcher.limits().connected() {
+            let connected_secs = now.duration_since(c.since).unwrap_or_default().as_secs();
+            entry(&mut clients, c.peer.ip().to_string()).connections.push(ConnectionInfo { peer: c.peer.to_string(), connected_secs });
+        }
+        for (host, path) in self.dispatcher.mount().mounts() {
+            entry(&mut clients, host).mounts.push(path);
+        }
+        for host in self.dispatcher.nsm().monitored() {
+            entry(&mut clients, host).monitored = true;
+        }
+        for (_, locks) in self.dispatcher.locks().all() {
+            for l in locks {
+                entry(&mut clients, l.owner.host).locks += 1;
+            }
+        }
+        clients.into_values().collect()
+    }
+
+    fn locks(&self) -> Vec<FileLocks> {
+        let mut files: Vec<FileLocks> = self
+            .dispatcher
+            .locks()
+            .all()
+            .into_iter()
+            .map(|(fh, locks)| FileLocks {
+                fh: hex(&fh),
+                locks: locks
+                    .iter()
+                    .map(|l| LockInfo {
+                        owner: l.owner.host.clone(),
+                        svid: l.owner.svid,
+                        offset: l.offset,
+                        length: l.length(),
+                        exclusive: l.exclusive,
+                    })
+                    .collect(),
+            })
+            .collect();
+        files.sort_by(|a, b| a.fh.cmp(&b.fh));
+        files
+    }
+
+    async fn exports(&self) -> Vec<ExportInfo> {
+        let table = self.dispatcher.exports();
+        let mounts = self.dispatcher.mount().mounts();
+        let mut out = Vec::new();
+        for export in table.exports() {
+            let stat = match export.root_fh().await {
+                Ok(fh) => table.fsstat(&fh).await.ok(),
+                Err(_) => None,
+            };
+            let under = |path: &str| {
+                export.path == "/" || path == export.path || path.strip_prefix(&export.path).is_some_and(|rest| rest.starts_with('/'))
+            };
+            out.push(ExportInfo {
+                path: export.path.clone(),
+                fsid: export.fsid,
+                mounts: mounts.iter().filter(|(_, path)| under(path)).count(),
+                total_bytes: stat.as_ref().map(|s| s.total_bytes),
+                free_bytes: stat.as_ref().map(|s| s.free_bytes),
+                total_files: stat.as_ref().map(|s| s.total_files),
+                free_files: stat.as_ref().map(|s| s.free_files),
+            });
+        }
+        out
+    }
+
+    fn expire(&self, client: &str) -> Expired {
+        let connections = match client.parse() {
+            Ok(ip) => self.dispatcher.limits().disconnect(ip),
+            Err(_) => 0,
+        };
+        let expired = Expired {
+            connections,
+            mounts: self.dispatcher.mount().forget(client),
+            locks: self.dispatcher.locks().release_host(client),
+            monitored: self.dispatcher.nsm().unmonitor(client),
+        };
+        info!("expired client {}: {:?}", client, expired);
+        expired
+    }
+}
+
+/// Bind the admin socket, replacing a stale one left by an earlier run.
+pub fn bind(path: &Path) -> NfsResult<UnixListener> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Answer admin requests on `listener` until `shutdown`.
+pub async fn serve(listener: UnixListener, admin: Arc<AdminService>, shutdown: ShutdownHandle) -> NfsResult<()> {
+    let path = listener.local_addr()?.as_pathname().map(Path::to_path_buf);
+    info!("admin socket at {:?}", path);
+    let mut sessions = JoinSet::new();
+    loop {
+        tokio::select! {
+            biased;
+            _ = shutdown.wait() => break,
+            Some(_) = sessions.join_next(), if !sessions.is_empty() => {}
+            accepted = listener.accept() => {
+                let (sock, _) = accepted?;
+                let admin = admin.clone();
+                sessions.spawn(async move {
+                    if let Err(e) = session(sock, &admin).await {
+                        debug!("admin session failed: {}", e);
+                    }
+                });
+            }
+        }
+    }
+    if let Some(path) = path {
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("removing admin socket {}: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+async fn session(sock: UnixStream, admin: &AdminService) -> NfsResult<()> {
+    let (read, mut write) = sock.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match serde_json::from_str::<AdminRequest>(&line) {
+            Ok(req) => {
+                debug!("admin request {:?}", req);
+                admin.handle(req).await
+            }
+            Err(e) => Err(NfsError::InvalidArgument(format!("bad request: {}", e))),
+        };
+        let reply = match result {
+            Ok(result) => AdminReply { ok: true, result: Some(result), error: None },
+            Err(e) => AdminReply { ok: false, result: None, error: Some(e.to_string()) },
+        };
+        let mut text = serde_json::to_vec(&reply)?;
+        text.push(b'\n');
+        write.write_all(&text).await?;
+    }
+    Ok(())
+}
+
+/// Send one request to the server listening on `socket`.
+pub async fn request(socket: &Path, req: &AdminRequest) -> NfsResult<Value> {
+    let sock = UnixStream::connect(socket).await.map_err(|e| NfsError::Io(std::io::Error::new(e.kind(), format!("{}: {}", socket.display(), e))))?;
+    let (read, mut write) = sock.into_split();
+    let mut text = serde_json::to_vec(req)?;
+    text.push(b'\n');
+    write.write_all(&text).await?;
+    let line = BufReader::new(read)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| NfsError::Protocol("admin socket closed without a reply".into()))?;
+    let reply: AdminReply = serde_json::from_str(&line)?;
+    match reply {
+        AdminReply { ok: true, result, .. } => Ok(result.unwrap_or(Value::Null)),
+        AdminReply { error, .. } => Err(NfsError::Protocol(error.unwrap_or_else(|| "request failed".into()))),
+    }
+}
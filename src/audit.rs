@@ -0,0 +1,228 @@
+//! Audit trail of changes made by clients: one JSON object per line for
+//! every create, remove, rename, link, write, copy, clone, allocation
+//! change and attribute change, with the client, its principal, the path and the outcome.
+//!
+//! Handles carry no path, so paths are pieced together from names the
+//! server has seen: LOOKUP, READDIR(PLUS), creates and renames record which
+//! name in which directory a handle was reached by, up to export roots whose
+//! paths are known. Part of a path that was never seen is given as the
+//! directory's handle in hex, e.g. `fh:0123…/report.txt`.
+
+use crate::auth::Caller;
+use crate::error::{NfsError, NfsResult};
+use crate::export::ExportTable;
+use crate::vfs::SetAttr;
+use chrono::{SecondsFormat, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+// Handles whose name we remember; the table starts over when it fills up
+const MAX_NAMES: usize = 1 << 16;
+// Longest chain of names followed when building a path
+const MAX_DEPTH: usize = 256;
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Mkdir,
+    Symlink,
+    Mknod,
+    Link,
+    Remove,
+    Rmdir,
+    Rename,
+    Write,
+    Copy,
+    Clone,
+    Allocate,
+    Deallocate,
+    Setattr,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// RFC 3339, UTC
+    pub time: String,
+    pub client: String,
+    pub principal: String,
+    pub action: AuditAction,
+    pub path: String,
+    /// New name for RENAME and LINK, destination of COPY and CLONE
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// What was asked for: attributes set, byte range written, copied,
+    /// cloned, allocated or deallocated, link target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// "ok", or why the change was refused
+    pub status: String,
+}
+
+/// What an audited change applies to.
+#[derive(Debug, Clone, Copy)]
+pub enum Target<'a> {
+    Handle(&'a [u8]),
+    /// A name in a directory
+    Entry(&'a [u8], &'a str),
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEvent<'a> {
+    action: AuditAction,
+    target: Target<'a>,
+    to: Option<Target<'a>>,
+    detail: Option<String>,
+}
+
+impl<'a> AuditEvent<'a> {
+    pub fn new(action: AuditAction, target: Target<'a>) -> Self {
+        Self { action, target, to: None, detail: None }
+    }
+
+    pub fn to(self, to: Target<'a>) -> Self {
+        Self { to: Some(to), ..self }
+    }
+
+    pub fn detail(self, detail: String) -> Self {
+        Self { detail: Some(detail), ..self }
+    }
+}
+
+/// The attributes a SETATTR asks for, e.g. "mode=0644 uid=1000".
+pub fn describe_setattr(set: &SetAttr) -> String {
+    let mut parts = Vec::new();
+    if let Some(mode) = set.mode {
+        parts.push(format!("mode={:04o}", mode & 0o7777));
+    }
+    if let Some(uid) = set.uid {
+        parts.push(format!("uid={}", uid));
+    }
+    if let Some(gid) = set.gid {
+        parts.push(format!("gid={}", gid));
+    }
+    if let Some(size) = set.size {
+        parts.push(format!("size={}", size));
+    }
+    if set.atime.is_some() {
+        parts.push("atime".into());
+    }
+    if set.mtime.is_some() {
+        parts.push("mtime".into());
+    }
+    if let Some(acl) = &set.acl {
+        parts.push(format!("acl={}", acl.aces.len()));
+    }
+    parts.join(" ")
+}
+
+pub struct AuditLog {
+    file: Mutex<File>,
+    exports: Arc<ExportTable>,
+    // handle -> (directory handle, name) it was last seen under
+    names: DashMap<Vec<u8>, (Vec<u8>, String)>,
+}
+
+impl AuditLog {
+    /// Append to the log at `path`, creating it readable only by us.
+    pub fn open(path: &Path, exports: Arc<ExportTable>) -> NfsResult<Self> {
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options
+            .open(path)
+            .map_err(|e| NfsError::Config(format!("audit log {}: {}", path.display(), e)))?;
+        Ok(Self { file: Mutex::new(file), exports, names: DashMap::new() })
+    }
+
+    /// Remember that `fh` is called `name` in directory `dir`.
+    pub fn learn(&self, dir: &[u8], name: &str, fh: &[u8]) {
+        if name == "." || name == ".." {
+            return;
+        }
+        if self.names.len() >= MAX_NAMES {
+            self.names.clear();
+        }
+        self.names.insert(fh.to_vec(), (dir.to_vec(), name.to_string()));
+    }
+
+    /// Best known path of `fh`.
+    pub async fn path(&self, fh: &[u8]) -> String {
+        let mut names = Vec::new();
+        let mut fh = fh.to_vec();
+        let base = loop {
+            if let Some(root) = self.exports.root_path(&fh).await {
+                break root;
+            }
+            let parent = match self.names.get(&fh) {
+                Some(entry) if names.len() < MAX_DEPTH => entry.clone(),
+                _ => break format!("fh:{}", hex(&fh)),
+            };
+            names.push(parent.1);
+            fh = parent.0;
+        };
+        names.iter().rev().fold(base, |path, name| join(&path, name))
+    }
+
+    async fn resolve(&self, target: Target<'_>) -> String {
+        match target {
+            Target::Handle(fh) => self.path(fh).await,
+            Target::Entry(dir, name) => join(&self.path(dir).await, name),
+        }
+    }
+
+    /// Log `event` by `caller` with the outcome `res`.
+    pub async fn record<T>(&self, caller: &Caller, event: AuditEvent<'_>, res: &NfsResult<T>) {
+        let to = match event.to {
+            Some(to) => Some(self.resolve(to).await),
+            None => None,
+        };
+        let record = AuditRecord {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            client: caller.addr.ip().to_string(),
+            principal: caller.principal(),
+            action: event.action,
+            path: self.resolve(event.target).await,
+            to,
+            detail: event.detail,
+            status: match res {
+                Ok(_) => "ok".into(),
+                Err(e) => e.to_string(),
+            },
+        };
+        self.append(&record);
+    }
+
+    fn append(&self, record: &AuditRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("audit record {:?}: {}", record, e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        // one write per line so concurrent writers never interleave
+        if let Err(e) = self.file.lock().unwrap().write_all(&line) {
+            warn!("writing audit log: {}", e);
+        }
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "/" => format!("/{}", name),
+        dir => format!("{}/{}", dir, name),
+    }
+}
@@ -0,0 +1,100 @@
+//! RPC credentials (RFC 5531 section 8 and appendix A)
+
+use crate::xdr::*;
+use bytes::{Bytes, BytesMut};
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::net::SocketAddr;
+
+pub const AUTH_NONE: u32 = 0;
+pub const AUTH_SYS: u32 = 1;
+pub const RPCSEC_GSS: u32 = 6;
+
+/// Longest credential body the RPC spec allows
+pub const MAX_AUTH_BYTES: usize = 400;
+const AUTH_SYS_MAX_MACHINENAME: usize = 255;
+const AUTH_SYS_MAX_GIDS: usize = 16;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthSys {
+    pub stamp: u32,
+    pub machinename: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub gids: Vec<u32>,
+}
+
+/// Credential presented with a call. Only AUTH_SYS bodies are interpreted;
+/// `Other` flavors carry no identity we can check and are refused access.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Credential {
+    #[default]
+    None,
+    Sys(AuthSys),
+    Other(u32),
+}
+
+impl Credential {
+    pub fn flavor(&self) -> u32 {
+        match self {
+            Credential::None => AUTH_NONE,
+            Credential::Sys(_) => AUTH_SYS,
+            Credential::Other(flavor) => *flavor,
+        }
+    }
+
+    /// Parse an opaque_auth body of the given flavor.
+    pub fn decode(flavor: u32, mut body: Bytes) -> IoResult<Self> {
+        match flavor {
+            AUTH_NONE => Ok(Credential::None),
+            AUTH_SYS => {
+                let stamp = u32::xdr_decode(&mut body)?;
+                let machinename = String::xdr_decode(&mut body)?;
+                let uid = u32::xdr_decode(&mut body)?;
+                let gid = u32::xdr_decode(&mut body)?;
+                let gids = Vec::<u32>::xdr_decode(&mut body)?;
+                if machinename.len() > AUTH_SYS_MAX_MACHINENAME || gids.len() > AUTH_SYS_MAX_GIDS {
+                    return Err(Error::new(ErrorKind::InvalidData, "oversized AUTH_SYS credential"));
+                }
+                Ok(Credential::Sys(AuthSys { stamp, machinename, uid, gid, gids }))
+            }
+            other => Ok(Credential::Other(other)),
+        }
+    }
+}
+
+impl XdrEncode for Credential {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.flavor().xdr_encode(buf);
+        match self {
+            Credential::Sys(sys) => {
+                let mut body = BytesMut::new();
+                sys.stamp.xdr_encode(&mut body);
+                sys.machinename.xdr_encode(&mut body);
+                sys.uid.xdr_encode(&mut body);
+                sys.gid.xdr_encode(&mut body);
+                sys.gids.xdr_encode(&mut body);
+                body[..].xdr_encode(buf);
+            }
+            _ => 0u32.xdr_encode(buf),
+        }
+    }
+}
+
+/// Who is making a call: the transport peer and its credential.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub addr: SocketAddr,
+    pub cred: Credential,
+}
+
+impl Caller {
+    /// Who the credential claims to be, for logs: "uid:gid@machine" for
+    /// AUTH_SYS, "none", or the flavor number of anything else.
+    pub fn principal(&self) -> String {
+        match &self.cred {
+            Credential::None => "none".into(),
+            Credential::Sys(sys) => format!("{}:{}@{}", sys.uid, sys.gid, sys.machinename),
+            Credential::Other(flavor) => format!("flavor {}", flavor),
+        }
+    }
+}
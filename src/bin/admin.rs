@@ -0,0 +1,64 @@
+use clap::{Parser, Subcommand};
+#[cfg(unix)]
+use nfs_rs::admin::{request, AdminRequest};
+use std::path::PathBuf;
+
+/// Inspect and control a running nfs-rs server through its admin socket.
+#[derive(Parser, Debug)]
+#[command(name = "nfs-rs-admin", version)]
+struct Cli {
+    /// The server's admin_socket
+    #[arg(short, long, env = "NFS_ADMIN_SOCKET", default_value = "/run/nfs-rs/admin.sock")]
+    socket: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List clients with their connections, mounts and locks
+    Clients,
+    /// List byte-range locks by file
+    Locks,
+    /// Show exports and their usage
+    Exports,
+    /// Close a client's connections and drop its mounts, locks and monitoring
+    Expire {
+        /// Client address, or the name it used for NLM
+        client: String,
+    },
+    /// Revoke a delegation
+    Revoke { stateid: String },
+    /// End the lock grace period now
+    EndGrace,
+    /// Reload the server's configuration
+    Reload,
+}
+
+#[cfg(unix)]
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let req = match cli.command {
+        Command::Clients => AdminRequest::Clients,
+        Command::Locks => AdminRequest::Locks,
+        Command::Exports => AdminRequest::Exports,
+        Command::Expire { client } => AdminRequest::ExpireClient { client },
+        Command::Revoke { stateid } => AdminRequest::RevokeDelegation { stateid },
+        Command::EndGrace => AdminRequest::EndGrace,
+        Command::Reload => AdminRequest::Reload,
+    };
+    match request(&cli.socket, &req).await {
+        Ok(result) => println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default()),
+        Err(e) => {
+            eprintln!("nfs-rs-admin: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("nfs-rs-admin: the admin socket needs a Unix platform");
+    std::process::exit(1);
+}
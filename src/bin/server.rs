@@ -1,24 +1,196 @@
+use clap::Parser;
+use nfs_rs::config::{BackendConfig, ClientRule, ExportConfig, ExportOptions, RpcbindMode};
 use nfs_rs::{NfsConfig, NfsServer};
-use tracing_subscriber::EnvFilter;
+use std::path::PathBuf;
+
+/// NFSv4.2 server (with NFSv3, MOUNT and NLM) in Rust.
+///
+/// Settings come from the defaults, then the --config file, then the
+/// options below, then --set overrides. SIGHUP reloads them, applying
+/// export rules and logging without a restart. SIGTERM or SIGINT stops the
+/// server once requests in progress are done; a second one exits at once.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "nfs-rs", version, after_help = "Example:\n    RUST_LOG=info nfs-rs --config /etc/nfs-rs.toml --port 20490")]
+struct Cli {
+    /// Configuration file, TOML or JSON (by ".json" extension)
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Address to listen on [default: 0.0.0.0]
+    #[arg(long, env = "NFS_BIND_ADDR", value_name = "ADDR")]
+    bind_addr: Option<String>,
+    /// Port to listen on [default: 2049]
+    #[arg(long, env = "NFS_PORT")]
+    port: Option<u16>,
+    /// Also listen on UDP for NULL and portmapper probes
+    #[arg(long)]
+    udp: bool,
+    /// Largest UDP datagram accepted or sent [default: 8192]
+    #[arg(long, value_name = "BYTES")]
+    max_udp_datagram: Option<usize>,
+    /// Portmapper integration: off, register or serve
+    #[arg(long, value_name = "MODE", value_parser = parse_rpcbind_mode)]
+    rpcbind: Option<RpcbindMode>,
+    /// Port of the built-in portmapper in serve mode [default: 111]
+    #[arg(long, value_name = "PORT")]
+    rpcbind_port: Option<u16>,
+    /// Socket of the system rpcbind in register mode [default: /run/rpcbind.sock]
+    #[arg(long, value_name = "PATH")]
+    rpcbind_socket: Option<String>,
+    /// Export PATH, from local directory DIR if given, else from memory, to
+    /// every client read-only with root_squash and sec=sys; loosen it with
+    /// e.g. --set exports.0.clients.0.access=rw. Replaces the
+    /// configured exports; may be repeated.
+    #[arg(long, value_name = "PATH[=DIR]")]
+    export: Vec<String>,
+    /// Log filter in RUST_LOG syntax; RUST_LOG itself takes precedence
+    #[arg(long, value_name = "FILTER")]
+    log: Option<String>,
+    /// Directory for the client recovery record kept across restarts
+    #[arg(long, value_name = "DIR")]
+    state_dir: Option<String>,
+    /// Unix socket for nfs-rs-admin
+    #[arg(long, value_name = "PATH")]
+    admin_socket: Option<String>,
+    /// Append a JSON line per change clients make to FILE
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<String>,
+    /// Serve Prometheus metrics at http://ADDR/metrics (metrics feature)
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<String>,
+    /// Seconds requests in progress get to finish on shutdown [default: 10]
+    #[arg(long, value_name = "SECS")]
+    shutdown_timeout: Option<u64>,
+    /// Override any setting by its dotted name, e.g. exports.0.fsid=7
+    #[arg(long, value_name = "KEY=VALUE")]
+    set: Vec<String>,
+    /// Print the effective configuration as TOML and exit
+    #[arg(long)]
+    print_config: bool,
+}
+
+fn parse_rpcbind_mode(s: &str) -> Result<RpcbindMode, String> {
+    match s {
+        "off" => Ok(RpcbindMode::Off),
+        "register" => Ok(RpcbindMode::Register),
+        "serve" => Ok(RpcbindMode::Serve),
+        _ => Err(format!("expected off, register or serve, not {:?}", s)),
+    }
+}
+
+fn effective_config(cli: Cli) -> nfs_rs::NfsResult<NfsConfig> {
+    let mut cfg = match &cli.config {
+        Some(path) => NfsConfig::load(path)?,
+        None => NfsConfig::default(),
+    };
+    if let Some(addr) = cli.bind_addr {
+        cfg.bind_addr = addr;
+    }
+    if let Some(port) = cli.port {
+        cfg.port = port;
+    }
+    cfg.udp |= cli.udp;
+    if let Some(size) = cli.max_udp_datagram {
+        cfg.max_udp_datagram = size;
+    }
+    if let Some(mode) = cli.rpcbind {
+        cfg.rpcbind.mode = mode;
+    }
+    if let Some(port) = cli.rpcbind_port {
+        cfg.rpcbind.port = port;
+    }
+    if let Some(socket) = cli.rpcbind_socket {
+        cfg.rpcbind.socket = socket;
+    }
+    if let Some(log) = cli.log {
+        cfg.log = Some(log);
+    }
+    if let Some(dir) = cli.state_dir {
+        cfg.state_dir = Some(dir);
+    }
+    if let Some(path) = cli.admin_socket {
+        cfg.admin_socket = Some(path);
+    }
+    if let Some(path) = cli.audit_log {
+        cfg.audit_log = Some(path);
+    }
+    if let Some(addr) = cli.metrics_addr {
+        cfg.metrics_addr = Some(addr);
+    }
+    if let Some(secs) = cli.shutdown_timeout {
+        cfg.shutdown_timeout = secs;
+    }
+    if !cli.export.is_empty() {
+        cfg.exports = cli
+            .export
+            .iter()
+            .map(|spec| {
+                let (path, backend) = match spec.split_once('=') {
+                    Some((path, dir)) => (path, BackendConfig::Local { root: dir.to_string() }),
+                    None => (spec.as_str(), BackendConfig::Memory),
+                };
+                // the exports(5) defaults rather than the open ones a bare
+                // ExportConfig gets
+                let clients = vec![ClientRule { host: "*".into(), options: ExportOptions::default() }];
+                ExportConfig { path: path.to_string(), backend, fsid: None, clients }
+            })
+            .collect();
+    }
+    for setting in &cli.set {
+        let (key, value) = setting
+            .split_once('=')
+            .ok_or_else(|| nfs_rs::NfsError::Config(format!("--set {:?} is not KEY=VALUE", setting)))?;
+        cfg.set(key, value)?;
+    }
+    cfg.validate()?;
+    Ok(cfg)
+}
+
+#[cfg(unix)]
+async fn terminated() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut term = signal(SignalKind::terminate())?;
+    let mut int = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = term.recv() => {}
+        _ = int.recv() => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn terminated() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
-
-    // Print help and exit if --help is present
-    let args: Vec<String> = std::env::args().collect();
-    if args.iter().any(|a| a == "--help" || a == "-h") {
-        println!("nfs-server: Minimal NFSv4.2 server in Rust\n\nUsage:\n    cargo run --bin nfs-server [--help]\n\nEnvironment variables:\n    NFS_BIND_ADDR   Bind address (default: 127.0.0.1)\n    NFS_PORT        Port to listen on (default: 20490)\n\nExample:\n    RUST_LOG=info NFS_BIND_ADDR=127.0.0.1 NFS_PORT=20490 cargo run --bin nfs-server\n");
+    let cli = Cli::parse();
+    let print = cli.print_config;
+    let cfg = match effective_config(cli.clone()) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            eprintln!("nfs-rs: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if print {
+        print!("{}", cfg.to_toml()?);
         return Ok(());
     }
 
-    // Allow simple configuration via environment variables for quick runs
-    let mut cfg = NfsConfig::default();
-    if let Ok(addr) = std::env::var("NFS_BIND_ADDR") { cfg.bind_addr = addr; }
-    if let Ok(port) = std::env::var("NFS_PORT") { if let Ok(p) = port.parse() { cfg.port = p; } }
-    let server = NfsServer::new(cfg).await?;
+    nfs_rs::logging::init(cfg.log.as_deref())?;
+    // SIGHUP re-reads the file and reapplies the same command line
+    let server = NfsServer::new(cfg).await?.with_config_source(move || effective_config(cli.clone()));
+    let shutdown = server.shutdown_handle();
+    tokio::spawn(async move {
+        if terminated().await.is_ok() {
+            tracing::info!("shutting down, waiting up to {:?} for requests in progress", shutdown.grace());
+            shutdown.shutdown();
+            if terminated().await.is_ok() {
+                std::process::exit(130);
+            }
+        }
+    });
     server.run().await?;
     Ok(())
 }
@@ -0,0 +1,237 @@
+//! A minimal NFSv4.2 client, with which the destination of an inter-server
+//! COPY (RFC 7862 section 4.9) reads from the source server. It knows just
+//! PUTFH, GETATTR of the size and READ_PLUS, falling back to READ where the
+//! source has no READ_PLUS. Calls go as AUTH_SYS nobody: the stateid the
+//! source issued with COPY_NOTIFY is what lets them read.
+//!
+//! Errors from the source come back as the partner errors where one fits:
+//! PARTNER_NOAUTH when it refuses to let us read, PARTNER_NOTSUPP when it
+//! cannot be reached or speaks no NFSv4.2.
+
+use crate::auth::{AuthSys, Credential};
+use crate::constants::{NFS_MINOR_VERSION, NFS_PORT};
+use crate::error::{Nfs4Status, NfsError, NfsResult};
+use crate::proto::nfs4::*;
+use crate::proto::portmap::from_uaddr;
+use crate::rpc::*;
+use crate::vfs::Vfs;
+use crate::xdr::*;
+use bytes::{Bytes, BytesMut};
+use num_traits::FromPrimitive;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::{lookup_host, TcpStream};
+use tracing::debug;
+
+// uid and gid the calls are made as
+const NOBODY: u32 = 65534;
+
+/// Most asked of the source in one read
+pub const READ_SIZE: u32 = 1024 * 1024;
+
+/// The socket addresses a netloc4 names. Names and URLs are looked up, on
+/// the NFS port unless they give one; of universal addresses only TCP ones
+/// are of use.
+pub async fn resolve(loc: &Netloc4) -> Vec<SocketAddr> {
+    let host = match loc {
+        Netloc4::Netaddr { netid, addr } => {
+            let addr = std::str::from_utf8(addr).ok().and_then(from_uaddr);
+            return match (&netid[..], addr) {
+                (b"tcp" | b"tcp6", Some(addr)) => vec![addr],
+                _ => vec![],
+            };
+        }
+        Netloc4::Name(name) => String::from_utf8_lossy(name).into_owned(),
+        // nfs://host[:port]/path
+        Netloc4::Url(url) => match String::from_utf8_lossy(url).strip_prefix("nfs://") {
+            Some(rest) => rest.split('/').next().unwrap_or_default().to_string(),
+            None => return vec![],
+        },
+    };
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return vec![SocketAddr::new(ip, NFS_PORT)];
+    }
+    let found = match host.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => lookup_host(host.as_str()).await.map(|addrs| addrs.collect()),
+        _ => lookup_host((host.trim_matches(['[', ']']), NFS_PORT)).await.map(|addrs| addrs.collect()),
+    };
+    found.unwrap_or_default()
+}
+
+// What the source refusing us means to whoever asked for the copy
+fn partner(e: NfsError) -> NfsError {
+    use Nfs4Status::*;
+    match e {
+        NfsError::Network(_) => NfsError::Status(PartnerNotsupp),
+        NfsError::Status(Notsupp | OpIllegal | MinorVersMismatch) => NfsError::Status(PartnerNotsupp),
+        NfsError::Status(BadStateid | OldStateid | Expired | AdminRevoked | Access | Perm | Wrongsec) => NfsError::Status(PartnerNoAuth),
+        e => e,
+    }
+}
+
+// Step past the result header of `op`, failing with its status
+fn result(reply: &mut Bytes, op: NfsOp4) -> NfsResult<()> {
+    let opcode = u32::xdr_decode(reply)?;
+    match u32::xdr_decode(reply)? {
+        NFS4_OK if opcode == op as u32 => Ok(()),
+        NFS4_OK => Err(NfsError::Protocol(format!("result of op {} where {:?} was due", opcode, op))),
+        status => Err(NfsError::Status(Nfs4Status::from_u32(status).unwrap_or(Nfs4Status::Serverfault))),
+    }
+}
+
+fn putfh(ops: &mut BytesMut, fh: &[u8]) {
+    (NfsOp4::OpPutfh as u32).xdr_encode(ops);
+    fh.xdr_encode(ops);
+}
+
+/// A connection to the source server of a copy, for reading its file
+pub struct SourceClient {
+    stream: TcpStream,
+    fh: Vec<u8>,
+    stateid: Stateid4,
+    rbuf: BytesMut,
+    xid: u32,
+    // cleared once the source turns READ_PLUS down
+    read_plus: bool,
+}
+
+impl SourceClient {
+    /// Connect to the first of `locations` that answers, to read the file
+    /// `fh` there with the copy stateid `stateid`
+    pub async fn connect(locations: &[Netloc4], fh: &[u8], stateid: Stateid4) -> NfsResult<Self> {
+        for loc in locations {
+            for addr in resolve(loc).await {
+                match TcpStream::connect(addr).await {
+                    Ok(stream) => {
+                        debug!("copy source {}", addr);
+                        let (fh, rbuf, xid) = (fh.to_vec(), BytesMut::new(), std::process::id());
+                        return Ok(Self { stream, fh, stateid, rbuf, xid, read_plus: true });
+                    }
+                    Err(e) => debug!("copy source {} unreachable: {}", addr, e),
+                }
+            }
+        }
+        Err(partner(NfsError::Network("no source server location answered".into())))
+    }
+
+    // Send a COMPOUND of the `nops` operations encoded in `ops` and return
+    // the reply from the first result on
+    async fn compound(&mut self, nops: u32, ops: BytesMut) -> NfsResult<Bytes> {
+        self.xid = self.xid.wrapping_add(1);
+        let header = RpcCallHeader {
+            xid: self.xid,
+            msg_type: RpcMessageType::Call,
+            rpcvers: 2,
+            prog: NFS4_PROGRAM,
+            vers: NFS4_VERSION,
+            proc: Nfs4Proc::Compound as u32,
+        };
+        let cred = Credential::Sys(AuthSys { stamp: self.xid, machinename: "nfs-rs".into(), uid: NOBODY, gid: NOBODY, gids: vec![] });
+        let mut call = BytesMut::new();
+        RpcCall { header, cred }.xdr_encode(&mut call);
+        b"".as_slice().xdr_encode(&mut call);
+        NFS_MINOR_VERSION.xdr_encode(&mut call);
+        nops.xdr_encode(&mut call);
+        write_record_vectored(&mut self.stream, &[call.freeze(), ops.freeze()]).await?;
+
+        let mut reply = read_record(&mut self.stream, &mut self.rbuf).await?;
+        let hdr = RpcReplyHeader::xdr_decode(&mut reply)?;
+        if hdr.xid != self.xid || hdr.reply_state != 0 || hdr.accept_state != ACCEPT_SUCCESS {
+            return Err(NfsError::Network(format!("source server rejected call (accept_stat {})", hdr.accept_state)));
+        }
+        let status = u32::xdr_decode(&mut reply)?;
+        Bytes::xdr_decode(&mut reply)?;
+        if status == Nfs4Status::MinorVersMismatch as u32 {
+            return Err(NfsError::Status(Nfs4Status::MinorVersMismatch));
+        }
+        // the results are checked one by one
+        u32::xdr_decode(&mut reply)?;
+        Ok(reply)
+    }
+
+    /// The size of the file
+    pub async fn size(&mut self) -> NfsResult<u64> {
+        let res = async {
+            let mut ops = BytesMut::new();
+            putfh(&mut ops, &self.fh);
+            (NfsOp4::OpGetattr as u32).xdr_encode(&mut ops);
+            bitmap4_with(&[FATTR4_SIZE]).xdr_encode(&mut ops);
+            let mut reply = self.compound(2, ops).await?;
+            result(&mut reply, NfsOp4::OpPutfh)?;
+            result(&mut reply, NfsOp4::OpGetattr)?;
+            let attrs = Fattr4::xdr_decode(&mut reply)?;
+            if !bitmap4_has(&attrs.attrmask, FATTR4_SIZE) {
+                return Err(NfsError::Protocol("source server left out the size".into()));
+            }
+            Ok(u64::xdr_decode(&mut attrs.attr_vals.clone())?)
+        };
+        res.await.map_err(partner)
+    }
+
+    // PUTFH and one READ or READ_PLUS; the reply from its result on
+    async fn read_op(&mut self, op: NfsOp4, args: &Read4args) -> NfsResult<Bytes> {
+        let mut ops = BytesMut::new();
+        putfh(&mut ops, &self.fh);
+        (op as u32).xdr_encode(&mut ops);
+        args.xdr_encode(&mut ops);
+        let mut reply = self.compound(2, ops).await?;
+        result(&mut reply, NfsOp4::OpPutfh)?;
+        result(&mut reply, op)?;
+        Ok(reply)
+    }
+
+    /// Up to `count` bytes of the file from `offset`, as data and holes
+    pub async fn read(&mut self, offset: u64, count: u32) -> NfsResult<ReadPlus4resok> {
+        let args = Read4args { stateid: self.stateid, offset, count };
+        if self.read_plus {
+            match self.read_op(NfsOp4::OpReadPlus, &args).await {
+                Err(NfsError::Status(Nfs4Status::Notsupp | Nfs4Status::OpIllegal)) => self.read_plus = false,
+                res => return res.and_then(|mut reply| Ok(ReadPlus4resok::xdr_decode(&mut reply)?)).map_err(partner),
+            }
+        }
+        let res = async {
+            let mut reply = self.read_op(NfsOp4::OpRead, &args).await?;
+            let eof = bool::xdr_decode(&mut reply)?;
+            let data = Bytes::xdr_decode(&mut reply)?;
+            Ok(ReadPlus4resok { eof, contents: vec![ReadPlusContent4::Data { offset, data }] })
+        };
+        res.await.map_err(partner)
+    }
+
+    /// Copy `count` bytes of the file from `src_offset` to `dst` at
+    /// `dst_offset` through `vfs`. Holes are written as zeros. Stops early
+    /// at the end of the source.
+    pub async fn pull(&mut self, src_offset: u64, vfs: &dyn Vfs, dst: &[u8], dst_offset: u64, count: u64) -> NfsResult<u64> {
+        let end = src_offset + count;
+        let mut at = src_offset;
+        while at < end {
+            let start = at;
+            let want = (end - at).min(READ_SIZE as u64);
+            let res = self.read(at, want as u32).await?;
+            // pieces come in order; a hole may start before the range asked
+            // for and run past it
+            for piece in res.contents {
+                let (offset, len) = match &piece {
+                    ReadPlusContent4::Data { offset, data } => (*offset, data.len() as u64),
+                    ReadPlusContent4::Hole { offset, length } => (*offset, *length),
+                };
+                if offset > at {
+                    return Err(NfsError::Protocol(format!("source server skipped {}..{}", at, offset)));
+                }
+                let to = offset.saturating_add(len).min(start + want);
+                if to <= at {
+                    continue;
+                }
+                let data = match piece {
+                    ReadPlusContent4::Data { data, .. } => data.slice((at - offset) as usize..(to - offset) as usize),
+                    ReadPlusContent4::Hole { .. } => Bytes::from(vec![0; (to - at) as usize]),
+                };
+                vfs.write(dst, dst_offset + (at - src_offset), data).await?;
+                at = to;
+            }
+            if res.eof || at == start {
+                break;
+            }
+        }
+        Ok(at - src_offset)
+    }
+}
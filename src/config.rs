@@ -1,13 +1,356 @@
+use crate::error::{NfsError, NfsResult};
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Largest payload of a UDP datagram over IPv4
+const MAX_UDP_PAYLOAD: usize = 65507;
+/// Smallest datagram every host must accept (RFC 791)
+const MIN_UDP_DATAGRAM: usize = 576;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct NfsConfig {
     pub bind_addr: String,
     pub port: u16,
+    /// Also listen on UDP (same address and port) for NULL probes
+    #[serde(default)]
+    pub udp: bool,
+    /// Largest UDP datagram accepted or sent
+    #[serde(default = "default_max_udp_datagram")]
+    pub max_udp_datagram: usize,
+    #[serde(default)]
+    pub rpcbind: RpcbindConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// What is exported and to whom. Paths are what clients mount.
+    #[serde(default = "default_exports")]
+    pub exports: Vec<ExportConfig>,
+    /// Log filter in RUST_LOG syntax, e.g. "info,nfs_rs::nfs3=debug";
+    /// RUST_LOG overrides it when set
+    #[serde(default)]
+    pub log: Option<String>,
+    /// Seconds requests already executing get to finish on shutdown
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Directory for the client recovery record kept across restarts;
+    /// without one, clients find no trace of earlier mounts or locks
+    #[serde(default)]
+    pub state_dir: Option<String>,
+    /// Seconds after a restart during which clients in the recovery record
+    /// may reclaim their locks before new locks are granted
+    #[serde(default = "default_grace_period")]
+    pub grace_period: u64,
+    /// Unix socket for `nfs-rs-admin`; only the server's user may connect
+    #[serde(default)]
+    pub admin_socket: Option<String>,
+    /// File that gets a JSON line for every change a client makes
+    #[serde(default)]
+    pub audit_log: Option<String>,
+    /// Address of the Prometheus endpoint, e.g. "127.0.0.1:9102"; needs
+    /// the `metrics` feature
+    #[serde(default)]
+    pub metrics_addr: Option<String>,
+}
+
+fn default_exports() -> Vec<ExportConfig> {
+    vec![ExportConfig::default()]
+}
+
+fn default_max_udp_datagram() -> usize {
+    8192
+}
+
+fn default_shutdown_timeout() -> u64 {
+    10
+}
+
+fn default_grace_period() -> u64 {
+    90
 }
 
 impl Default for NfsConfig {
     fn default() -> Self {
-        Self { bind_addr: "0.0.0.0".into(), port: 2049 }
+        Self {
+            bind_addr: "0.0.0.0".into(),
+            port: 2049,
+            udp: false,
+            max_udp_datagram: default_max_udp_datagram(),
+            rpcbind: RpcbindConfig::default(),
+            limits: LimitsConfig::default(),
+            exports: default_exports(),
+            log: None,
+            shutdown_timeout: default_shutdown_timeout(),
+            state_dir: None,
+            grace_period: default_grace_period(),
+            admin_socket: None,
+            audit_log: None,
+            metrics_addr: None,
+        }
+    }
+}
+
+fn config_err(msg: String) -> NfsError {
+    NfsError::Config(msg)
+}
+
+impl NfsConfig {
+    /// Read a configuration file: JSON if the name ends in ".json", TOML
+    /// otherwise. Settings missing from the file keep their defaults.
+    pub fn load(path: &Path) -> NfsResult<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| config_err(format!("{}: {}", path.display(), e)))?;
+        let parsed = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|e| config_err(format!("{}: {}", path.display(), e.trim_end())))
+    }
+
+    /// Override one setting by its dotted path, e.g. `rpcbind.mode=serve`
+    /// or `exports.0.path=/srv`. The value is read as TOML, falling back to
+    /// a plain string.
+    pub fn set(&mut self, key: &str, value: &str) -> NfsResult<()> {
+        let mut tree = toml::Value::try_from(&*self).map_err(|e| config_err(e.to_string()))?;
+        let unknown = || config_err(format!("unknown setting {:?}", key));
+        let (parents, last) = key.rsplit_once('.').map_or(("", key), |(p, l)| (p, l));
+        let mut node = &mut tree;
+        for seg in parents.split('.').filter(|s| !s.is_empty()) {
+            node = match node {
+                toml::Value::Table(t) => t.get_mut(seg),
+                toml::Value::Array(a) => seg.parse::<usize>().ok().and_then(|i| a.get_mut(i)),
+                _ => None,
+            }
+            .ok_or_else(unknown)?;
+        }
+        let value = toml::from_str::<toml::Table>(&format!("v = {}", value))
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+            .unwrap_or_else(|| toml::Value::String(value.to_string()));
+        match node {
+            toml::Value::Table(t) => {
+                t.insert(last.to_string(), value);
+            }
+            toml::Value::Array(a) => *last.parse::<usize>().ok().and_then(|i| a.get_mut(i)).ok_or_else(unknown)? = value,
+            _ => return Err(unknown()),
+        }
+        *self = tree.try_into().map_err(|e: toml::de::Error| config_err(format!("{}: {}", key, e.message())))?;
+        Ok(())
+    }
+
+    /// Check everything that can be checked without binding sockets or
+    /// opening backends.
+    pub fn validate(&self) -> NfsResult<()> {
+        self.bind_addr
+            .parse::<IpAddr>()
+            .map_err(|_| config_err(format!("bind_addr {:?} is not an IP address", self.bind_addr)))?;
+        if !(MIN_UDP_DATAGRAM..=MAX_UDP_PAYLOAD).contains(&self.max_udp_datagram) {
+            return Err(config_err(format!(
+                "max_udp_datagram must be between {} and {}",
+                MIN_UDP_DATAGRAM, MAX_UDP_PAYLOAD
+            )));
+        }
+        match self.rpcbind.mode {
+            RpcbindMode::Serve if self.rpcbind.port == self.port => {
+                return Err(config_err(format!("rpcbind.port {} is also the NFS port", self.port)));
+            }
+            RpcbindMode::Register if self.rpcbind.socket.is_empty() => {
+                return Err(config_err("rpcbind.socket is required in register mode".into()));
+            }
+            _ => {}
+        }
+        if let Some(addr) = &self.metrics_addr {
+            addr.parse::<std::net::SocketAddr>()
+                .map_err(|_| config_err(format!("metrics_addr {:?} is not an address and port", addr)))?;
+            if !cfg!(feature = "metrics") {
+                return Err(config_err("metrics_addr needs nfs-rs built with the metrics feature".into()));
+            }
+        }
+        if self.limits.requests_per_second > 0 && self.limits.burst == 0 {
+            return Err(config_err("limits.burst must be at least 1 when requests_per_second is set".into()));
+        }
+        if let Some(log) = &self.log {
+            crate::logging::parse(log)?;
+        }
+        crate::export::validate_exports(&self.exports)
+    }
+
+    /// The configuration as a TOML document
+    pub fn to_toml(&self) -> NfsResult<String> {
+        toml::to_string_pretty(self).map_err(|e| config_err(e.to_string()))
+    }
+}
+
+/// How the server makes itself discoverable through the portmapper
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcbindMode {
+    /// Not advertised; NFSv4 clients connect to the port directly
+    #[default]
+    Off,
+    /// Register with the system rpcbind over its Unix socket
+    Register,
+    /// Run a built-in PMAP v2 / RPCBIND v3/v4 responder
+    Serve,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcbindConfig {
+    pub mode: RpcbindMode,
+    /// Port for the built-in responder
+    pub port: u16,
+    /// Socket of the system rpcbind used in `register` mode
+    pub socket: String,
+}
+
+impl Default for RpcbindConfig {
+    fn default() -> Self {
+        Self { mode: RpcbindMode::Off, port: crate::proto::portmap::PMAP_PORT, socket: "/run/rpcbind.sock".into() }
     }
 }
+
+/// Caps that keep one client from starving the others. A limit of 0 means
+/// no limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Connections open at once; more are closed as soon as accepted
+    pub max_connections: usize,
+    /// Connections open at once from one client address
+    pub max_connections_per_client: usize,
+    /// Sustained NFS requests per second from one client address
+    pub requests_per_second: u32,
+    /// Requests a client may send in a burst above its rate
+    pub burst: u32,
+    /// NFS requests executing at once; more are answered with
+    /// NFS4ERR_DELAY (NFS3ERR_JUKEBOX) so the client retries later
+    pub max_in_flight: usize,
+    /// Bytes of NFS requests executing at once
+    pub max_in_flight_bytes: usize,
+    /// Seconds a connection may go without starting a request before it
+    /// is closed
+    pub idle_timeout: u64,
+    /// Seconds the rest of a request may take to arrive once it has
+    /// started; a connection that is slower is closed
+    pub record_timeout: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 1024,
+            max_connections_per_client: 64,
+            requests_per_second: 0,
+            burst: 64,
+            max_in_flight: 256,
+            max_in_flight_bytes: 64 << 20,
+            idle_timeout: 360,
+            record_timeout: 60,
+        }
+    }
+}
+
+/// One exported tree, in the spirit of an exports(5) line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportConfig {
+    /// Path clients mount, e.g. "/builds"
+    pub path: String,
+    #[serde(default)]
+    pub backend: BackendConfig,
+    /// Filesystem id reported for the export; derived from `path` if unset
+    #[serde(default)]
+    pub fsid: Option<u64>,
+    /// Per-client rules. The first rule matching a client applies; clients
+    /// matching none are refused.
+    #[serde(default)]
+    pub clients: Vec<ClientRule>,
+}
+
+impl Default for ExportConfig {
+    /// An in-memory "/" open to everyone, read-write
+    fn default() -> Self {
+        Self {
+            path: "/".into(),
+            backend: BackendConfig::Memory,
+            fsid: None,
+            clients: vec![ClientRule {
+                host: "*".into(),
+                options: ExportOptions {
+                    access: AccessMode::Rw,
+                    squash: Squash::None,
+                    sec: vec![SecFlavor::Sys, SecFlavor::None],
+                    ..ExportOptions::default()
+                },
+            }],
+        }
+    }
+}
+
+/// Where an export's data lives
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BackendConfig {
+    /// Volatile in-memory filesystem
+    #[default]
+    Memory,
+    /// A directory on the server's local filesystem
+    Local { root: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientRule {
+    /// "*", an address, a CIDR block, a hostname or a "*.domain" wildcard
+    pub host: String,
+    #[serde(flatten)]
+    pub options: ExportOptions,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub access: AccessMode,
+    pub squash: Squash,
+    /// Identity squashed callers are mapped to
+    pub anonuid: u32,
+    pub anongid: u32,
+    /// Security flavors a client may use; others get WRONGSEC
+    pub sec: Vec<SecFlavor>,
+}
+
+impl Default for ExportOptions {
+    /// Same defaults as exports(5): ro, root_squash, sec=sys
+    fn default() -> Self {
+        Self { access: AccessMode::Ro, squash: Squash::Root, anonuid: 65534, anongid: 65534, sec: vec![SecFlavor::Sys] }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessMode {
+    #[default]
+    Ro,
+    Rw,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Squash {
+    #[serde(rename = "no_root_squash")]
+    None,
+    #[default]
+    #[serde(rename = "root_squash")]
+    Root,
+    #[serde(rename = "all_squash")]
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecFlavor {
+    None,
+    Sys,
+    Krb5,
+    Krb5i,
+    Krb5p,
+}
@@ -1,5 +1,6 @@
 //! Error types for the NFS server
 
+use num_derive::FromPrimitive;
 use thiserror::Error;
 
 pub type NfsResult<T> = Result<T, NfsError>;
@@ -42,6 +43,30 @@ pub enum NfsError {
     #[error("Stale file handle")]
     StaleHandle,
 
+    #[error("Malformed file handle")]
+    BadHandle,
+
+    #[error("Not a directory")]
+    NotDir,
+
+    #[error("Is a directory")]
+    IsDir,
+
+    #[error("Directory not empty")]
+    NotEmpty,
+
+    #[error("File name too long")]
+    NameTooLong,
+
+    #[error("File too large")]
+    FileTooBig,
+
+    #[error("Cross-device link")]
+    CrossDevice,
+
+    #[error("Security flavor not allowed")]
+    WrongSec,
+
     #[error("Bad stateid")]
     BadStateid,
 
@@ -51,6 +76,11 @@ pub enum NfsError {
     #[error("Server fault")]
     ServerFault,
 
+    /// An NFSv4 outcome with no counterpart among the other variants,
+    /// e.g. DENIED or EXPIRED
+    #[error("NFSv4 status {0:?}")]
+    Status(Nfs4Status),
+
     #[error("Network error: {0}")]
     Network(String),
 
@@ -61,8 +91,9 @@ pub enum NfsError {
     Auth(String),
 }
 
-/// NFS v4.2 status codes (from RFC 7862)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// NFSv4 status codes: the NFSv4.1 table (RFC 8881 section 15) plus the
+/// NFSv4.2 additions (RFC 7862 section 11)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
 #[repr(u32)]
 pub enum Nfs4Status {
     Ok = 0,
@@ -73,7 +104,6 @@ pub enum Nfs4Status {
     Access = 13,
     Exist = 17,
     Xdev = 18,
-    Nodev = 19,
     Notdir = 20,
     Isdir = 21,
     Inval = 22,
@@ -92,22 +122,103 @@ pub enum Nfs4Status {
     Serverfault = 10006,
     Badtype = 10007,
     Delay = 10008,
-    SameSession = 10018,
+    Same = 10009,
+    Denied = 10010,
+    Expired = 10011,
+    Locked = 10012,
+    Grace = 10013,
+    Fhexpired = 10014,
+    ShareDenied = 10015,
+    Wrongsec = 10016,
+    ClidInuse = 10017,
+    // NFSv4.0 only
+    Resource = 10018,
+    Moved = 10019,
+    Nofilehandle = 10020,
+    MinorVersMismatch = 10021,
+    StaleClientid = 10022,
+    StaleStateid = 10023,
+    OldStateid = 10024,
+    BadStateid = 10025,
+    BadSeqid = 10026,
+    NotSame = 10027,
+    LockRange = 10028,
+    Symlink = 10029,
+    Restorefh = 10030,
+    LeaseMoved = 10031,
+    Attrnotsupp = 10032,
+    NoGrace = 10033,
+    ReclaimBad = 10034,
+    ReclaimConflict = 10035,
+    Badxdr = 10036,
+    LocksHeld = 10037,
+    Openmode = 10038,
+    Badowner = 10039,
+    Badchar = 10040,
+    Badname = 10041,
+    BadRange = 10042,
+    LockNotsupp = 10043,
+    OpIllegal = 10044,
+    Deadlock = 10045,
+    FileOpen = 10046,
+    AdminRevoked = 10047,
+    CbPathDown = 10048,
 
-    // NFSv4.2 specific errors
-    BadLabel = 10093,
-    OffloadDenied = 10091,
-    OffloadNoReqs = 10094,
-    PartnerNoAuth = 10089,
+    // NFSv4.1
+    Badiomode = 10049,
+    Badlayout = 10050,
+    BadSessionDigest = 10051,
+    Badsession = 10052,
+    Badslot = 10053,
+    CompleteAlready = 10054,
+    ConnNotBoundToSession = 10055,
+    DelegAlreadyWanted = 10056,
+    BackChanBusy = 10057,
+    Layouttrylater = 10058,
+    Layoutunavailable = 10059,
+    NomatchingLayout = 10060,
+    Recallconflict = 10061,
+    UnknownLayouttype = 10062,
+    SeqMisordered = 10063,
+    SequencePos = 10064,
+    ReqTooBig = 10065,
+    RepTooBig = 10066,
+    RepTooBigToCache = 10067,
+    RetryUncachedRep = 10068,
+    UnsafeCompound = 10069,
+    TooManyOps = 10070,
+    OpNotInSession = 10071,
+    HashAlgUnsupp = 10072,
+    ClientidBusy = 10074,
+    PnfsIoHole = 10075,
+    SeqFalseRetry = 10076,
+    BadHighSlot = 10077,
+    Deadsession = 10078,
+    EncrAlgUnsupp = 10079,
+    PnfsNoLayout = 10080,
+    NotOnlyOp = 10081,
+    WrongCred = 10082,
+    WrongType = 10083,
+    DirdelegUnavail = 10084,
+    RejectDeleg = 10085,
+    Returnconflict = 10086,
+    DelegRevoked = 10087,
+
+    // NFSv4.2
     PartnerNotsupp = 10088,
+    PartnerNoAuth = 10089,
     UnionNotsupp = 10090,
+    OffloadDenied = 10091,
     WrongLfs = 10092,
+    Badlabel = 10093,
+    OffloadNoReqs = 10094,
 }
 
 impl From<NfsError> for Nfs4Status {
     fn from(error: NfsError) -> Self {
         match error {
             NfsError::Io(_) => Nfs4Status::Io,
+            NfsError::Xdr(_) => Nfs4Status::Badxdr,
             NfsError::PermissionDenied => Nfs4Status::Access,
             NfsError::NotFound => Nfs4Status::Noent,
             NfsError::AlreadyExists => Nfs4Status::Exist,
@@ -115,9 +226,90 @@ impl From<NfsError> for Nfs4Status {
             NfsError::NoSpace => Nfs4Status::Nospc,
             NfsError::ReadOnlyFs => Nfs4Status::Rofs,
             NfsError::StaleHandle => Nfs4Status::Stale,
-            NfsError::BadStateid => Nfs4Status::Badhandle,
+            NfsError::BadHandle => Nfs4Status::Badhandle,
+            NfsError::NotDir => Nfs4Status::Notdir,
+            NfsError::IsDir => Nfs4Status::Isdir,
+            NfsError::NotEmpty => Nfs4Status::Notempty,
+            NfsError::NameTooLong => Nfs4Status::Nametoolong,
+            NfsError::FileTooBig => Nfs4Status::Fbig,
+            NfsError::CrossDevice => Nfs4Status::Xdev,
+            NfsError::WrongSec => Nfs4Status::Wrongsec,
+            NfsError::BadStateid => Nfs4Status::BadStateid,
+            NfsError::Grace => Nfs4Status::Grace,
             NfsError::InvalidArgument(_) => Nfs4Status::Inval,
-            _ => Nfs4Status::Serverfault,
+            NfsError::Status(status) => status,
+            NfsError::ServerFault
+            | NfsError::Serialization(_)
+            | NfsError::Protocol(_)
+            | NfsError::Network(_)
+            | NfsError::Config(_)
+            | NfsError::Auth(_) => Nfs4Status::Serverfault,
+        }
+    }
+}
+
+/// NFSv3 status codes (RFC 1813 section 2.6)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+#[repr(u32)]
+pub enum Nfs3Status {
+    Ok = 0,
+    Perm = 1,
+    Noent = 2,
+    Io = 5,
+    Nxio = 6,
+    Acces = 13,
+    Exist = 17,
+    Xdev = 18,
+    Nodev = 19,
+    Notdir = 20,
+    Isdir = 21,
+    Inval = 22,
+    Fbig = 27,
+    Nospc = 28,
+    Rofs = 30,
+    Mlink = 31,
+    Nametoolong = 63,
+    Notempty = 66,
+    Dquot = 69,
+    Stale = 70,
+    Remote = 71,
+    Badhandle = 10001,
+    NotSync = 10002,
+    BadCookie = 10003,
+    Notsupp = 10004,
+    Toosmall = 10005,
+    Serverfault = 10006,
+    Badtype = 10007,
+    Jukebox = 10008,
+}
+
+impl From<NfsError> for Nfs3Status {
+    fn from(error: NfsError) -> Self {
+        match error {
+            NfsError::Io(_) => Nfs3Status::Io,
+            NfsError::PermissionDenied => Nfs3Status::Acces,
+            NfsError::NotFound => Nfs3Status::Noent,
+            NfsError::AlreadyExists => Nfs3Status::Exist,
+            NfsError::NotSupported => Nfs3Status::Notsupp,
+            NfsError::NoSpace => Nfs3Status::Nospc,
+            NfsError::ReadOnlyFs => Nfs3Status::Rofs,
+            NfsError::StaleHandle => Nfs3Status::Stale,
+            NfsError::BadHandle => Nfs3Status::Badhandle,
+            NfsError::NotDir => Nfs3Status::Notdir,
+            NfsError::IsDir => Nfs3Status::Isdir,
+            NfsError::NotEmpty => Nfs3Status::Notempty,
+            NfsError::NameTooLong => Nfs3Status::Nametoolong,
+            NfsError::FileTooBig => Nfs3Status::Fbig,
+            NfsError::CrossDevice => Nfs3Status::Xdev,
+            // NFSv3 has no WRONGSEC; MOUNT tells clients which flavors to use
+            NfsError::WrongSec => Nfs3Status::Acces,
+            NfsError::InvalidArgument(_) => Nfs3Status::Inval,
+            NfsError::Grace => Nfs3Status::Jukebox,
+            // the codes the two versions share mean the same in both
+            NfsError::Status(status) => {
+                num_traits::FromPrimitive::from_u32(status as u32).unwrap_or(Nfs3Status::Serverfault)
+            }
+            _ => Nfs3Status::Serverfault,
         }
     }
 }
@@ -0,0 +1,892 @@
+//! Export table: which trees are exported, from which backend, and to whom.
+//!
+//! Server filehandles are the export's fsid followed by the backend's own
+//! handle, so any handle a client presents can be traced to its export and
+//! checked against that export's client rules. `ExportTable` implements
+//! `Vfs` by routing each call to the owning backend, which lets protocol
+//! handlers stay unaware of how many exports there are.
+//!
+//! NFSv4 clients see all exports in one namespace. Directories leading to
+//! exports that are not themselves inside an export form a read-only pseudo
+//! filesystem (fsid 0); LOOKUP crosses into an export at its path, and
+//! LOOKUPP out of an export root returns to the directory it covers.
+
+use crate::auth::*;
+use crate::config::{AccessMode, BackendConfig, ClientRule, ExportConfig, ExportOptions, SecFlavor, Squash};
+use crate::error::{NfsError, NfsResult};
+use crate::proto::nfs4::ReadPlus4resok;
+use crate::vfs::*;
+use async_trait::async_trait;
+use bytes::Bytes;
+use dashmap::DashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use tracing::debug;
+
+/// RPCSEC_GSS pseudo-flavors MOUNT reports for Kerberos exports (RFC 2623)
+pub const RPC_AUTH_GSS_KRB5: u32 = 390003;
+pub const RPC_AUTH_GSS_KRB5I: u32 = 390004;
+pub const RPC_AUTH_GSS_KRB5P: u32 = 390005;
+
+/// fsid of the pseudo filesystem; exports never use it
+pub const PSEUDO_FSID: u64 = 0;
+
+const FSID_LEN: usize = 8;
+const PSEUDO_FH_LEN: usize = FSID_LEN + 8;
+// Directory locks are striped by handle; unrelated directories rarely share one
+const DIR_LOCKS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostMatch {
+    Any,
+    Net(IpAddr, u8),
+    Name(String),
+    // "*.example.com" stored as ".example.com"
+    Suffix(String),
+}
+
+fn config_err(msg: String) -> NfsError {
+    NfsError::Config(msg)
+}
+
+fn parse_host(host: &str) -> NfsResult<HostMatch> {
+    if host == "*" {
+        return Ok(HostMatch::Any);
+    }
+    if let Some((addr, prefix)) = host.split_once('/') {
+        let addr: IpAddr = addr.parse().map_err(|_| config_err(format!("bad network address in {:?}", host)))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix: u8 = prefix.parse().ok().filter(|p| *p <= max).ok_or_else(|| config_err(format!("bad prefix length in {:?}", host)))?;
+        return Ok(HostMatch::Net(addr, prefix));
+    }
+    if let Ok(addr) = host.parse::<IpAddr>() {
+        return Ok(HostMatch::Net(addr, if addr.is_ipv4() { 32 } else { 128 }));
+    }
+    let (suffix, name) = match host.strip_prefix('*') {
+        Some(rest) if rest.starts_with('.') => (true, rest),
+        _ => (false, host),
+    };
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if !valid {
+        return Err(config_err(format!("bad client host {:?}", host)));
+    }
+    let name = name.to_ascii_lowercase();
+    Ok(if suffix { HostMatch::Suffix(name) } else { HostMatch::Name(name) })
+}
+
+fn in_net(addr: IpAddr, net: IpAddr, prefix: u8) -> bool {
+    match (addr, net) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(a) & mask == u32::from(n) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(n)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(a) & mask == u128::from(n) & mask
+        }
+        _ => false,
+    }
+}
+
+// Kerberos flavors are advertised but admit nobody: no RPCSEC_GSS context
+// is ever set up, so a GSS credential is only a claim we cannot check
+fn flavor_allows(sec: SecFlavor, flavor: u32) -> bool {
+    match sec {
+        SecFlavor::None => flavor == AUTH_NONE,
+        SecFlavor::Sys => flavor == AUTH_SYS,
+        SecFlavor::Krb5 | SecFlavor::Krb5i | SecFlavor::Krb5p => false,
+    }
+}
+
+/// Flavor number as reported by MOUNT and SECINFO
+pub fn wire_flavor(sec: SecFlavor) -> u32 {
+    match sec {
+        SecFlavor::None => AUTH_NONE,
+        SecFlavor::Sys => AUTH_SYS,
+        SecFlavor::Krb5 => RPC_AUTH_GSS_KRB5,
+        SecFlavor::Krb5i => RPC_AUTH_GSS_KRB5I,
+        SecFlavor::Krb5p => RPC_AUTH_GSS_KRB5P,
+    }
+}
+
+// Stable default fsid: FNV-1a of the export path, never zero
+fn path_fsid(path: &str) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in path.bytes() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h.max(1)
+}
+
+fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|c| !c.is_empty()).collect()
+}
+
+fn child_path(dir: &str, name: &str) -> String {
+    if dir == "/" {
+        format!("/{}", name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+// Whether normalized `path` is `dir` or lies below it
+fn within(path: &str, dir: &str) -> bool {
+    components(path).starts_with(&components(dir))
+}
+
+/// Canonical form of an export path: "/" or "/a/b", no "." or ".."
+pub fn normalize_path(path: &str) -> NfsResult<String> {
+    if !path.starts_with('/') {
+        return Err(config_err(format!("export path {:?} is not absolute", path)));
+    }
+    let comps: Vec<&str> = path.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+    if comps.contains(&"..") {
+        return Err(config_err(format!("export path {:?} contains \"..\"", path)));
+    }
+    Ok(format!("/{}", comps.join("/")))
+}
+
+/// What a caller may do within an export, after squashing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Access {
+    pub read_only: bool,
+    pub uid: u32,
+    pub gid: u32,
+    pub gids: Vec<u32>,
+    /// Flavors the matching rule allows, as MOUNT reports them
+    pub flavors: Vec<u32>,
+}
+
+impl Access {
+    /// New objects belong to the (squashed) caller unless it asked otherwise
+    pub fn owned(&self, set: SetAttr) -> SetAttr {
+        SetAttr { uid: set.uid.or(Some(self.uid)), gid: set.gid.or(Some(self.gid)), ..set }
+    }
+}
+
+// Checks across exports: at least one, and no shared paths or fsids
+fn check_table<'a>(exports: impl Iterator<Item = (&'a str, u64)>) -> NfsResult<()> {
+    let exports: Vec<_> = exports.collect();
+    if exports.is_empty() {
+        return Err(config_err("no exports configured".into()));
+    }
+    for (i, (path, fsid)) in exports.iter().enumerate() {
+        if *fsid == PSEUDO_FSID {
+            return Err(config_err(format!("export {}: fsid {} is reserved for the pseudo filesystem", path, PSEUDO_FSID)));
+        }
+        for (other, other_fsid) in &exports[..i] {
+            if other == path {
+                return Err(config_err(format!("export {} is listed twice", path)));
+            }
+            if other_fsid == fsid {
+                return Err(config_err(format!("exports {} and {} share fsid {}", other, path, fsid)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check export configuration without building any backend.
+pub fn validate_exports(cfg: &[ExportConfig]) -> NfsResult<()> {
+    let mut table = Vec::with_capacity(cfg.len());
+    for e in cfg {
+        let path = normalize_path(&e.path)?;
+        for rule in &e.clients {
+            parse_host(&rule.host)?;
+            if rule.options.sec.is_empty() {
+                return Err(config_err(format!("export {}: rule for {:?} allows no security flavor", path, rule.host)));
+            }
+        }
+        if matches!(&e.backend, BackendConfig::Local { root } if root.is_empty()) {
+            return Err(config_err(format!("export {}: local backend needs a root directory", path)));
+        }
+        let fsid = e.fsid.unwrap_or_else(|| path_fsid(&path));
+        table.push((path, fsid));
+    }
+    check_table(table.iter().map(|(p, f)| (p.as_str(), *f)))
+}
+
+pub struct Export {
+    /// Path clients mount
+    pub path: String,
+    pub fsid: u64,
+    pub vfs: Arc<dyn Vfs>,
+    /// Host patterns as configured, for the MOUNT export list
+    pub hosts: Vec<String>,
+    rules: Vec<(HostMatch, ExportOptions)>,
+    // Configuration the backend was built from; reloads keep the backend
+    // while this is unchanged
+    backend: Option<BackendConfig>,
+}
+
+impl Export {
+    pub fn new(path: &str, fsid: Option<u64>, vfs: Arc<dyn Vfs>, clients: &[ClientRule]) -> NfsResult<Self> {
+        let path = normalize_path(path)?;
+        let rules = clients
+            .iter()
+            .map(|r| Ok((parse_host(&r.host)?, r.options.clone())))
+            .collect::<NfsResult<Vec<_>>>()?;
+        let hosts = clients.iter().map(|r| r.host.clone()).collect();
+        Ok(Self { fsid: fsid.unwrap_or_else(|| path_fsid(&path)), path, vfs, hosts, rules, backend: None })
+    }
+
+    /// Server handle for one of this export's backend handles
+    pub fn wrap(&self, fh: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(FSID_LEN + fh.len());
+        out.extend_from_slice(&self.fsid.to_be_bytes());
+        out.extend_from_slice(fh);
+        out
+    }
+
+    pub async fn root_fh(&self) -> NfsResult<Vec<u8>> {
+        Ok(self.wrap(&self.vfs.root_fh().await?))
+    }
+
+    fn grant(&self, opts: &ExportOptions, cred: &Credential) -> NfsResult<Access> {
+        if !opts.sec.iter().any(|s| flavor_allows(*s, cred.flavor())) {
+            return Err(NfsError::WrongSec);
+        }
+        let (mut uid, mut gid, mut gids) = match cred {
+            Credential::Sys(sys) => (sys.uid, sys.gid, sys.gids.clone()),
+            Credential::None => (opts.anonuid, opts.anongid, Vec::new()),
+            // no principal of another flavor, RPCSEC_GSS included, is ever
+            // verified or mapped to a uid; passing it off as anonymous
+            // would hide that
+            Credential::Other(_) => return Err(NfsError::WrongSec),
+        };
+        match opts.squash {
+            Squash::None => {}
+            Squash::Root => {
+                if uid == 0 {
+                    uid = opts.anonuid;
+                }
+                for g in std::iter::once(&mut gid).chain(gids.iter_mut()) {
+                    if *g == 0 {
+                        *g = opts.anongid;
+                    }
+                }
+            }
+            Squash::All => {
+                uid = opts.anonuid;
+                gid = opts.anongid;
+                gids.clear();
+            }
+        }
+        Ok(Access {
+            read_only: opts.access == AccessMode::Ro,
+            uid,
+            gid,
+            gids,
+            flavors: opts.sec.iter().map(|s| wire_flavor(*s)).collect(),
+        })
+    }
+}
+
+fn build_export(e: &ExportConfig) -> NfsResult<Export> {
+    let vfs: Arc<dyn Vfs> = match &e.backend {
+        BackendConfig::Memory => MemVfs::new(),
+        #[cfg(unix)]
+        BackendConfig::Local { root } => crate::localfs::LocalVfs::new(root)
+            .map_err(|err| config_err(format!("export {}: backend {}: {}", e.path, root, err)))?,
+        #[cfg(not(unix))]
+        BackendConfig::Local { .. } => return Err(config_err("local backends need a Unix host".into())),
+    };
+    let mut export = Export::new(&e.path, e.fsid, vfs, &e.clients)?;
+    export.backend = Some(e.backend.clone());
+    Ok(export)
+}
+
+// A pseudo filesystem directory, keyed by its fileid
+#[derive(Clone)]
+struct PseudoDir {
+    path: String,
+    parent: u64,
+    children: BTreeSet<String>,
+}
+
+// The exports and the pseudo filesystem joining them. A reload swaps in a
+// new layout; calls in progress finish with the one they started with.
+struct Layout {
+    exports: Vec<Arc<Export>>,
+    pseudo: HashMap<u64, PseudoDir>,
+}
+
+impl Layout {
+    fn new(exports: Vec<Arc<Export>>) -> NfsResult<Self> {
+        check_table(exports.iter().map(|e| (e.path.as_str(), e.fsid)))?;
+        let pseudo = Self::pseudo_tree(&exports);
+        Ok(Self { exports, pseudo })
+    }
+
+    // Every ancestor of an export that no export contains
+    fn pseudo_tree(exports: &[Arc<Export>]) -> HashMap<u64, PseudoDir> {
+        let mut tree: HashMap<u64, PseudoDir> = HashMap::new();
+        for e in exports {
+            let comps = components(&e.path);
+            let mut parent = path_fsid("/");
+            for depth in 0..comps.len() {
+                let path = format!("/{}", comps[..depth].join("/"));
+                if exports.iter().any(|x| within(&path, &x.path)) {
+                    break;
+                }
+                let id = path_fsid(&path);
+                tree.entry(id)
+                    .or_insert_with(|| PseudoDir { path, parent, children: BTreeSet::new() })
+                    .children
+                    .insert(comps[depth].to_string());
+                parent = id;
+            }
+        }
+        tree
+    }
+}
+
+pub struct ExportTable {
+    layout: RwLock<Arc<Layout>>,
+    // Paths of directories inside an export that lead to a nested export,
+    // recorded as LOOKUP passes through them so the next step can cross
+    dir_paths: DashMap<Vec<u8>, String>,
+    // reverse-resolved, forward-confirmed client names; None if unresolvable
+    hostnames: DashMap<IpAddr, Option<String>>,
+    dir_locks: Vec<tokio::sync::Mutex<()>>,
+}
+
+impl ExportTable {
+    pub fn new(exports: Vec<Export>) -> NfsResult<Self> {
+        let layout = Layout::new(exports.into_iter().map(Arc::new).collect())?;
+        Ok(Self {
+            layout: RwLock::new(Arc::new(layout)),
+            dir_paths: DashMap::new(),
+            hostnames: DashMap::new(),
+            dir_locks: (0..DIR_LOCKS).map(|_| tokio::sync::Mutex::new(())).collect(),
+        })
+    }
+
+    /// Build backends and rules from configuration.
+    pub fn from_config(cfg: &[ExportConfig]) -> NfsResult<Self> {
+        Self::new(cfg.iter().map(build_export).collect::<NfsResult<Vec<_>>>()?)
+    }
+
+    /// Replace the exports and their rules with `cfg`. Exports whose path,
+    /// fsid and backend are unchanged keep their backend, so their handles
+    /// and data survive; others are built afresh or dropped. Nothing
+    /// changes if `cfg` is invalid.
+    pub fn reload(&self, cfg: &[ExportConfig]) -> NfsResult<()> {
+        validate_exports(cfg)?;
+        let old = self.layout();
+        let exports = cfg
+            .iter()
+            .map(|e| {
+                let path = normalize_path(&e.path)?;
+                let fsid = e.fsid.unwrap_or_else(|| path_fsid(&path));
+                let kept = old.exports.iter().find(|x| x.path == path && x.fsid == fsid && x.backend.as_ref() == Some(&e.backend));
+                let export = match kept {
+                    Some(x) => Export { backend: x.backend.clone(), ..Export::new(&path, Some(fsid), x.vfs.clone(), &e.clients)? },
+                    None => build_export(e)?,
+                };
+                Ok(Arc::new(export))
+            })
+            .collect::<NfsResult<Vec<_>>>()?;
+        *self.layout.write().unwrap() = Arc::new(Layout::new(exports)?);
+        // names may resolve differently by now
+        self.hostnames.clear();
+        Ok(())
+    }
+
+    fn layout(&self) -> Arc<Layout> {
+        self.layout.read().unwrap().clone()
+    }
+
+    /// Export `vfs` as "/" to everyone, read-write.
+    pub fn single(vfs: Arc<dyn Vfs>) -> Self {
+        let cfg = ExportConfig::default();
+        let export = Export::new(&cfg.path, cfg.fsid, vfs, &cfg.clients).expect("default export is valid");
+        Self::new(vec![export]).expect("one export is a valid table")
+    }
+
+    pub fn exports(&self) -> Vec<Arc<Export>> {
+        self.layout().exports.clone()
+    }
+
+    /// The export a normalized path lies in (longest match) and the path
+    /// components below it.
+    pub fn find<'p>(&self, path: &'p str) -> Option<(Arc<Export>, Vec<&'p str>)> {
+        let wanted: Vec<&str> = path.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+        self.layout()
+            .exports
+            .iter()
+            .filter_map(|e| {
+                let comps: Vec<&str> = e.path.split('/').filter(|c| !c.is_empty()).collect();
+                wanted.starts_with(&comps).then_some((e, comps.len()))
+            })
+            .max_by_key(|(_, depth)| *depth)
+            .map(|(e, depth)| (e.clone(), wanted[depth..].to_vec()))
+    }
+
+    /// Split a server handle into its export and backend handle. Pseudo
+    /// filesystem handles have no export and are `ReadOnlyFs`, as the
+    /// pseudo filesystem only supports lookups, attributes and listing.
+    pub fn export_of<'f>(&self, fh: &'f [u8]) -> NfsResult<(Arc<Export>, &'f [u8])> {
+        if fh.len() <= FSID_LEN {
+            return Err(NfsError::BadHandle);
+        }
+        let fsid = u64::from_be_bytes(fh[..FSID_LEN].try_into().unwrap());
+        if fsid == PSEUDO_FSID {
+            self.pseudo_of(fh)?;
+            return Err(NfsError::ReadOnlyFs);
+        }
+        let export = self.layout().exports.iter().find(|e| e.fsid == fsid).cloned().ok_or(NfsError::StaleHandle)?;
+        Ok((export, &fh[FSID_LEN..]))
+    }
+
+    /// Apply the export's client rules: the first rule matching the caller's
+    /// address decides; none matching is `PermissionDenied`, a disallowed
+    /// flavor `WrongSec`.
+    pub async fn authorize(&self, export: &Export, caller: &Caller) -> NfsResult<Access> {
+        let ip = caller.addr.ip().to_canonical();
+        let mut name: Option<Option<String>> = None;
+        for (host, opts) in &export.rules {
+            let hit = match host {
+                HostMatch::Any => true,
+                HostMatch::Net(net, prefix) => in_net(ip, *net, *prefix),
+                HostMatch::Name(_) | HostMatch::Suffix(_) => {
+                    if name.is_none() {
+                        name = Some(self.client_name(ip).await);
+                    }
+                    match (host, name.as_ref().and_then(|n| n.as_deref())) {
+                        (HostMatch::Name(want), Some(n)) => n == want,
+                        (HostMatch::Suffix(suffix), Some(n)) => n.ends_with(suffix.as_str()),
+                        _ => false,
+                    }
+                }
+            };
+            if hit {
+                return export.grant(opts, &caller.cred);
+            }
+        }
+        debug!("{} matches no client rule of {}", ip, export.path);
+        Err(NfsError::PermissionDenied)
+    }
+
+    /// `authorize` for the export a handle belongs to. Anyone may browse
+    /// the pseudo filesystem; export rules apply once a lookup crosses
+    /// into an export.
+    pub async fn authorize_fh(&self, fh: &[u8], caller: &Caller) -> NfsResult<Access> {
+        if self.pseudo_of(fh)?.is_some() {
+            let (uid, gid, gids) = match &caller.cred {
+                Credential::Sys(sys) => (sys.uid, sys.gid, sys.gids.clone()),
+                Credential::None => (ExportOptions::default().anonuid, ExportOptions::default().anongid, Vec::new()),
+                Credential::Other(_) => return Err(NfsError::WrongSec),
+            };
+            return Ok(Access { read_only: true, uid, gid, gids, flavors: vec![AUTH_SYS, AUTH_NONE] });
+        }
+        let (export, _) = self.export_of(fh)?;
+        self.authorize(&export, caller).await
+    }
+
+    /// Hold off other namespace changes to `dirs` until the guards drop, so
+    /// a directory's change attribute read before and after a change
+    /// brackets that change alone. NFSv3 and NFSv4 both take these.
+    pub async fn lock_dirs(&self, dirs: &[&[u8]]) -> Vec<tokio::sync::MutexGuard<'_, ()>> {
+        // always in the same order, so two callers cannot deadlock
+        let stripes: BTreeSet<usize> = dirs
+            .iter()
+            .map(|fh| {
+                let mut h = std::collections::hash_map::DefaultHasher::new();
+                fh.hash(&mut h);
+                h.finish() as usize % DIR_LOCKS
+            })
+            .collect();
+        let mut guards = Vec::with_capacity(stripes.len());
+        for i in stripes {
+            guards.push(self.dir_locks[i].lock().await);
+        }
+        guards
+    }
+
+    /// Whether changes made under `lock_dirs` to the directory `fh` are the
+    /// only ones its backend sees; not for pseudo or unknown handles.
+    pub fn atomic_changes(&self, fh: &[u8]) -> bool {
+        self.export_of(fh).is_ok_and(|(e, _)| e.vfs.sole_writer())
+    }
+
+    /// What the backend under `fh` reports for ACLs; the pseudo filesystem
+    /// keeps none
+    pub fn acl_support(&self, fh: &[u8]) -> u32 {
+        match self.export_of(fh) {
+            Ok((e, _)) => e.vfs.acl_support(),
+            Err(_) => 0,
+        }
+    }
+
+    /// The alignment CLONE needs under `fh`, 0 where it cannot be done
+    pub fn clone_blksize(&self, fh: &[u8]) -> u32 {
+        match self.export_of(fh) {
+            Ok((e, _)) => e.vfs.clone_blksize(),
+            Err(_) => 0,
+        }
+    }
+
+    /// Absolute path of a handle that is an export root or a pseudo
+    /// directory; `None` for anything else, or a handle we do not know.
+    pub async fn root_path(&self, fh: &[u8]) -> Option<String> {
+        if let Ok(Some((_, dir))) = self.pseudo_of(fh) {
+            return Some(dir.path);
+        }
+        let (e, inner) = self.export_of(fh).ok()?;
+        self.is_root(&e, inner).await.ok()?.then(|| e.path.clone())
+    }
+
+    /// Fileid of the directory an object is mounted on: for an export's
+    /// root, the directory its path covers in the enclosing namespace;
+    /// otherwise the object's own fileid.
+    pub async fn mounted_on_fileid(&self, fh: &[u8]) -> NfsResult<u64> {
+        if let Some((id, _)) = self.pseudo_of(fh)? {
+            return Ok(id);
+        }
+        let (e, inner) = self.export_of(fh)?;
+        if e.path == "/" || !self.is_root(&e, inner).await? {
+            return Ok(e.vfs.getattr(inner).await?.fileid);
+        }
+        // a pseudo parent has no node for the covered path itself
+        let Some(outer) = self.enclosing(&e.path) else {
+            return Ok(path_fsid(&e.path));
+        };
+        let comps = components(&e.path);
+        let mut dir = outer.vfs.root_fh().await?;
+        for name in &comps[components(&outer.path).len()..] {
+            dir = outer.vfs.lookup(&dir, name).await?;
+        }
+        Ok(outer.vfs.getattr(&dir).await?.fileid)
+    }
+
+    // The pseudo directory a handle names, if it is a pseudo handle
+    fn pseudo_of(&self, fh: &[u8]) -> NfsResult<Option<(u64, PseudoDir)>> {
+        if fh.len() < FSID_LEN || u64::from_be_bytes(fh[..FSID_LEN].try_into().unwrap()) != PSEUDO_FSID {
+            return Ok(None);
+        }
+        if fh.len() != PSEUDO_FH_LEN {
+            return Err(NfsError::BadHandle);
+        }
+        let id = u64::from_be_bytes(fh[FSID_LEN..].try_into().unwrap());
+        let dir = self.layout().pseudo.get(&id).cloned().ok_or(NfsError::StaleHandle)?;
+        Ok(Some((id, dir)))
+    }
+
+    fn pseudo_fh(id: u64) -> Vec<u8> {
+        let mut fh = PSEUDO_FSID.to_be_bytes().to_vec();
+        fh.extend_from_slice(&id.to_be_bytes());
+        fh
+    }
+
+    fn pseudo_attr(id: u64, dir: &PseudoDir) -> FileAttr {
+        FileAttr {
+            changeid: 0,
+            size: 0,
+            mtime: 0,
+            ctime: 0,
+            atime: 0,
+            ftype: FileType::Directory,
+            mode: 0o555,
+            nlink: 2 + dir.children.len() as u32,
+            uid: 0,
+            gid: 0,
+            fileid: id,
+            fsid: PSEUDO_FSID,
+            used: 0,
+            rdev: (0, 0),
+            acl: None,
+        }
+    }
+
+    // The innermost export strictly containing `path`
+    fn enclosing(&self, path: &str) -> Option<Arc<Export>> {
+        self.layout()
+            .exports
+            .iter()
+            .filter(|e| e.path != path && within(path, &e.path))
+            .max_by_key(|e| components(&e.path).len())
+            .cloned()
+    }
+
+    async fn is_root(&self, e: &Export, inner: &[u8]) -> NfsResult<bool> {
+        Ok(e.vfs.root_fh().await? == inner)
+    }
+
+    // Handle of the directory at an absolute path of the joined namespace
+    async fn path_fh(&self, path: &str) -> NfsResult<Vec<u8>> {
+        let id = path_fsid(path);
+        if self.layout().pseudo.get(&id).is_some_and(|d| d.path == path) {
+            return Ok(Self::pseudo_fh(id));
+        }
+        let (e, rest) = self.find(path).ok_or(NfsError::NotFound)?;
+        let mut fh = e.root_fh().await?;
+        for name in rest {
+            fh = self.lookup(&fh, name).await?;
+        }
+        Ok(fh)
+    }
+
+    // Absolute path of an export directory, if it is one that leads to a
+    // nested export
+    async fn dir_path(&self, e: &Export, fh: &[u8], inner: &[u8]) -> NfsResult<Option<String>> {
+        if !self.layout().exports.iter().any(|x| x.path != e.path && within(&x.path, &e.path)) {
+            return Ok(None);
+        }
+        if let Some(path) = self.dir_paths.get(fh) {
+            return Ok(Some(path.clone()));
+        }
+        Ok(self.is_root(e, inner).await?.then(|| e.path.clone()))
+    }
+
+    // Where LOOKUP of `name` in directory `path` leads: an export root
+    // (crossing), or a plain child to remember if it leads further down
+    async fn cross(&self, path: &str, name: &str, fh: &[u8]) -> NfsResult<Option<Vec<u8>>> {
+        let child = child_path(path, name);
+        let layout = self.layout();
+        if let Some(sub) = layout.exports.iter().find(|x| x.path == child) {
+            return Ok(Some(sub.root_fh().await?));
+        }
+        if layout.exports.iter().any(|x| within(&x.path, &child)) {
+            self.dir_paths.insert(fh.to_vec(), child);
+        }
+        Ok(None)
+    }
+
+    async fn client_name(&self, ip: IpAddr) -> Option<String> {
+        if let Some(cached) = self.hostnames.get(&ip) {
+            return cached.clone();
+        }
+        let name = tokio::task::spawn_blocking(move || reverse_lookup(ip)).await.ok().flatten();
+        // only trust a PTR record whose name resolves back to the address
+        let confirmed = match name {
+            Some(n) => {
+                let matches = match tokio::net::lookup_host((n.as_str(), 0)).await {
+                    Ok(mut addrs) => addrs.any(|a| a.ip().to_canonical() == ip),
+                    Err(_) => false,
+                };
+                matches.then_some(n)
+            }
+            None => None,
+        };
+        self.hostnames.insert(ip, confirmed.clone());
+        confirmed
+    }
+
+    // Legacy path calls go to whichever export is mounted at "/"
+    fn root_export(&self) -> NfsResult<Arc<Export>> {
+        self.layout().exports.iter().find(|e| e.path == "/").cloned().ok_or(NfsError::NotFound)
+    }
+
+    fn same_export<'f>(&self, a: &[u8], b: &'f [u8]) -> NfsResult<(Arc<Export>, &'f [u8])> {
+        let (ea, _) = self.export_of(a)?;
+        let (eb, fb) = self.export_of(b)?;
+        if ea.fsid != eb.fsid {
+            return Err(NfsError::CrossDevice);
+        }
+        Ok((eb, fb))
+    }
+}
+
+#[cfg(unix)]
+fn reverse_lookup(ip: IpAddr) -> Option<String> {
+    let addr = socket2::SockAddr::from(std::net::SocketAddr::new(ip, 0));
+    let mut host = [0 as libc::c_char; 1025];
+    // SAFETY: `addr` is a valid sockaddr of the given length and `host` is a
+    // writable buffer whose size we pass
+    let rc = unsafe {
+        libc::getnameinfo(
+            addr.as_ptr(),
+            addr.len(),
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+    // SAFETY: getnameinfo NUL-terminates on success
+    let name = unsafe { std::ffi::CStr::from_ptr(host.as_ptr()) };
+    name.to_str().ok().map(|n| n.trim_end_matches('.').to_ascii_lowercase())
+}
+
+#[cfg(not(unix))]
+fn reverse_lookup(_ip: IpAddr) -> Option<String> {
+    None
+}
+
+#[async_trait]
+impl Vfs for ExportTable {
+    async fn root_fh(&self) -> NfsResult<Vec<u8>> {
+        match self.root_export() {
+            Ok(e) => e.root_fh().await,
+            Err(_) => Ok(Self::pseudo_fh(path_fsid("/"))),
+        }
+    }
+    async fn getattr_root(&self, attr_request: &[u32]) -> NfsResult<Vec<u8>> {
+        let fh = self.root_fh().await?;
+        let attr = self.getattr(&fh).await?;
+        encode_file_fattr4(&attr, &fh, attr.fileid, self.acl_support(&fh), self.clone_blksize(&fh), attr_request)
+    }
+    async fn create_file(&self, path: &str, size: u64) -> NfsResult<()> {
+        self.root_export()?.vfs.create_file(path, size).await
+    }
+    async fn modify_file(&self, path: &str, new_size: u64) -> NfsResult<()> {
+        self.root_export()?.vfs.modify_file(path, new_size).await
+    }
+    async fn create_dir(&self, path: &str) -> NfsResult<()> {
+        self.root_export()?.vfs.create_dir(path).await
+    }
+    async fn remove_entry(&self, path: &str) -> NfsResult<()> {
+        self.root_export()?.vfs.remove_entry(path).await
+    }
+
+    async fn getattr(&self, fh: &[u8]) -> NfsResult<FileAttr> {
+        if let Some((id, dir)) = self.pseudo_of(fh)? {
+            return Ok(Self::pseudo_attr(id, &dir));
+        }
+        let (e, fh) = self.export_of(fh)?;
+        let mut attr = e.vfs.getattr(fh).await?;
+        attr.fsid = e.fsid;
+        Ok(attr)
+    }
+    async fn setattr(&self, fh: &[u8], set: &SetAttr) -> NfsResult<FileAttr> {
+        let (e, fh) = self.export_of(fh)?;
+        let mut attr = e.vfs.setattr(fh, set).await?;
+        attr.fsid = e.fsid;
+        Ok(attr)
+    }
+    async fn lookup(&self, dir: &[u8], name: &str) -> NfsResult<Vec<u8>> {
+        if let Some((_, pd)) = self.pseudo_of(dir)? {
+            if !pd.children.contains(name) {
+                return Err(NfsError::NotFound);
+            }
+            return self.path_fh(&child_path(&pd.path, name)).await;
+        }
+        let (e, inner) = self.export_of(dir)?;
+        let fh = e.wrap(&e.vfs.lookup(inner, name).await?);
+        if let Some(path) = self.dir_path(&e, dir, inner).await? {
+            if let Some(root) = self.cross(&path, name, &fh).await? {
+                return Ok(root);
+            }
+        }
+        Ok(fh)
+    }
+    async fn lookup_parent(&self, dir: &[u8]) -> NfsResult<Vec<u8>> {
+        if let Some((_, pd)) = self.pseudo_of(dir)? {
+            return Ok(Self::pseudo_fh(pd.parent));
+        }
+        let (e, inner) = self.export_of(dir)?;
+        if e.path != "/" && self.is_root(&e, inner).await? {
+            let comps = components(&e.path);
+            return self.path_fh(&format!("/{}", comps[..comps.len() - 1].join("/"))).await;
+        }
+        Ok(e.wrap(&e.vfs.lookup_parent(inner).await?))
+    }
+    async fn read(&self, fh: &[u8], offset: u64, count: u32) -> NfsResult<(Bytes, bool)> {
+        let (e, fh) = self.export_of(fh)?;
+        e.vfs.read(fh, offset, count).await
+    }
+    async fn write(&self, fh: &[u8], offset: u64, data: Bytes) -> NfsResult<u32> {
+        let (e, fh) = self.export_of(fh)?;
+        e.vfs.write(fh, offset, data).await
+    }
+    async fn commit(&self, fh: &[u8], offset: u64, count: u64) -> NfsResult<()> {
+        let (e, fh) = self.export_of(fh)?;
+        e.vfs.commit(fh, offset, count).await
+    }
+    async fn copy_range(&self, src: &[u8], src_offset: u64, dst: &[u8], dst_offset: u64, count: u64) -> NfsResult<u64> {
+        let (e, dst) = self.same_export(src, dst)?;
+        e.vfs.copy_range(&src[FSID_LEN..], src_offset, dst, dst_offset, count).await
+    }
+    async fn clone_range(&self, src: &[u8], src_offset: u64, dst: &[u8], dst_offset: u64, count: u64) -> NfsResult<()> {
+        let (e, dst) = self.same_export(src, dst)?;
+        e.vfs.clone_range(&src[FSID_LEN..], src_offset, dst, dst_offset, count).await
+    }
+    async fn seek(&self, fh: &[u8], offset: u64, what: Content) -> NfsResult<Option<u64>> {
+        let (e, fh) = self.export_of(fh)?;
+        e.vfs.seek(fh, offset, what).await
+    }
+    async fn read_extents(&self, fh: &[u8], offset: u64, count: u32) -> NfsResult<ReadPlus4resok> {
+        let (e, fh) = self.export_of(fh)?;
+        e.vfs.read_extents(fh, offset, count).await
+    }
+    async fn allocate(&self, fh: &[u8], offset: u64, length: u64) -> NfsResult<()> {
+        let (e, fh) = self.export_of(fh)?;
+        e.vfs.allocate(fh, offset, length).await
+    }
+    async fn deallocate(&self, fh: &[u8], offset: u64, length: u64) -> NfsResult<()> {
+        let (e, fh) = self.export_of(fh)?;
+        e.vfs.deallocate(fh, offset, length).await
+    }
+    async fn create(&self, dir: &[u8], name: &str, kind: CreateKind, attr: &SetAttr) -> NfsResult<Vec<u8>> {
+        let (e, dir) = self.export_of(dir)?;
+        Ok(e.wrap(&e.vfs.create(dir, name, kind, attr).await?))
+    }
+    async fn readlink(&self, fh: &[u8]) -> NfsResult<String> {
+        let (e, fh) = self.export_of(fh)?;
+        e.vfs.readlink(fh).await
+    }
+    async fn remove(&self, dir: &[u8], name: &str) -> NfsResult<()> {
+        let (e, dir) = self.export_of(dir)?;
+        e.vfs.remove(dir, name).await
+    }
+    async fn rename(&self, from_dir: &[u8], from: &str, to_dir: &[u8], to: &str) -> NfsResult<()> {
+        let (e, to_dir) = self.same_export(from_dir, to_dir)?;
+        e.vfs.rename(&from_dir[FSID_LEN..], from, to_dir, to).await
+    }
+    async fn link(&self, fh: &[u8], dir: &[u8], name: &str) -> NfsResult<()> {
+        let (e, dir) = self.same_export(fh, dir)?;
+        e.vfs.link(&fh[FSID_LEN..], dir, name).await
+    }
+    async fn readdir(&self, dir: &[u8], cookie: u64, max_entries: usize) -> NfsResult<ReadDir> {
+        if let Some((_, pd)) = self.pseudo_of(dir)? {
+            let mut rd = ReadDir { entries: Vec::new(), eof: true, verifier: 0 };
+            for (i, name) in pd.children.iter().enumerate() {
+                let entry_cookie = FIRST_COOKIE + i as u64;
+                if entry_cookie <= cookie {
+                    continue;
+                }
+                if rd.entries.len() == max_entries {
+                    rd.eof = false;
+                    break;
+                }
+                let fh = self.path_fh(&child_path(&pd.path, name)).await?;
+                let attr = self.getattr(&fh).await.ok();
+                rd.entries.push(DirEntry { name: name.clone(), cookie: entry_cookie, fh, attr });
+            }
+            return Ok(rd);
+        }
+        let (e, inner) = self.export_of(dir)?;
+        let mut rd = e.vfs.readdir(inner, cookie, max_entries).await?;
+        let path = self.dir_path(&e, dir, inner).await?;
+        for entry in &mut rd.entries {
+            entry.fh = e.wrap(&entry.fh);
+            if let Some(attr) = &mut entry.attr {
+                attr.fsid = e.fsid;
+            }
+            // entries that are mount points show the export mounted there
+            if let Some(path) = &path {
+                if let Some(root) = self.cross(path, &entry.name, &entry.fh).await? {
+                    entry.attr = self.getattr(&root).await.ok();
+                    entry.fh = root;
+                }
+            }
+        }
+        Ok(rd)
+    }
+    async fn fsstat(&self, fh: &[u8]) -> NfsResult<FsStat> {
+        if self.pseudo_of(fh)?.is_some() {
+            return Ok(FsStat::default());
+        }
+        let (e, fh) = self.export_of(fh)?;
+        e.vfs.fsstat(fh).await
+    }
+}
@@ -35,10 +35,32 @@
 //! }
 //! ```
 
+pub mod acl;
+#[cfg(unix)]
+pub mod admin;
+pub mod audit;
+pub mod auth;
+pub mod client;
 pub mod config;
 pub mod error;
+pub mod export;
+#[cfg(unix)]
+pub mod localfs;
+pub mod limits;
+pub mod lock;
+pub mod logging;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mount;
+pub mod nfs3;
+pub mod nlm;
+pub mod nsm;
+pub mod offload;
+pub mod perm;
 pub mod proto;
+pub mod recovery;
 pub mod rpc;
+pub mod portmap;
 pub mod server;
 pub mod vfs;
 pub mod xdr;
@@ -0,0 +1,211 @@
+//! Admission control shared by every listener of a server: caps on open
+//! connections, a token bucket per client address, and a budget of NFS
+//! requests in progress. Permits are released when dropped.
+//!
+//! Open connections are also listed here, so that an operator can see who
+//! is connected and close a client's connections.
+
+use crate::config::LimitsConfig;
+use crate::server::ShutdownHandle;
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::debug;
+
+// Idle buckets are only dropped once there are this many
+const BUCKET_PRUNE_THRESHOLD: usize = 4096;
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// An open connection, as listed for operators.
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub peer: SocketAddr,
+    pub since: SystemTime,
+}
+
+struct OpenConnection {
+    info: Connection,
+    closer: ShutdownHandle,
+}
+
+pub struct Limits {
+    cfg: LimitsConfig,
+    connections: AtomicUsize,
+    per_client: DashMap<IpAddr, usize>,
+    open: DashMap<u64, OpenConnection>,
+    next_id: AtomicU64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    requests: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+/// Held for the life of an accepted connection.
+pub struct ConnectionPermit {
+    limits: Arc<Limits>,
+    ip: IpAddr,
+    id: u64,
+    closer: ShutdownHandle,
+}
+
+impl ConnectionPermit {
+    /// Signalled when the connection should be closed, e.g. because an
+    /// operator expired the client.
+    pub fn closer(&self) -> &ShutdownHandle {
+        &self.closer
+    }
+}
+
+/// Held while one request executes.
+pub struct RequestPermit<'a> {
+    limits: &'a Limits,
+    bytes: usize,
+}
+
+// Add `amount` to `counter` unless that would pass `max` (0: unlimited).
+// Something larger than the whole budget may still go when nothing else is.
+fn acquire(counter: &AtomicUsize, amount: usize, max: usize) -> bool {
+    counter
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+            let next = n.saturating_add(amount);
+            (max == 0 || next <= max || n == 0).then_some(next)
+        })
+        .is_ok()
+}
+
+impl Limits {
+    pub fn new(cfg: LimitsConfig) -> Self {
+        Self {
+            cfg,
+            connections: AtomicUsize::new(0),
+            per_client: DashMap::new(),
+            open: DashMap::new(),
+            next_id: AtomicU64::new(0),
+            buckets: Mutex::new(HashMap::new()),
+            requests: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &LimitsConfig {
+        &self.cfg
+    }
+
+    /// Connections currently open.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Acquire)
+    }
+
+    /// Distinct client addresses with a connection open.
+    pub fn clients(&self) -> usize {
+        self.per_client.len()
+    }
+
+    /// Requests and request bytes currently executing.
+    pub fn in_flight(&self) -> (usize, usize) {
+        (self.requests.load(Ordering::Acquire), self.bytes.load(Ordering::Acquire))
+    }
+
+    /// Open connections, oldest first.
+    pub fn connected(&self) -> Vec<Connection> {
+        let mut open: Vec<_> = self.open.iter().map(|c| (*c.key(), c.info.clone())).collect();
+        open.sort_by_key(|(id, _)| *id);
+        open.into_iter().map(|(_, c)| c).collect()
+    }
+
+    /// Ask every connection from `ip` to close; returns how many there were.
+    pub fn disconnect(&self, ip: IpAddr) -> usize {
+        let mut closed = 0;
+        for c in self.open.iter().filter(|c| c.info.peer.ip() == ip) {
+            c.closer.shutdown();
+            closed += 1;
+        }
+        closed
+    }
+
+    /// Admit a new connection from `peer`, or None if it would pass the
+    /// total or per-client cap.
+    pub fn connect(self: &Arc<Self>, peer: SocketAddr) -> Option<ConnectionPermit> {
+        let ip = peer.ip();
+        if !acquire(&self.connections, 1, self.cfg.max_connections) {
+            return None;
+        }
+        let mut count = self.per_client.entry(ip).or_insert(0);
+        if self.cfg.max_connections_per_client != 0 && *count >= self.cfg.max_connections_per_client {
+            drop(count);
+            self.connections.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        *count += 1;
+        drop(count);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let closer = ShutdownHandle::new(Duration::ZERO);
+        self.open.insert(id, OpenConnection { info: Connection { peer, since: SystemTime::now() }, closer: closer.clone() });
+        Some(ConnectionPermit { limits: self.clone(), ip, id, closer })
+    }
+
+    /// Admit a request of `bytes` from `ip`, or None if the client is over
+    /// its rate or the server is over its in-flight budget.
+    pub fn admit(&self, ip: IpAddr, bytes: usize) -> Option<RequestPermit<'_>> {
+        if !self.take_token(ip) {
+            debug!("{} is over its request rate", ip);
+            return None;
+        }
+        if !acquire(&self.requests, 1, self.cfg.max_in_flight) {
+            debug!("request budget exhausted, delaying {}", ip);
+            return None;
+        }
+        if !acquire(&self.bytes, bytes, self.cfg.max_in_flight_bytes) {
+            self.requests.fetch_sub(1, Ordering::AcqRel);
+            debug!("byte budget exhausted, delaying {}", ip);
+            return None;
+        }
+        Some(RequestPermit { limits: self, bytes })
+    }
+
+    fn take_token(&self, ip: IpAddr) -> bool {
+        let rate = self.cfg.requests_per_second as f64;
+        if rate == 0.0 {
+            return true;
+        }
+        let burst = self.cfg.burst as f64;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= BUCKET_PRUNE_THRESHOLD {
+            // a bucket that would be full again carries no information
+            buckets.retain(|_, b| b.tokens + now.duration_since(b.last).as_secs_f64() * rate < burst);
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket { tokens: burst, last: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.last).as_secs_f64() * rate).min(burst);
+        bucket.last = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limits.connections.fetch_sub(1, Ordering::AcqRel);
+        self.limits.open.remove(&self.id);
+        self.limits.per_client.remove_if_mut(&self.ip, |_, count| {
+            *count -= 1;
+            *count == 0
+        });
+    }
+}
+
+impl Drop for RequestPermit<'_> {
+    fn drop(&mut self) {
+        self.limits.requests.fetch_sub(1, Ordering::AcqRel);
+        self.limits.bytes.fetch_sub(self.bytes, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,826 @@
+//! Backend serving a directory of the server's local filesystem.
+//!
+//! Handles carry the inode number. The backend remembers the (parent, name)
+//! under which it last saw each inode and rebuilds paths from that, checking
+//! the inode on every use, so a handle goes stale rather than pointing at a
+//! different object once its file is replaced. Handles do not survive a
+//! restart. Filesystem calls run on tokio's blocking pool and use the
+//! server's own identity; per-caller permission checks belong above.
+
+use crate::error::{Nfs4Status, NfsError, NfsResult};
+use crate::vfs::*;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, FileExt, FileTypeExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const LOCAL_FH_MAGIC: &[u8; 4] = b"nfsl";
+// guards against (parent, name) cycles left behind by concurrent renames
+const MAX_DEPTH: usize = 4096;
+// directory listings kept for paging; the least recently used goes first
+const MAX_LISTINGS: usize = 32;
+
+// ((directory inode, its change attribute), sorted names)
+type Listing = ((u64, u64), Arc<Vec<String>>);
+
+pub struct LocalVfs {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    root: PathBuf,
+    root_ino: u64,
+    dev: u64,
+    instance: u32,
+    // the filesystem's block size, which FICLONERANGE ranges align to; 0
+    // where there is no FICLONERANGE
+    clone_blksize: u32,
+    // inode -> (parent inode, name) for everything we have issued a handle for
+    names: DashMap<u64, (u64, String)>,
+    // directory listings as last made, least recently used first
+    listings: Mutex<VecDeque<Listing>>,
+}
+
+fn io_err(e: io::Error) -> NfsError {
+    match e.raw_os_error() {
+        Some(libc::ENOTDIR) => NfsError::NotDir,
+        Some(libc::EISDIR) => NfsError::IsDir,
+        Some(libc::ENOTEMPTY) => NfsError::NotEmpty,
+        Some(libc::ENOSPC) | Some(libc::EDQUOT) => NfsError::NoSpace,
+        Some(libc::EROFS) => NfsError::ReadOnlyFs,
+        Some(libc::EFBIG) => NfsError::FileTooBig,
+        Some(libc::EXDEV) => NfsError::CrossDevice,
+        Some(libc::ELOOP) => NfsError::InvalidArgument("is a symlink".into()),
+        Some(libc::ENAMETOOLONG) => NfsError::NameTooLong,
+        _ => match e.kind() {
+            io::ErrorKind::NotFound => NfsError::NotFound,
+            io::ErrorKind::PermissionDenied => NfsError::PermissionDenied,
+            io::ErrorKind::AlreadyExists => NfsError::AlreadyExists,
+            _ => NfsError::Io(e),
+        },
+    }
+}
+
+fn cstring(path: &Path) -> NfsResult<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| NfsError::InvalidArgument("NUL in path".into()))
+}
+
+// A single directory entry name: no separators, no "." or ".."
+fn check_name(name: &str) -> NfsResult<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return Err(NfsError::InvalidArgument(format!("invalid file name {:?}", name)));
+    }
+    Ok(())
+}
+
+fn secs(t: i64) -> u64 {
+    t.max(0) as u64
+}
+
+// Change attribute: the ctime in nanoseconds
+fn change(m: &Metadata) -> u64 {
+    secs(m.ctime()) * 1_000_000_000 + m.ctime_nsec().max(0) as u64
+}
+
+fn attr_from(m: &Metadata) -> FileAttr {
+    let ft = m.file_type();
+    let ftype = if ft.is_dir() {
+        FileType::Directory
+    } else if ft.is_symlink() {
+        FileType::Symlink
+    } else if ft.is_block_device() {
+        FileType::BlockDevice
+    } else if ft.is_char_device() {
+        FileType::CharDevice
+    } else if ft.is_fifo() {
+        FileType::Fifo
+    } else if ft.is_socket() {
+        FileType::Socket
+    } else {
+        FileType::Regular
+    };
+    let rdev = m.rdev();
+    FileAttr {
+        changeid: change(m),
+        size: m.size(),
+        mtime: secs(m.mtime()),
+        ctime: secs(m.ctime()),
+        atime: secs(m.atime()),
+        ftype,
+        mode: m.mode() & 0o7777,
+        nlink: m.nlink() as u32,
+        uid: m.uid(),
+        gid: m.gid(),
+        fileid: m.ino(),
+        fsid: m.dev(),
+        used: m.blocks() * 512,
+        // Linux dev_t layout
+        rdev: ((((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff)) as u32, ((rdev & 0xff) | ((rdev >> 12) & !0xff)) as u32),
+        acl: None,
+    }
+}
+
+fn timespec(t: Option<SetTime>) -> libc::timespec {
+    let (tv_sec, tv_nsec) = match t {
+        None => (0, libc::UTIME_OMIT),
+        Some(SetTime::ServerNow) => (0, libc::UTIME_NOW),
+        Some(SetTime::Client(s)) => (s as libc::time_t, 0),
+    };
+    libc::timespec { tv_sec, tv_nsec }
+}
+
+// Copy `count` bytes between open files, stopping early at the end of
+// `from`. The kernel does it where it can, sharing blocks on filesystems
+// that reflink.
+#[cfg(target_os = "linux")]
+fn copy_file_range(from: &File, from_offset: u64, to: &File, to_offset: u64, count: u64) -> io::Result<u64> {
+    use std::os::fd::AsRawFd;
+    let mut done = 0;
+    while done < count {
+        let (mut off_in, mut off_out) = ((from_offset + done) as libc::loff_t, (to_offset + done) as libc::loff_t);
+        let len = (count - done).min(1 << 30) as usize;
+        // SAFETY: both descriptors are open for as long as the files are
+        // borrowed, and the offsets are ours to update
+        let n = unsafe { libc::copy_file_range(from.as_raw_fd(), &mut off_in, to.as_raw_fd(), &mut off_out, len, 0) };
+        match n {
+            0 => break,
+            n if n > 0 => done += n as u64,
+            _ => {
+                let e = io::Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(libc::EINTR) => {}
+                    // kernels and filesystems that cannot do it at all
+                    Some(libc::ENOSYS | libc::EXDEV | libc::EOPNOTSUPP | libc::EINVAL) if done == 0 => {
+                        return copy_buffered(from, from_offset, to, to_offset, count);
+                    }
+                    _ => return Err(e),
+                }
+            }
+        }
+    }
+    Ok(done)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_file_range(from: &File, from_offset: u64, to: &File, to_offset: u64, count: u64) -> io::Result<u64> {
+    copy_buffered(from, from_offset, to, to_offset, count)
+}
+
+fn copy_buffered(from: &File, from_offset: u64, to: &File, to_offset: u64, count: u64) -> io::Result<u64> {
+    let mut buf = vec![0u8; count.min(1024 * 1024) as usize];
+    let mut done = 0;
+    while done < count {
+        let want = (count - done).min(buf.len() as u64) as usize;
+        let n = match from.read_at(&mut buf[..want], from_offset + done) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        to.write_all_at(&buf[..n], to_offset + done)?;
+        done += n as u64;
+    }
+    Ok(done)
+}
+
+// Share `count` bytes of `from` with `to`, on filesystems that reflink
+#[cfg(target_os = "linux")]
+fn clone_file_range(from: &File, from_offset: u64, to: &File, to_offset: u64, count: u64) -> NfsResult<()> {
+    use std::os::fd::AsRawFd;
+    let range = libc::file_clone_range { src_fd: from.as_raw_fd() as i64, src_offset: from_offset, src_length: count, dest_offset: to_offset };
+    // SAFETY: `range` is plain data naming a descriptor that stays open,
+    // as does `to`, for the call
+    if unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONERANGE, &range) } == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::EOPNOTSUPP | libc::ENOTTY | libc::ENOSYS) => Err(NfsError::NotSupported),
+        // the kernel's own alignment rules, stricter at the end of a file
+        Some(libc::EINVAL) => Err(NfsError::InvalidArgument("range the filesystem cannot clone".into())),
+        _ => Err(io_err(e)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clone_file_range(_from: &File, _from_offset: u64, _to: &File, _to_offset: u64, _count: u64) -> NfsResult<()> {
+    Err(NfsError::NotSupported)
+}
+
+// Where the first data or hole at or after `offset`, which is before the
+// end of the file, starts
+#[cfg(target_os = "linux")]
+fn seek_content(f: &File, offset: u64, what: Content) -> NfsResult<Option<u64>> {
+    use std::os::fd::AsRawFd;
+    let whence = match what {
+        Content::Data => libc::SEEK_DATA,
+        Content::Hole => libc::SEEK_HOLE,
+    };
+    // SAFETY: the descriptor stays open for the call
+    let at = unsafe { libc::lseek(f.as_raw_fd(), offset as libc::off_t, whence) };
+    if at >= 0 {
+        return Ok(Some(at as u64));
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        // no data between `offset` and the end
+        Some(libc::ENXIO) => Ok(None),
+        _ => Err(io_err(e)),
+    }
+}
+
+// Without SEEK_DATA the whole file is data
+#[cfg(not(target_os = "linux"))]
+fn seek_content(f: &File, offset: u64, what: Content) -> NfsResult<Option<u64>> {
+    Ok(match what {
+        Content::Data => Some(offset),
+        Content::Hole => Some(f.metadata().map_err(io_err)?.len()),
+    })
+}
+
+// Give `length` bytes from `offset` storage, or with `punch` take it away
+#[cfg(target_os = "linux")]
+fn fallocate(f: &File, offset: u64, length: u64, punch: bool) -> NfsResult<()> {
+    use std::os::fd::AsRawFd;
+    let mode = if punch { libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE } else { 0 };
+    let (Ok(offset), Ok(length)) = (libc::off_t::try_from(offset), libc::off_t::try_from(length)) else {
+        return Err(NfsError::FileTooBig);
+    };
+    loop {
+        // SAFETY: the descriptor stays open for the call
+        if unsafe { libc::fallocate(f.as_raw_fd(), mode, offset, length) } == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EINTR) => {}
+            Some(libc::EOPNOTSUPP | libc::ENOSYS) => return Err(NfsError::NotSupported),
+            _ => return Err(io_err(e)),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fallocate(_f: &File, _offset: u64, _length: u64, _punch: bool) -> NfsResult<()> {
+    Err(NfsError::NotSupported)
+}
+
+impl LocalVfs {
+    /// Serve the directory at `root`.
+    pub fn new(root: impl AsRef<Path>) -> NfsResult<Arc<Self>> {
+        let root = fs::canonicalize(root.as_ref()).map_err(io_err)?;
+        let m = fs::metadata(&root).map_err(io_err)?;
+        if !m.is_dir() {
+            return Err(NfsError::NotDir);
+        }
+        let instance = u32::from_be_bytes(uuid::Uuid::new_v4().as_bytes()[..4].try_into().unwrap());
+        Ok(Arc::new(Self {
+            inner: Arc::new(Inner {
+                root,
+                root_ino: m.ino(),
+                dev: m.dev(),
+                instance,
+                clone_blksize: if cfg!(target_os = "linux") { m.blksize() as u32 } else { 0 },
+                names: DashMap::new(),
+                listings: Mutex::new(VecDeque::new()),
+            }),
+        }))
+    }
+
+    // ALLOCATE or, with `punch`, DEALLOCATE
+    async fn allocate_range(&self, fh: &[u8], offset: u64, length: u64, punch: bool) -> NfsResult<()> {
+        let fh = fh.to_vec();
+        self.run(move |fs| {
+            let (path, m) = fs.resolve(&fh)?;
+            if m.is_dir() {
+                return Err(NfsError::IsDir);
+            }
+            if !m.is_file() {
+                return Err(NfsError::InvalidArgument("allocation in non-regular file".into()));
+            }
+            fallocate(&fs.open(&path, true)?, offset, length, punch)
+        })
+        .await
+    }
+
+    // Run filesystem work off the async threads
+    async fn run<T, F>(&self, f: F) -> NfsResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Inner) -> NfsResult<T> + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || f(&inner))
+            .await
+            .map_err(|e| NfsError::Io(io::Error::other(e)))?
+    }
+}
+
+impl Inner {
+    fn fh_for(&self, ino: u64) -> Vec<u8> {
+        let mut fh = Vec::with_capacity(16);
+        fh.extend_from_slice(LOCAL_FH_MAGIC);
+        fh.extend_from_slice(&self.instance.to_be_bytes());
+        fh.extend_from_slice(&ino.to_be_bytes());
+        fh
+    }
+
+    fn ino_of(&self, fh: &[u8]) -> NfsResult<u64> {
+        if fh.len() != 16 || &fh[..4] != LOCAL_FH_MAGIC {
+            return Err(NfsError::BadHandle);
+        }
+        if fh[4..8] != self.instance.to_be_bytes() {
+            return Err(NfsError::StaleHandle);
+        }
+        Ok(u64::from_be_bytes(fh[8..16].try_into().unwrap()))
+    }
+
+    // Path of an inode we issued a handle for, verified to still be that inode
+    fn path_of(&self, ino: u64) -> NfsResult<(PathBuf, Metadata)> {
+        let mut parts = Vec::new();
+        let mut cur = ino;
+        while cur != self.root_ino {
+            if parts.len() > MAX_DEPTH {
+                return Err(NfsError::StaleHandle);
+            }
+            let (parent, name) = self.names.get(&cur).map(|e| e.clone()).ok_or(NfsError::StaleHandle)?;
+            parts.push(name);
+            cur = parent;
+        }
+        let mut path = self.root.clone();
+        path.extend(parts.iter().rev());
+        match fs::symlink_metadata(&path) {
+            Ok(m) if m.ino() == ino && m.dev() == self.dev => Ok((path, m)),
+            _ => {
+                self.names.remove(&ino);
+                Err(NfsError::StaleHandle)
+            }
+        }
+    }
+
+    fn resolve(&self, fh: &[u8]) -> NfsResult<(PathBuf, Metadata)> {
+        self.path_of(self.ino_of(fh)?)
+    }
+
+    fn dir_path(&self, fh: &[u8]) -> NfsResult<(u64, PathBuf)> {
+        let ino = self.ino_of(fh)?;
+        let (path, m) = self.path_of(ino)?;
+        if !m.is_dir() {
+            return Err(NfsError::NotDir);
+        }
+        Ok((ino, path))
+    }
+
+    // Record a newly seen child and return its handle
+    fn remember(&self, dir: u64, name: &str, m: &Metadata) -> NfsResult<Vec<u8>> {
+        if m.dev() != self.dev {
+            return Err(NfsError::CrossDevice);
+        }
+        if m.ino() != self.root_ino {
+            self.names.insert(m.ino(), (dir, name.to_string()));
+        }
+        Ok(self.fh_for(m.ino()))
+    }
+
+    // Sorted names in a directory, so that positions, and hence cookies, are
+    // repeatable. Paging through a directory lists it once, not per page.
+    fn listing(&self, ino: u64, path: &Path, changeid: u64) -> NfsResult<Arc<Vec<String>>> {
+        let key = (ino, changeid);
+        {
+            let mut listings = self.listings.lock().unwrap();
+            if let Some(i) = listings.iter().position(|(k, _)| *k == key) {
+                let hit = listings.remove(i).unwrap();
+                let names = hit.1.clone();
+                listings.push_back(hit);
+                return Ok(names);
+            }
+        }
+        let mut names: Vec<String> = fs::read_dir(path)
+            .map_err(io_err)?
+            .filter_map(|e| e.ok()?.file_name().into_string().ok())
+            .collect();
+        names.sort_unstable();
+        let names = Arc::new(names);
+        let mut listings = self.listings.lock().unwrap();
+        // any earlier listing of the directory is out of date
+        listings.retain(|((i, _), _)| *i != ino);
+        if listings.len() >= MAX_LISTINGS {
+            listings.pop_front();
+        }
+        listings.push_back((key, names.clone()));
+        Ok(names)
+    }
+
+    fn open(&self, path: &Path, write: bool) -> NfsResult<File> {
+        OpenOptions::new()
+            .read(!write)
+            .write(write)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)
+            .map_err(io_err)
+    }
+
+    fn apply(&self, path: &Path, m: &Metadata, set: &SetAttr) -> NfsResult<()> {
+        // the mode is all the access control kept on disk
+        if set.acl.is_some() {
+            return Err(NfsError::NotSupported);
+        }
+        if let Some(size) = set.size {
+            if m.is_dir() {
+                return Err(NfsError::IsDir);
+            }
+            self.open(path, true)?.set_len(size).map_err(io_err)?;
+        }
+        // symlinks have no mode of their own
+        if let (Some(mode), false) = (set.mode, m.file_type().is_symlink()) {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777)).map_err(io_err)?;
+        }
+        if set.uid.is_some() || set.gid.is_some() {
+            std::os::unix::fs::lchown(path, set.uid, set.gid).map_err(io_err)?;
+        }
+        if set.atime.is_some() || set.mtime.is_some() {
+            let times = [timespec(set.atime), timespec(set.mtime)];
+            let c = cstring(path)?;
+            // SAFETY: `c` is a valid NUL-terminated path and `times` has two entries
+            let rc = unsafe { libc::utimensat(libc::AT_FDCWD, c.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW) };
+            if rc != 0 {
+                return Err(io_err(io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    // Path API helpers: paths are relative to the export root
+    fn rel_path(&self, path: &str) -> NfsResult<PathBuf> {
+        let mut out = self.root.clone();
+        for comp in path.split('/').filter(|c| !c.is_empty()) {
+            check_name(comp)?;
+            out.push(comp);
+        }
+        Ok(out)
+    }
+}
+
+#[async_trait]
+impl Vfs for LocalVfs {
+    async fn root_fh(&self) -> NfsResult<Vec<u8>> {
+        Ok(self.inner.fh_for(self.inner.root_ino))
+    }
+
+    async fn getattr_root(&self, attr_request: &[u32]) -> NfsResult<Vec<u8>> {
+        let request = attr_request.to_vec();
+        self.run(move |fs| {
+            let m = fs::metadata(&fs.root).map_err(io_err)?;
+            encode_root_fattr4(&attr_from(&m), &fs.fh_for(fs.root_ino), 0, fs.clone_blksize, &request)
+        })
+        .await
+    }
+
+    async fn create_file(&self, path: &str, size: u64) -> NfsResult<()> {
+        let path = path.to_string();
+        self.run(move |fs| {
+            let p = fs.rel_path(&path)?;
+            let f = OpenOptions::new().write(true).create(true).truncate(false).custom_flags(libc::O_NOFOLLOW).open(p).map_err(io_err)?;
+            f.set_len(size).map_err(io_err)
+        })
+        .await
+    }
+
+    async fn modify_file(&self, path: &str, new_size: u64) -> NfsResult<()> {
+        let path = path.to_string();
+        self.run(move |fs| fs.open(&fs.rel_path(&path)?, true)?.set_len(new_size).map_err(io_err)).await
+    }
+
+    async fn create_dir(&self, path: &str) -> NfsResult<()> {
+        let path = path.to_string();
+        self.run(move |fs| match fs::create_dir(fs.rel_path(&path)?) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+            r => r.map_err(io_err),
+        })
+        .await
+    }
+
+    async fn remove_entry(&self, path: &str) -> NfsResult<()> {
+        let path = path.to_string();
+        self.run(move |fs| {
+            let p = fs.rel_path(&path)?;
+            let m = fs::symlink_metadata(&p).map_err(io_err)?;
+            if m.is_dir() { fs::remove_dir(p) } else { fs::remove_file(p) }.map_err(io_err)
+        })
+        .await
+    }
+
+    async fn getattr(&self, fh: &[u8]) -> NfsResult<FileAttr> {
+        let fh = fh.to_vec();
+        self.run(move |fs| Ok(attr_from(&fs.resolve(&fh)?.1))).await
+    }
+
+    async fn setattr(&self, fh: &[u8], set: &SetAttr) -> NfsResult<FileAttr> {
+        let (fh, set) = (fh.to_vec(), set.clone());
+        self.run(move |fs| {
+            let (path, m) = fs.resolve(&fh)?;
+            fs.apply(&path, &m, &set)?;
+            Ok(attr_from(&fs::symlink_metadata(&path).map_err(io_err)?))
+        })
+        .await
+    }
+
+    async fn lookup(&self, dir: &[u8], name: &str) -> NfsResult<Vec<u8>> {
+        let (dir, name) = (dir.to_vec(), name.to_string());
+        self.run(move |fs| {
+            check_name(&name)?;
+            let (ino, path) = fs.dir_path(&dir)?;
+            let m = fs::symlink_metadata(path.join(&name)).map_err(io_err)?;
+            fs.remember(ino, &name, &m)
+        })
+        .await
+    }
+
+    async fn lookup_parent(&self, dir: &[u8]) -> NfsResult<Vec<u8>> {
+        let dir = dir.to_vec();
+        self.run(move |fs| {
+            let (ino, _) = fs.dir_path(&dir)?;
+            if ino == fs.root_ino {
+                return Ok(fs.fh_for(ino));
+            }
+            let parent = fs.names.get(&ino).map(|e| e.0).ok_or(NfsError::StaleHandle)?;
+            Ok(fs.fh_for(parent))
+        })
+        .await
+    }
+
+    async fn read(&self, fh: &[u8], offset: u64, count: u32) -> NfsResult<(Bytes, bool)> {
+        let fh = fh.to_vec();
+        self.run(move |fs| {
+            let (path, m) = fs.resolve(&fh)?;
+            if m.is_dir() {
+                return Err(NfsError::IsDir);
+            }
+            if !m.is_file() {
+                return Err(NfsError::InvalidArgument("read of non-regular file".into()));
+            }
+            let f = fs.open(&path, false)?;
+            let want = (count as u64).min(m.size().saturating_sub(offset)) as usize;
+            let mut buf = BytesMut::zeroed(want);
+            let mut got = 0;
+            while got < want {
+                match f.read_at(&mut buf[got..], offset + got as u64) {
+                    Ok(0) => break,
+                    Ok(n) => got += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(io_err(e)),
+                }
+            }
+            buf.truncate(got);
+            Ok((buf.freeze(), offset + got as u64 >= m.size()))
+        })
+        .await
+    }
+
+    async fn write(&self, fh: &[u8], offset: u64, data: Bytes) -> NfsResult<u32> {
+        let fh = fh.to_vec();
+        self.run(move |fs| {
+            let (path, m) = fs.resolve(&fh)?;
+            if m.is_dir() {
+                return Err(NfsError::IsDir);
+            }
+            fs.open(&path, true)?.write_all_at(&data, offset).map_err(io_err)?;
+            Ok(data.len() as u32)
+        })
+        .await
+    }
+
+    async fn commit(&self, fh: &[u8], _offset: u64, _count: u64) -> NfsResult<()> {
+        let fh = fh.to_vec();
+        self.run(move |fs| {
+            let (path, m) = fs.resolve(&fh)?;
+            // there is no portable way to make just a range durable; a
+            // full fsync also covers the times, as FILE_SYNC promises
+            if m.is_file() {
+                fs.open(&path, true)?.sync_all().map_err(io_err)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn copy_range(&self, src: &[u8], src_offset: u64, dst: &[u8], dst_offset: u64, count: u64) -> NfsResult<u64> {
+        let (src, dst) = (src.to_vec(), dst.to_vec());
+        self.run(move |fs| {
+            let (from_path, from_m) = fs.resolve(&src)?;
+            let (to_path, to_m) = fs.resolve(&dst)?;
+            if from_m.is_dir() || to_m.is_dir() {
+                return Err(NfsError::IsDir);
+            }
+            if !from_m.is_file() || !to_m.is_file() {
+                return Err(NfsError::InvalidArgument("copy of non-regular file".into()));
+            }
+            let count = count.min(from_m.size().saturating_sub(src_offset));
+            copy_file_range(&fs.open(&from_path, false)?, src_offset, &fs.open(&to_path, true)?, dst_offset, count).map_err(io_err)
+        })
+        .await
+    }
+
+    async fn clone_range(&self, src: &[u8], src_offset: u64, dst: &[u8], dst_offset: u64, count: u64) -> NfsResult<()> {
+        let (src, dst) = (src.to_vec(), dst.to_vec());
+        self.run(move |fs| {
+            let (from_path, from_m) = fs.resolve(&src)?;
+            let (to_path, to_m) = fs.resolve(&dst)?;
+            if from_m.is_dir() || to_m.is_dir() {
+                return Err(NfsError::IsDir);
+            }
+            if !from_m.is_file() || !to_m.is_file() {
+                return Err(NfsError::InvalidArgument("clone of non-regular file".into()));
+            }
+            // a length of 0 would mean to the end of the file
+            if count == 0 {
+                return Ok(());
+            }
+            clone_file_range(&fs.open(&from_path, false)?, src_offset, &fs.open(&to_path, true)?, dst_offset, count)
+        })
+        .await
+    }
+
+    async fn seek(&self, fh: &[u8], offset: u64, what: Content) -> NfsResult<Option<u64>> {
+        let fh = fh.to_vec();
+        self.run(move |fs| {
+            let (path, m) = fs.resolve(&fh)?;
+            if m.is_dir() {
+                return Err(NfsError::IsDir);
+            }
+            if !m.is_file() {
+                return Err(NfsError::InvalidArgument("seek in non-regular file".into()));
+            }
+            if offset >= m.size() {
+                return Ok(None);
+            }
+            seek_content(&fs.open(&path, false)?, offset, what)
+        })
+        .await
+    }
+
+    async fn allocate(&self, fh: &[u8], offset: u64, length: u64) -> NfsResult<()> {
+        self.allocate_range(fh, offset, length, false).await
+    }
+
+    async fn deallocate(&self, fh: &[u8], offset: u64, length: u64) -> NfsResult<()> {
+        self.allocate_range(fh, offset, length, true).await
+    }
+
+    async fn create(&self, dir: &[u8], name: &str, kind: CreateKind, attr: &SetAttr) -> NfsResult<Vec<u8>> {
+        let (dir, name, attr) = (dir.to_vec(), name.to_string(), attr.clone());
+        self.run(move |fs| {
+            check_name(&name)?;
+            let (ino, dir_path) = fs.dir_path(&dir)?;
+            let path = dir_path.join(&name);
+            let mode = attr.mode.unwrap_or(if kind == CreateKind::Directory { 0o755 } else { 0o644 }) & 0o7777;
+            match &kind {
+                CreateKind::Regular => {
+                    OpenOptions::new().write(true).create_new(true).mode(mode).open(&path).map_err(io_err)?;
+                }
+                CreateKind::Directory => fs::DirBuilder::new().mode(mode).create(&path).map_err(io_err)?,
+                CreateKind::Symlink(target) => std::os::unix::fs::symlink(target, &path).map_err(io_err)?,
+                CreateKind::Fifo => {
+                    let c = cstring(&path)?;
+                    // SAFETY: `c` is a valid NUL-terminated path
+                    if unsafe { libc::mkfifo(c.as_ptr(), mode as libc::mode_t) } != 0 {
+                        return Err(io_err(io::Error::last_os_error()));
+                    }
+                }
+                CreateKind::BlockDevice(..) | CreateKind::CharDevice(..) | CreateKind::Socket => {
+                    return Err(NfsError::NotSupported);
+                }
+            }
+            // the umask may have trimmed the mode, so set it explicitly
+            let m = fs::symlink_metadata(&path).map_err(io_err)?;
+            fs.apply(&path, &m, &SetAttr { uid: None, gid: None, ..attr.clone() })?;
+            // Handing the object to its creator needs privilege; an
+            // unprivileged server keeps ownership rather than failing
+            match std::os::unix::fs::lchown(&path, attr.uid, attr.gid) {
+                Err(e) if e.raw_os_error() == Some(libc::EPERM) => {}
+                res => res.map_err(io_err)?,
+            }
+            fs.remember(ino, &name, &m)
+        })
+        .await
+    }
+
+    async fn readlink(&self, fh: &[u8]) -> NfsResult<String> {
+        let fh = fh.to_vec();
+        self.run(move |fs| {
+            let (path, m) = fs.resolve(&fh)?;
+            if !m.file_type().is_symlink() {
+                return Err(NfsError::InvalidArgument("not a symlink".into()));
+            }
+            let target = fs::read_link(path).map_err(io_err)?;
+            target.into_os_string().into_string().map_err(|_| NfsError::InvalidArgument("symlink target is not UTF-8".into()))
+        })
+        .await
+    }
+
+    async fn remove(&self, dir: &[u8], name: &str) -> NfsResult<()> {
+        let (dir, name) = (dir.to_vec(), name.to_string());
+        self.run(move |fs| {
+            check_name(&name)?;
+            let (_, dir_path) = fs.dir_path(&dir)?;
+            let path = dir_path.join(&name);
+            let m = fs::symlink_metadata(&path).map_err(io_err)?;
+            if m.is_dir() { fs::remove_dir(&path) } else { fs::remove_file(&path) }.map_err(io_err)?;
+            if m.is_dir() || m.nlink() <= 1 {
+                fs.names.remove(&m.ino());
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn rename(&self, from_dir: &[u8], from: &str, to_dir: &[u8], to: &str) -> NfsResult<()> {
+        let (from_dir, from, to_dir, to) = (from_dir.to_vec(), from.to_string(), to_dir.to_vec(), to.to_string());
+        self.run(move |fs| {
+            check_name(&from)?;
+            check_name(&to)?;
+            let (_, src_dir) = fs.dir_path(&from_dir)?;
+            let (dst_ino, dst_dir) = fs.dir_path(&to_dir)?;
+            let dst = dst_dir.join(&to);
+            fs::rename(src_dir.join(&from), &dst).map_err(io_err)?;
+            let m = fs::symlink_metadata(&dst).map_err(io_err)?;
+            fs.names.insert(m.ino(), (dst_ino, to));
+            Ok(())
+        })
+        .await
+    }
+
+    async fn link(&self, fh: &[u8], dir: &[u8], name: &str) -> NfsResult<()> {
+        let (fh, dir, name) = (fh.to_vec(), dir.to_vec(), name.to_string());
+        self.run(move |fs| {
+            check_name(&name)?;
+            let (src, m) = fs.resolve(&fh)?;
+            if m.is_dir() {
+                return Err(NfsError::IsDir);
+            }
+            let (_, dir_path) = fs.dir_path(&dir)?;
+            fs::hard_link(src, dir_path.join(&name)).map_err(io_err)
+        })
+        .await
+    }
+
+    async fn readdir(&self, dir: &[u8], cookie: u64, max_entries: usize) -> NfsResult<ReadDir> {
+        let dir = dir.to_vec();
+        self.run(move |fs| {
+            let ino = fs.ino_of(&dir)?;
+            let (path, m) = fs.path_of(ino)?;
+            if !m.is_dir() {
+                return Err(NfsError::NotDir);
+            }
+            // cookies are positions, which any change to the directory shifts
+            let verifier = change(&m);
+            let names = fs.listing(ino, &path, verifier)?;
+            let skip = if cookie == 0 { 0 } else { (cookie + 1).saturating_sub(FIRST_COOKIE) as usize };
+            if skip > names.len() {
+                return Err(NfsError::Status(Nfs4Status::BadCookie));
+            }
+            let mut entries = Vec::new();
+            for (i, name) in names.iter().enumerate().skip(skip).take(max_entries) {
+                let Ok(m) = fs::symlink_metadata(path.join(name)) else { continue };
+                let Ok(fh) = fs.remember(ino, name, &m) else { continue };
+                entries.push(DirEntry { name: name.clone(), cookie: FIRST_COOKIE + i as u64, fh, attr: Some(attr_from(&m)) });
+            }
+            Ok(ReadDir { eof: skip + max_entries >= names.len(), entries, verifier })
+        })
+        .await
+    }
+
+    async fn fsstat(&self, fh: &[u8]) -> NfsResult<FsStat> {
+        let fh = fh.to_vec();
+        self.run(move |fs| {
+            let (path, _) = fs.resolve(&fh)?;
+            let c = cstring(&path)?;
+            // SAFETY: statvfs is plain data and `c` is a valid path
+            let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+            if unsafe { libc::statvfs(c.as_ptr(), &mut st) } != 0 {
+                return Err(io_err(io::Error::last_os_error()));
+            }
+            let frsize = st.f_frsize as u64;
+            Ok(FsStat {
+                total_bytes: st.f_blocks as u64 * frsize,
+                free_bytes: st.f_bfree as u64 * frsize,
+                avail_bytes: st.f_bavail as u64 * frsize,
+                total_files: st.f_files as u64,
+                free_files: st.f_ffree as u64,
+                avail_files: st.f_favail as u64,
+            })
+        })
+        .await
+    }
+
+    fn clone_blksize(&self) -> u32 {
+        self.inner.clone_blksize
+    }
+}
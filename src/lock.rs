@@ -0,0 +1,181 @@
+//! Byte-range lock table. NLM (for NFSv3 clients) and NFSv4 LOCK share one
+//! `LockManager`, so a lock taken over one protocol conflicts with the other.
+//!
+//! After a restart that found clients in the recovery record, the manager
+//! is in a grace period: only reclaims of locks held before the restart are
+//! granted, so no new lock can get in ahead of them.
+
+use dashmap::DashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Identity of a lock holder. NLM owners are (caller_name, oh, svid); NFSv4
+/// owners use the client id as `host`, the lock_owner as `id` and a zero `svid`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LockOwner {
+    pub host: String,
+    pub id: Vec<u8>,
+    pub svid: i32,
+}
+
+/// A granted lock covering `offset..=end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteLock {
+    pub owner: LockOwner,
+    pub offset: u64,
+    pub end: u64,
+    pub exclusive: bool,
+}
+
+impl ByteLock {
+    /// Length in NLM convention: 0 means "to end of file".
+    pub fn length(&self) -> u64 {
+        if self.end == u64::MAX {
+            0
+        } else {
+            self.end - self.offset + 1
+        }
+    }
+
+    fn overlaps(&self, offset: u64, end: u64) -> bool {
+        self.offset <= end && offset <= self.end
+    }
+}
+
+// Last byte covered by a range. A zero length, or one that runs past the end
+// of the offset space (NFSv4 uses all ones), locks to end of file.
+fn range_end(offset: u64, length: u64) -> u64 {
+    match offset.checked_add(length) {
+        Some(end) if length != 0 => end - 1,
+        _ => u64::MAX,
+    }
+}
+
+#[derive(Default)]
+pub struct LockManager {
+    files: DashMap<Vec<u8>, Vec<ByteLock>>,
+    grace_until: Mutex<Option<Instant>>,
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return a lock held by another owner that would block this request.
+    pub fn test(&self, fh: &[u8], owner: &LockOwner, offset: u64, length: u64, exclusive: bool) -> Option<ByteLock> {
+        let end = range_end(offset, length);
+        let locks = self.files.get(fh)?;
+        locks
+            .iter()
+            .find(|l| l.owner != *owner && l.overlaps(offset, end) && (exclusive || l.exclusive))
+            .cloned()
+    }
+
+    /// Acquire a lock, replacing whatever the owner already held in the range
+    /// (upgrade, downgrade or extension). On conflict the holder is returned.
+    pub fn lock(&self, fh: &[u8], owner: &LockOwner, offset: u64, length: u64, exclusive: bool) -> Result<(), ByteLock> {
+        let end = range_end(offset, length);
+        if let Some(conflict) = self.test(fh, owner, offset, length, exclusive) {
+            return Err(conflict);
+        }
+        let mut locks = self.files.entry(fh.to_vec()).or_default();
+        // re-check under the entry lock in case another owner raced us
+        if let Some(conflict) = locks
+            .iter()
+            .find(|l| l.owner != *owner && l.overlaps(offset, end) && (exclusive || l.exclusive))
+        {
+            return Err(conflict.clone());
+        }
+        carve(&mut locks, owner, offset, end);
+        locks.push(ByteLock { owner: owner.clone(), offset, end, exclusive });
+        Ok(())
+    }
+
+    /// Release the owner's locks in a range, splitting locks that only
+    /// partially overlap it. Unlocking a range that is not held is not an error.
+    pub fn unlock(&self, fh: &[u8], owner: &LockOwner, offset: u64, length: u64) {
+        let end = range_end(offset, length);
+        if let Some(mut locks) = self.files.get_mut(fh) {
+            carve(&mut locks, owner, offset, end);
+        }
+        self.files.remove_if(fh, |_, locks| locks.is_empty());
+    }
+
+    /// Drop every lock held by `host`, e.g. after it reports a reboot.
+    /// Returns the number of locks released.
+    pub fn release_host(&self, host: &str) -> usize {
+        let mut released = 0;
+        for mut locks in self.files.iter_mut() {
+            let before = locks.len();
+            locks.retain(|l| l.owner.host != host);
+            released += before - locks.len();
+        }
+        self.files.retain(|_, locks| !locks.is_empty());
+        released
+    }
+
+    /// Drop every lock, as a server restart would.
+    pub fn release_all(&self) -> usize {
+        let released = self.files.iter().map(|l| l.len()).sum();
+        self.files.clear();
+        released
+    }
+
+    /// Refuse new (non-reclaim) locks for `period`.
+    pub fn start_grace(&self, period: Duration) {
+        *self.grace_until.lock().unwrap() = Some(Instant::now() + period);
+    }
+
+    pub fn in_grace(&self) -> bool {
+        let mut until = self.grace_until.lock().unwrap();
+        match *until {
+            Some(end) if Instant::now() < end => true,
+            Some(_) => {
+                *until = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// End the grace period now; false if there was none.
+    pub fn end_grace(&self) -> bool {
+        let was = self.in_grace();
+        *self.grace_until.lock().unwrap() = None;
+        was
+    }
+
+    /// Every lock held, by file handle.
+    pub fn all(&self) -> Vec<(Vec<u8>, Vec<ByteLock>)> {
+        self.files.iter().map(|e| (e.key().clone(), e.value().clone())).collect()
+    }
+
+    /// Locks currently held, across all files.
+    pub fn count(&self) -> usize {
+        self.files.iter().map(|l| l.len()).sum()
+    }
+
+    /// Locks currently held on a file.
+    pub fn locks(&self, fh: &[u8]) -> Vec<ByteLock> {
+        self.files.get(fh).map(|l| l.clone()).unwrap_or_default()
+    }
+}
+
+// Remove `offset..=end` from the owner's locks, keeping the parts outside it
+fn carve(locks: &mut Vec<ByteLock>, owner: &LockOwner, offset: u64, end: u64) {
+    let mut kept = Vec::with_capacity(locks.len());
+    for l in locks.drain(..) {
+        if l.owner != *owner || !l.overlaps(offset, end) {
+            kept.push(l);
+            continue;
+        }
+        if l.offset < offset {
+            kept.push(ByteLock { end: offset - 1, ..l.clone() });
+        }
+        if l.end > end {
+            kept.push(ByteLock { offset: end + 1, ..l });
+        }
+    }
+    *locks = kept;
+}
@@ -0,0 +1,42 @@
+//! Process-wide log output whose filter can be replaced while running.
+//! `RUST_LOG`, when set, takes precedence over the configured filter.
+
+use crate::error::{NfsError, NfsResult};
+use std::sync::OnceLock;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+// Without RUST_LOG or a configured filter only errors are shown
+const DEFAULT_FILTER: &str = "error";
+
+fn build(directives: Option<&str>) -> NfsResult<EnvFilter> {
+    match std::env::var("RUST_LOG") {
+        Ok(env) => Ok(EnvFilter::new(env)),
+        Err(_) => parse(directives.unwrap_or(DEFAULT_FILTER)),
+    }
+}
+
+/// Parse a filter in `RUST_LOG` syntax, e.g. "info,nfs_rs::nfs3=debug".
+pub fn parse(directives: &str) -> NfsResult<EnvFilter> {
+    EnvFilter::try_new(directives).map_err(|e| NfsError::Config(format!("log filter {:?}: {}", directives, e)))
+}
+
+/// Install the global subscriber. Only the first call has any effect.
+pub fn init(directives: Option<&str>) -> NfsResult<()> {
+    let (layer, handle) = reload::Layer::new(build(directives)?);
+    if FILTER.set(handle).is_ok() {
+        tracing_subscriber::registry().with(layer).with(tracing_subscriber::fmt::layer()).init();
+    }
+    Ok(())
+}
+
+/// Replace the filter installed by `init`; does nothing before `init`.
+pub fn set_filter(directives: Option<&str>) -> NfsResult<()> {
+    let Some(handle) = FILTER.get() else {
+        return Ok(());
+    };
+    let filter = build(directives)?;
+    handle.reload(filter).map_err(|e| NfsError::Config(format!("log filter: {}", e)))
+}
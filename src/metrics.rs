@@ -0,0 +1,155 @@
+//! Prometheus metrics, served in the text format at `GET /metrics`.
+//! Operation counters are process-wide; connection and state gauges are
+//! read from the dispatcher when scraped.
+
+use crate::error::{Nfs4Status, NfsResult};
+use crate::proto::nfs4::op_name;
+use crate::server::{Dispatcher, ShutdownHandle};
+use num_traits::FromPrimitive;
+use prometheus::core::Collector;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tracing::{debug, info};
+
+// Largest request head we read before answering
+const MAX_REQUEST: usize = 8192;
+
+struct Counters {
+    ops: IntCounterVec,
+    op_seconds: HistogramVec,
+    errors: IntCounterVec,
+    read_bytes: IntCounter,
+    written_bytes: IntCounter,
+}
+
+static COUNTERS: OnceLock<Counters> = OnceLock::new();
+
+fn counters() -> &'static Counters {
+    COUNTERS.get_or_init(|| {
+        let buckets = vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+        Counters {
+            ops: IntCounterVec::new(Opts::new("nfs_ops_total", "NFSv4 operations executed"), &["op"]).unwrap(),
+            op_seconds: HistogramVec::new(HistogramOpts::new("nfs_op_duration_seconds", "NFSv4 operation latency").buckets(buckets), &["op"])
+                .unwrap(),
+            errors: IntCounterVec::new(Opts::new("nfs_errors_total", "NFSv4 operations that failed, by status"), &["status"]).unwrap(),
+            read_bytes: IntCounter::new("nfs_read_bytes_total", "File data bytes returned by READ").unwrap(),
+            written_bytes: IntCounter::new("nfs_written_bytes_total", "File data bytes accepted by WRITE").unwrap(),
+        }
+    })
+}
+
+fn status_name(status: u32) -> String {
+    match Nfs4Status::from_u32(status) {
+        Some(s) => format!("{:?}", s),
+        None => status.to_string(),
+    }
+}
+
+/// Count one NFSv4 operation and how long it took.
+pub fn record_op(opcode: u32, status: u32, elapsed: Duration) {
+    let c = counters();
+    let op = op_name(opcode);
+    c.ops.with_label_values(&[&op]).inc();
+    c.op_seconds.with_label_values(&[&op]).observe(elapsed.as_secs_f64());
+    if status != 0 {
+        c.errors.with_label_values(&[&status_name(status)]).inc();
+    }
+}
+
+pub fn record_read(bytes: usize) {
+    counters().read_bytes.inc_by(bytes as u64);
+}
+
+pub fn record_write(bytes: usize) {
+    counters().written_bytes.inc_by(bytes as u64);
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str, value: usize) {
+    let g = IntGauge::new(name, help).unwrap();
+    g.set(value as i64);
+    register(registry, g);
+}
+
+fn register(registry: &Registry, collector: impl Collector + 'static) {
+    registry.register(Box::new(collector)).expect("metric names are unique");
+}
+
+/// Everything we know about `dispatcher`, in the Prometheus text format.
+pub fn render(dispatcher: &Dispatcher) -> String {
+    let registry = Registry::new();
+    let c = counters();
+    register(&registry, c.ops.clone());
+    register(&registry, c.op_seconds.clone());
+    register(&registry, c.errors.clone());
+    register(&registry, c.read_bytes.clone());
+    register(&registry, c.written_bytes.clone());
+
+    let limits = dispatcher.limits();
+    let (requests, bytes) = limits.in_flight();
+    gauge(&registry, "nfs_connections", "Open client connections", limits.connections());
+    gauge(&registry, "nfs_clients", "Distinct client addresses with a connection open", limits.clients());
+    gauge(&registry, "nfs_requests_in_flight", "Requests executing", requests);
+    gauge(&registry, "nfs_request_bytes_in_flight", "Bytes of requests executing", bytes);
+    gauge(&registry, "nfs_mounts", "MOUNT entries", dispatcher.mount().mounts().len());
+    gauge(&registry, "nfs_locks", "Byte-range locks held over NLM and NFSv4", dispatcher.locks().count());
+    gauge(&registry, "nfs_exports", "Exports being served", dispatcher.exports().exports().len());
+
+    let mut out = Vec::new();
+    TextEncoder::new().encode(&registry.gather(), &mut out).expect("text encoding cannot fail");
+    String::from_utf8(out).expect("text format is UTF-8")
+}
+
+/// Answer scrapes on `listener` until `shutdown`.
+pub async fn serve(listener: TcpListener, dispatcher: Arc<Dispatcher>, shutdown: ShutdownHandle) -> NfsResult<()> {
+    info!("metrics endpoint on http://{}/metrics", listener.local_addr()?);
+    let mut scrapes = JoinSet::new();
+    loop {
+        tokio::select! {
+            biased;
+            _ = shutdown.wait() => break,
+            Some(_) = scrapes.join_next(), if !scrapes.is_empty() => {}
+            accepted = listener.accept() => {
+                let (sock, peer) = accepted?;
+                let dispatcher = dispatcher.clone();
+                scrapes.spawn(async move {
+                    if let Err(e) = answer(sock, &dispatcher).await {
+                        debug!("metrics request from {} failed: {}", peer, e);
+                    }
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+// One HTTP/1.x request per connection; only the request line matters
+async fn answer(mut sock: TcpStream, dispatcher: &Dispatcher) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = sock.read(&mut buf).await?;
+        if n == 0 || head.len() + n > MAX_REQUEST {
+            return Ok(());
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = line.split(|&b| b == b' ');
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", render(dispatcher)),
+        (Some(b"GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "only GET is supported\n".to_string()),
+    };
+    let reply = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    sock.write_all(reply.as_bytes()).await?;
+    sock.shutdown().await
+}
@@ -0,0 +1,164 @@
+//! MOUNT program handler (RFC 1813 appendix I). NFSv3 clients use it to turn
+//! an export path into a root filehandle; it also answers `showmount`.
+//! Versions 1 and 3 are served, but MNT only in version 3 since the older
+//! fixed-size fhandle cannot carry our handles.
+
+use crate::auth::Caller;
+use crate::error::NfsError;
+use crate::export::ExportTable;
+use crate::proto::mount::*;
+use crate::proto::nfs3::NFS3_FHSIZE;
+use crate::rpc::AcceptError;
+use crate::vfs::{FileType, Vfs};
+use crate::xdr::*;
+use bytes::Bytes;
+use num_traits::FromPrimitive;
+use std::collections::BTreeSet;
+use std::io::Result as IoResult;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+pub struct MountService {
+    exports: Arc<ExportTable>,
+    // (client host, mounted path), as reported by DUMP
+    mounts: Mutex<BTreeSet<(String, String)>>,
+}
+
+fn mountstat(e: NfsError) -> u32 {
+    match e {
+        NfsError::PermissionDenied | NfsError::WrongSec => MNT3ERR_ACCES,
+        NfsError::NotFound | NfsError::StaleHandle => MNT3ERR_NOENT,
+        NfsError::NotDir => MNT3ERR_NOTDIR,
+        NfsError::InvalidArgument(_) => MNT3ERR_INVAL,
+        NfsError::NotSupported => MNT3ERR_NOTSUPP,
+        NfsError::Io(_) => MNT3ERR_IO,
+        _ => MNT3ERR_SERVERFAULT,
+    }
+}
+
+impl MountService {
+    pub fn new(exports: Arc<ExportTable>) -> Self {
+        Self { exports, mounts: Mutex::new(BTreeSet::new()) }
+    }
+
+    /// Current (host, path) mount entries.
+    pub fn mounts(&self) -> Vec<(String, String)> {
+        self.mounts.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Drop every entry of `host`, as UMNTALL does; returns how many.
+    pub fn forget(&self, host: &str) -> usize {
+        let mut mounts = self.mounts.lock().unwrap();
+        let before = mounts.len();
+        mounts.retain(|(h, _)| h != host);
+        before - mounts.len()
+    }
+
+    /// Re-add mount entries recorded before a restart.
+    pub fn restore(&self, mounts: &[(String, String)]) {
+        self.mounts.lock().unwrap().extend(mounts.iter().cloned());
+    }
+
+    pub async fn call(&self, vers: u32, proc: u32, mut args: Bytes, caller: &Caller) -> Result<XdrChain, AcceptError> {
+        if vers != 1 && vers != MOUNT_VERSION3 {
+            return Err(AcceptError::ProgMismatch { low: 1, high: MOUNT_VERSION3 });
+        }
+        let proc = MountProc::from_u32(proc).ok_or(AcceptError::ProcUnavail)?;
+        let host = caller.addr.ip().to_string();
+        let mut out = XdrChain::new();
+        let args = &mut args;
+        let o = &mut out;
+        let res = match proc {
+            MountProc::Null => Ok(()),
+            MountProc::Mnt if vers == MOUNT_VERSION3 => self.mnt(args, o, caller, host).await,
+            MountProc::Mnt => return Err(AcceptError::ProcUnavail),
+            MountProc::Dump => {
+                self.dump(o);
+                Ok(())
+            }
+            MountProc::Umnt => self.umnt(args, host),
+            MountProc::Umntall => {
+                self.forget(&host);
+                Ok(())
+            }
+            MountProc::Export => {
+                self.export(o);
+                Ok(())
+            }
+        };
+        res.map_err(|_| AcceptError::GarbageArgs)?;
+        Ok(out)
+    }
+
+    /// Resolve a mount path to a directory handle and the flavors the
+    /// caller may use. The path must be an export or lie below one; the
+    /// longest matching export wins and its client rules apply.
+    pub async fn resolve(&self, path: &str, caller: &Caller) -> Result<(Vec<u8>, Vec<u32>), u32> {
+        if path.len() > MNTPATHLEN {
+            return Err(MNT3ERR_NAMETOOLONG);
+        }
+        let (export, rest) = self.exports.find(path).ok_or(MNT3ERR_NOENT)?;
+        if rest.contains(&"..") {
+            return Err(MNT3ERR_INVAL);
+        }
+        let access = self.exports.authorize(&export, caller).await.map_err(mountstat)?;
+        let mut fh = export.root_fh().await.map_err(mountstat)?;
+        for name in rest {
+            fh = self.exports.lookup(&fh, name).await.map_err(mountstat)?;
+        }
+        let attr = self.exports.getattr(&fh).await.map_err(mountstat)?;
+        if attr.ftype != FileType::Directory {
+            return Err(MNT3ERR_NOTDIR);
+        }
+        Ok((fh, access.flavors))
+    }
+
+    // dirpath -> mountres3
+    async fn mnt(&self, args: &mut Bytes, out: &mut XdrChain, caller: &Caller, host: String) -> IoResult<()> {
+        let path = String::xdr_decode(args)?;
+        match self.resolve(&path, caller).await {
+            Ok((fh, flavors)) if fh.len() <= NFS3_FHSIZE => {
+                info!("{} mounted {}", host, path);
+                self.mounts.lock().unwrap().insert((host, path));
+                out.put(&MNT3_OK);
+                out.put(&fh);
+                out.put(&flavors);
+            }
+            Ok(_) => out.put(&MNT3ERR_SERVERFAULT),
+            Err(stat) => out.put(&stat),
+        }
+        Ok(())
+    }
+
+    fn umnt(&self, args: &mut Bytes, host: String) -> IoResult<()> {
+        let path = String::xdr_decode(args)?;
+        self.mounts.lock().unwrap().remove(&(host, path));
+        Ok(())
+    }
+
+    // mountlist: optional-data linked list of (hostname, directory)
+    fn dump(&self, out: &mut XdrChain) {
+        for (host, path) in self.mounts.lock().unwrap().iter() {
+            out.put(&true);
+            out.put(host);
+            out.put(path);
+        }
+        out.put(&false);
+    }
+
+    // exports: (ex_dir, ex_groups); an empty group list means everyone
+    fn export(&self, out: &mut XdrChain) {
+        for export in self.exports.exports() {
+            out.put(&true);
+            out.put(&export.path);
+            if !export.hosts.iter().any(|h| h == "*") {
+                for host in &export.hosts {
+                    out.put(&true);
+                    out.put(host);
+                }
+            }
+            out.put(&false);
+        }
+        out.put(&false);
+    }
+}
@@ -0,0 +1,879 @@
+//! NFSv3 program handler (RFC 1813). Shares the `Vfs` backend and its
+//! filehandles with NFSv4, so a handle obtained over one version is valid
+//! over the other.
+
+use crate::audit::{describe_setattr, AuditAction, AuditEvent, AuditLog, Target};
+use crate::auth::Caller;
+use crate::error::{Nfs3Status, NfsError, NfsResult};
+use crate::export::{Access, ExportTable};
+use crate::perm;
+use crate::proto::nfs3::*;
+use crate::rpc::AcceptError;
+use crate::vfs::{CreateKind, DirEntry, FileAttr, FileType, SetAttr, SetTime, Vfs};
+use crate::xdr::*;
+use bytes::Bytes;
+use num_traits::FromPrimitive;
+use std::io::Result as IoResult;
+use std::sync::Arc;
+
+/// Largest READ/WRITE transfer we advertise in FSINFO
+pub const NFS3_MAX_IO: u32 = 1024 * 1024;
+const NFS3_OK: u32 = 0;
+
+pub struct Nfs3Service {
+    exports: Arc<ExportTable>,
+    vfs: Arc<dyn Vfs>,
+    write_verf: u64,
+    audit: Option<Arc<AuditLog>>,
+}
+
+fn status(e: NfsError) -> u32 {
+    Nfs3Status::from(e) as u32
+}
+
+fn ftype3(t: FileType) -> u32 {
+    match t {
+        FileType::Regular => NF3REG,
+        FileType::Directory => NF3DIR,
+        FileType::BlockDevice => NF3BLK,
+        FileType::CharDevice => NF3CHR,
+        FileType::Symlink => NF3LNK,
+        FileType::Socket => NF3SOCK,
+        FileType::Fifo => NF3FIFO,
+    }
+}
+
+// nfstime3: we keep whole seconds
+fn put_time(out: &mut XdrChain, secs: u64) {
+    out.put(&(secs as u32));
+    out.put(&0u32);
+}
+
+fn put_fattr3(out: &mut XdrChain, a: &FileAttr) {
+    out.put(&ftype3(a.ftype));
+    out.put(&a.mode);
+    out.put(&a.nlink);
+    out.put(&a.uid);
+    out.put(&a.gid);
+    out.put(&a.size);
+    out.put(&a.used);
+    out.put(&a.rdev.0);
+    out.put(&a.rdev.1);
+    out.put(&a.fsid);
+    out.put(&a.fileid);
+    put_time(out, a.atime);
+    put_time(out, a.mtime);
+    put_time(out, a.ctime);
+}
+
+fn put_post_op_attr(out: &mut XdrChain, a: Option<&FileAttr>) {
+    out.put(&a.is_some());
+    if let Some(a) = a {
+        put_fattr3(out, a);
+    }
+}
+
+fn put_post_op_fh(out: &mut XdrChain, fh: Option<&Vec<u8>>) {
+    out.put(&fh.is_some());
+    if let Some(fh) = fh {
+        out.put(fh);
+    }
+}
+
+fn put_wcc(out: &mut XdrChain, pre: Option<&FileAttr>, post: Option<&FileAttr>) {
+    out.put(&pre.is_some());
+    if let Some(a) = pre {
+        out.put(&a.size);
+        put_time(out, a.mtime);
+        put_time(out, a.ctime);
+    }
+    put_post_op_attr(out, post);
+}
+
+fn decode_fh(args: &mut Bytes) -> IoResult<Vec<u8>> {
+    Vec::<u8>::xdr_decode(args)
+}
+
+// filename3 as raw bytes; converted with `name_str` so bad names get a status
+fn decode_diropargs(args: &mut Bytes) -> IoResult<(Vec<u8>, Vec<u8>)> {
+    Ok((decode_fh(args)?, Vec::<u8>::xdr_decode(args)?))
+}
+
+fn name_str(raw: &[u8]) -> NfsResult<&str> {
+    std::str::from_utf8(raw).map_err(|_| NfsError::InvalidArgument("file name is not UTF-8".into()))
+}
+
+// A name that may be created or removed: not empty, not a path, not . or ..
+fn component(raw: &[u8]) -> NfsResult<&str> {
+    let name = name_str(raw)?;
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(NfsError::InvalidArgument(format!("invalid file name {:?}", name)));
+    }
+    Ok(name)
+}
+
+fn decode_time(args: &mut Bytes) -> IoResult<Option<SetTime>> {
+    match u32::xdr_decode(args)? {
+        SET_TO_SERVER_TIME => Ok(Some(SetTime::ServerNow)),
+        SET_TO_CLIENT_TIME => {
+            let secs = u32::xdr_decode(args)?;
+            let _nsecs = u32::xdr_decode(args)?;
+            Ok(Some(SetTime::Client(secs as u64)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn decode_sattr3(args: &mut Bytes) -> IoResult<SetAttr> {
+    let mut set = SetAttr::default();
+    if bool::xdr_decode(args)? {
+        set.mode = Some(u32::xdr_decode(args)?);
+    }
+    if bool::xdr_decode(args)? {
+        set.uid = Some(u32::xdr_decode(args)?);
+    }
+    if bool::xdr_decode(args)? {
+        set.gid = Some(u32::xdr_decode(args)?);
+    }
+    if bool::xdr_decode(args)? {
+        set.size = Some(u64::xdr_decode(args)?);
+    }
+    set.atime = decode_time(args)?;
+    set.mtime = decode_time(args)?;
+    Ok(set)
+}
+
+fn verifier8(args: &mut Bytes) -> IoResult<[u8; 8]> {
+    Ok(u64::xdr_decode(args)?.to_be_bytes())
+}
+
+// Procedures refused with ROFS on read-only exports
+fn modifies(proc: Nfs3Proc) -> bool {
+    use Nfs3Proc::*;
+    matches!(proc, Setattr | Write | Create | Mkdir | Symlink | Mknod | Remove | Rmdir | Rename | Link)
+}
+
+// A failed result for `proc`: the status, then every optional attribute of
+// its resfail arm marked absent
+fn put_fail(out: &mut XdrChain, proc: Nfs3Proc, stat: u32) {
+    use Nfs3Proc::*;
+    out.put(&stat);
+    let absent = match proc {
+        Null | Getattr => 0,
+        Lookup | Access | Readlink | Read | Readdir | Readdirplus | Fsstat | Fsinfo | Pathconf => 1,
+        Setattr | Write | Create | Mkdir | Symlink | Mknod | Remove | Rmdir | Commit => 2,
+        Link => 3,
+        Rename => 4,
+    };
+    for _ in 0..absent {
+        out.put(&false);
+    }
+}
+
+impl Nfs3Service {
+    pub fn new(exports: Arc<ExportTable>, write_verf: u64, audit: Option<Arc<AuditLog>>) -> Self {
+        Self { vfs: exports.clone(), exports, write_verf, audit }
+    }
+
+    /// Reply asking the client to retry later, for when the server is busy.
+    pub fn busy(&self, proc: u32) -> Result<XdrChain, AcceptError> {
+        let proc = Nfs3Proc::from_u32(proc).ok_or(AcceptError::ProcUnavail)?;
+        let mut out = XdrChain::new();
+        put_fail(&mut out, proc, Nfs3Status::Jukebox as u32);
+        Ok(out)
+    }
+
+    pub async fn call(&self, proc: u32, mut args: Bytes, caller: &Caller) -> Result<XdrChain, AcceptError> {
+        let proc = Nfs3Proc::from_u32(proc).ok_or(AcceptError::ProcUnavail)?;
+        let mut out = XdrChain::new();
+        if proc == Nfs3Proc::Null {
+            return Ok(out);
+        }
+        // Every other procedure's arguments start with the handle they act
+        // on; the export it belongs to decides whether the caller may proceed
+        let fh = decode_fh(&mut args.clone()).map_err(|_| AcceptError::GarbageArgs)?;
+        let access = match self.exports.authorize_fh(&fh, caller).await {
+            Ok(access) => access,
+            Err(e) => {
+                put_fail(&mut out, proc, status(e));
+                return Ok(out);
+            }
+        };
+        if access.read_only && modifies(proc) {
+            put_fail(&mut out, proc, Nfs3Status::Rofs as u32);
+            return Ok(out);
+        }
+        let args = &mut args;
+        let o = &mut out;
+        let a = &access;
+        let c = caller;
+        let res = match proc {
+            Nfs3Proc::Null => Ok(()),
+            Nfs3Proc::Getattr => self.getattr(args, o).await,
+            Nfs3Proc::Setattr => self.setattr(args, o, a, c).await,
+            Nfs3Proc::Lookup => self.lookup(args, o, a).await,
+            Nfs3Proc::Access => self.access(args, o, a).await,
+            Nfs3Proc::Readlink => self.readlink(args, o).await,
+            Nfs3Proc::Read => self.read(args, o, a).await,
+            Nfs3Proc::Write => self.write(args, o, a, c).await,
+            Nfs3Proc::Create => self.create(args, o, a, c).await,
+            Nfs3Proc::Mkdir => self.mkdir(args, o, a, c).await,
+            Nfs3Proc::Symlink => self.symlink(args, o, a, c).await,
+            Nfs3Proc::Mknod => self.mknod(args, o, a, c).await,
+            Nfs3Proc::Remove => self.remove(args, o, false, a, c).await,
+            Nfs3Proc::Rmdir => self.remove(args, o, true, a, c).await,
+            Nfs3Proc::Rename => self.rename(args, o, a, c).await,
+            Nfs3Proc::Link => self.link(args, o, a, c).await,
+            Nfs3Proc::Readdir => self.readdir(args, o, false, a).await,
+            Nfs3Proc::Readdirplus => self.readdir(args, o, true, a).await,
+            Nfs3Proc::Fsstat => self.fsstat(args, o).await,
+            Nfs3Proc::Fsinfo => self.fsinfo(args, o).await,
+            Nfs3Proc::Pathconf => self.pathconf(args, o).await,
+            Nfs3Proc::Commit => self.commit(args, o).await,
+        };
+        // Arguments that do not decode are GARBAGE_ARGS, not an NFS status
+        res.map_err(|_| AcceptError::GarbageArgs)?;
+        Ok(out)
+    }
+
+    async fn attr(&self, fh: &[u8]) -> Option<FileAttr> {
+        self.vfs.getattr(fh).await.ok()
+    }
+
+    async fn audit<T>(&self, caller: &Caller, event: AuditEvent<'_>, res: &NfsResult<T>) {
+        if let Some(audit) = &self.audit {
+            audit.record(caller, event, res).await;
+        }
+    }
+
+    // Audit a create and remember the new object's name
+    async fn audit_create(&self, caller: &Caller, action: AuditAction, dir: &[u8], name: &[u8], res: &NfsResult<Vec<u8>>) {
+        let name = String::from_utf8_lossy(name);
+        if let (Some(audit), Ok(fh)) = (&self.audit, res) {
+            audit.learn(dir, &name, fh);
+        }
+        let event = AuditEvent::new(action, Target::Entry(dir, &name));
+        self.audit(caller, event, res).await;
+    }
+
+    async fn getattr(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        match self.vfs.getattr(&fh).await {
+            Ok(a) => {
+                out.put(&NFS3_OK);
+                put_fattr3(out, &a);
+            }
+            Err(e) => out.put(&status(e)),
+        }
+        Ok(())
+    }
+
+    async fn setattr(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access, caller: &Caller) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        let set = decode_sattr3(args)?;
+        // sattrguard3: only apply if ctime still matches
+        let guard = if bool::xdr_decode(args)? {
+            let secs = u32::xdr_decode(args)?;
+            let _nsecs = u32::xdr_decode(args)?;
+            Some(secs as u64)
+        } else {
+            None
+        };
+        let pre = self.attr(&fh).await;
+        if let (Some(guard), Some(pre)) = (guard, &pre) {
+            if pre.ctime != guard {
+                out.put(&(Nfs3Status::NotSync as u32));
+                put_wcc(out, Some(pre), Some(pre));
+                return Ok(());
+            }
+        }
+        let res = match &pre {
+            Some(pre) => match perm::check_setattr(pre, &set, access) {
+                Ok(()) => self.vfs.setattr(&fh, &set).await,
+                Err(e) => Err(e),
+            },
+            None => self.vfs.setattr(&fh, &set).await,
+        };
+        self.audit(caller, AuditEvent::new(AuditAction::Setattr, Target::Handle(&fh)).detail(describe_setattr(&set)), &res).await;
+        match res {
+            Ok(post) => {
+                out.put(&NFS3_OK);
+                put_wcc(out, pre.as_ref(), Some(&post));
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_wcc(out, pre.as_ref(), self.attr(&fh).await.as_ref());
+            }
+        }
+        Ok(())
+    }
+
+    async fn lookup_name(&self, dir: &[u8], name: &[u8], access: &Access) -> NfsResult<Vec<u8>> {
+        let name = name_str(name)?;
+        let dir_attr = self.vfs.getattr(dir).await?;
+        // a non-directory is NOTDIR, which the lookup reports
+        if dir_attr.ftype == FileType::Directory {
+            perm::check(&dir_attr, access, ACCESS3_LOOKUP)?;
+        }
+        match name {
+            "." => {
+                self.vfs.getattr(dir).await?;
+                Ok(dir.to_vec())
+            }
+            ".." => self.vfs.lookup_parent(dir).await,
+            name => self.vfs.lookup(dir, name).await,
+        }
+    }
+
+    async fn lookup(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access) -> IoResult<()> {
+        let (dir, name) = decode_diropargs(args)?;
+        match self.lookup_name(&dir, &name, access).await {
+            Ok(fh) => {
+                if let Some(audit) = &self.audit {
+                    audit.learn(&dir, &String::from_utf8_lossy(&name), &fh);
+                }
+                out.put(&NFS3_OK);
+                out.put(&fh);
+                put_post_op_attr(out, self.attr(&fh).await.as_ref());
+                put_post_op_attr(out, self.attr(&dir).await.as_ref());
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_post_op_attr(out, self.attr(&dir).await.as_ref());
+            }
+        }
+        Ok(())
+    }
+
+    async fn access(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        let requested = u32::xdr_decode(args)?;
+        match self.vfs.getattr(&fh).await {
+            Ok(a) => {
+                out.put(&NFS3_OK);
+                put_post_op_attr(out, Some(&a));
+                out.put(&(requested & perm::granted(&a, access)));
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_post_op_attr(out, None);
+            }
+        }
+        Ok(())
+    }
+
+    async fn readlink(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        let post = self.attr(&fh).await;
+        match self.vfs.readlink(&fh).await {
+            Ok(target) => {
+                out.put(&NFS3_OK);
+                put_post_op_attr(out, post.as_ref());
+                out.put(target.as_bytes());
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_post_op_attr(out, post.as_ref());
+            }
+        }
+        Ok(())
+    }
+
+    async fn read(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        let offset = u64::xdr_decode(args)?;
+        let count = u32::xdr_decode(args)?.min(NFS3_MAX_IO);
+        let res = match self.vfs.getattr(&fh).await {
+            Ok(attr) => match perm::check_io(&attr, access, false) {
+                Ok(()) => self.vfs.read(&fh, offset, count).await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok((data, eof)) => {
+                out.put(&NFS3_OK);
+                put_post_op_attr(out, self.attr(&fh).await.as_ref());
+                #[cfg(feature = "metrics")]
+                crate::metrics::record_read(data.len());
+                out.put(&(data.len() as u32));
+                out.put(&eof);
+                // file data goes out as its own segment
+                out.put_opaque(data);
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_post_op_attr(out, self.attr(&fh).await.as_ref());
+            }
+        }
+        Ok(())
+    }
+
+    async fn write(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access, caller: &Caller) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        let offset = u64::xdr_decode(args)?;
+        let count = u32::xdr_decode(args)?;
+        let stable = u32::xdr_decode(args)?;
+        let mut data = Bytes::xdr_decode(args)?;
+        data.truncate(count as usize);
+        let pre = self.attr(&fh).await;
+        let written = match &pre {
+            Some(pre) => match perm::check_io(pre, access, true) {
+                Ok(()) => self.vfs.write(&fh, offset, data).await,
+                Err(e) => Err(e),
+            },
+            None => self.vfs.write(&fh, offset, data).await,
+        };
+        let res = match written {
+            Ok(n) if stable != UNSTABLE => self.vfs.commit(&fh, offset, n as u64).await.map(|_| (n, FILE_SYNC)),
+            Ok(n) => Ok((n, UNSTABLE)),
+            Err(e) => Err(e),
+        };
+        let range = format!("offset={} count={}", offset, count);
+        self.audit(caller, AuditEvent::new(AuditAction::Write, Target::Handle(&fh)).detail(range), &res).await;
+        let post = self.attr(&fh).await;
+        match res {
+            Ok((n, committed)) => {
+                #[cfg(feature = "metrics")]
+                crate::metrics::record_write(n as usize);
+                out.put(&NFS3_OK);
+                put_wcc(out, pre.as_ref(), post.as_ref());
+                out.put(&n);
+                out.put(&committed);
+                out.put(&self.write_verf);
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_wcc(out, pre.as_ref(), post.as_ref());
+            }
+        }
+        Ok(())
+    }
+
+    // Shared reply for CREATE, MKDIR, SYMLINK and MKNOD
+    async fn reply_create(&self, out: &mut XdrChain, dir: &[u8], pre: Option<FileAttr>, res: NfsResult<Vec<u8>>) {
+        let post_dir = self.attr(dir).await;
+        match res {
+            Ok(fh) => {
+                out.put(&NFS3_OK);
+                let attr = self.attr(&fh).await;
+                put_post_op_fh(out, Some(&fh));
+                put_post_op_attr(out, attr.as_ref());
+                put_wcc(out, pre.as_ref(), post_dir.as_ref());
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_wcc(out, pre.as_ref(), post_dir.as_ref());
+            }
+        }
+    }
+
+    async fn create(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access, caller: &Caller) -> IoResult<()> {
+        let (dir, name) = decode_diropargs(args)?;
+        let how = u32::xdr_decode(args)?;
+        let (set, verf) = match how {
+            UNCHECKED | GUARDED => (decode_sattr3(args)?, None),
+            EXCLUSIVE => (SetAttr::default(), Some(verifier8(args)?)),
+            _ => return Err(std::io::ErrorKind::InvalidData.into()),
+        };
+        let _locked = self.exports.lock_dirs(&[&dir]).await;
+        let pre = self.attr(&dir).await;
+        let res = match self.may_add(&dir, &set, access).await {
+            Ok(()) => self.create_file(&dir, &name, how, set, verf, access).await,
+            Err(e) => Err(e),
+        };
+        self.audit_create(caller, AuditAction::Create, &dir, &name, &res).await;
+        self.reply_create(out, &dir, pre, res).await;
+        Ok(())
+    }
+
+    // Whether `access` may add an entry to `dir` owned as `set` asks. A
+    // non-directory is left to the VFS, which reports NOTDIR.
+    async fn may_add(&self, dir: &[u8], set: &SetAttr, access: &Access) -> NfsResult<()> {
+        let attr = self.vfs.getattr(dir).await?;
+        if attr.ftype == FileType::Directory {
+            perm::check(&attr, access, ACCESS3_EXTEND | ACCESS3_LOOKUP)?;
+        }
+        perm::check_new_owner(set, access)
+    }
+
+    async fn create_file(
+        &self,
+        dir: &[u8],
+        name: &[u8],
+        how: u32,
+        attrs: SetAttr,
+        verf: Option<[u8; 8]>,
+        access: &Access,
+    ) -> NfsResult<Vec<u8>> {
+        let name = component(name)?;
+        // Exclusive create keeps the verifier in atime/mtime, like other
+        // servers, so a retransmitted CREATE can be recognised
+        let verf_times = verf.map(|v| {
+            let hi = u32::from_be_bytes(v[..4].try_into().unwrap()) as u64;
+            let lo = u32::from_be_bytes(v[4..].try_into().unwrap()) as u64;
+            (hi, lo)
+        });
+        let set = access.owned(attrs.clone());
+        let set = match verf_times {
+            Some((hi, lo)) => SetAttr {
+                atime: Some(SetTime::Client(hi)),
+                mtime: Some(SetTime::Client(lo)),
+                ..set
+            },
+            None => set,
+        };
+        match self.vfs.create(dir, name, CreateKind::Regular, &set).await {
+            Err(NfsError::AlreadyExists) if how != GUARDED => {
+                let fh = self.vfs.lookup(dir, name).await?;
+                let existing = self.vfs.getattr(&fh).await?;
+                match verf_times {
+                    Some((hi, lo)) if existing.atime == hi && existing.mtime == lo => Ok(fh),
+                    Some(_) => Err(NfsError::AlreadyExists),
+                    None if existing.ftype != FileType::Regular => Err(NfsError::AlreadyExists),
+                    None => {
+                        // UNCHECKED on an existing file applies the attributes
+                        // the client sent, as a SETATTR would
+                        perm::check_setattr(&existing, &attrs, access)?;
+                        self.vfs.setattr(&fh, &attrs).await?;
+                        Ok(fh)
+                    }
+                }
+            }
+            res => res,
+        }
+    }
+
+    async fn mkdir(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access, caller: &Caller) -> IoResult<()> {
+        let (dir, name) = decode_diropargs(args)?;
+        let set = access.owned(decode_sattr3(args)?);
+        let _locked = self.exports.lock_dirs(&[&dir]).await;
+        let pre = self.attr(&dir).await;
+        let res = match (component(&name), self.may_add(&dir, &set, access).await) {
+            (Ok(name), Ok(())) => self.vfs.create(&dir, name, CreateKind::Directory, &set).await,
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+        self.audit_create(caller, AuditAction::Mkdir, &dir, &name, &res).await;
+        self.reply_create(out, &dir, pre, res).await;
+        Ok(())
+    }
+
+    async fn symlink(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access, caller: &Caller) -> IoResult<()> {
+        let (dir, name) = decode_diropargs(args)?;
+        let set = access.owned(decode_sattr3(args)?);
+        let target = Vec::<u8>::xdr_decode(args)?;
+        let _locked = self.exports.lock_dirs(&[&dir]).await;
+        let pre = self.attr(&dir).await;
+        let res = match (component(&name), name_str(&target), self.may_add(&dir, &set, access).await) {
+            (Ok(name), Ok(target), Ok(())) => {
+                self.vfs.create(&dir, name, CreateKind::Symlink(target.to_string()), &set).await
+            }
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => Err(e),
+        };
+        self.audit_create(caller, AuditAction::Symlink, &dir, &name, &res).await;
+        self.reply_create(out, &dir, pre, res).await;
+        Ok(())
+    }
+
+    async fn mknod(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access, caller: &Caller) -> IoResult<()> {
+        let (dir, name) = decode_diropargs(args)?;
+        let ftype = u32::xdr_decode(args)?;
+        let kind = match ftype {
+            NF3CHR | NF3BLK => {
+                let set = decode_sattr3(args)?;
+                let major = u32::xdr_decode(args)?;
+                let minor = u32::xdr_decode(args)?;
+                let kind = if ftype == NF3CHR {
+                    CreateKind::CharDevice(major, minor)
+                } else {
+                    CreateKind::BlockDevice(major, minor)
+                };
+                Some((kind, set))
+            }
+            NF3SOCK => Some((CreateKind::Socket, decode_sattr3(args)?)),
+            NF3FIFO => Some((CreateKind::Fifo, decode_sattr3(args)?)),
+            _ => None,
+        };
+        let _locked = self.exports.lock_dirs(&[&dir]).await;
+        let pre = self.attr(&dir).await;
+        let Some((kind, set)) = kind else {
+            out.put(&(Nfs3Status::Badtype as u32));
+            put_wcc(out, pre.as_ref(), pre.as_ref());
+            return Ok(());
+        };
+        let set = access.owned(set);
+        let res = match (component(&name), self.may_add(&dir, &set, access).await) {
+            (Ok(name), Ok(())) => self.vfs.create(&dir, name, kind, &set).await,
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+        self.audit_create(caller, AuditAction::Mknod, &dir, &name, &res).await;
+        self.reply_create(out, &dir, pre, res).await;
+        Ok(())
+    }
+
+    async fn remove_name(&self, dir: &[u8], name: &[u8], want_dir: bool, access: &Access) -> NfsResult<()> {
+        let name = component(name)?;
+        let fh = self.vfs.lookup(dir, name).await?;
+        let victim = self.vfs.getattr(&fh).await?;
+        match (want_dir, victim.ftype == FileType::Directory) {
+            (true, false) => Err(NfsError::NotDir),
+            (false, true) => Err(NfsError::IsDir),
+            _ => {
+                perm::check_delete(&self.vfs.getattr(dir).await?, &victim, access)?;
+                self.vfs.remove(dir, name).await
+            }
+        }
+    }
+
+    async fn remove(&self, args: &mut Bytes, out: &mut XdrChain, want_dir: bool, access: &Access, caller: &Caller) -> IoResult<()> {
+        let (dir, name) = decode_diropargs(args)?;
+        let _locked = self.exports.lock_dirs(&[&dir]).await;
+        let pre = self.attr(&dir).await;
+        let res = self.remove_name(&dir, &name, want_dir, access).await;
+        let action = if want_dir { AuditAction::Rmdir } else { AuditAction::Remove };
+        self.audit(caller, AuditEvent::new(action, Target::Entry(&dir, &String::from_utf8_lossy(&name))), &res).await;
+        let post = self.attr(&dir).await;
+        out.put(&match res {
+            Ok(()) => NFS3_OK,
+            Err(e) => status(e),
+        });
+        put_wcc(out, pre.as_ref(), post.as_ref());
+        Ok(())
+    }
+
+    async fn rename_name(&self, from_dir: &[u8], from: &[u8], to_dir: &[u8], to: &[u8], access: &Access) -> NfsResult<()> {
+        let (from, to) = (component(from)?, component(to)?);
+        let src = self.vfs.getattr(&self.vfs.lookup(from_dir, from).await?).await?;
+        let dst = match self.vfs.lookup(to_dir, to).await {
+            Ok(fh) => Some(self.vfs.getattr(&fh).await?),
+            Err(NfsError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        let (from_attr, to_attr) = (self.vfs.getattr(from_dir).await?, self.vfs.getattr(to_dir).await?);
+        perm::check_rename(&from_attr, &src, &to_attr, dst.as_ref(), access)?;
+        self.vfs.rename(from_dir, from, to_dir, to).await
+    }
+
+    async fn rename(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access, caller: &Caller) -> IoResult<()> {
+        let (from_dir, from) = decode_diropargs(args)?;
+        let (to_dir, to) = decode_diropargs(args)?;
+        let _locked = self.exports.lock_dirs(&[&from_dir, &to_dir]).await;
+        let pre_from = self.attr(&from_dir).await;
+        let pre_to = self.attr(&to_dir).await;
+        let res = self.rename_name(&from_dir, &from, &to_dir, &to, access).await;
+        if let Some(audit) = &self.audit {
+            let (from, to) = (String::from_utf8_lossy(&from), String::from_utf8_lossy(&to));
+            let event = AuditEvent::new(AuditAction::Rename, Target::Entry(&from_dir, &from)).to(Target::Entry(&to_dir, &to));
+            audit.record(caller, event, &res).await;
+            // the object and whatever is below it now live under the new name
+            if let (Ok(()), Ok(fh)) = (&res, self.vfs.lookup(&to_dir, &to).await) {
+                audit.learn(&to_dir, &to, &fh);
+            }
+        }
+        out.put(&match res {
+            Ok(()) => NFS3_OK,
+            Err(e) => status(e),
+        });
+        put_wcc(out, pre_from.as_ref(), self.attr(&from_dir).await.as_ref());
+        put_wcc(out, pre_to.as_ref(), self.attr(&to_dir).await.as_ref());
+        Ok(())
+    }
+
+    async fn link(&self, args: &mut Bytes, out: &mut XdrChain, access: &Access, caller: &Caller) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        let (dir, name) = decode_diropargs(args)?;
+        let _locked = self.exports.lock_dirs(&[&dir]).await;
+        let pre = self.attr(&dir).await;
+        let res = match (component(&name), self.may_add(&dir, &SetAttr::default(), access).await) {
+            (Ok(name), Ok(())) => self.vfs.link(&fh, &dir, name).await,
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+        let new_name = String::from_utf8_lossy(&name);
+        let event = AuditEvent::new(AuditAction::Link, Target::Handle(&fh)).to(Target::Entry(&dir, &new_name));
+        self.audit(caller, event, &res).await;
+        out.put(&match res {
+            Ok(()) => NFS3_OK,
+            Err(e) => status(e),
+        });
+        put_post_op_attr(out, self.attr(&fh).await.as_ref());
+        put_wcc(out, pre.as_ref(), self.attr(&dir).await.as_ref());
+        Ok(())
+    }
+
+    async fn readdir(&self, args: &mut Bytes, out: &mut XdrChain, plus: bool, access: &Access) -> IoResult<()> {
+        let dir = decode_fh(args)?;
+        let cookie = u64::xdr_decode(args)?;
+        let _cookieverf = u64::xdr_decode(args)?;
+        // READDIR has one limit; READDIRPLUS limits the names and the whole reply
+        let (dircount, maxcount) = if plus {
+            (u32::xdr_decode(args)? as usize, u32::xdr_decode(args)? as usize)
+        } else {
+            let count = u32::xdr_decode(args)? as usize;
+            (count, count)
+        };
+        let dir_attr = match self.vfs.getattr(&dir).await {
+            Ok(a) => a,
+            Err(e) => {
+                out.put(&status(e));
+                put_post_op_attr(out, None);
+                return Ok(());
+            }
+        };
+        if dir_attr.ftype == FileType::Directory {
+            if let Err(e) = perm::check(&dir_attr, access, ACCESS3_READ) {
+                out.put(&status(e));
+                put_post_op_attr(out, Some(&dir_attr));
+                return Ok(());
+            }
+        }
+        match self.collect_entries(&dir, cookie, dircount, maxcount, plus).await {
+            Ok((entries, eof)) => {
+                out.put(&NFS3_OK);
+                put_post_op_attr(out, Some(&dir_attr));
+                // cookies are stable, so the verifier is informational only
+                out.put(&dir_attr.changeid);
+                for e in &entries {
+                    out.put(&true);
+                    out.put(&e.attr.as_ref().map(|a| a.fileid).unwrap_or(0));
+                    out.put(e.name.as_bytes());
+                    out.put(&e.cookie);
+                    if plus {
+                        if let Some(audit) = &self.audit {
+                            audit.learn(&dir, &e.name, &e.fh);
+                        }
+                        put_post_op_attr(out, e.attr.as_ref());
+                        put_post_op_fh(out, Some(&e.fh));
+                    }
+                }
+                out.put(&false);
+                out.put(&eof);
+            }
+            Err(st) => {
+                out.put(&(st as u32));
+                put_post_op_attr(out, Some(&dir_attr));
+            }
+        }
+        Ok(())
+    }
+
+    // Gather entries after `cookie` that fit the client's byte budgets
+    async fn collect_entries(
+        &self,
+        dir: &[u8],
+        mut cookie: u64,
+        dircount: usize,
+        maxcount: usize,
+        plus: bool,
+    ) -> Result<(Vec<DirEntry>, bool), Nfs3Status> {
+        // status, dir attributes, verifier and list terminators
+        let mut reply_size = 4 + 4 + 84 + 8 + 4 + 4;
+        let mut dir_size = 0;
+        let mut entries = Vec::new();
+        loop {
+            let page = self.vfs.readdir(dir, cookie, 64).await?;
+            for e in page.entries {
+                let name_part = 4 + 8 + opaque_size(e.name.len()) + 8;
+                let plus_part = if plus { 4 + 84 + 4 + opaque_size(e.fh.len()) } else { 0 };
+                if reply_size + name_part + plus_part > maxcount || (plus && dir_size + name_part > dircount) {
+                    if entries.is_empty() {
+                        return Err(Nfs3Status::Toosmall);
+                    }
+                    return Ok((entries, false));
+                }
+                reply_size += name_part + plus_part;
+                dir_size += name_part;
+                cookie = e.cookie;
+                entries.push(e);
+            }
+            if page.eof {
+                return Ok((entries, true));
+            }
+        }
+    }
+
+    async fn fsstat(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        let post = self.attr(&fh).await;
+        match self.vfs.fsstat(&fh).await {
+            Ok(st) => {
+                out.put(&NFS3_OK);
+                put_post_op_attr(out, post.as_ref());
+                out.put(&st.total_bytes);
+                out.put(&st.free_bytes);
+                out.put(&st.avail_bytes);
+                out.put(&st.total_files);
+                out.put(&st.free_files);
+                out.put(&st.avail_files);
+                out.put(&0u32); // invarsec
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_post_op_attr(out, post.as_ref());
+            }
+        }
+        Ok(())
+    }
+
+    async fn fsinfo(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        match self.vfs.getattr(&fh).await {
+            Ok(a) => {
+                out.put(&NFS3_OK);
+                put_post_op_attr(out, Some(&a));
+                for v in [NFS3_MAX_IO, NFS3_MAX_IO, 4096, NFS3_MAX_IO, NFS3_MAX_IO, 4096, 64 * 1024] {
+                    out.put(&v); // rtmax rtpref rtmult wtmax wtpref wtmult dtpref
+                }
+                out.put(&(i64::MAX as u64)); // maxfilesize
+                put_time(out, 1); // time_delta: whole seconds
+                out.put(&(FSF3_LINK | FSF3_SYMLINK | FSF3_HOMOGENEOUS | FSF3_CANSETTIME));
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_post_op_attr(out, None);
+            }
+        }
+        Ok(())
+    }
+
+    async fn pathconf(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        match self.vfs.getattr(&fh).await {
+            Ok(a) => {
+                out.put(&NFS3_OK);
+                put_post_op_attr(out, Some(&a));
+                out.put(&u32::MAX); // linkmax
+                out.put(&crate::constants::NFS4_MAXNAMLEN);
+                out.put(&true); // no_trunc
+                out.put(&true); // chown_restricted
+                out.put(&false); // case_insensitive
+                out.put(&true); // case_preserving
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_post_op_attr(out, None);
+            }
+        }
+        Ok(())
+    }
+
+    async fn commit(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        let fh = decode_fh(args)?;
+        let offset = u64::xdr_decode(args)?;
+        let count = u32::xdr_decode(args)?;
+        let pre = self.attr(&fh).await;
+        let res = self.vfs.commit(&fh, offset, count as u64).await;
+        let post = self.attr(&fh).await;
+        match res {
+            Ok(()) => {
+                out.put(&NFS3_OK);
+                put_wcc(out, pre.as_ref(), post.as_ref());
+                out.put(&self.write_verf);
+            }
+            Err(e) => {
+                out.put(&status(e));
+                put_wcc(out, pre.as_ref(), post.as_ref());
+            }
+        }
+        Ok(())
+    }
+}
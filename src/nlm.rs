@@ -0,0 +1,154 @@
+//! NLM v4 program handler: byte-range locking for NFSv3 clients. Locks live
+//! in the shared `LockManager`, so they conflict with NFSv4 LOCK.
+//!
+//! Blocking requests are answered with DENIED rather than BLOCKED, since we
+//! do not make GRANTED callbacks; clients fall back to retrying. The _MSG/_RES
+//! asynchronous procedures and DOS share reservations are not supported.
+
+use crate::auth::Caller;
+use crate::error::NfsError;
+use crate::export::ExportTable;
+use crate::lock::{ByteLock, LockManager, LockOwner};
+use crate::proto::nlm::*;
+use crate::rpc::AcceptError;
+use crate::vfs::Vfs;
+use crate::xdr::*;
+use bytes::Bytes;
+use num_traits::FromPrimitive;
+use std::io::Result as IoResult;
+use std::sync::Arc;
+use tracing::info;
+
+pub struct NlmService {
+    exports: Arc<ExportTable>,
+    locks: Arc<LockManager>,
+}
+
+fn owner_of(lock: &Nlm4Lock) -> LockOwner {
+    LockOwner { host: lock.caller_name.clone(), id: lock.oh.clone(), svid: lock.svid }
+}
+
+fn holder(l: &ByteLock) -> Nlm4Holder {
+    Nlm4Holder { exclusive: l.exclusive, svid: l.owner.svid, oh: l.owner.id.clone(), l_offset: l.offset, l_len: l.length() }
+}
+
+// nlm4_res: cookie echoed back with a status
+fn put_res(out: &mut XdrChain, cookie: &[u8], stat: u32) {
+    out.put(cookie);
+    out.put(&stat);
+}
+
+impl NlmService {
+    pub fn new(exports: Arc<ExportTable>, locks: Arc<LockManager>) -> Self {
+        Self { exports, locks }
+    }
+
+    pub async fn call(&self, proc: u32, mut args: Bytes, caller: &Caller) -> Result<XdrChain, AcceptError> {
+        let proc = NlmProc::from_u32(proc).ok_or(AcceptError::ProcUnavail)?;
+        let mut out = XdrChain::new();
+        let args = &mut args;
+        let o = &mut out;
+        let res = match proc {
+            NlmProc::Null => Ok(()),
+            NlmProc::Test => self.test(args, o, caller).await,
+            NlmProc::Lock | NlmProc::NmLock => self.lock(args, o, caller).await,
+            NlmProc::Cancel => self.cancel(args, o),
+            NlmProc::Unlock => self.unlock(args, o, caller).await,
+            NlmProc::FreeAll => self.free_all(args),
+            NlmProc::Granted | NlmProc::Share | NlmProc::Unshare => return Err(AcceptError::ProcUnavail),
+        };
+        res.map_err(|_| AcceptError::GarbageArgs)?;
+        Ok(out)
+    }
+
+    // NLM4_STALE_FH for handles we do not recognise, NLM4_FAILED for
+    // clients the export refuses
+    async fn check_fh(&self, fh: &[u8], caller: &Caller) -> Option<u32> {
+        match self.exports.authorize_fh(fh, caller).await {
+            Ok(_) => {}
+            Err(NfsError::BadHandle | NfsError::StaleHandle) => return Some(NLM4_STALE_FH),
+            Err(_) => return Some(NLM4_FAILED),
+        }
+        match self.exports.getattr(fh).await {
+            Ok(_) => None,
+            Err(_) => Some(NLM4_STALE_FH),
+        }
+    }
+
+    async fn test(&self, args: &mut Bytes, out: &mut XdrChain, caller: &Caller) -> IoResult<()> {
+        let cookie = Vec::<u8>::xdr_decode(args)?;
+        let exclusive = bool::xdr_decode(args)?;
+        let lock = Nlm4Lock::xdr_decode(args)?;
+        if let Some(stat) = self.check_fh(&lock.fh, caller).await {
+            put_res(out, &cookie, stat);
+            return Ok(());
+        }
+        // locks not yet reclaimed would make any answer wrong
+        if self.locks.in_grace() {
+            put_res(out, &cookie, NLM4_DENIED_GRACE_PERIOD);
+            return Ok(());
+        }
+        match self.locks.test(&lock.fh, &owner_of(&lock), lock.l_offset, lock.l_len, exclusive) {
+            None => put_res(out, &cookie, NLM4_GRANTED),
+            Some(conflict) => {
+                put_res(out, &cookie, NLM4_DENIED);
+                out.put(&holder(&conflict));
+            }
+        }
+        Ok(())
+    }
+
+    async fn lock(&self, args: &mut Bytes, out: &mut XdrChain, caller: &Caller) -> IoResult<()> {
+        let cookie = Vec::<u8>::xdr_decode(args)?;
+        let _block = bool::xdr_decode(args)?;
+        let exclusive = bool::xdr_decode(args)?;
+        let lock = Nlm4Lock::xdr_decode(args)?;
+        let reclaim = bool::xdr_decode(args)?;
+        let _state = i32::xdr_decode(args)?;
+        if let Some(stat) = self.check_fh(&lock.fh, caller).await {
+            put_res(out, &cookie, stat);
+            return Ok(());
+        }
+        if !reclaim && self.locks.in_grace() {
+            put_res(out, &cookie, NLM4_DENIED_GRACE_PERIOD);
+            return Ok(());
+        }
+        let stat = match self.locks.lock(&lock.fh, &owner_of(&lock), lock.l_offset, lock.l_len, exclusive) {
+            Ok(()) => NLM4_GRANTED,
+            Err(_) => NLM4_DENIED,
+        };
+        put_res(out, &cookie, stat);
+        Ok(())
+    }
+
+    // Nothing is ever left pending, so there is nothing to cancel
+    fn cancel(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        let cookie = Vec::<u8>::xdr_decode(args)?;
+        let _block = bool::xdr_decode(args)?;
+        let _exclusive = bool::xdr_decode(args)?;
+        let _lock = Nlm4Lock::xdr_decode(args)?;
+        put_res(out, &cookie, NLM4_GRANTED);
+        Ok(())
+    }
+
+    async fn unlock(&self, args: &mut Bytes, out: &mut XdrChain, caller: &Caller) -> IoResult<()> {
+        let cookie = Vec::<u8>::xdr_decode(args)?;
+        let lock = Nlm4Lock::xdr_decode(args)?;
+        if let Some(stat) = self.check_fh(&lock.fh, caller).await {
+            put_res(out, &cookie, stat);
+            return Ok(());
+        }
+        self.locks.unlock(&lock.fh, &owner_of(&lock), lock.l_offset, lock.l_len);
+        put_res(out, &cookie, NLM4_GRANTED);
+        Ok(())
+    }
+
+    // nlm4_notify: the named host rebooted; the reply is void
+    fn free_all(&self, args: &mut Bytes) -> IoResult<()> {
+        let name = String::xdr_decode(args)?;
+        let _state = i32::xdr_decode(args)?;
+        let released = self.locks.release_host(&name);
+        info!("FREE_ALL from {}: released {} locks", name, released);
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! NSM (status monitor, program 100024). Tracks the hosts monitored on behalf
+//! of NLM and, when a peer reports a reboot with SM_NOTIFY, releases the
+//! locks it held so other clients are not blocked by a dead owner.
+//!
+//! Monitor records survive a restart only through the server's recovery
+//! record, and we do not send SM_NOTIFY to clients after our own restart.
+
+use crate::lock::LockManager;
+use crate::proto::nlm::*;
+use crate::rpc::AcceptError;
+use crate::xdr::*;
+use bytes::Bytes;
+use num_traits::FromPrimitive;
+use std::collections::HashSet;
+use std::io::Result as IoResult;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+pub struct NsmService {
+    locks: Arc<LockManager>,
+    // odd while up; bumped by two on every (simulated) restart
+    state: AtomicI32,
+    monitored: Mutex<HashSet<String>>,
+}
+
+// my_id: who to call back when a monitored host changes state
+fn skip_my_id(args: &mut Bytes) -> IoResult<()> {
+    let _my_name = String::xdr_decode(args)?;
+    let _my_prog = u32::xdr_decode(args)?;
+    let _my_vers = u32::xdr_decode(args)?;
+    let _my_proc = u32::xdr_decode(args)?;
+    Ok(())
+}
+
+impl NsmService {
+    pub fn new(locks: Arc<LockManager>, state: i32) -> Self {
+        Self { locks, state: AtomicI32::new(state | 1), monitored: Mutex::new(HashSet::new()) }
+    }
+
+    /// Our current NSM state number.
+    pub fn state(&self) -> i32 {
+        self.state.load(Ordering::Relaxed)
+    }
+
+    /// Hosts currently being monitored.
+    pub fn monitored(&self) -> Vec<String> {
+        self.monitored.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Carry over state from before a restart. The state number moves past
+    /// the saved one so peers see that we rebooted.
+    pub fn restore(&self, state: i32, monitored: &[String]) {
+        self.state.fetch_max(state.wrapping_add(2) | 1, Ordering::Relaxed);
+        self.monitored.lock().unwrap().extend(monitored.iter().cloned());
+    }
+
+    /// Stop monitoring `host`; false if it was not monitored.
+    pub fn unmonitor(&self, host: &str) -> bool {
+        self.monitored.lock().unwrap().remove(host)
+    }
+
+    pub async fn call(&self, proc: u32, mut args: Bytes) -> Result<XdrChain, AcceptError> {
+        let proc = NsmProc::from_u32(proc).ok_or(AcceptError::ProcUnavail)?;
+        let mut out = XdrChain::new();
+        let args = &mut args;
+        let o = &mut out;
+        let res = match proc {
+            NsmProc::Null => Ok(()),
+            NsmProc::Stat => self.stat(args, o),
+            NsmProc::Mon => self.mon(args, o),
+            NsmProc::Unmon => self.unmon(args, o),
+            NsmProc::UnmonAll => self.unmon_all(args, o),
+            NsmProc::SimuCrash => {
+                self.simu_crash();
+                Ok(())
+            }
+            NsmProc::Notify => self.notify(args),
+        };
+        res.map_err(|_| AcceptError::GarbageArgs)?;
+        Ok(out)
+    }
+
+    // sm_name -> sm_stat_res
+    fn stat(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        let _mon_name = String::xdr_decode(args)?;
+        out.put(&STAT_SUCC);
+        out.put(&self.state());
+        Ok(())
+    }
+
+    // mon { mon_id { mon_name, my_id }, priv[16] } -> sm_stat_res
+    fn mon(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        let mon_name = String::xdr_decode(args)?;
+        skip_my_id(args)?;
+        // priv: fixed 16 bytes of caller data we never hand back
+        u64::xdr_decode(args)?;
+        u64::xdr_decode(args)?;
+        self.monitored.lock().unwrap().insert(mon_name);
+        out.put(&STAT_SUCC);
+        out.put(&self.state());
+        Ok(())
+    }
+
+    // mon_id -> sm_stat
+    fn unmon(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        let mon_name = String::xdr_decode(args)?;
+        skip_my_id(args)?;
+        self.monitored.lock().unwrap().remove(&mon_name);
+        out.put(&self.state());
+        Ok(())
+    }
+
+    // my_id -> sm_stat; NLM is our only caller so everything goes
+    fn unmon_all(&self, args: &mut Bytes, out: &mut XdrChain) -> IoResult<()> {
+        skip_my_id(args)?;
+        self.monitored.lock().unwrap().clear();
+        out.put(&self.state());
+        Ok(())
+    }
+
+    fn simu_crash(&self) {
+        self.state.fetch_add(2, Ordering::Relaxed);
+        let released = self.locks.release_all();
+        info!("SM_SIMU_CRASH: released {} locks, state now {}", released, self.state());
+    }
+
+    // stat_chge { mon_name, state }: the peer restarted and lost its locks
+    fn notify(&self, args: &mut Bytes) -> IoResult<()> {
+        let mon_name = String::xdr_decode(args)?;
+        let state = i32::xdr_decode(args)?;
+        let released = self.locks.release_host(&mon_name);
+        info!("SM_NOTIFY from {} (state {}): released {} locks", mon_name, state, released);
+        Ok(())
+    }
+}
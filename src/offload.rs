@@ -0,0 +1,98 @@
+//! Server-side COPY (RFC 7862 section 4).
+//!
+//! An asynchronous copy must report how it ended with CB_OFFLOAD on the
+//! client's backchannel. This server keeps no client state and so has no
+//! sessions and no backchannel, so it never copies asynchronously: every
+//! COPY is done before it is answered, whatever the client would wait for,
+//! and OFFLOAD_STATUS has no copy to report on.
+//!
+//! When this server is the source of an inter-server copy, COPY_NOTIFY
+//! gives the destination it names a stateid to read the file with; those
+//! are kept here, and OFFLOAD_CANCEL withdraws them.
+
+use crate::error::{NfsError, NfsResult};
+use crate::proto::nfs4::*;
+use crate::vfs::Vfs;
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// How much a copy moves through the Vfs at a time
+pub const COPY_CHUNK: u64 = 4 * 1024 * 1024;
+/// How long a COPY_NOTIFY stateid lasts unused
+pub const NOTIFY_LEASE: Duration = Duration::from_secs(90);
+
+// COPY_NOTIFY's leave for the servers at `readers` to read `src`
+struct Grant {
+    src: Vec<u8>,
+    readers: Vec<IpAddr>,
+    expires: Instant,
+}
+
+pub struct OffloadTable {
+    // keyed by the stateid's `other`
+    grants: DashMap<[u8; 12], Grant>,
+    // leads every stateid, so none from before a restart is recognised
+    instance: u32,
+    next: AtomicU64,
+}
+
+impl OffloadTable {
+    pub fn new(instance: u32) -> Self {
+        Self { grants: DashMap::new(), instance, next: AtomicU64::new(1) }
+    }
+
+    fn stateid(&self) -> Stateid4 {
+        let mut other = [0u8; 12];
+        other[..4].copy_from_slice(&self.instance.to_be_bytes());
+        other[4..].copy_from_slice(&self.next.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+        Stateid4 { seqid: 1, other }
+    }
+
+    /// Withdraw the COPY_NOTIFY stateid `stateid` for `fh`, as
+    /// OFFLOAD_CANCEL on the source of an inter-server copy does. No copy
+    /// runs in the background, so no other stateid names anything here.
+    pub fn cancel(&self, fh: &[u8], stateid: &Stateid4) -> NfsResult<()> {
+        self.grants.remove_if(&stateid.other, |_, g| g.src == fh).map(|_| ()).ok_or(NfsError::BadStateid)
+    }
+
+    /// Let the servers at `readers` read `src` with the stateid returned,
+    /// as the source of an inter-server copy
+    pub fn notify(&self, src: &[u8], readers: Vec<IpAddr>) -> Stateid4 {
+        let now = Instant::now();
+        self.grants.retain(|_, g| g.expires > now);
+        let stateid = self.stateid();
+        let readers = readers.into_iter().map(|ip| ip.to_canonical()).collect();
+        self.grants.insert(stateid.other, Grant { src: src.to_vec(), readers, expires: now + NOTIFY_LEASE });
+        stateid
+    }
+
+    /// Whether `stateid` is a COPY_NOTIFY one letting `reader` read `src`.
+    /// Each use renews it.
+    pub fn granted(&self, src: &[u8], stateid: &Stateid4, reader: IpAddr) -> bool {
+        let now = Instant::now();
+        match self.grants.get_mut(&stateid.other) {
+            Some(mut g) if g.expires > now && g.src == src && g.readers.contains(&reader.to_canonical()) => {
+                g.expires = now + NOTIFY_LEASE;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Copy `count` bytes of `src` to `dst` through `vfs` a chunk at a time.
+/// Stops early at the end of `src`.
+pub async fn copy_chunks(vfs: &dyn Vfs, src: &[u8], src_offset: u64, dst: &[u8], dst_offset: u64, count: u64) -> NfsResult<u64> {
+    let mut done = 0;
+    while done < count {
+        let want = (count - done).min(COPY_CHUNK);
+        let n = vfs.copy_range(src, src_offset + done, dst, dst_offset + done, want).await?;
+        done += n;
+        if n < want {
+            break;
+        }
+    }
+    Ok(done)
+}
@@ -0,0 +1,209 @@
+//! Permission checks: what a caller may do to an object, judged from its
+//! ACL if it has one (RFC 8881 6.2.1), else from its mode bits, owner and
+//! group as POSIX does. The caller is the identity its export granted
+//! (`Access`), after squashing, so AUTH_SYS callers and AUTH_NONE ones
+//! mapped to the anonymous user are judged alike. Other flavors, RPCSEC_GSS
+//! among them, never get this far: exports refuse them.
+//!
+//! Masks use the ACCESS4 bits, which NFSv3's ACCESS shares.
+
+use crate::acl::{Acl, Who};
+use crate::error::{NfsError, NfsResult};
+use crate::export::Access;
+use crate::proto::nfs4::*;
+use crate::vfs::{FileAttr, FileType, SetAttr, SetTime};
+
+const S_ISVTX: u32 = 0o1000;
+
+// Bits that take write permission
+const WRITE_BITS: u32 = ACCESS4_MODIFY | ACCESS4_EXTEND | ACCESS4_DELETE;
+
+/// The ACCESS bits that mean something for an object of type `ftype`
+pub fn supported(ftype: FileType) -> u32 {
+    match ftype {
+        FileType::Directory => ACCESS4_READ | ACCESS4_LOOKUP | ACCESS4_MODIFY | ACCESS4_EXTEND | ACCESS4_DELETE,
+        _ => ACCESS4_READ | ACCESS4_MODIFY | ACCESS4_EXTEND | ACCESS4_EXECUTE,
+    }
+}
+
+fn in_group(who: &Access, gid: u32) -> bool {
+    who.gid == gid || who.gids.contains(&gid)
+}
+
+/// The ACE4 bits an ACL allows `who` on an object: each bit is decided by
+/// the first ALLOW or DENY entry naming it that applies to the caller, and
+/// bits no entry names are denied
+pub fn acl_allowed(acl: &Acl, attr: &FileAttr, who: &Access) -> u32 {
+    let (mut allowed, mut denied) = (0, 0);
+    for ace in acl.aces.iter().filter(|a| a.decides() && a.effective()) {
+        let applies = match ace.who {
+            Who::Owner => who.uid == attr.uid,
+            Who::Group => in_group(who, attr.gid),
+            Who::Everyone => true,
+            Who::User(uid) => who.uid == uid,
+            Who::GroupId(gid) => in_group(who, gid),
+        };
+        if !applies {
+            continue;
+        }
+        let undecided = ace.mask & !(allowed | denied);
+        match ace.kind {
+            ACE4_ACCESS_ALLOWED_ACE_TYPE => allowed |= undecided,
+            _ => denied |= undecided,
+        }
+    }
+    allowed
+}
+
+// The ACE4 bits each ACCESS bit needs
+fn ace_bits(access: u32, dir: bool) -> u32 {
+    match (access, dir) {
+        (ACCESS4_READ, _) => ACE4_READ_DATA,
+        (ACCESS4_MODIFY, true) => ACE4_ADD_FILE | ACE4_ADD_SUBDIRECTORY | ACE4_DELETE_CHILD,
+        (ACCESS4_MODIFY, false) => ACE4_WRITE_DATA,
+        (ACCESS4_EXTEND, true) => ACE4_ADD_FILE | ACE4_ADD_SUBDIRECTORY,
+        (ACCESS4_EXTEND, false) => ACE4_APPEND_DATA,
+        (ACCESS4_DELETE, _) => ACE4_DELETE_CHILD,
+        _ => ACE4_EXECUTE,
+    }
+}
+
+/// The ACCESS bits `who` holds on an object
+pub fn granted(attr: &FileAttr, who: &Access) -> u32 {
+    let dir = attr.ftype == FileType::Directory;
+    let mut mask = match &attr.acl {
+        Some(acl) if who.uid != 0 => {
+            let allowed = acl_allowed(acl, attr, who);
+            let bits = [ACCESS4_READ, ACCESS4_LOOKUP, ACCESS4_MODIFY, ACCESS4_EXTEND, ACCESS4_DELETE, ACCESS4_EXECUTE];
+            bits.into_iter().filter(|&b| allowed & ace_bits(b, dir) == ace_bits(b, dir)).fold(0, |m, b| m | b)
+        }
+        _ => mode_granted(attr, who),
+    };
+    if who.read_only {
+        mask &= !WRITE_BITS;
+    }
+    mask & supported(attr.ftype)
+}
+
+fn mode_granted(attr: &FileAttr, who: &Access) -> u32 {
+    let dir = attr.ftype == FileType::Directory;
+    let rwx = if who.uid == 0 {
+        // root may do anything but run a file nobody may run
+        if dir || attr.mode & 0o111 != 0 { 0o7 } else { 0o6 }
+    } else if who.uid == attr.uid {
+        attr.mode >> 6 & 0o7
+    } else if in_group(who, attr.gid) {
+        attr.mode >> 3 & 0o7
+    } else {
+        attr.mode & 0o7
+    };
+    let mut mask = 0;
+    if rwx & 0o4 != 0 {
+        mask |= ACCESS4_READ;
+    }
+    if rwx & 0o2 != 0 {
+        mask |= WRITE_BITS;
+    }
+    if rwx & 0o1 != 0 {
+        mask |= ACCESS4_LOOKUP | ACCESS4_EXECUTE;
+    }
+    mask
+}
+
+/// `PermissionDenied` unless `who` holds every bit of `want`
+pub fn check(attr: &FileAttr, who: &Access, want: u32) -> NfsResult<()> {
+    match granted(attr, who) & want == want {
+        true => Ok(()),
+        false => Err(NfsError::PermissionDenied),
+    }
+}
+
+/// READ and WRITE of file data. The owner may always do both: a client
+/// checks permissions when it opens a file, and the mode may have changed
+/// since. Reading what one may execute is allowed, as clients fetch
+/// programs to run them.
+pub fn check_io(attr: &FileAttr, who: &Access, write: bool) -> NfsResult<()> {
+    let granted = granted(attr, who);
+    let ok = match write {
+        _ if who.uid == attr.uid => true,
+        true => granted & ACCESS4_MODIFY != 0,
+        false => granted & (ACCESS4_READ | ACCESS4_EXECUTE) != 0,
+    };
+    ok.then_some(()).ok_or(NfsError::PermissionDenied)
+}
+
+/// Removing or renaming `victim` out of `dir`. In a sticky directory only
+/// the owner of the entry or of the directory may. An ACL on the victim
+/// that allows DELETE stands in for delete permission on the directory.
+pub fn check_delete(dir: &FileAttr, victim: &FileAttr, who: &Access) -> NfsResult<()> {
+    check(dir, who, ACCESS4_LOOKUP)?;
+    let victim_allows = match &victim.acl {
+        Some(acl) => !who.read_only && acl_allowed(acl, victim, who) & ACE4_DELETE != 0,
+        None => false,
+    };
+    if !victim_allows {
+        check(dir, who, ACCESS4_DELETE)?;
+    }
+    if dir.mode & S_ISVTX != 0 && who.uid != 0 && who.uid != dir.uid && who.uid != victim.uid {
+        return Err(NfsError::PermissionDenied);
+    }
+    Ok(())
+}
+
+/// Moving `src` out of `from_dir` into `to_dir`, replacing `dst` if a
+/// name there is taken
+pub fn check_rename(from_dir: &FileAttr, src: &FileAttr, to_dir: &FileAttr, dst: Option<&FileAttr>, who: &Access) -> NfsResult<()> {
+    check_delete(from_dir, src, who)?;
+    check(to_dir, who, ACCESS4_EXTEND | ACCESS4_LOOKUP)?;
+    if let Some(dst) = dst {
+        check_delete(to_dir, dst, who)?;
+    }
+    // a directory that moves elsewhere gets a new ".."
+    if src.ftype == FileType::Directory && (from_dir.fsid, from_dir.fileid) != (to_dir.fsid, to_dir.fileid) {
+        check(src, who, ACCESS4_MODIFY)?;
+    }
+    Ok(())
+}
+
+/// Owners a new object may be given: only root may give it away
+pub fn check_new_owner(set: &SetAttr, who: &Access) -> NfsResult<()> {
+    let uid_ok = set.uid.is_none_or(|uid| uid == who.uid);
+    let gid_ok = set.gid.is_none_or(|gid| in_group(who, gid));
+    match who.uid == 0 || (uid_ok && gid_ok) {
+        true => Ok(()),
+        false => Err(NfsError::PermissionDenied),
+    }
+}
+
+/// Changing the attributes of an existing object. The owner may change
+/// its mode, ACL, times and group (to one of theirs); only root may change
+/// the owner. Anyone who may write may set the size, or the times to now,
+/// and anyone its ACL allows WRITE_ACL may set the ACL.
+pub fn check_setattr(attr: &FileAttr, set: &SetAttr, who: &Access) -> NfsResult<()> {
+    let root = who.uid == 0;
+    let owner = root || who.uid == attr.uid;
+    let deny = Err(NfsError::PermissionDenied);
+    if set.acl.is_some() && !owner && attr.acl.as_ref().is_none_or(|acl| acl_allowed(acl, attr, who) & ACE4_WRITE_ACL == 0) {
+        return deny;
+    }
+    if set.uid.is_some_and(|uid| uid != attr.uid) && !root {
+        return deny;
+    }
+    if set.gid.is_some_and(|gid| gid != attr.gid && !(root || (owner && in_group(who, gid)))) {
+        return deny;
+    }
+    if set.mode.is_some() && !owner {
+        return deny;
+    }
+    for t in [set.atime, set.mtime].into_iter().flatten() {
+        match t {
+            SetTime::Client(_) if !owner => return deny,
+            SetTime::ServerNow if !owner => check(attr, who, ACCESS4_MODIFY)?,
+            _ => {}
+        }
+    }
+    if set.size.is_some() {
+        check_io(attr, who, true)?;
+    }
+    Ok(())
+}
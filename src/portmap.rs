@@ -0,0 +1,200 @@
+//! Portmapper support: either a minimal PMAP v2 / RPCBIND v3/v4 responder
+//! answering for the programs this server serves, or a client that
+//! registers them with the system rpcbind over its Unix socket.
+
+use crate::config::{NfsConfig, RpcbindMode};
+use crate::error::{NfsError, NfsResult};
+use crate::proto::mount::{MOUNT_PROGRAM, MOUNT_VERSION3};
+use crate::proto::nfs3::NFS3_VERSION;
+use crate::proto::nfs4::{NFS4_PROGRAM, NFS4_VERSION};
+use crate::proto::nlm::{NLM_PROGRAM, NLM_VERSION4, NSM_PROGRAM, NSM_VERSION};
+use crate::proto::portmap::*;
+use crate::rpc::*;
+use crate::xdr::*;
+use bytes::{Bytes, BytesMut};
+use std::net::SocketAddr;
+use std::path::Path;
+use tracing::debug;
+
+/// Answers portmapper queries from a fixed table of registrations
+pub struct Portmapper {
+    services: Vec<Rpcb>,
+}
+
+impl Portmapper {
+    pub fn new(services: Vec<Rpcb>) -> Self {
+        Self { services }
+    }
+
+    pub fn services(&self) -> &[Rpcb] {
+        &self.services
+    }
+
+    pub fn call(&self, call: &RpcCallHeader, mut args: Bytes) -> Result<XdrChain, AcceptError> {
+        let mut out = XdrChain::new();
+        match (call.vers, call.proc) {
+            (_, PMAPPROC_NULL) if (PMAP_VERSION..=RPCBIND_VERSION4).contains(&call.vers) => {}
+            (PMAP_VERSION, PMAPPROC_SET) | (PMAP_VERSION, PMAPPROC_UNSET) => {
+                // registrations are fixed at startup
+                Mapping::xdr_decode(&mut args).map_err(|_| AcceptError::GarbageArgs)?;
+                out.put(&false);
+            }
+            (PMAP_VERSION, PMAPPROC_GETPORT) => {
+                let want = Mapping::xdr_decode(&mut args).map_err(|_| AcceptError::GarbageArgs)?;
+                out.put(&self.getport(&want));
+            }
+            (PMAP_VERSION, PMAPPROC_DUMP) => {
+                for m in self.services.iter().filter_map(to_mapping) {
+                    out.put(&true);
+                    out.put(&m);
+                }
+                out.put(&false);
+            }
+            (RPCBIND_VERSION3..=RPCBIND_VERSION4, RPCBPROC_SET) | (RPCBIND_VERSION3..=RPCBIND_VERSION4, RPCBPROC_UNSET) => {
+                Rpcb::xdr_decode(&mut args).map_err(|_| AcceptError::GarbageArgs)?;
+                out.put(&false);
+            }
+            (RPCBIND_VERSION3..=RPCBIND_VERSION4, RPCBPROC_GETADDR) => {
+                let want = Rpcb::xdr_decode(&mut args).map_err(|_| AcceptError::GarbageArgs)?;
+                out.put(self.getaddr(&want, false).as_bytes());
+            }
+            (RPCBIND_VERSION4, RPCBPROC_GETVERSADDR) => {
+                let want = Rpcb::xdr_decode(&mut args).map_err(|_| AcceptError::GarbageArgs)?;
+                out.put(self.getaddr(&want, true).as_bytes());
+            }
+            (RPCBIND_VERSION3..=RPCBIND_VERSION4, RPCBPROC_DUMP) => {
+                for r in &self.services {
+                    out.put(&true);
+                    out.put(r);
+                }
+                out.put(&false);
+            }
+            (PMAP_VERSION..=RPCBIND_VERSION4, _) => return Err(AcceptError::ProcUnavail),
+            _ => return Err(AcceptError::ProgMismatch { low: PMAP_VERSION, high: RPCBIND_VERSION4 }),
+        }
+        Ok(out)
+    }
+
+    fn getport(&self, want: &Mapping) -> u32 {
+        self.services
+            .iter()
+            .filter_map(to_mapping)
+            .find(|m| m.prog == want.prog && m.vers == want.vers && m.prot == want.prot)
+            .map(|m| m.port)
+            .unwrap_or(0)
+    }
+
+    // GETADDR may answer with any version of the program; GETVERSADDR may not
+    fn getaddr(&self, want: &Rpcb, exact_vers: bool) -> String {
+        let candidates = self
+            .services
+            .iter()
+            .filter(|r| r.prog == want.prog && (want.netid.is_empty() || r.netid == want.netid));
+        let mut fallback = None;
+        for r in candidates {
+            if r.vers == want.vers {
+                return r.addr.clone();
+            }
+            fallback.get_or_insert_with(|| r.addr.clone());
+        }
+        if exact_vers { String::new() } else { fallback.unwrap_or_default() }
+    }
+}
+
+fn to_mapping(r: &Rpcb) -> Option<Mapping> {
+    let prot = match r.netid.as_str() {
+        "tcp" | "tcp6" => IPPROTO_TCP,
+        "udp" | "udp6" => IPPROTO_UDP,
+        _ => return None,
+    };
+    // PMAP v2 is IPv4 only
+    if r.netid.ends_with('6') {
+        return None;
+    }
+    let addr = from_uaddr(&r.addr)?;
+    Some(Mapping { prog: r.prog, vers: r.vers, prot, port: addr.port() as u32 })
+}
+
+/// The netid for `proto` ("tcp" or "udp") over `addr`'s address family
+pub fn netid(proto: &str, addr: &SocketAddr) -> String {
+    if addr.is_ipv6() { format!("{}6", proto) } else { proto.to_string() }
+}
+
+/// The programs, versions and transports this server answers on.
+pub fn served_services(cfg: &NfsConfig) -> NfsResult<Vec<Rpcb>> {
+    let ip: std::net::IpAddr = cfg
+        .bind_addr
+        .parse()
+        .map_err(|_| NfsError::Config(format!("bind_addr {:?} is not an IP address", cfg.bind_addr)))?;
+    let owner = "nfs-rs".to_string();
+    let mut out = Vec::new();
+    let mut add = |prog: u32, vers: u32, proto: &str, port: u16| {
+        let addr = SocketAddr::new(ip, port);
+        out.push(Rpcb { prog, vers, netid: netid(proto, &addr), addr: to_uaddr(&addr), owner: owner.clone() });
+    };
+    add(NFS4_PROGRAM, NFS3_VERSION, "tcp", cfg.port);
+    if cfg.udp {
+        add(NFS4_PROGRAM, NFS3_VERSION, "udp", cfg.port);
+    }
+    add(NFS4_PROGRAM, NFS4_VERSION, "tcp", cfg.port);
+    // MOUNT, NLM and NSM share the NFS listeners
+    for (prog, vers) in [(MOUNT_PROGRAM, 1), (MOUNT_PROGRAM, MOUNT_VERSION3), (NLM_PROGRAM, NLM_VERSION4), (NSM_PROGRAM, NSM_VERSION)] {
+        add(prog, vers, "tcp", cfg.port);
+        if cfg.udp {
+            add(prog, vers, "udp", cfg.port);
+        }
+    }
+    if cfg.rpcbind.mode == RpcbindMode::Serve {
+        for vers in PMAP_VERSION..=RPCBIND_VERSION4 {
+            add(PMAP_PROGRAM, vers, "tcp", cfg.rpcbind.port);
+            add(PMAP_PROGRAM, vers, "udp", cfg.rpcbind.port);
+        }
+    }
+    Ok(out)
+}
+
+/// Register `services` with the local rpcbind, replacing stale entries left
+/// by a previous run.
+#[cfg(unix)]
+pub async fn register_with_rpcbind(socket_path: &Path, services: &[Rpcb]) -> NfsResult<()> {
+    let mut stream = tokio::net::UnixStream::connect(socket_path).await?;
+    let mut rbuf = BytesMut::new();
+    let mut xid = std::process::id();
+    for svc in services {
+        for proc in [RPCBPROC_UNSET, RPCBPROC_SET] {
+            xid = xid.wrapping_add(1);
+            let hdr = RpcCallHeader {
+                xid,
+                msg_type: RpcMessageType::Call,
+                rpcvers: 2,
+                prog: PMAP_PROGRAM,
+                vers: RPCBIND_VERSION4,
+                proc,
+            };
+            let mut call = XdrChain::new();
+            call.put_raw(&serialize_to_vec(&hdr)?);
+            call.put(svc);
+            write_record_vectored(&mut stream, &call.into_segments()).await?;
+
+            let mut reply = read_record(&mut stream, &mut rbuf).await?;
+            let hdr = RpcReplyHeader::xdr_decode(&mut reply)?;
+            if hdr.xid != xid || hdr.reply_state != 0 || hdr.accept_state != ACCEPT_SUCCESS {
+                return Err(NfsError::Network(format!("rpcbind rejected call (accept_stat {})", hdr.accept_state)));
+            }
+            let ok = bool::xdr_decode(&mut reply)?;
+            if proc == RPCBPROC_SET && !ok {
+                return Err(NfsError::Network(format!(
+                    "rpcbind refused {}/{} on {}",
+                    svc.prog, svc.vers, svc.netid
+                )));
+            }
+            debug!("rpcbind proc {} for {}/{} {} -> {}", proc, svc.prog, svc.vers, svc.netid, ok);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn register_with_rpcbind(_socket_path: &Path, _services: &[Rpcb]) -> NfsResult<()> {
+    Err(NfsError::NotSupported)
+}
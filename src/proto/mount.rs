@@ -0,0 +1,29 @@
+//! MOUNT v3 protocol (RFC 1813 appendix I) constants
+use num_derive::{FromPrimitive, ToPrimitive};
+
+pub const MOUNT_PROGRAM: u32 = 100005;
+pub const MOUNT_VERSION3: u32 = 3;
+pub const MNTPATHLEN: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
+pub enum MountProc {
+    Null = 0,
+    Mnt = 1,
+    Dump = 2,
+    Umnt = 3,
+    Umntall = 4,
+    Export = 5,
+}
+
+// mountstat3
+pub const MNT3_OK: u32 = 0;
+pub const MNT3ERR_PERM: u32 = 1;
+pub const MNT3ERR_NOENT: u32 = 2;
+pub const MNT3ERR_IO: u32 = 5;
+pub const MNT3ERR_ACCES: u32 = 13;
+pub const MNT3ERR_NOTDIR: u32 = 20;
+pub const MNT3ERR_INVAL: u32 = 22;
+pub const MNT3ERR_NAMETOOLONG: u32 = 63;
+pub const MNT3ERR_NOTSUPP: u32 = 10004;
+pub const MNT3ERR_SERVERFAULT: u32 = 10006;
@@ -0,0 +1,72 @@
+//! NFSv3 (RFC 1813) constants
+use num_derive::{FromPrimitive, ToPrimitive};
+
+pub const NFS3_VERSION: u32 = 3;
+pub const NFS3_FHSIZE: usize = 64;
+pub const NFS3_COOKIEVERFSIZE: usize = 8;
+pub const NFS3_WRITEVERFSIZE: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
+pub enum Nfs3Proc {
+    Null = 0,
+    Getattr = 1,
+    Setattr = 2,
+    Lookup = 3,
+    Access = 4,
+    Readlink = 5,
+    Read = 6,
+    Write = 7,
+    Create = 8,
+    Mkdir = 9,
+    Symlink = 10,
+    Mknod = 11,
+    Remove = 12,
+    Rmdir = 13,
+    Rename = 14,
+    Link = 15,
+    Readdir = 16,
+    Readdirplus = 17,
+    Fsstat = 18,
+    Fsinfo = 19,
+    Pathconf = 20,
+    Commit = 21,
+}
+
+// ftype3
+pub const NF3REG: u32 = 1;
+pub const NF3DIR: u32 = 2;
+pub const NF3BLK: u32 = 3;
+pub const NF3CHR: u32 = 4;
+pub const NF3LNK: u32 = 5;
+pub const NF3SOCK: u32 = 6;
+pub const NF3FIFO: u32 = 7;
+
+// ACCESS3 bits
+pub const ACCESS3_READ: u32 = 0x0001;
+pub const ACCESS3_LOOKUP: u32 = 0x0002;
+pub const ACCESS3_MODIFY: u32 = 0x0004;
+pub const ACCESS3_EXTEND: u32 = 0x0008;
+pub const ACCESS3_DELETE: u32 = 0x0010;
+pub const ACCESS3_EXECUTE: u32 = 0x0020;
+
+// stable_how
+pub const UNSTABLE: u32 = 0;
+pub const DATA_SYNC: u32 = 1;
+pub const FILE_SYNC: u32 = 2;
+
+// createmode3
+pub const UNCHECKED: u32 = 0;
+pub const GUARDED: u32 = 1;
+pub const EXCLUSIVE: u32 = 2;
+
+// time_how
+pub const DONT_CHANGE: u32 = 0;
+pub const SET_TO_SERVER_TIME: u32 = 1;
+pub const SET_TO_CLIENT_TIME: u32 = 2;
+
+// FSINFO properties
+pub const FSF3_LINK: u32 = 0x0001;
+pub const FSF3_SYMLINK: u32 = 0x0002;
+pub const FSF3_HOMOGENEOUS: u32 = 0x0008;
+pub const FSF3_CANSETTIME: u32 = 0x0010;
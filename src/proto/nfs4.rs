@@ -1,15 +1,34 @@
 //! Minimal NFSv4.2 XDR data types and opcodes needed for a skeleton server
 use crate::xdr::*;
+use bytes::{Bytes, BytesMut};
 use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive as _;
 
 pub const NFS4_PROGRAM: u32 = 100003;
 pub const NFS4_VERSION: u32 = 4;
 pub const NFS4_OK: u32 = 0;
-pub const NFS4ERR_NOFILEHANDLE: u32 = 10020;
 
-// Minimal file types
+// File types
 pub const NF4REG: u32 = 1;
 pub const NF4DIR: u32 = 2;
+pub const NF4BLK: u32 = 3;
+pub const NF4CHR: u32 = 4;
+pub const NF4LNK: u32 = 5;
+pub const NF4SOCK: u32 = 6;
+pub const NF4FIFO: u32 = 7;
+
+// ACCESS4 bits
+pub const ACCESS4_READ: u32 = 0x01;
+pub const ACCESS4_LOOKUP: u32 = 0x02;
+pub const ACCESS4_MODIFY: u32 = 0x04;
+pub const ACCESS4_EXTEND: u32 = 0x08;
+pub const ACCESS4_DELETE: u32 = 0x10;
+pub const ACCESS4_EXECUTE: u32 = 0x20;
+
+// stable_how4
+pub const UNSTABLE4: u32 = 0;
+pub const DATA_SYNC4: u32 = 1;
+pub const FILE_SYNC4: u32 = 2;
 
 // Filehandle expire types
 pub const FH4_PERSISTENT: u32 = 0x0000_0000;
@@ -20,7 +39,79 @@ pub const FATTR4_TYPE: u32 = 1;
 pub const FATTR4_FH_EXPIRE_TYPE: u32 = 2;
 pub const FATTR4_CHANGE: u32 = 3;
 pub const FATTR4_SIZE: u32 = 4;
+pub const FATTR4_FSID: u32 = 8;
+pub const FATTR4_RDATTR_ERROR: u32 = 11;
+pub const FATTR4_ACL: u32 = 12;
+pub const FATTR4_ACLSUPPORT: u32 = 13;
 pub const FATTR4_FILEHANDLE: u32 = 19;
+pub const FATTR4_FILEID: u32 = 20;
+pub const FATTR4_MODE: u32 = 33;
+pub const FATTR4_OWNER: u32 = 36;
+pub const FATTR4_OWNER_GROUP: u32 = 37;
+pub const FATTR4_TIME_ACCESS_SET: u32 = 48;
+pub const FATTR4_TIME_MODIFY_SET: u32 = 54;
+pub const FATTR4_MOUNTED_ON_FILEID: u32 = 55;
+pub const FATTR4_DACL: u32 = 58;
+pub const FATTR4_CLONE_BLKSIZE: u32 = 77;
+
+// acetype4
+pub const ACE4_ACCESS_ALLOWED_ACE_TYPE: u32 = 0;
+pub const ACE4_ACCESS_DENIED_ACE_TYPE: u32 = 1;
+pub const ACE4_SYSTEM_AUDIT_ACE_TYPE: u32 = 2;
+pub const ACE4_SYSTEM_ALARM_ACE_TYPE: u32 = 3;
+
+// aceflag4
+pub const ACE4_FILE_INHERIT_ACE: u32 = 0x01;
+pub const ACE4_DIRECTORY_INHERIT_ACE: u32 = 0x02;
+pub const ACE4_NO_PROPAGATE_INHERIT_ACE: u32 = 0x04;
+pub const ACE4_INHERIT_ONLY_ACE: u32 = 0x08;
+pub const ACE4_SUCCESSFUL_ACCESS_ACE_FLAG: u32 = 0x10;
+pub const ACE4_FAILED_ACCESS_ACE_FLAG: u32 = 0x20;
+pub const ACE4_IDENTIFIER_GROUP: u32 = 0x40;
+pub const ACE4_INHERITED_ACE: u32 = 0x80;
+
+// acemask4; directories reuse the file bits under other names
+pub const ACE4_READ_DATA: u32 = 0x0000_0001;
+pub const ACE4_LIST_DIRECTORY: u32 = 0x0000_0001;
+pub const ACE4_WRITE_DATA: u32 = 0x0000_0002;
+pub const ACE4_ADD_FILE: u32 = 0x0000_0002;
+pub const ACE4_APPEND_DATA: u32 = 0x0000_0004;
+pub const ACE4_ADD_SUBDIRECTORY: u32 = 0x0000_0004;
+pub const ACE4_READ_NAMED_ATTRS: u32 = 0x0000_0008;
+pub const ACE4_WRITE_NAMED_ATTRS: u32 = 0x0000_0010;
+pub const ACE4_EXECUTE: u32 = 0x0000_0020;
+pub const ACE4_DELETE_CHILD: u32 = 0x0000_0040;
+pub const ACE4_READ_ATTRIBUTES: u32 = 0x0000_0080;
+pub const ACE4_WRITE_ATTRIBUTES: u32 = 0x0000_0100;
+pub const ACE4_DELETE: u32 = 0x0001_0000;
+pub const ACE4_READ_ACL: u32 = 0x0002_0000;
+pub const ACE4_WRITE_ACL: u32 = 0x0004_0000;
+pub const ACE4_WRITE_OWNER: u32 = 0x0008_0000;
+pub const ACE4_SYNCHRONIZE: u32 = 0x0010_0000;
+
+// aclsupport4
+pub const ACL4_SUPPORT_ALLOW_ACL: u32 = 0x01;
+pub const ACL4_SUPPORT_DENY_ACL: u32 = 0x02;
+pub const ACL4_SUPPORT_AUDIT_ACL: u32 = 0x04;
+pub const ACL4_SUPPORT_ALARM_ACL: u32 = 0x08;
+
+// aclflag4 (NFSv4.1 dacl)
+pub const ACL4_AUTO_INHERIT: u32 = 0x01;
+pub const ACL4_PROTECTED: u32 = 0x02;
+pub const ACL4_DEFAULTED: u32 = 0x04;
+
+// time_how4
+pub const SET_TO_SERVER_TIME4: u32 = 0;
+pub const SET_TO_CLIENT_TIME4: u32 = 1;
+
+// netloc_type4
+pub const NL4_NAME: u32 = 1;
+pub const NL4_URL: u32 = 2;
+pub const NL4_NETADDR: u32 = 3;
+
+// data_content4
+pub const NFS4_CONTENT_DATA: u32 = 0;
+pub const NFS4_CONTENT_HOLE: u32 = 1;
 
 #[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive)]
 #[repr(u32)]
@@ -70,7 +161,27 @@ pub enum NfsOp4 {
     OpVerify = 37,
     OpWrite = 38,
     OpReleaseLockowner = 39,
-    // v4.1+ add many ops; v4.2 adds additional ops.
+    // NFSv4.1
+    OpBackchannelCtl = 40,
+    OpBindConnToSession = 41,
+    OpExchangeId = 42,
+    OpCreateSession = 43,
+    OpDestroySession = 44,
+    OpFreeStateid = 45,
+    OpGetDirDelegation = 46,
+    OpGetdeviceinfo = 47,
+    OpGetdevicelist = 48,
+    OpLayoutcommit = 49,
+    OpLayoutget = 50,
+    OpLayoutreturn = 51,
+    OpSecinfoNoName = 52,
+    OpSequence = 53,
+    OpSetSsv = 54,
+    OpTestStateid = 55,
+    OpWantDelegation = 56,
+    OpDestroyClientid = 57,
+    OpReclaimComplete = 58,
+    // NFSv4.2
     OpAllocate = 59,
     OpCopy = 60,
     OpCopyNotify = 61,
@@ -84,6 +195,23 @@ pub enum NfsOp4 {
     OpSeek = 69,
     OpWriteSame = 70,
     OpClone = 71,
+    OpIllegal = 10044,
+}
+
+/// Name of an operation as the RFC spells it, e.g. "PUTROOTFH" or
+/// "READ_PLUS"; unknown opcodes by number
+pub fn op_name(opcode: u32) -> String {
+    let Some(op) = NfsOp4::from_u32(opcode) else {
+        return opcode.to_string();
+    };
+    let mut name = String::new();
+    for (i, c) in format!("{:?}", op).trim_start_matches("Op").chars().enumerate() {
+        if i > 0 && c.is_ascii_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    name
 }
 
 #[derive(Debug, Default, Clone)]
@@ -102,49 +230,798 @@ pub struct Compound4res {
 #[derive(Debug, Clone)]
 pub struct Op4 {
     pub opcode: u32,
-    pub opdata: Vec<u8>,
+    pub opdata: Bytes,
 }
 
-impl XdrDeserialize for Compound4args {
-    fn xdr_deserialize<R: std::io::Read>(r: &mut R) -> std::io::Result<Self> {
-        let tag = XdrString::xdr_deserialize(r)?;
-        let minorversion = u32::xdr_deserialize(r)?;
-        let numops = u32::xdr_deserialize(r)? as usize;
-        let mut operations = Vec::with_capacity(numops);
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stateid4 {
+    pub seqid: u32,
+    pub other: [u8; 12],
+}
+
+impl Stateid4 {
+    /// All zeros: I/O outside any OPEN, subject to share reservations
+    pub const ANONYMOUS: Stateid4 = Stateid4 { seqid: 0, other: [0; 12] };
+    /// All ones: READ that bypasses share reservations and locks
+    pub const READ_BYPASS: Stateid4 = Stateid4 { seqid: u32::MAX, other: [0xff; 12] };
+}
+
+#[derive(Debug, Clone)]
+pub struct Read4args {
+    pub stateid: Stateid4,
+    pub offset: u64,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Read4resok {
+    pub eof: bool,
+    pub data: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct Write4args {
+    pub stateid: Stateid4,
+    pub offset: u64,
+    pub stable: u32,
+    /// Slice of the receive buffer; never copied on decode
+    pub data: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct Commit4args {
+    pub offset: u64,
+    /// 0 means to the end of the file
+    pub count: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Readdir4args {
+    pub cookie: u64,
+    pub cookieverf: u64,
+    /// Budget for cookies and names; 0 leaves it to `maxcount`
+    pub dircount: u32,
+    /// Budget for the whole READDIR4resok
+    pub maxcount: u32,
+    pub attr_request: Vec<u32>,
+}
+
+/// A set of attributes: which ones, then their values in bit order
+#[derive(Debug, Default, Clone)]
+pub struct Fattr4 {
+    pub attrmask: Vec<u32>,
+    pub attr_vals: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct Create4args {
+    /// NF4DIR, NF4LNK, NF4BLK, NF4CHR, NF4SOCK or NF4FIFO
+    pub objtype: u32,
+    /// Target of a symlink
+    pub linkdata: Bytes,
+    /// Major and minor numbers of a device
+    pub devdata: (u32, u32),
+    pub objname: Bytes,
+    pub createattrs: Fattr4,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rename4args {
+    pub oldname: Bytes,
+    pub newname: Bytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nfsace4 {
+    pub acetype: u32,
+    pub flag: u32,
+    pub access_mask: u32,
+    /// OWNER@, GROUP@, EVERYONE@ or a user or group
+    pub who: Bytes,
+}
+
+/// The dacl attribute: the ACL's flags and its ALLOW and DENY entries
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Nfsacl41 {
+    pub flag: u32,
+    pub aces: Vec<Nfsace4>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Setattr4args {
+    pub stateid: Stateid4,
+    pub attrs: Fattr4,
+}
+
+/// Where a server may be reached: a name, a URL or a universal address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Netloc4 {
+    Name(Bytes),
+    Url(Bytes),
+    Netaddr { netid: Bytes, addr: Bytes },
+}
+
+#[derive(Debug, Clone)]
+pub struct Copy4args {
+    pub src_stateid: Stateid4,
+    pub dst_stateid: Stateid4,
+    pub src_offset: u64,
+    pub dst_offset: u64,
+    /// 0 means to the end of the source
+    pub count: u64,
+    pub consecutive: bool,
+    pub synchronous: bool,
+    /// Empty when the source is on this server
+    pub source_server: Vec<Netloc4>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CopyNotify4args {
+    pub src_stateid: Stateid4,
+    pub destination: Netloc4,
+}
+
+/// COPY_NOTIFY's answer: the stateid the destination is to read with, how
+/// long it has to start, and where it may find this server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyNotify4resok {
+    /// Whole seconds; sent as an nfstime4
+    pub lease_time: u64,
+    pub stateid: Stateid4,
+    pub source_server: Vec<Netloc4>,
+}
+
+/// A piece of a file as READ_PLUS returns it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadPlusContent4 {
+    Data { offset: u64, data: Bytes },
+    Hole { offset: u64, length: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadPlus4resok {
+    pub eof: bool,
+    pub contents: Vec<ReadPlusContent4>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Clone4args {
+    pub src_stateid: Stateid4,
+    pub dst_stateid: Stateid4,
+    pub src_offset: u64,
+    pub dst_offset: u64,
+    /// 0 means to the end of the source
+    pub count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Seek4args {
+    pub stateid: Stateid4,
+    pub offset: u64,
+    /// NFS4_CONTENT_DATA or NFS4_CONTENT_HOLE
+    pub what: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seek4resok {
+    /// True if `offset` is the end of the file
+    pub eof: bool,
+    pub offset: u64,
+}
+
+/// The arguments of ALLOCATE, and of DEALLOCATE, which has the same ones
+#[derive(Debug, Clone)]
+pub struct Allocate4args {
+    pub stateid: Stateid4,
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// The outcome of a COPY. An asynchronous copy carries the stateid that
+/// names it and reports its count later.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WriteResponse4 {
+    pub callback_id: Option<Stateid4>,
+    pub count: u64,
+    pub committed: u32,
+    pub writeverf: u64,
+}
+
+/// How a directory changed: its change attribute just before and just
+/// after, and whether nothing else changed it in between
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChangeInfo4 {
+    pub atomic: bool,
+    pub before: u64,
+    pub after: u64,
+}
+
+impl XdrEncode for Stateid4 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.seqid.xdr_encode(buf);
+        buf.extend_from_slice(&self.other);
+    }
+}
+impl XdrDecode for Stateid4 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let seqid = u32::xdr_decode(buf)?;
+        if buf.len() < 12 {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "short stateid4"));
+        }
+        let mut other = [0u8; 12];
+        other.copy_from_slice(&buf.split_to(12));
+        Ok(Stateid4 { seqid, other })
+    }
+}
+
+impl XdrEncode for Read4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.stateid.xdr_encode(buf);
+        self.offset.xdr_encode(buf);
+        self.count.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Read4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let stateid = Stateid4::xdr_decode(buf)?;
+        let offset = u64::xdr_decode(buf)?;
+        let count = u32::xdr_decode(buf)?;
+        Ok(Read4args { stateid, offset, count })
+    }
+}
+
+impl Read4resok {
+    /// Encode into a chain so the file data is sent as its own iovec
+    pub fn encode_into(&self, out: &mut XdrChain) {
+        out.put(&self.eof);
+        out.put_opaque(self.data.clone());
+    }
+}
+
+impl XdrEncode for Write4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.stateid.xdr_encode(buf);
+        self.offset.xdr_encode(buf);
+        self.stable.xdr_encode(buf);
+        self.data.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Write4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let stateid = Stateid4::xdr_decode(buf)?;
+        let offset = u64::xdr_decode(buf)?;
+        let stable = u32::xdr_decode(buf)?;
+        let data = Bytes::xdr_decode(buf)?;
+        Ok(Write4args { stateid, offset, stable, data })
+    }
+}
+
+impl XdrEncode for Commit4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.offset.xdr_encode(buf);
+        self.count.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Commit4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let offset = u64::xdr_decode(buf)?;
+        let count = u32::xdr_decode(buf)?;
+        Ok(Commit4args { offset, count })
+    }
+}
+
+impl XdrEncode for Readdir4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.cookie.xdr_encode(buf);
+        self.cookieverf.xdr_encode(buf);
+        self.dircount.xdr_encode(buf);
+        self.maxcount.xdr_encode(buf);
+        self.attr_request.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Readdir4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let cookie = u64::xdr_decode(buf)?;
+        let cookieverf = u64::xdr_decode(buf)?;
+        let dircount = u32::xdr_decode(buf)?;
+        let maxcount = u32::xdr_decode(buf)?;
+        let attr_request = Vec::<u32>::xdr_decode(buf)?;
+        Ok(Readdir4args { cookie, cookieverf, dircount, maxcount, attr_request })
+    }
+}
+
+impl XdrEncode for Fattr4 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.attrmask.xdr_encode(buf);
+        self.attr_vals.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Fattr4 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let attrmask = Vec::<u32>::xdr_decode(buf)?;
+        let attr_vals = Bytes::xdr_decode(buf)?;
+        Ok(Fattr4 { attrmask, attr_vals })
+    }
+}
+
+impl XdrEncode for Create4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.objtype.xdr_encode(buf);
+        match self.objtype {
+            NF4LNK => self.linkdata.xdr_encode(buf),
+            NF4BLK | NF4CHR => {
+                self.devdata.0.xdr_encode(buf);
+                self.devdata.1.xdr_encode(buf);
+            }
+            _ => {}
+        }
+        self.objname.xdr_encode(buf);
+        self.createattrs.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Create4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let objtype = u32::xdr_decode(buf)?;
+        let (mut linkdata, mut devdata) = (Bytes::new(), (0, 0));
+        // every other type, valid or not, has no arm data
+        match objtype {
+            NF4LNK => linkdata = Bytes::xdr_decode(buf)?,
+            NF4BLK | NF4CHR => devdata = (u32::xdr_decode(buf)?, u32::xdr_decode(buf)?),
+            _ => {}
+        }
+        let objname = Bytes::xdr_decode(buf)?;
+        let createattrs = Fattr4::xdr_decode(buf)?;
+        Ok(Create4args { objtype, linkdata, devdata, objname, createattrs })
+    }
+}
+
+impl XdrEncode for Rename4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.oldname.xdr_encode(buf);
+        self.newname.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Rename4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let oldname = Bytes::xdr_decode(buf)?;
+        let newname = Bytes::xdr_decode(buf)?;
+        Ok(Rename4args { oldname, newname })
+    }
+}
+
+impl XdrEncode for ChangeInfo4 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.atomic.xdr_encode(buf);
+        self.before.xdr_encode(buf);
+        self.after.xdr_encode(buf);
+    }
+}
+impl XdrDecode for ChangeInfo4 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let atomic = bool::xdr_decode(buf)?;
+        let before = u64::xdr_decode(buf)?;
+        let after = u64::xdr_decode(buf)?;
+        Ok(ChangeInfo4 { atomic, before, after })
+    }
+}
+
+impl XdrEncode for Nfsace4 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.acetype.xdr_encode(buf);
+        self.flag.xdr_encode(buf);
+        self.access_mask.xdr_encode(buf);
+        self.who.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Nfsace4 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let acetype = u32::xdr_decode(buf)?;
+        let flag = u32::xdr_decode(buf)?;
+        let access_mask = u32::xdr_decode(buf)?;
+        let who = Bytes::xdr_decode(buf)?;
+        Ok(Nfsace4 { acetype, flag, access_mask, who })
+    }
+}
+
+impl XdrEncode for Vec<Nfsace4> {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        (self.len() as u32).xdr_encode(buf);
+        for ace in self {
+            ace.xdr_encode(buf);
+        }
+    }
+}
+impl XdrDecode for Vec<Nfsace4> {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let n = u32::xdr_decode(buf)? as usize;
+        // each entry takes at least 16 bytes; don't trust n for the allocation
+        let mut aces = Vec::with_capacity(n.min(buf.len() / 16));
+        for _ in 0..n {
+            aces.push(Nfsace4::xdr_decode(buf)?);
+        }
+        Ok(aces)
+    }
+}
+
+impl XdrEncode for Nfsacl41 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.flag.xdr_encode(buf);
+        self.aces.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Nfsacl41 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let flag = u32::xdr_decode(buf)?;
+        let aces = Vec::<Nfsace4>::xdr_decode(buf)?;
+        Ok(Nfsacl41 { flag, aces })
+    }
+}
+
+impl XdrEncode for Setattr4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.stateid.xdr_encode(buf);
+        self.attrs.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Setattr4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let stateid = Stateid4::xdr_decode(buf)?;
+        let attrs = Fattr4::xdr_decode(buf)?;
+        Ok(Setattr4args { stateid, attrs })
+    }
+}
+
+impl XdrEncode for Netloc4 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        match self {
+            Netloc4::Name(name) => {
+                NL4_NAME.xdr_encode(buf);
+                name.xdr_encode(buf);
+            }
+            Netloc4::Url(url) => {
+                NL4_URL.xdr_encode(buf);
+                url.xdr_encode(buf);
+            }
+            Netloc4::Netaddr { netid, addr } => {
+                NL4_NETADDR.xdr_encode(buf);
+                netid.xdr_encode(buf);
+                addr.xdr_encode(buf);
+            }
+        }
+    }
+}
+impl XdrDecode for Netloc4 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        match u32::xdr_decode(buf)? {
+            NL4_NAME => Ok(Netloc4::Name(Bytes::xdr_decode(buf)?)),
+            NL4_URL => Ok(Netloc4::Url(Bytes::xdr_decode(buf)?)),
+            NL4_NETADDR => Ok(Netloc4::Netaddr { netid: Bytes::xdr_decode(buf)?, addr: Bytes::xdr_decode(buf)? }),
+            other => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("bad netloc_type4 {}", other))),
+        }
+    }
+}
+
+impl XdrEncode for Copy4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.src_stateid.xdr_encode(buf);
+        self.dst_stateid.xdr_encode(buf);
+        self.src_offset.xdr_encode(buf);
+        self.dst_offset.xdr_encode(buf);
+        self.count.xdr_encode(buf);
+        self.consecutive.xdr_encode(buf);
+        self.synchronous.xdr_encode(buf);
+        (self.source_server.len() as u32).xdr_encode(buf);
+        for loc in &self.source_server {
+            loc.xdr_encode(buf);
+        }
+    }
+}
+impl XdrDecode for Copy4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let src_stateid = Stateid4::xdr_decode(buf)?;
+        let dst_stateid = Stateid4::xdr_decode(buf)?;
+        let src_offset = u64::xdr_decode(buf)?;
+        let dst_offset = u64::xdr_decode(buf)?;
+        let count = u64::xdr_decode(buf)?;
+        let consecutive = bool::xdr_decode(buf)?;
+        let synchronous = bool::xdr_decode(buf)?;
+        let n = u32::xdr_decode(buf)? as usize;
+        // each netloc4 takes at least 8 bytes; don't trust n for the allocation
+        let mut source_server = Vec::with_capacity(n.min(buf.len() / 8));
+        for _ in 0..n {
+            source_server.push(Netloc4::xdr_decode(buf)?);
+        }
+        Ok(Copy4args { src_stateid, dst_stateid, src_offset, dst_offset, count, consecutive, synchronous, source_server })
+    }
+}
+
+impl XdrEncode for Clone4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.src_stateid.xdr_encode(buf);
+        self.dst_stateid.xdr_encode(buf);
+        self.src_offset.xdr_encode(buf);
+        self.dst_offset.xdr_encode(buf);
+        self.count.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Clone4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let src_stateid = Stateid4::xdr_decode(buf)?;
+        let dst_stateid = Stateid4::xdr_decode(buf)?;
+        let src_offset = u64::xdr_decode(buf)?;
+        let dst_offset = u64::xdr_decode(buf)?;
+        let count = u64::xdr_decode(buf)?;
+        Ok(Clone4args { src_stateid, dst_stateid, src_offset, dst_offset, count })
+    }
+}
+
+impl XdrEncode for Seek4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.stateid.xdr_encode(buf);
+        self.offset.xdr_encode(buf);
+        self.what.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Seek4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let stateid = Stateid4::xdr_decode(buf)?;
+        let offset = u64::xdr_decode(buf)?;
+        let what = u32::xdr_decode(buf)?;
+        Ok(Seek4args { stateid, offset, what })
+    }
+}
+
+impl XdrEncode for Seek4resok {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.eof.xdr_encode(buf);
+        self.offset.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Seek4resok {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let eof = bool::xdr_decode(buf)?;
+        let offset = u64::xdr_decode(buf)?;
+        Ok(Seek4resok { eof, offset })
+    }
+}
+
+impl XdrEncode for Allocate4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.stateid.xdr_encode(buf);
+        self.offset.xdr_encode(buf);
+        self.length.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Allocate4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let stateid = Stateid4::xdr_decode(buf)?;
+        let offset = u64::xdr_decode(buf)?;
+        let length = u64::xdr_decode(buf)?;
+        Ok(Allocate4args { stateid, offset, length })
+    }
+}
+
+impl XdrEncode for CopyNotify4args {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.src_stateid.xdr_encode(buf);
+        self.destination.xdr_encode(buf);
+    }
+}
+impl XdrDecode for CopyNotify4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let src_stateid = Stateid4::xdr_decode(buf)?;
+        let destination = Netloc4::xdr_decode(buf)?;
+        Ok(CopyNotify4args { src_stateid, destination })
+    }
+}
+
+impl XdrEncode for CopyNotify4resok {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.lease_time.xdr_encode(buf);
+        0u32.xdr_encode(buf);
+        self.stateid.xdr_encode(buf);
+        (self.source_server.len() as u32).xdr_encode(buf);
+        for loc in &self.source_server {
+            loc.xdr_encode(buf);
+        }
+    }
+}
+impl XdrDecode for CopyNotify4resok {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let lease_time = u64::xdr_decode(buf)?;
+        let _nsecs = u32::xdr_decode(buf)?;
+        let stateid = Stateid4::xdr_decode(buf)?;
+        let n = u32::xdr_decode(buf)? as usize;
+        let mut source_server = Vec::with_capacity(n.min(buf.len() / 8));
+        for _ in 0..n {
+            source_server.push(Netloc4::xdr_decode(buf)?);
+        }
+        Ok(CopyNotify4resok { lease_time, stateid, source_server })
+    }
+}
+
+impl XdrEncode for ReadPlusContent4 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        match self {
+            ReadPlusContent4::Data { offset, data } => {
+                NFS4_CONTENT_DATA.xdr_encode(buf);
+                offset.xdr_encode(buf);
+                data.xdr_encode(buf);
+            }
+            ReadPlusContent4::Hole { offset, length } => {
+                NFS4_CONTENT_HOLE.xdr_encode(buf);
+                offset.xdr_encode(buf);
+                length.xdr_encode(buf);
+            }
+        }
+    }
+}
+impl XdrDecode for ReadPlusContent4 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        match u32::xdr_decode(buf)? {
+            NFS4_CONTENT_DATA => Ok(ReadPlusContent4::Data { offset: u64::xdr_decode(buf)?, data: Bytes::xdr_decode(buf)? }),
+            NFS4_CONTENT_HOLE => Ok(ReadPlusContent4::Hole { offset: u64::xdr_decode(buf)?, length: u64::xdr_decode(buf)? }),
+            other => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("bad data_content4 {}", other))),
+        }
+    }
+}
+
+impl XdrEncode for ReadPlus4resok {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.eof.xdr_encode(buf);
+        (self.contents.len() as u32).xdr_encode(buf);
+        for content in &self.contents {
+            content.xdr_encode(buf);
+        }
+    }
+}
+impl XdrDecode for ReadPlus4resok {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let eof = bool::xdr_decode(buf)?;
+        let n = u32::xdr_decode(buf)? as usize;
+        // each content takes at least 16 bytes; don't trust n for the allocation
+        let mut contents = Vec::with_capacity(n.min(buf.len() / 16));
+        for _ in 0..n {
+            contents.push(ReadPlusContent4::xdr_decode(buf)?);
+        }
+        Ok(ReadPlus4resok { eof, contents })
+    }
+}
+
+impl XdrEncode for WriteResponse4 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        // wr_callback_id<1>
+        match &self.callback_id {
+            Some(stateid) => {
+                1u32.xdr_encode(buf);
+                stateid.xdr_encode(buf);
+            }
+            None => 0u32.xdr_encode(buf),
+        }
+        self.count.xdr_encode(buf);
+        self.committed.xdr_encode(buf);
+        self.writeverf.xdr_encode(buf);
+    }
+}
+impl XdrDecode for WriteResponse4 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let callback_id = match u32::xdr_decode(buf)? {
+            0 => None,
+            1 => Some(Stateid4::xdr_decode(buf)?),
+            n => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} callback ids", n))),
+        };
+        let count = u64::xdr_decode(buf)?;
+        let committed = u32::xdr_decode(buf)?;
+        let writeverf = u64::xdr_decode(buf)?;
+        Ok(WriteResponse4 { callback_id, count, committed, writeverf })
+    }
+}
+
+impl XdrDecode for Compound4args {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let tag = XdrString::xdr_decode(buf)?;
+        let minorversion = u32::xdr_decode(buf)?;
+        let numops = u32::xdr_decode(buf)? as usize;
+        // every op takes at least its opcode; don't trust numops for the allocation
+        let mut operations = Vec::with_capacity(numops.min(buf.len() / 4));
         for _ in 0..numops {
-            let opcode = u32::xdr_deserialize(r)?;
-            if opcode == NfsOp4::OpGetattr as u32 {
-                let bm: Vec<u32> = Vec::<u32>::xdr_deserialize(r)?;
-                let opdata = crate::xdr::serialize_to_vec(&bm)?;
-                operations.push(Op4 { opcode, opdata });
-            } else if opcode == NfsOp4::OpPutfh as u32 {
-                let fh: Vec<u8> = Vec::<u8>::xdr_deserialize(r)?;
-                let opdata = crate::xdr::serialize_to_vec(&fh)?;
-                operations.push(Op4 { opcode, opdata });
-            } else if opcode == NfsOp4::OpLookup as u32 {
-                let name: XdrString = XdrString::xdr_deserialize(r)?;
-                let opdata = crate::xdr::serialize_to_vec(&name)?;
-                operations.push(Op4 { opcode, opdata });
-            } else if opcode == NfsOp4::OpSetattr as u32 {
-                let bm: Vec<u32> = Vec::<u32>::xdr_deserialize(r)?;
-                let attrs: Vec<u8> = Vec::<u8>::xdr_deserialize(r)?;
-                let mut opdata = crate::xdr::serialize_to_vec(&bm)?;
-                opdata.extend(crate::xdr::serialize_to_vec(&attrs)?);
-                operations.push(Op4 { opcode, opdata });
-            } else {
-                operations.push(Op4 { opcode, opdata: vec![] });
+            let opcode = u32::xdr_decode(buf)?;
+            // opdata is the op's argument bytes, sliced out of the receive buffer
+            let start = buf.clone();
+            if !matches!(skip_op4_args(opcode, buf), Ok(true)) {
+                // Arguments we cannot find the end of take the rest of the
+                // call with them. The op fails, with BADXDR, NOTSUPP or
+                // OP_ILLEGAL, and nothing after it would run anyway.
+                *buf = start.slice(start.len()..);
+                operations.push(Op4 { opcode, opdata: start });
+                break;
             }
+            let opdata = start.slice(..start.len() - buf.len());
+            operations.push(Op4 { opcode, opdata });
         }
         Ok(Compound4args { tag, minorversion, operations })
     }
 }
 
+impl XdrDeserialize for Compound4args {
+    fn xdr_deserialize<R: std::io::Read>(r: &mut R) -> std::io::Result<Self> {
+        // COMPOUND args always run to the end of the call body
+        let mut raw = Vec::new();
+        r.read_to_end(&mut raw)?;
+        Compound4args::xdr_decode(&mut Bytes::from(raw))
+    }
+}
+
+// Advance past the arguments of `opcode` without interpreting them; false
+// for an op whose arguments we do not know
+fn skip_op4_args(opcode: u32, buf: &mut Bytes) -> std::io::Result<bool> {
+    let no_args = [
+        NfsOp4::OpPutrootfh,
+        NfsOp4::OpPutpubfh,
+        NfsOp4::OpGetfh,
+        NfsOp4::OpSavefh,
+        NfsOp4::OpRestorefh,
+        NfsOp4::OpLookupp,
+        NfsOp4::OpReadlink,
+    ];
+    if no_args.iter().any(|&op| opcode == op as u32) {
+        return Ok(true);
+    }
+    if opcode == NfsOp4::OpGetattr as u32 {
+        Vec::<u32>::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpAccess as u32 {
+        u32::xdr_decode(buf)?;
+    } else if [NfsOp4::OpPutfh, NfsOp4::OpLookup, NfsOp4::OpRemove, NfsOp4::OpLink].iter().any(|&op| opcode == op as u32) {
+        Bytes::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpSetattr as u32 {
+        Setattr4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpCreate as u32 {
+        Create4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpRename as u32 {
+        Rename4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpRead as u32 || opcode == NfsOp4::OpReadPlus as u32 {
+        Read4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpWrite as u32 {
+        Write4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpCommit as u32 {
+        Commit4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpReaddir as u32 {
+        Readdir4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpCopy as u32 {
+        Copy4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpClone as u32 {
+        Clone4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpCopyNotify as u32 {
+        CopyNotify4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpSeek as u32 {
+        Seek4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpAllocate as u32 || opcode == NfsOp4::OpDeallocate as u32 {
+        Allocate4args::xdr_decode(buf)?;
+    } else if opcode == NfsOp4::OpOffloadCancel as u32 || opcode == NfsOp4::OpOffloadStatus as u32 {
+        Stateid4::xdr_decode(buf)?;
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
 impl XdrSerialize for Compound4res {
     fn xdr_serialize<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> {
         self.status.xdr_serialize(w)?;
         self.tag.xdr_serialize(w)
     }
 }
+impl XdrEncode for Compound4res {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.status.xdr_encode(buf);
+        self.tag.xdr_encode(buf);
+    }
+}
+
+/// Whether `bit` is set in a bitmap4
+pub fn bitmap4_has(bitmap: &[u32], bit: u32) -> bool {
+    bitmap.get((bit / 32) as usize).is_some_and(|w| w & (1 << (bit % 32)) != 0)
+}
 
 // Helper to build a simple bitmap4 as Vec<u32>
 pub fn bitmap4_with(bits: &[u32]) -> Vec<u32> {
@@ -0,0 +1,116 @@
+//! NLM v4 (network lock manager) and NSM (status monitor) XDR types, as
+//! specified by the X/Open XNFS document
+use crate::xdr::*;
+use bytes::{Bytes, BytesMut};
+use num_derive::{FromPrimitive, ToPrimitive};
+
+pub const NLM_PROGRAM: u32 = 100021;
+pub const NLM_VERSION4: u32 = 4;
+pub const NSM_PROGRAM: u32 = 100024;
+pub const NSM_VERSION: u32 = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
+pub enum NlmProc {
+    Null = 0,
+    Test = 1,
+    Lock = 2,
+    Cancel = 3,
+    Unlock = 4,
+    Granted = 5,
+    Share = 20,
+    Unshare = 21,
+    NmLock = 22,
+    FreeAll = 23,
+}
+
+// nlm4_stats
+pub const NLM4_GRANTED: u32 = 0;
+pub const NLM4_DENIED: u32 = 1;
+pub const NLM4_DENIED_NOLOCKS: u32 = 2;
+pub const NLM4_BLOCKED: u32 = 3;
+pub const NLM4_DENIED_GRACE_PERIOD: u32 = 4;
+pub const NLM4_DEADLCK: u32 = 5;
+pub const NLM4_ROFS: u32 = 6;
+pub const NLM4_STALE_FH: u32 = 7;
+pub const NLM4_FBIG: u32 = 8;
+pub const NLM4_FAILED: u32 = 9;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
+pub enum NsmProc {
+    Null = 0,
+    Stat = 1,
+    Mon = 2,
+    Unmon = 3,
+    UnmonAll = 4,
+    SimuCrash = 5,
+    Notify = 6,
+}
+
+// sm_res
+pub const STAT_SUCC: u32 = 0;
+pub const STAT_FAIL: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Nlm4Lock {
+    pub caller_name: String,
+    pub fh: Vec<u8>,
+    pub oh: Vec<u8>,
+    pub svid: i32,
+    pub l_offset: u64,
+    pub l_len: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Nlm4Holder {
+    pub exclusive: bool,
+    pub svid: i32,
+    pub oh: Vec<u8>,
+    pub l_offset: u64,
+    pub l_len: u64,
+}
+
+impl XdrEncode for Nlm4Lock {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.caller_name.xdr_encode(buf);
+        self.fh.xdr_encode(buf);
+        self.oh.xdr_encode(buf);
+        self.svid.xdr_encode(buf);
+        self.l_offset.xdr_encode(buf);
+        self.l_len.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Nlm4Lock {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        Ok(Nlm4Lock {
+            caller_name: String::xdr_decode(buf)?,
+            fh: Vec::<u8>::xdr_decode(buf)?,
+            oh: Vec::<u8>::xdr_decode(buf)?,
+            svid: i32::xdr_decode(buf)?,
+            l_offset: u64::xdr_decode(buf)?,
+            l_len: u64::xdr_decode(buf)?,
+        })
+    }
+}
+
+impl XdrEncode for Nlm4Holder {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.exclusive.xdr_encode(buf);
+        self.svid.xdr_encode(buf);
+        self.oh.xdr_encode(buf);
+        self.l_offset.xdr_encode(buf);
+        self.l_len.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Nlm4Holder {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        Ok(Nlm4Holder {
+            exclusive: bool::xdr_decode(buf)?,
+            svid: i32::xdr_decode(buf)?,
+            oh: Vec::<u8>::xdr_decode(buf)?,
+            l_offset: u64::xdr_decode(buf)?,
+            l_len: u64::xdr_decode(buf)?,
+        })
+    }
+}
@@ -0,0 +1,109 @@
+//! Portmapper (PMAP v2, RFC 1833 section 3) and RPCBIND v3/v4 (RFC 1833
+//! section 2) XDR types
+use crate::xdr::*;
+use bytes::{Bytes, BytesMut};
+
+pub const PMAP_PROGRAM: u32 = 100000;
+pub const PMAP_PORT: u16 = 111;
+pub const PMAP_VERSION: u32 = 2;
+pub const RPCBIND_VERSION3: u32 = 3;
+pub const RPCBIND_VERSION4: u32 = 4;
+
+pub const IPPROTO_TCP: u32 = 6;
+pub const IPPROTO_UDP: u32 = 17;
+
+// PMAP v2 procedures
+pub const PMAPPROC_NULL: u32 = 0;
+pub const PMAPPROC_SET: u32 = 1;
+pub const PMAPPROC_UNSET: u32 = 2;
+pub const PMAPPROC_GETPORT: u32 = 3;
+pub const PMAPPROC_DUMP: u32 = 4;
+
+// RPCBIND v3/v4 procedures
+pub const RPCBPROC_NULL: u32 = 0;
+pub const RPCBPROC_SET: u32 = 1;
+pub const RPCBPROC_UNSET: u32 = 2;
+pub const RPCBPROC_GETADDR: u32 = 3;
+pub const RPCBPROC_DUMP: u32 = 4;
+/// v4 only; same reply as GETADDR but the version must match exactly
+pub const RPCBPROC_GETVERSADDR: u32 = 9;
+
+/// PMAP v2 `mapping`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub prog: u32,
+    pub vers: u32,
+    pub prot: u32,
+    pub port: u32,
+}
+
+/// RPCBIND v3/v4 `rpcb`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rpcb {
+    pub prog: u32,
+    pub vers: u32,
+    pub netid: String,
+    pub addr: String,
+    pub owner: String,
+}
+
+impl XdrEncode for Mapping {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.prog.xdr_encode(buf);
+        self.vers.xdr_encode(buf);
+        self.prot.xdr_encode(buf);
+        self.port.xdr_encode(buf);
+    }
+}
+impl XdrDecode for Mapping {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        Ok(Mapping {
+            prog: u32::xdr_decode(buf)?,
+            vers: u32::xdr_decode(buf)?,
+            prot: u32::xdr_decode(buf)?,
+            port: u32::xdr_decode(buf)?,
+        })
+    }
+}
+
+fn decode_string(buf: &mut Bytes) -> std::io::Result<String> {
+    let raw = XdrString::xdr_decode(buf)?;
+    String::from_utf8(raw.0).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "non-utf8 rpcb string"))
+}
+
+impl XdrEncode for Rpcb {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.prog.xdr_encode(buf);
+        self.vers.xdr_encode(buf);
+        self.netid.as_bytes().xdr_encode(buf);
+        self.addr.as_bytes().xdr_encode(buf);
+        self.owner.as_bytes().xdr_encode(buf);
+    }
+}
+impl XdrDecode for Rpcb {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        Ok(Rpcb {
+            prog: u32::xdr_decode(buf)?,
+            vers: u32::xdr_decode(buf)?,
+            netid: decode_string(buf)?,
+            addr: decode_string(buf)?,
+            owner: decode_string(buf)?,
+        })
+    }
+}
+
+/// Format a socket address as an RPCBIND universal address
+/// (`h1.h2.h3.h4.p1.p2` for IPv4, `addr.p1.p2` for IPv6).
+pub fn to_uaddr(addr: &std::net::SocketAddr) -> String {
+    let port = addr.port();
+    format!("{}.{}.{}", addr.ip(), port >> 8, port & 0xff)
+}
+
+/// Parse a universal address back into a socket address.
+pub fn from_uaddr(uaddr: &str) -> Option<std::net::SocketAddr> {
+    let mut parts = uaddr.rsplitn(3, '.');
+    let lo: u8 = parts.next()?.parse().ok()?;
+    let hi: u8 = parts.next()?.parse().ok()?;
+    let ip: std::net::IpAddr = parts.next()?.parse().ok()?;
+    Some(std::net::SocketAddr::new(ip, u16::from_be_bytes([hi, lo])))
+}
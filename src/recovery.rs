@@ -0,0 +1,46 @@
+//! Client recovery record. Written to the state directory when the server
+//! shuts down and read back when it starts, so that a restart looks like a
+//! reboot to clients rather than like a different server: the NSM state
+//! number keeps growing, and the hosts NLM monitored and the MOUNT list
+//! reported by DUMP are carried over.
+
+use crate::error::{NfsError, NfsResult};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const RECORD_FILE: &str = "recovery.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryRecord {
+    /// NSM state number at shutdown
+    pub nsm_state: i32,
+    /// Hosts holding (or having held) NLM locks
+    pub monitored: Vec<String>,
+    /// (host, path) MOUNT entries
+    pub mounts: Vec<(String, String)>,
+}
+
+impl RecoveryRecord {
+    /// The record saved in `dir`, if there is one.
+    pub fn load(dir: &Path) -> NfsResult<Option<Self>> {
+        let path = dir.join(RECORD_FILE);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_str(&text).map(Some).map_err(|e| NfsError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Save the record to `dir`, replacing the previous one atomically so a
+    /// crash mid-write leaves the old record intact.
+    pub fn save(&self, dir: &Path) -> NfsResult<()> {
+        std::fs::create_dir_all(dir)?;
+        let tmp = dir.join(format!("{}.tmp", RECORD_FILE));
+        let text = serde_json::to_vec_pretty(self).map_err(|e| NfsError::Config(e.to_string()))?;
+        std::fs::write(&tmp, text)?;
+        std::fs::File::open(&tmp)?.sync_all()?;
+        std::fs::rename(&tmp, dir.join(RECORD_FILE))?;
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
+use crate::auth::{Credential, MAX_AUTH_BYTES};
 use crate::xdr::*;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::io::{Read, Write};
+use bytes::{Bytes, BytesMut};
+use std::io::{IoSlice, Read, Write};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 // ONC RPC over TCP record marking standard
 pub fn write_record_marked(mut w: impl Write, payload: &[u8]) -> std::io::Result<()> {
@@ -117,19 +120,104 @@ pub struct RpcReplyHeader {
     pub accept_state: u32, // SUCCESS = 0
 }
 
+// accept_stat values for MSG_ACCEPTED replies
+pub const ACCEPT_SUCCESS: u32 = 0;
+pub const PROG_UNAVAIL: u32 = 1;
+pub const PROG_MISMATCH: u32 = 2;
+pub const PROC_UNAVAIL: u32 = 3;
+pub const GARBAGE_ARGS: u32 = 4;
+pub const SYSTEM_ERR: u32 = 5;
+
 impl RpcReplyHeader {
     pub fn success(xid: u32) -> Self {
+        Self::accept_error(xid, ACCEPT_SUCCESS)
+    }
+
+    /// An accepted reply carrying a non-success accept_stat
+    pub fn accept_error(xid: u32, accept_state: u32) -> Self {
         RpcReplyHeader {
             xid,
             msg_type: RpcMessageType::Reply,
             reply_state: 0,
             verf_flavor: 0,
             verf_len: 0,
-            accept_state: 0,
+            accept_state,
+        }
+    }
+}
+
+// auth_stat values for MSG_DENIED replies
+pub const AUTH_TOOWEAK: u32 = 5;
+
+/// The one version of RPC there is
+pub const RPC_VERSION: u32 = 2;
+
+/// A call of an RPC version we do not speak: MSG_DENIED with RPC_MISMATCH
+/// and the versions we do
+pub fn encode_rpc_mismatch(xid: u32) -> XdrChain {
+    let mut reply = XdrChain::new();
+    reply.put(&xid);
+    reply.put(&(RpcMessageType::Reply as u32));
+    reply.put(&1u32); // MSG_DENIED
+    reply.put(&0u32); // RPC_MISMATCH
+    reply.put(&RPC_VERSION);
+    reply.put(&RPC_VERSION);
+    reply
+}
+
+/// A call refused for its credential: MSG_DENIED with AUTH_ERROR and the
+/// reason, carrying no verifier or body
+pub fn encode_auth_error(xid: u32, auth_stat: u32) -> XdrChain {
+    let mut reply = XdrChain::new();
+    reply.put(&xid);
+    reply.put(&(RpcMessageType::Reply as u32));
+    reply.put(&1u32); // MSG_DENIED
+    reply.put(&1u32); // AUTH_ERROR
+    reply.put(&auth_stat);
+    reply
+}
+
+/// Why an accepted call produced no result body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptError {
+    ProgUnavail,
+    ProgMismatch { low: u32, high: u32 },
+    ProcUnavail,
+    GarbageArgs,
+    SystemErr,
+}
+
+impl AcceptError {
+    pub fn accept_stat(&self) -> u32 {
+        match self {
+            AcceptError::ProgUnavail => PROG_UNAVAIL,
+            AcceptError::ProgMismatch { .. } => PROG_MISMATCH,
+            AcceptError::ProcUnavail => PROC_UNAVAIL,
+            AcceptError::GarbageArgs => GARBAGE_ARGS,
+            AcceptError::SystemErr => SYSTEM_ERR,
         }
     }
 }
 
+/// Build a complete accepted reply from a program handler's outcome
+pub fn encode_rpc_reply(xid: u32, result: Result<XdrChain, AcceptError>) -> XdrChain {
+    let mut reply = XdrChain::new();
+    match result {
+        Ok(body) => {
+            reply.put(&RpcReplyHeader::success(xid));
+            reply.append(body);
+        }
+        Err(err) => {
+            reply.put(&RpcReplyHeader::accept_error(xid, err.accept_stat()));
+            if let AcceptError::ProgMismatch { low, high } = err {
+                reply.put(&low);
+                reply.put(&high);
+            }
+        }
+    }
+    reply
+}
+
 impl XdrSerialize for RpcReplyHeader {
     fn xdr_serialize<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
         self.xid.xdr_serialize(w)?;
@@ -162,3 +250,139 @@ impl XdrDeserialize for RpcReplyHeader {
         Ok(RpcReplyHeader { xid, msg_type, reply_state, verf_flavor, verf_len, accept_state })
     }
 }
+
+impl XdrDecode for RpcMessageType {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        match u32::xdr_decode(buf)? {
+            0 => Ok(RpcMessageType::Call),
+            1 => Ok(RpcMessageType::Reply),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid rpc msg type")),
+        }
+    }
+}
+
+impl XdrDecode for RpcCallHeader {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        Ok(RpcCall::xdr_decode(buf)?.header)
+    }
+}
+
+/// A call header together with the caller's credential. The verifier is
+/// skipped; we do not support flavors that need one checked.
+#[derive(Debug, Clone)]
+pub struct RpcCall {
+    pub header: RpcCallHeader,
+    pub cred: Credential,
+}
+
+impl XdrDecode for RpcCall {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let xid = u32::xdr_decode(buf)?;
+        let msg_type = RpcMessageType::xdr_decode(buf)?;
+        if !matches!(msg_type, RpcMessageType::Call) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a call"));
+        }
+        let rpcvers = u32::xdr_decode(buf)?;
+        let prog = u32::xdr_decode(buf)?;
+        let vers = u32::xdr_decode(buf)?;
+        let proc = u32::xdr_decode(buf)?;
+        // credential and verifier: flavor + opaque body
+        let auth_flavor = u32::xdr_decode(buf)?;
+        let auth_body = Bytes::xdr_decode(buf)?;
+        if auth_body.len() > MAX_AUTH_BYTES {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "oversized credential"));
+        }
+        let cred = Credential::decode(auth_flavor, auth_body)?;
+        let _verf_flavor = u32::xdr_decode(buf)?;
+        let _verf_body = Bytes::xdr_decode(buf)?;
+        Ok(RpcCall { header: RpcCallHeader { xid, msg_type, rpcvers, prog, vers, proc }, cred })
+    }
+}
+
+impl XdrEncode for RpcCall {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        let h = &self.header;
+        h.xid.xdr_encode(buf);
+        (h.msg_type as u32).xdr_encode(buf);
+        h.rpcvers.xdr_encode(buf);
+        h.prog.xdr_encode(buf);
+        h.vers.xdr_encode(buf);
+        h.proc.xdr_encode(buf);
+        self.cred.xdr_encode(buf);
+        // verf: AUTH_NULL
+        0u32.xdr_encode(buf);
+        0u32.xdr_encode(buf);
+    }
+}
+
+impl XdrEncode for RpcReplyHeader {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.xid.xdr_encode(buf);
+        (self.msg_type as u32).xdr_encode(buf);
+        self.reply_state.xdr_encode(buf);
+        self.verf_flavor.xdr_encode(buf);
+        self.verf_len.xdr_encode(buf);
+        self.accept_state.xdr_encode(buf);
+    }
+}
+
+impl XdrDecode for RpcReplyHeader {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let xid = u32::xdr_decode(buf)?;
+        let msg_type = RpcMessageType::xdr_decode(buf)?;
+        let reply_state = u32::xdr_decode(buf)?;
+        let verf_flavor = u32::xdr_decode(buf)?;
+        let verf = Bytes::xdr_decode(buf)?;
+        let accept_state = u32::xdr_decode(buf)?;
+        Ok(RpcReplyHeader { xid, msg_type, reply_state, verf_flavor, verf_len: verf.len() as u32, accept_state })
+    }
+}
+
+/// Largest record we are willing to reassemble from fragments.
+pub const MAX_RECORD_SIZE: usize = 16 * 1024 * 1024;
+
+/// Read one record-marked RPC message, reassembling fragments, into `buf`
+/// and return it as a frozen `Bytes` that decoders can slice without copying.
+pub async fn read_record<R: AsyncRead + Unpin>(r: &mut R, buf: &mut BytesMut) -> std::io::Result<Bytes> {
+    let hdr = r.read_u32().await?;
+    read_record_from(r, hdr, buf).await
+}
+
+/// `read_record` for a record whose first fragment header, `hdr`, has
+/// already been read
+pub async fn read_record_from<R: AsyncRead + Unpin>(r: &mut R, mut hdr: u32, buf: &mut BytesMut) -> std::io::Result<Bytes> {
+    buf.clear();
+    loop {
+        let last = (hdr & (1u32 << 31)) != 0;
+        let len = (hdr & 0x7fff_ffff) as usize;
+        if buf.len() + len > MAX_RECORD_SIZE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "rpc record too large"));
+        }
+        let start = buf.len();
+        buf.resize(start + len, 0);
+        r.read_exact(&mut buf[start..]).await?;
+        if last {
+            return Ok(buf.split().freeze());
+        }
+        hdr = r.read_u32().await?;
+    }
+}
+
+/// Write `segs` as a single record-marked message using vectored writes, so
+/// large payload segments go to the socket without being concatenated first.
+pub async fn write_record_vectored<W: AsyncWrite + Unpin>(w: &mut W, segs: &[Bytes]) -> std::io::Result<()> {
+    let len: usize = segs.iter().map(|s| s.len()).sum();
+    let header = ((1u32 << 31) | len as u32).to_be_bytes();
+    let mut slices: Vec<IoSlice<'_>> = Vec::with_capacity(segs.len() + 1);
+    slices.push(IoSlice::new(&header));
+    slices.extend(segs.iter().filter(|s| !s.is_empty()).map(|s| IoSlice::new(s)));
+    let mut remaining = &mut slices[..];
+    while !remaining.is_empty() {
+        let n = w.write_vectored(remaining).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        IoSlice::advance_slices(&mut remaining, n);
+    }
+    w.flush().await
+}
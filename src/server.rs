@@ -1,193 +1,1766 @@
-use crate::error::{NfsResult};
+use crate::audit::{describe_setattr, hex, AuditAction, AuditEvent, AuditLog, Target};
+use crate::auth::{Caller, RPCSEC_GSS};
+use crate::client::{resolve, SourceClient};
+use crate::config::{LimitsConfig, NfsConfig, RpcbindMode};
+use crate::error::{Nfs4Status, NfsError, NfsResult};
+use crate::export::{Access, ExportTable};
+use crate::limits::Limits;
+use crate::lock::LockManager;
+use crate::mount::MountService;
+use crate::portmap::{netid, register_with_rpcbind, served_services, Portmapper};
+use crate::nfs3::Nfs3Service;
+use crate::nlm::NlmService;
+use crate::nsm::NsmService;
+use crate::offload::{copy_chunks, OffloadTable, NOTIFY_LEASE};
+use crate::proto::mount::MOUNT_PROGRAM;
+use crate::proto::nfs3::NFS3_VERSION;
 use crate::proto::nfs4::*;
+use crate::proto::nlm::{NLM_PROGRAM, NLM_VERSION4, NSM_PROGRAM, NSM_VERSION};
+use crate::proto::portmap::{to_uaddr, PMAP_PROGRAM};
+use crate::recovery::RecoveryRecord;
 use crate::rpc::*;
 use crate::xdr::*;
-use crate::vfs::{MemVfs, Vfs};
-use tokio::net::TcpListener;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tracing::{debug, error, info};
-use std::sync::Arc;
+use crate::perm;
+use crate::vfs::{decode_settable_fattr4, encode_file_fattr4, Content, CreateKind, DirEntry, FileAttr, FileType, SetAttr, Vfs};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::field::{display, Empty};
+use tracing::{debug, debug_span, error, info, info_span, warn, Instrument};
+use bytes::{Bytes, BytesMut};
+use futures::future::{try_join_all, BoxFuture};
+use num_traits::FromPrimitive;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+/// Produces a fresh configuration when a reload is requested
+pub type ConfigSource = Arc<dyn Fn() -> NfsResult<NfsConfig> + Send + Sync>;
+
+/// The configuration a server is running with. Reloads apply export rules
+/// and logging to new requests; listener settings only change on restart.
+pub struct ConfigHandle {
+    current: RwLock<Arc<NfsConfig>>,
+    exports: Arc<ExportTable>,
+    source: OnceLock<ConfigSource>,
+}
+
+impl ConfigHandle {
+    pub fn current(&self) -> Arc<NfsConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Switch to `cfg`. A configuration that fails validation, or whose
+    /// exports cannot be set up, is refused and nothing changes.
+    pub fn reload(&self, cfg: NfsConfig) -> NfsResult<()> {
+        cfg.validate()?;
+        let old = self.current();
+        let applied = NfsConfig { exports: cfg.exports.clone(), log: cfg.log.clone(), ..(*old).clone() };
+        if serde_json::to_value(&applied).ok() != serde_json::to_value(&cfg).ok() {
+            warn!("listener, portmapper, limit and audit settings take effect after a restart");
+        }
+        self.exports.reload(&applied.exports)?;
+        if applied.log != old.log {
+            crate::logging::set_filter(applied.log.as_deref())?;
+        }
+        info!("configuration reloaded: {} exports", applied.exports.len());
+        *self.current.write().unwrap() = Arc::new(applied);
+        Ok(())
+    }
+
+    /// Reload from the server's configuration source, e.g. on SIGHUP.
+    pub fn reload_from_source(&self) -> NfsResult<()> {
+        let source = self.source.get().ok_or_else(|| NfsError::Config("no configuration source to reload from".into()))?;
+        self.reload(source()?)
+    }
+}
+
+/// Stops a running server. Listeners stop accepting at once; requests
+/// already executing get the grace period to finish and send their replies
+/// before the connections carrying them are dropped.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    tx: Arc<watch::Sender<bool>>,
+    grace: Duration,
+}
+
+impl ShutdownHandle {
+    pub fn new(grace: Duration) -> Self {
+        Self { tx: Arc::new(watch::Sender::new(false)), grace }
+    }
+
+    pub fn shutdown(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Resolves once `shutdown` has been called.
+    pub async fn wait(&self) {
+        let mut rx = self.tx.subscribe();
+        let _ = rx.wait_for(|stopped| *stopped).await;
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+}
 
 pub struct NfsServer {
-    cfg: crate::config::NfsConfig,
-    vfs: Arc<dyn Vfs>,
+    cfg: NfsConfig,
+    dispatcher: Arc<Dispatcher>,
+    config: Arc<ConfigHandle>,
+    shutdown: ShutdownHandle,
 }
 
 impl NfsServer {
-    pub async fn new(cfg: crate::config::NfsConfig) -> NfsResult<Self> {
-        Ok(Self { cfg, vfs: MemVfs::new() })
+    pub async fn new(cfg: NfsConfig) -> NfsResult<Self> {
+        cfg.validate()?;
+        let dispatcher = Dispatcher::from_config(&cfg)?;
+        let config = Arc::new(ConfigHandle {
+            current: RwLock::new(Arc::new(cfg.clone())),
+            exports: dispatcher.exports().clone(),
+            source: OnceLock::new(),
+        });
+        if let Some(dir) = &cfg.state_dir {
+            if let Some(record) = RecoveryRecord::load(Path::new(dir))? {
+                info!("recovered {} mounts and {} monitored hosts from {}", record.mounts.len(), record.monitored.len(), dir);
+                dispatcher.restore(&record);
+                if !record.monitored.is_empty() && cfg.grace_period > 0 {
+                    info!("lock grace period of {}s for clients to reclaim", cfg.grace_period);
+                    dispatcher.locks().start_grace(Duration::from_secs(cfg.grace_period));
+                }
+            }
+        }
+        let shutdown = ShutdownHandle::new(Duration::from_secs(cfg.shutdown_timeout));
+        Ok(Self { cfg, dispatcher, config, shutdown })
+    }
+
+    /// Where SIGHUP reloads read the configuration from, typically the
+    /// configuration file plus command line overrides.
+    pub fn with_config_source(self, source: impl Fn() -> NfsResult<NfsConfig> + Send + Sync + 'static) -> Self {
+        let _ = self.config.source.set(Arc::new(source));
+        self
+    }
+
+    pub fn config(&self) -> &Arc<ConfigHandle> {
+        &self.config
+    }
+
+    pub fn dispatcher(&self) -> &Arc<Dispatcher> {
+        &self.dispatcher
+    }
+
+    /// Handle that makes `run` return once in-flight requests are done.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
     }
 
     pub async fn run(self) -> NfsResult<()> {
         let addr = format!("{}:{}", self.cfg.bind_addr, self.cfg.port);
         let listener = TcpListener::bind(&addr).await?;
         info!("NFSv4.2 server listening on {}", addr);
-        run_on_listener(listener, self.vfs.clone()).await
+        let dispatcher = self.dispatcher.clone();
+        let shutdown = self.shutdown.clone();
+        let mut tasks: Vec<BoxFuture<'static, NfsResult<()>>> = vec![Box::pin(serve_tcp(listener, dispatcher.clone(), shutdown.clone()))];
+        #[cfg(unix)]
+        tasks.push(Box::pin(reload_on_sighup(self.config.clone(), shutdown.clone())));
+        if self.cfg.udp {
+            let socket = UdpSocket::bind(&addr).await?;
+            info!("NULL/portmapper UDP listener on {}", addr);
+            tasks.push(Box::pin(serve_udp(socket, dispatcher.clone(), self.cfg.max_udp_datagram, shutdown.clone())));
+        }
+        #[cfg(unix)]
+        if let Some(path) = &self.cfg.admin_socket {
+            let listener = crate::admin::bind(Path::new(path))?;
+            let admin = Arc::new(crate::admin::AdminService::new(dispatcher.clone(), self.config.clone()));
+            tasks.push(Box::pin(crate::admin::serve(listener, admin, shutdown.clone())));
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(metrics_addr) = &self.cfg.metrics_addr {
+            let listener = TcpListener::bind(metrics_addr).await?;
+            tasks.push(Box::pin(crate::metrics::serve(listener, dispatcher.clone(), shutdown.clone())));
+        }
+        match self.cfg.rpcbind.mode {
+            RpcbindMode::Off => {}
+            RpcbindMode::Register => {
+                let services = served_services(&self.cfg)?;
+                // NFSv4 clients do not need rpcbind, so this is not fatal
+                match register_with_rpcbind(Path::new(&self.cfg.rpcbind.socket), &services).await {
+                    Ok(()) => info!("registered {} services with rpcbind", services.len()),
+                    Err(e) => warn!("rpcbind registration via {} failed: {}", self.cfg.rpcbind.socket, e),
+                }
+            }
+            RpcbindMode::Serve => {
+                let pmap_addr = format!("{}:{}", self.cfg.bind_addr, self.cfg.rpcbind.port);
+                let pmap_tcp = TcpListener::bind(&pmap_addr).await?;
+                let pmap_udp = UdpSocket::bind(&pmap_addr).await?;
+                info!("portmapper listening on {}", pmap_addr);
+                tasks.push(Box::pin(serve_tcp(pmap_tcp, dispatcher.clone(), shutdown.clone())));
+                tasks.push(Box::pin(serve_udp(pmap_udp, dispatcher.clone(), self.cfg.max_udp_datagram, shutdown.clone())));
+            }
+        }
+        try_join_all(tasks).await?;
+        if let Some(dir) = &self.cfg.state_dir {
+            dispatcher.recovery_record().save(Path::new(dir))?;
+            info!("client recovery record saved to {}", dir);
+        }
+        info!("server stopped");
+        Ok(())
     }
 }
 
-// Expose accept loop for tests/integration to run on a pre-bound listener
-pub async fn run_on_listener(listener: TcpListener, vfs: Arc<dyn Vfs>) -> NfsResult<()> {
+#[cfg(unix)]
+async fn reload_on_sighup(config: Arc<ConfigHandle>, shutdown: ShutdownHandle) -> NfsResult<()> {
+    let mut hup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
     loop {
-        let (mut sock, peer) = listener.accept().await?;
-        info!("connection from {}", peer);
-        let vfs = vfs.clone();
-        tokio::spawn(async move {
-            if let Err(e) = handle_conn(&mut sock, vfs).await {
-                error!("conn error: {:?}", e);
-            }
-        });
+        tokio::select! {
+            received = hup.recv() => if received.is_none() { break },
+            _ = shutdown.wait() => break,
+        }
+        info!("SIGHUP received, reloading configuration");
+        if let Err(e) = config.reload_from_source() {
+            error!("configuration reload refused: {}", e);
+        }
     }
+    Ok(())
 }
 
-async fn handle_conn(sock: &mut tokio::net::TcpStream, vfs: Arc<dyn Vfs>) -> NfsResult<()> {
-    let mut buf = vec![0u8; 64 * 1024];
-    loop {
-        // Read a record-marked RPC message
-        sock.read_exact(&mut buf[..4]).await?;
-        let len_hdr = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
-        let last = (len_hdr & (1u32 << 31)) != 0;
-        let len = (len_hdr & 0x7fff_ffff) as usize;
-        if !last {
-            // For simplicity, expect single fragment
-        }
-        if buf.len() < len { buf.resize(len, 0); }
-        sock.read_exact(&mut buf[..len]).await?;
-
-        let mut cur = std::io::Cursor::new(&buf[..len]);
-        let call = RpcCallHeader::xdr_deserialize(&mut cur)?;
-        debug!("rpc call: {:?}", call);
-    let mut reply_cur = std::io::Cursor::new(Vec::new());
-        let reply_hdr = RpcReplyHeader::success(call.xid);
-        reply_hdr.xdr_serialize(&mut reply_cur)?;
-
-        if call.prog != NFS4_PROGRAM || call.vers != NFS4_VERSION {
-            // RPC PROG_MISMATCH is not encoded here; just accept failure
-            10007u32.xdr_serialize(&mut reply_cur)?; // NFS4ERR_BADTYPE-ish placeholder
-        } else if call.proc == Nfs4Proc::Null as u32 {
+/// Transport a call arrived on. NFSv4 COMPOUND is only allowed over
+/// transports with congestion control, so UDP is limited to NULL-style probes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// Decodes RPC calls and executes them against the backend. One dispatcher
+/// is shared by every listener of a server.
+pub struct Dispatcher {
+    exports: Arc<ExportTable>,
+    nfs3: Nfs3Service,
+    mount: MountService,
+    nlm: NlmService,
+    nsm: NsmService,
+    locks: Arc<LockManager>,
+    portmap: Option<Portmapper>,
+    limits: Arc<Limits>,
+    audit: Option<Arc<AuditLog>>,
+    offloads: OffloadTable,
+    // Where COPY_NOTIFY tells the destination of a copy to find us
+    netlocs: Vec<Netloc4>,
+    // Returned by WRITE and COMMIT in both NFSv3 and NFSv4
+    write_verf: u64,
+}
+
+// Largest READ we answer; clients ask for more and get a short read
+const NFS4_MAX_IO: u32 = 1024 * 1024;
+
+// A component4 naming an entry: UTF-8, not empty, within NFS4_MAXNAMLEN
+// and neither "." nor ".." nor anything with a separator in it
+fn component4(raw: &[u8]) -> NfsResult<&str> {
+    if raw.is_empty() {
+        return Err(NfsError::InvalidArgument("empty name".into()));
+    }
+    if raw.len() > crate::constants::NFS4_MAXNAMLEN as usize {
+        return Err(NfsError::NameTooLong);
+    }
+    let name = std::str::from_utf8(raw).map_err(|_| NfsError::InvalidArgument("name is not UTF-8".into()))?;
+    if name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(NfsError::Status(Nfs4Status::Badname));
+    }
+    Ok(name)
+}
+
+// No OPEN or LOCK state is handed out, so the special stateids are the
+// only valid ones: anonymous for any I/O and the bypass for READ
+fn check_stateid(stateid: &Stateid4, read: bool) -> NfsResult<()> {
+    match *stateid {
+        Stateid4::ANONYMOUS => Ok(()),
+        Stateid4::READ_BYPASS if read => Ok(()),
+        _ => Err(NfsError::BadStateid),
+    }
+}
+
+// The PUTFH naming the source of an inter-server COPY, in PUTFH SAVEFH
+// PUTFH COPY: that handle is the source server's, so is not checked here,
+// and the COPY forgets it once done
+fn foreign_source(ops: &[Op4]) -> Option<usize> {
+    let pattern = [NfsOp4::OpPutfh, NfsOp4::OpSavefh, NfsOp4::OpPutfh, NfsOp4::OpCopy];
+    ops.windows(4).position(|w| {
+        w.iter().zip(pattern).all(|(op, code)| op.opcode == code as u32)
+            && Copy4args::xdr_decode(&mut w[3].opdata.clone()).is_ok_and(|copy| !copy.source_server.is_empty())
+    })
+}
+
+// The bytes a COPY or CLONE of `count` from `src_offset` covers, 0 meaning
+// to the end of the source: the range must lie within the source and, in
+// one file, not overlap the destination's
+fn range_count(src_size: u64, same_file: bool, src_offset: u64, dst_offset: u64, count: u64) -> NfsResult<u64> {
+    let count = match count {
+        0 => src_size.checked_sub(src_offset),
+        n => src_offset.checked_add(n).filter(|&end| end <= src_size).map(|_| n),
+    };
+    let count = count.ok_or_else(|| NfsError::InvalidArgument("range past the end of the source".into()))?;
+    if same_file && src_offset < dst_offset.saturating_add(count) && dst_offset < src_offset + count {
+        return Err(NfsError::InvalidArgument("overlapping ranges within a file".into()));
+    }
+    Ok(count)
+}
+
+// READ, WRITE, COMMIT and the NFSv4.2 operations on file contents apply
+// to regular files; NFSv4.0 has no error codes for the other types
+fn check_io_type(ftype: FileType, minor: u32) -> NfsResult<()> {
+    match ftype {
+        FileType::Regular => Ok(()),
+        FileType::Directory => Err(NfsError::IsDir),
+        _ if minor == 0 => Err(NfsError::InvalidArgument("not a regular file".into())),
+        FileType::Symlink => Err(NfsError::Status(Nfs4Status::Symlink)),
+        _ => Err(NfsError::Status(Nfs4Status::WrongType)),
+    }
+}
+
+// The reply to a COMPOUND whose arguments do not decode: BADXDR and no
+// results, the tag being lost with the rest
+fn badxdr() -> XdrChain {
+    let mut reply = XdrChain::new();
+    reply.put(&Compound4res { status: Nfs4Status::Badxdr as u32, tag: XdrString::default() });
+    reply.put(&0u32);
+    reply
+}
+
+// Write verifier: changes on every restart so clients resend unstable writes
+fn boot_verifier() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+impl Dispatcher {
+    /// Serve `vfs` as a single export at "/" open to every client.
+    pub fn new(vfs: Arc<dyn Vfs>) -> Arc<Self> {
+        Arc::new(Self::build(Arc::new(ExportTable::single(vfs)), None, LimitsConfig::default(), None, vec![]))
+    }
+
+    pub fn from_config(cfg: &NfsConfig) -> NfsResult<Arc<Self>> {
+        let exports = Arc::new(ExportTable::from_config(&cfg.exports)?);
+        let portmap = match cfg.rpcbind.mode {
+            RpcbindMode::Serve => Some(Portmapper::new(served_services(cfg)?)),
+            _ => None,
+        };
+        let audit = match &cfg.audit_log {
+            Some(path) => Some(Arc::new(AuditLog::open(Path::new(path), exports.clone())?)),
+            None => None,
+        };
+        // only a specific bind address says where we can be reached
+        let netlocs = match cfg.bind_addr.parse::<IpAddr>() {
+            Ok(ip) if !ip.is_unspecified() => {
+                let addr = SocketAddr::new(ip, cfg.port);
+                vec![Netloc4::Netaddr { netid: netid("tcp", &addr).into(), addr: to_uaddr(&addr).into() }]
+            }
+            _ => vec![],
+        };
+        Ok(Arc::new(Self::build(exports, portmap, cfg.limits.clone(), audit, netlocs)))
+    }
+
+    fn build(exports: Arc<ExportTable>, portmap: Option<Portmapper>, limits: LimitsConfig, audit: Option<Arc<AuditLog>>, netlocs: Vec<Netloc4>) -> Self {
+        let boot = boot_verifier();
+        let locks = Arc::new(LockManager::new());
+        Self {
+            nfs3: Nfs3Service::new(exports.clone(), boot, audit.clone()),
+            mount: MountService::new(exports.clone()),
+            nlm: NlmService::new(exports.clone(), locks.clone()),
+            nsm: NsmService::new(locks.clone(), (boot / 1_000_000_000) as i32),
+            exports,
+            locks,
+            portmap,
+            limits: Arc::new(Limits::new(limits)),
+            audit,
+            offloads: OffloadTable::new(boot as u32),
+            netlocs,
+            write_verf: boot,
+        }
+    }
+
+    pub fn exports(&self) -> &Arc<ExportTable> {
+        &self.exports
+    }
+
+    /// Byte-range locks shared by NLM and NFSv4.
+    pub fn locks(&self) -> &Arc<LockManager> {
+        &self.locks
+    }
+
+    /// COPY_NOTIFY stateids this server has granted.
+    pub fn offloads(&self) -> &OffloadTable {
+        &self.offloads
+    }
+
+    pub fn mount(&self) -> &MountService {
+        &self.mount
+    }
+
+    /// Connection caps, client rates and the in-flight budget.
+    pub fn limits(&self) -> &Arc<Limits> {
+        &self.limits
+    }
+
+    /// What clients need from us to recover after a restart.
+    pub fn recovery_record(&self) -> RecoveryRecord {
+        RecoveryRecord { nsm_state: self.nsm.state(), monitored: self.nsm.monitored(), mounts: self.mount.mounts() }
+    }
+
+    pub fn nsm(&self) -> &NsmService {
+        &self.nsm
+    }
+
+    /// Apply a record saved by a previous run.
+    pub fn restore(&self, record: &RecoveryRecord) {
+        self.nsm.restore(record.nsm_state, &record.monitored);
+        self.mount.restore(&record.mounts);
+    }
+
+    async fn audit<T>(&self, caller: &Caller, event: AuditEvent<'_>, res: &NfsResult<T>) {
+        if let Some(audit) = &self.audit {
+            audit.record(caller, event, res).await;
+        }
+    }
+
+    // PUTFH: a handle this server issued, still naming an object, in an
+    // export the caller may use
+    async fn check_fh(&self, fh: &[u8], caller: &Caller) -> NfsResult<()> {
+        if fh.is_empty() || fh.len() > crate::constants::NFS4_FHSIZE as usize {
+            return Err(NfsError::BadHandle);
+        }
+        self.exports.authorize_fh(fh, caller).await?;
+        // the backend checks its part: format, instance and that the
+        // object still exists
+        self.exports.getattr(fh).await.map(|_| ())
+    }
+
+    // A symlink is no directory to look in either, but NFSv4.1 has its
+    // own error for one
+    async fn notdir4(&self, fh: &[u8], minor: u32) -> NfsError {
+        match self.exports.getattr(fh).await {
+            Ok(attr) if minor > 0 && attr.ftype == FileType::Symlink => NfsError::Status(Nfs4Status::Symlink),
+            _ => NfsError::NotDir,
+        }
+    }
+
+    // LOOKUP and LOOKUPP search the directory; for anything else the
+    // lookup itself says what is wrong
+    async fn check_search(&self, dir: &[u8], caller: &Caller) -> NfsResult<()> {
+        let attr = self.exports.getattr(dir).await?;
+        if attr.ftype != FileType::Directory {
+            return Ok(());
+        }
+        perm::check(&attr, &self.exports.authorize_fh(dir, caller).await?, ACCESS4_LOOKUP)
+    }
+
+    /// Of the ACCESS bits asked about, those that apply to the object and
+    /// those of them the caller holds
+    async fn access4(&self, fh: &[u8], requested: u32, caller: &Caller) -> NfsResult<(u32, u32)> {
+        let who = self.exports.authorize_fh(fh, caller).await?;
+        let attr = self.exports.getattr(fh).await?;
+        let supported = requested & perm::supported(attr.ftype);
+        Ok((supported, supported & perm::granted(&attr, &who)))
+    }
+
+    // What READ, READ_PLUS and SEEK check before looking into `fh`;
+    // returns its attributes
+    async fn readable(&self, fh: &[u8], stateid: &Stateid4, caller: &Caller, minor: u32) -> NfsResult<FileAttr> {
+        // the destination of a copy reads with what COPY_NOTIFY gave it,
+        // in place of permission of its own
+        let granted = self.offloads.granted(fh, stateid, caller.addr.ip());
+        if !granted {
+            check_stateid(stateid, true)?;
+        }
+        let who = self.exports.authorize_fh(fh, caller).await?;
+        let attr = self.exports.getattr(fh).await?;
+        check_io_type(attr.ftype, minor)?;
+        if !granted {
+            perm::check_io(&attr, &who, false)?;
+        }
+        Ok(attr)
+    }
+
+    async fn read4(&self, fh: &[u8], args: &Read4args, caller: &Caller, minor: u32) -> NfsResult<Read4resok> {
+        self.readable(fh, &args.stateid, caller, minor).await?;
+        let (data, eof) = self.exports.read(fh, args.offset, args.count.min(NFS4_MAX_IO)).await?;
+        Ok(Read4resok { eof, data })
+    }
+
+    /// READ that returns holes as such rather than as zeros
+    async fn read_plus4(&self, fh: &[u8], args: &Read4args, caller: &Caller, minor: u32) -> NfsResult<ReadPlus4resok> {
+        self.readable(fh, &args.stateid, caller, minor).await?;
+        self.exports.read_extents(fh, args.offset, args.count.min(NFS4_MAX_IO)).await
+    }
+
+    /// Where the first data or hole from SEEK's offset starts
+    async fn seek4(&self, fh: &[u8], args: &Seek4args, caller: &Caller, minor: u32) -> NfsResult<Seek4resok> {
+        let what = match args.what {
+            NFS4_CONTENT_DATA => Content::Data,
+            NFS4_CONTENT_HOLE => Content::Hole,
+            _ => return Err(NfsError::Status(Nfs4Status::UnionNotsupp)),
+        };
+        let attr = self.readable(fh, &args.stateid, caller, minor).await?;
+        match self.exports.seek(fh, args.offset, what).await? {
+            Some(offset) => Ok(Seek4resok { eof: offset >= attr.size, offset }),
+            None => Err(NfsError::Status(Nfs4Status::Nxio)),
+        }
+    }
+
+    /// Returns the count written and how stable it is now.
+    async fn write4(&self, fh: &[u8], args: Write4args, caller: &Caller, minor: u32) -> NfsResult<(u32, u32)> {
+        check_stateid(&args.stateid, false)?;
+        let who = self.writable(fh, caller).await?;
+        let attr = self.exports.getattr(fh).await?;
+        check_io_type(attr.ftype, minor)?;
+        perm::check_io(&attr, &who, true)?;
+        let n = self.exports.write(fh, args.offset, args.data).await?;
+        if args.stable == UNSTABLE4 {
+            return Ok((n, UNSTABLE4));
+        }
+        // A flush covers metadata too, so DATA_SYNC4 comes back as FILE_SYNC4
+        self.exports.commit(fh, args.offset, n as u64).await?;
+        Ok((n, FILE_SYNC4))
+    }
+
+    /// Copy from `src` to `dst` as COPY asks and return what was copied.
+    /// With no backchannel to send CB_OFFLOAD on, the copy is always done
+    /// before COPY is answered, even when the client would not wait.
+    /// With `source_server` set, `src` is a handle on that server, read
+    /// with the stateid its COPY_NOTIFY gave.
+    async fn copy4(&self, src: &[u8], dst: &[u8], args: &Copy4args, caller: &Caller, minor: u32) -> NfsResult<WriteResponse4> {
+        let here = args.source_server.is_empty();
+        if here {
+            check_stateid(&args.src_stateid, true)?;
+        }
+        check_stateid(&args.dst_stateid, false)?;
+        let src_attr = match here {
+            true => {
+                let who = self.exports.authorize_fh(src, caller).await?;
+                let attr = self.exports.getattr(src).await?;
+                check_io_type(attr.ftype, minor)?;
+                perm::check_io(&attr, &who, false)?;
+                Some(attr)
+            }
+            false => None,
+        };
+        let dst_who = self.writable(dst, caller).await?;
+        let dst_attr = self.exports.getattr(dst).await?;
+        check_io_type(dst_attr.ftype, minor)?;
+        perm::check_io(&dst_attr, &dst_who, true)?;
+        let mut remote = None;
+        let src_size = match &src_attr {
+            Some(attr) => attr.size,
+            None => {
+                let mut source = SourceClient::connect(&args.source_server, src, args.src_stateid).await?;
+                let size = source.size().await?;
+                remote = Some(source);
+                size
+            }
+        };
+        let same = src_attr.as_ref().is_some_and(|a| (a.fsid, a.fileid) == (dst_attr.fsid, dst_attr.fileid));
+        let count = range_count(src_size, same, args.src_offset, args.dst_offset, args.count)?;
+        let n = match remote {
+            Some(mut source) => source.pull(args.src_offset, self.exports.as_ref(), dst, args.dst_offset, count).await?,
+            None => copy_chunks(self.exports.as_ref(), src, args.src_offset, dst, args.dst_offset, count).await?,
+        };
+        // as for a stable WRITE, a flush covers metadata too
+        self.exports.commit(dst, args.dst_offset, n).await?;
+        Ok(WriteResponse4 { callback_id: None, count: n, committed: FILE_SYNC4, writeverf: self.write_verf })
+    }
+
+    /// Make a range of `dst` share `src`'s storage as CLONE asks
+    async fn clone4(&self, src: &[u8], dst: &[u8], args: &Clone4args, caller: &Caller, minor: u32) -> NfsResult<()> {
+        check_stateid(&args.src_stateid, true)?;
+        check_stateid(&args.dst_stateid, false)?;
+        let src_who = self.exports.authorize_fh(src, caller).await?;
+        let dst_who = self.writable(dst, caller).await?;
+        let src_attr = self.exports.getattr(src).await?;
+        let dst_attr = self.exports.getattr(dst).await?;
+        check_io_type(src_attr.ftype, minor)?;
+        check_io_type(dst_attr.ftype, minor)?;
+        perm::check_io(&src_attr, &src_who, false)?;
+        perm::check_io(&dst_attr, &dst_who, true)?;
+        let blksize = match self.exports.clone_blksize(dst) {
+            0 => return Err(NfsError::NotSupported),
+            n => n as u64,
+        };
+        let same = (src_attr.fsid, src_attr.fileid) == (dst_attr.fsid, dst_attr.fileid);
+        let count = range_count(src_attr.size, same, args.src_offset, args.dst_offset, args.count)?;
+        // whole blocks, but for a range that ends where the source does
+        let to_end = args.src_offset + count == src_attr.size;
+        if !args.src_offset.is_multiple_of(blksize) || !args.dst_offset.is_multiple_of(blksize) || (!count.is_multiple_of(blksize) && !to_end) {
+            return Err(NfsError::InvalidArgument(format!("clone not aligned to {} bytes", blksize)));
+        }
+        self.exports.clone_range(src, args.src_offset, dst, args.dst_offset, count).await
+    }
+
+    /// Give a range of `fh` storage as ALLOCATE asks or, with `punch`, make
+    /// it a hole as DEALLOCATE does
+    async fn allocate4(&self, fh: &[u8], args: &Allocate4args, punch: bool, caller: &Caller, minor: u32) -> NfsResult<()> {
+        check_stateid(&args.stateid, false)?;
+        let who = self.writable(fh, caller).await?;
+        let attr = self.exports.getattr(fh).await?;
+        check_io_type(attr.ftype, minor)?;
+        perm::check_io(&attr, &who, true)?;
+        if args.length == 0 || args.offset.checked_add(args.length).is_none() {
+            return Err(NfsError::InvalidArgument("empty or overflowing range".into()));
+        }
+        match punch {
+            false => self.exports.allocate(fh, args.offset, args.length).await,
+            true => self.exports.deallocate(fh, args.offset, args.length).await,
+        }
+    }
+
+    /// Let the server `args.destination` names read `fh` as the source of
+    /// a copy, with the stateid returned
+    async fn copy_notify4(&self, fh: &[u8], args: &CopyNotify4args, caller: &Caller, minor: u32) -> NfsResult<CopyNotify4resok> {
+        check_stateid(&args.src_stateid, true)?;
+        let who = self.exports.authorize_fh(fh, caller).await?;
+        let attr = self.exports.getattr(fh).await?;
+        check_io_type(attr.ftype, minor)?;
+        perm::check_io(&attr, &who, false)?;
+        let readers: Vec<IpAddr> = resolve(&args.destination).await.iter().map(|addr| addr.ip()).collect();
+        if readers.is_empty() {
+            return Err(NfsError::InvalidArgument("destination server not found".into()));
+        }
+        let stateid = self.offloads.notify(fh, readers);
+        Ok(CopyNotify4resok { lease_time: NOTIFY_LEASE.as_secs(), stateid, source_server: self.netlocs.clone() })
+    }
+
+    /// Apply SETATTR's attributes and return the bitmap of those set
+    async fn setattr4(&self, fh: &[u8], args: &Setattr4args, caller: &Caller) -> NfsResult<Vec<u32>> {
+        check_stateid(&args.stateid, false)?;
+        let (mut set, attrset) = decode_settable_fattr4(&args.attrs)?;
+        let res = async {
+            let who = self.writable(fh, caller).await?;
+            self.check_acl_support(fh, &set)?;
+            let attr = self.exports.getattr(fh).await?;
+            // a dacl leaves the AUDIT and ALARM entries alone
+            if let (Some(dacl), Some(acl), true) = (&set.acl, &attr.acl, bitmap4_has(&attrset, FATTR4_DACL)) {
+                set.acl = Some(acl.with_dacl(dacl));
+            }
+            perm::check_setattr(&attr, &set, &who)?;
+            self.exports.setattr(fh, &set).await
+        }
+        .await;
+        self.audit(caller, AuditEvent::new(AuditAction::Setattr, Target::Handle(fh)).detail(describe_setattr(&set)), &res).await;
+        res.map(|_| attrset)
+    }
+
+    // An ACL may only be given to objects whose backend keeps one
+    fn check_acl_support(&self, fh: &[u8], set: &SetAttr) -> NfsResult<()> {
+        match set.acl.is_some() && self.exports.acl_support(fh) == 0 {
+            true => Err(NfsError::Status(Nfs4Status::Attrnotsupp)),
+            false => Ok(()),
+        }
+    }
+
+    async fn commit4(&self, fh: &[u8], args: &Commit4args, minor: u32) -> NfsResult<()> {
+        check_io_type(self.exports.getattr(fh).await?.ftype, minor)?;
+        self.exports.commit(fh, args.offset, args.count as u64).await
+    }
+
+    // Callers may change things under `fh` only on a read-write export
+    async fn writable(&self, fh: &[u8], caller: &Caller) -> NfsResult<Access> {
+        let access = self.exports.authorize_fh(fh, caller).await?;
+        if access.read_only {
+            return Err(NfsError::ReadOnlyFs);
+        }
+        Ok(access)
+    }
+
+    // The directory operations act in must be one
+    async fn dir4(&self, fh: &[u8], minor: u32) -> NfsResult<FileAttr> {
+        let attr = self.exports.getattr(fh).await?;
+        match attr.ftype {
+            FileType::Directory => Ok(attr),
+            _ => Err(self.notdir4(fh, minor).await),
+        }
+    }
+
+    /// Run `change` with `dirs` locked and return its result with each
+    /// directory's change_info4.
+    async fn change4<T>(&self, dirs: &[&[u8]], change: impl Future<Output = NfsResult<T>>) -> NfsResult<(T, Vec<ChangeInfo4>)> {
+        let _locked = self.exports.lock_dirs(dirs).await;
+        let mut before = Vec::with_capacity(dirs.len());
+        for dir in dirs {
+            before.push(self.exports.getattr(dir).await?.changeid);
+        }
+        let res = change.await?;
+        let mut cinfo = Vec::with_capacity(dirs.len());
+        for (dir, before) in dirs.iter().zip(before) {
+            let after = self.exports.getattr(dir).await?.changeid;
+            cinfo.push(ChangeInfo4 { atomic: self.exports.atomic_changes(dir), before, after });
+        }
+        Ok((res, cinfo))
+    }
+
+    /// Returns the new object's handle, the directory's change_info4 and
+    /// the attributes set.
+    async fn create4(&self, dir: &[u8], args: &Create4args, caller: &Caller, minor: u32) -> NfsResult<(Vec<u8>, ChangeInfo4, Vec<u32>)> {
+        let (kind, action) = match args.objtype {
+            NF4DIR => (CreateKind::Directory, AuditAction::Mkdir),
+            NF4LNK => {
+                let target = std::str::from_utf8(&args.linkdata).map_err(|_| NfsError::InvalidArgument("link target is not UTF-8".into()))?;
+                (CreateKind::Symlink(target.to_string()), AuditAction::Symlink)
+            }
+            NF4BLK => (CreateKind::BlockDevice(args.devdata.0, args.devdata.1), AuditAction::Mknod),
+            NF4CHR => (CreateKind::CharDevice(args.devdata.0, args.devdata.1), AuditAction::Mknod),
+            NF4SOCK => (CreateKind::Socket, AuditAction::Mknod),
+            NF4FIFO => (CreateKind::Fifo, AuditAction::Mknod),
+            // regular files are made by OPEN
+            _ => return Err(NfsError::Status(Nfs4Status::Badtype)),
+        };
+        let name = component4(&args.objname)?;
+        let (set, attrset) = decode_settable_fattr4(&args.createattrs)?;
+        let res = async {
+            let who = self.writable(dir, caller).await?;
+            perm::check(&self.dir4(dir, minor).await?, &who, ACCESS4_EXTEND | ACCESS4_LOOKUP)?;
+            perm::check_new_owner(&set, &who)?;
+            self.check_acl_support(dir, &set)?;
+            self.change4(&[dir], self.exports.create(dir, name, kind, &who.owned(set))).await
+        }
+        .await;
+        if let (Some(audit), Ok((fh, _))) = (&self.audit, &res) {
+            audit.learn(dir, name, fh);
+        }
+        self.audit(caller, AuditEvent::new(action, Target::Entry(dir, name)), &res).await;
+        let (fh, cinfo) = res?;
+        Ok((fh, cinfo[0], attrset))
+    }
+
+    async fn remove4(&self, dir: &[u8], raw: &[u8], caller: &Caller, minor: u32) -> NfsResult<ChangeInfo4> {
+        let name = component4(raw)?;
+        let mut action = AuditAction::Remove;
+        let res = async {
+            let who = self.writable(dir, caller).await?;
+            let dir_attr = self.dir4(dir, minor).await?;
+            let victim = self.exports.getattr(&self.exports.lookup(dir, name).await?).await?;
+            if victim.ftype == FileType::Directory {
+                action = AuditAction::Rmdir;
+            }
+            perm::check_delete(&dir_attr, &victim, &who)?;
+            self.change4(&[dir], self.exports.remove(dir, name)).await
+        }
+        .await;
+        self.audit(caller, AuditEvent::new(action, Target::Entry(dir, name)), &res).await;
+        Ok(res?.1[0])
+    }
+
+    /// Move `args.oldname` in `from_dir` to `args.newname` in `to_dir`,
+    /// replacing an object of the same kind there. Returns the source and
+    /// target directories' change_info4.
+    async fn rename4(&self, from_dir: &[u8], to_dir: &[u8], args: &Rename4args, caller: &Caller, minor: u32) -> NfsResult<(ChangeInfo4, ChangeInfo4)> {
+        let (from, to) = (component4(&args.oldname)?, component4(&args.newname)?);
+        let res = async {
+            let who = self.writable(from_dir, caller).await?;
+            self.writable(to_dir, caller).await?;
+            let from_attr = self.dir4(from_dir, minor).await?;
+            let to_attr = self.dir4(to_dir, minor).await?;
+            let rename = async {
+                let src = self.exports.lookup(from_dir, from).await?;
+                let src_attr = self.exports.getattr(&src).await?;
+                let dst_attr = match self.exports.lookup(to_dir, to).await {
+                    // two names for one object: nothing to do
+                    Ok(dst) if dst == src => return Ok(()),
+                    Ok(dst) => {
+                        let dst_attr = self.exports.getattr(&dst).await?;
+                        if (src_attr.ftype == FileType::Directory) != (dst_attr.ftype == FileType::Directory) {
+                            return Err(NfsError::AlreadyExists);
+                        }
+                        Some(dst_attr)
+                    }
+                    Err(NfsError::NotFound) => None,
+                    Err(e) => return Err(e),
+                };
+                perm::check_rename(&from_attr, &src_attr, &to_attr, dst_attr.as_ref(), &who)?;
+                self.exports.rename(from_dir, from, to_dir, to).await
+            };
+            self.change4(&[from_dir, to_dir], rename).await
+        }
+        .await;
+        if let Some(audit) = &self.audit {
+            let event = AuditEvent::new(AuditAction::Rename, Target::Entry(from_dir, from)).to(Target::Entry(to_dir, to));
+            audit.record(caller, event, &res).await;
+            // the object and whatever is below it now live under the new name
+            if let (Ok(_), Ok(fh)) = (&res, self.exports.lookup(to_dir, to).await) {
+                audit.learn(to_dir, to, &fh);
+            }
+        }
+        let ((), cinfo) = res?;
+        Ok((cinfo[0], cinfo[1]))
+    }
+
+    /// Give `fh` the name `raw` in `dir` too.
+    async fn link4(&self, fh: &[u8], dir: &[u8], raw: &[u8], caller: &Caller, minor: u32) -> NfsResult<ChangeInfo4> {
+        let name = component4(raw)?;
+        let res = async {
+            let who = self.writable(dir, caller).await?;
+            perm::check(&self.dir4(dir, minor).await?, &who, ACCESS4_EXTEND | ACCESS4_LOOKUP)?;
+            self.change4(&[dir], self.exports.link(fh, dir, name)).await
+        }
+        .await;
+        if let (Some(audit), Ok(_)) = (&self.audit, &res) {
+            audit.learn(dir, name, fh);
+        }
+        let event = AuditEvent::new(AuditAction::Link, Target::Handle(fh)).to(Target::Entry(dir, name));
+        self.audit(caller, event, &res).await;
+        Ok(res?.1[0])
+    }
+
+    async fn readlink4(&self, fh: &[u8], minor: u32) -> NfsResult<String> {
+        match self.exports.getattr(fh).await?.ftype {
+            FileType::Symlink => self.exports.readlink(fh).await,
+            _ if minor == 0 => Err(NfsError::InvalidArgument("not a symlink".into())),
+            _ => Err(NfsError::Status(Nfs4Status::WrongType)),
+        }
+    }
+
+    /// Encode a READDIR4resok holding the entries after `args.cookie` that
+    /// fit the client's budgets.
+    async fn readdir4(&self, dir: &[u8], args: &Readdir4args, caller: &Caller) -> NfsResult<BytesMut> {
+        // 1 and 2 stand for "." and ".." and are never handed out
+        if args.cookie == 1 || args.cookie == 2 {
+            return Err(NfsError::Status(Nfs4Status::BadCookie));
+        }
+        let dir_attr = self.exports.getattr(dir).await?;
+        if dir_attr.ftype != FileType::Directory {
+            return Err(NfsError::NotDir);
+        }
+        perm::check(&dir_attr, &self.exports.authorize_fh(dir, caller).await?, ACCESS4_READ)?;
+        let (maxcount, dircount) = (args.maxcount as usize, args.dircount as usize);
+        // ask the backend for about as many entries as could fit
+        let page_len = (maxcount / 64).clamp(8, 1024);
+        let mut entries = BytesMut::new();
+        // cookie verifier, then the list terminator and eof
+        let mut size = 8 + 4 + 4;
+        let mut names_size = 0;
+        let mut cookie = args.cookie;
+        let mut verifier = None;
+        let eof = 'fill: loop {
+            let page = self.exports.readdir(dir, cookie, page_len).await?;
+            match verifier {
+                // cookies from before a change may now point elsewhere
+                None if args.cookie != 0 && page.verifier != args.cookieverf => {
+                    return Err(NfsError::Status(Nfs4Status::NotSame));
+                }
+                None => verifier = Some(page.verifier),
+                // changed while we were listing; the client carries on with
+                // the next call and is told there
+                Some(v) if v != page.verifier => break false,
+                Some(_) => {}
+            }
+            for e in &page.entries {
+                let attrs = self.entry_fattr4(e, dir_attr.fsid, &args.attr_request).await?;
+                let name_part = 8 + opaque_size(e.name.len());
+                let entry_size = 4 + name_part + attrs.len();
+                if size + entry_size > maxcount || (dircount > 0 && names_size + name_part > dircount) {
+                    if entries.is_empty() {
+                        return Err(NfsError::Status(Nfs4Status::Toosmall));
+                    }
+                    break 'fill false;
+                }
+                size += entry_size;
+                names_size += name_part;
+                true.xdr_encode(&mut entries);
+                e.cookie.xdr_encode(&mut entries);
+                e.name.as_bytes().xdr_encode(&mut entries);
+                entries.extend_from_slice(&attrs);
+                cookie = e.cookie;
+                if let Some(audit) = &self.audit {
+                    audit.learn(dir, &e.name, &e.fh);
+                }
+            }
+            if page.eof {
+                break true;
+            }
+        };
+        let mut resok = BytesMut::with_capacity(size);
+        verifier.unwrap_or_default().xdr_encode(&mut resok);
+        resok.extend_from_slice(&entries);
+        false.xdr_encode(&mut resok);
+        eof.xdr_encode(&mut resok);
+        Ok(resok)
+    }
+
+    // The fattr4 of a directory entry. Attributes that cannot be read are
+    // reported as rdattr_error if the client asked for it, else they fail
+    // the READDIR.
+    async fn entry_fattr4(&self, e: &DirEntry, dir_fsid: u64, request: &[u32]) -> NfsResult<Vec<u8>> {
+        let attr = match &e.attr {
+            Some(attr) => Ok(attr.clone()),
+            // ask again to learn why
+            None => self.exports.getattr(&e.fh).await,
+        };
+        match attr {
+            Ok(attr) => {
+                // only an export's root differs from what it is mounted on
+                let mounted_on = if attr.fsid == dir_fsid || !bitmap4_has(request, FATTR4_MOUNTED_ON_FILEID) {
+                    attr.fileid
+                } else {
+                    self.exports.mounted_on_fileid(&e.fh).await?
+                };
+                encode_file_fattr4(&attr, &e.fh, mounted_on, self.exports.acl_support(&e.fh), self.exports.clone_blksize(&e.fh), request)
+            }
+            Err(err) if bitmap4_has(request, FATTR4_RDATTR_ERROR) => {
+                let mut out = std::io::Cursor::new(Vec::new());
+                let status = (Nfs4Status::from(err) as u32).to_be_bytes();
+                encode_fattr4(&mut out, &bitmap4_with(&[FATTR4_RDATTR_ERROR]), &status)?;
+                Ok(out.into_inner())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Handle one RPC call message and return the encoded reply: empty
+    /// when the message is not a call or too short to say which call it
+    /// was. Arguments that do not decode are answered like any other error.
+    pub async fn dispatch(&self, mut msg: Bytes, peer: SocketAddr, transport: Transport) -> NfsResult<XdrChain> {
+        let word = |i: usize| msg.get(4 * i..4 * i + 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()));
+        let xid = word(0);
+        match (xid, word(1), word(2)) {
+            // replies are not answered
+            (Some(_), Some(msg_type), _) if msg_type != RpcMessageType::Call as u32 => {
+                debug!("dropping rpc message of type {} from {}", msg_type, peer);
+                return Ok(XdrChain::default());
+            }
+            // nothing past the version can be read in another one
+            (Some(xid), Some(_), Some(rpcvers)) if rpcvers != RPC_VERSION => return Ok(encode_rpc_mismatch(xid)),
+            _ => {}
+        }
+        let RpcCall { header: call, cred } = match RpcCall::xdr_decode(&mut msg) {
+            Ok(call) => call,
+            // a call we cannot make out is answered if we can tell which it
+            // was; not even an xid leaves nothing to reply to
+            Err(e) => {
+                debug!("unreadable call header from {}: {}", peer, e);
+                return Ok(xid.map(|xid| encode_rpc_reply(xid, Err(AcceptError::GarbageArgs))).unwrap_or_default());
+            }
+        };
+        let caller = Caller { addr: peer, cred };
+        let span = info_span!("rpc", xid = call.xid, %peer, prog = call.prog, vers = call.vers, proc = call.proc, principal = Empty);
+        if !span.is_disabled() {
+            span.record("principal", display(caller.principal()));
+        }
+        self.call(call, msg, &caller, transport).instrument(span).await
+    }
+
+    async fn call(&self, call: RpcCallHeader, msg: Bytes, caller: &Caller, transport: Transport) -> NfsResult<XdrChain> {
+        debug!("rpc call: {:?} via {:?}", call, transport);
+        // No RPCSEC_GSS context is ever established, so a GSS credential is
+        // too weak for anything. NFSv4 says so per export with WRONGSEC.
+        let compound = call.prog == NFS4_PROGRAM && call.vers == NFS4_VERSION && call.proc == Nfs4Proc::Compound as u32;
+        if caller.cred.flavor() == RPCSEC_GSS && !compound {
+            return Ok(encode_auth_error(call.xid, AUTH_TOOWEAK));
+        }
+        // Requests that do filesystem work count against the client's rate
+        // and the server's budget; pings and the side protocols do not
+        let metered = call.prog == NFS4_PROGRAM && call.proc != 0 && (call.vers == NFS3_VERSION || call.vers == NFS4_VERSION);
+        let _permit = match metered {
+            true => match self.limits.admit(caller.addr.ip(), msg.len()) {
+                Some(permit) => Some(permit),
+                None => return Ok(encode_rpc_reply(call.xid, self.busy(&call, msg))),
+            },
+            false => None,
+        };
+        let result = match (call.prog, call.vers) {
+            (NFS4_PROGRAM, NFS3_VERSION) => self.nfs3.call(call.proc, msg, caller).await,
+            (NFS4_PROGRAM, _) => self.nfs4(&call, msg, transport, caller).await,
+            (MOUNT_PROGRAM, vers) => self.mount.call(vers, call.proc, msg, caller).await,
+            (NLM_PROGRAM, NLM_VERSION4) => self.nlm.call(call.proc, msg, caller).await,
+            (NLM_PROGRAM, _) => Err(AcceptError::ProgMismatch { low: NLM_VERSION4, high: NLM_VERSION4 }),
+            (NSM_PROGRAM, NSM_VERSION) => self.nsm.call(call.proc, msg).await,
+            (NSM_PROGRAM, _) => Err(AcceptError::ProgMismatch { low: NSM_VERSION, high: NSM_VERSION }),
+            (PMAP_PROGRAM, _) => match &self.portmap {
+                Some(pm) => pm.call(&call, msg),
+                None => Err(AcceptError::ProgUnavail),
+            },
+            _ => Err(AcceptError::ProgUnavail),
+        };
+        Ok(encode_rpc_reply(call.xid, result))
+    }
+
+    // Ask the client to retry later without doing any of the work
+    fn busy(&self, call: &RpcCallHeader, mut msg: Bytes) -> Result<XdrChain, AcceptError> {
+        if call.vers == NFS3_VERSION {
+            return self.nfs3.busy(call.proc);
+        }
+        let Ok(args) = Compound4args::xdr_decode(&mut msg) else {
+            return Ok(badxdr());
+        };
+        let mut out = XdrChain::new();
+        out.put(&Compound4res { status: Nfs4Status::Delay as u32, tag: args.tag });
+        // the first operation carries the error, none of the rest ran
+        match args.operations.first() {
+            Some(op) => {
+                out.put(&1u32);
+                out.put(&op.opcode);
+                out.put(&(Nfs4Status::Delay as u32));
+            }
+            None => out.put(&0u32),
+        }
+        Ok(out)
+    }
+
+    async fn nfs4(&self, call: &RpcCallHeader, msg: Bytes, transport: Transport, caller: &Caller) -> Result<XdrChain, AcceptError> {
+        if call.vers != NFS4_VERSION {
+            return Err(AcceptError::ProgMismatch { low: NFS3_VERSION, high: NFS4_VERSION });
+        }
+        match call.proc {
             // NULL: no body, success
-            // Some stacks expect empty body beyond header
-        } else if call.proc == Nfs4Proc::Compound as u32 {
-            // Parse COMPOUND args
-            let args = Compound4args::xdr_deserialize(&mut cur)?;
-            debug!("compound minor={} ops={} tag={:?}", args.minorversion, args.operations.len(), args.tag);
-
-            // Evaluate minimal ops with current FH tracking
-            let mut current_fh: Option<Vec<u8>> = None;
-            // Result array header: we'll serialize after building entries
-            let mut comp_res = std::io::Cursor::new(Vec::new());
-            let mut overall_status = NFS4_OK;
+            x if x == Nfs4Proc::Null as u32 => Ok(XdrChain::new()),
+            x if x == Nfs4Proc::Compound as u32 && transport == Transport::Udp => {
+                debug!("rejecting COMPOUND over UDP");
+                Err(AcceptError::ProcUnavail)
+            }
+            x if x == Nfs4Proc::Compound as u32 => Ok(self.compound(msg, caller).await),
+            _ => Err(AcceptError::ProcUnavail),
+        }
+    }
+
+    async fn compound(&self, mut msg: Bytes, caller: &Caller) -> XdrChain {
+        let vfs = &self.exports;
+        let mut reply = XdrChain::new();
+        // Parse COMPOUND args
+        let Ok(args) = Compound4args::xdr_decode(&mut msg) else {
+            return badxdr();
+        };
+        debug!("compound minor={} ops={} tag={:?}", args.minorversion, args.operations.len(), args.tag);
+        if args.minorversion > crate::constants::NFS_MINOR_VERSION {
+            reply.put(&Compound4res { status: Nfs4Status::MinorVersMismatch as u32, tag: args.tag });
+            reply.put(&0u32);
+            return reply;
+        }
 
+        // Evaluate minimal ops with current FH tracking
+        let mut current_fh: Option<Vec<u8>> = None;
+        // SAVEFH keeps a handle for RESTOREFH, RENAME and LINK
+        let mut saved_fh: Option<Vec<u8>> = None;
+        // Result array header: we'll serialize after building entries
+        let mut comp_res = XdrChain::new();
+        let mut overall_status = NFS4_OK;
+
+        // Count results we'll produce
+        let mut res_count: u32 = 0;
+        let foreign = foreign_source(&args.operations);
+        for (i, op) in args.operations.iter().enumerate() {
+            // A span per op; it is told the outcome when the result is written
+            let span = debug_span!("op", op = %op_name(op.opcode), fh = Empty, status = Empty, elapsed_us = Empty);
+            if let (Some(fh), false) = (&current_fh, span.is_disabled()) {
+                span.record("fh", display(hex(fh)));
+            }
+            let started = Instant::now();
             // Helper: write a resop header (opcode and status) and payload
-            let write_resop = |w: &mut std::io::Cursor<Vec<u8>>, opcode: u32, status: u32, payload: &[u8]| -> std::io::Result<()> {
-                opcode.xdr_serialize(w)?; // resop opcode
-                status.xdr_serialize(w)?; // nfsstat4
-                w.get_mut().extend_from_slice(payload);
-                Ok(())
+            // and account for the op
+            let write_resop = |w: &mut XdrChain, opcode: u32, status: u32, payload: &[u8]| {
+                let elapsed = started.elapsed();
+                span.record("status", status);
+                span.record("elapsed_us", elapsed.as_micros() as u64);
+                debug!("op done");
+                #[cfg(feature = "metrics")]
+                crate::metrics::record_op(opcode, status, elapsed);
+                w.put(&opcode); // resop opcode
+                w.put(&status); // nfsstat4
+                w.put_raw(payload);
             };
-
-            // Count results we'll produce
-            let mut res_count: u32 = 0;
-            for op in &args.operations {
+            // Ok(true) ends the compound after this op
+            let stop = async {
                 match op.opcode {
-                    x if x == NfsOp4::OpPutrootfh as u32 => {
-                        current_fh = Some(vfs.root_fh().await?);
-                        write_resop(&mut comp_res, NfsOp4::OpPutrootfh as u32, NFS4_OK, &[])?;
-                        res_count += 1;
-                    }
-                    x if x == NfsOp4::OpPutfh as u32 => {
-                        let fh: Vec<u8> = if !op.opdata.is_empty() {
-                            crate::xdr::deserialize_from_slice::<Vec<u8>>(&op.opdata).unwrap_or_default()
-                        } else { vec![] };
+                    // The public filehandle is the root of the joined namespace
+                    x if x == NfsOp4::OpPutrootfh as u32 || x == NfsOp4::OpPutpubfh as u32 || x == NfsOp4::OpPutfh as u32 => {
+                        let fh = if x != NfsOp4::OpPutfh as u32 {
+                            // The export owning the handle decides whether this
+                            // caller and flavor may use it
+                            match vfs.root_fh().await {
+                                Ok(fh) => vfs.authorize_fh(&fh, caller).await.map(|_| fh),
+                                Err(e) => Err(e),
+                            }
+                        } else {
+                            match Vec::<u8>::xdr_decode(&mut op.opdata.clone()) {
+                                Ok(fh) if foreign == Some(i) => Ok(fh),
+                                Ok(fh) => self.check_fh(&fh, caller).await.map(|_| fh),
+                                Err(e) => Err(NfsError::Xdr(e.to_string())),
+                            }
+                        };
+                        // nothing after a refusal runs
+                        let fh = match fh {
+                            Ok(fh) => fh,
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                res_count += 1;
+                                return true;
+                            }
+                        };
                         current_fh = Some(fh);
-                        write_resop(&mut comp_res, NfsOp4::OpPutfh as u32, NFS4_OK, &[])?;
+                        write_resop(&mut comp_res, x, NFS4_OK, &[]);
                         res_count += 1;
                     }
                     x if x == NfsOp4::OpGetfh as u32 => {
-                        if let Some(fh) = &current_fh {
-                            let mut payload = std::io::Cursor::new(Vec::new());
-                            let len = fh.len() as u32;
-                            len.xdr_serialize(&mut payload)?;
-                            payload.get_mut().extend_from_slice(fh);
-                            let pad = (4 - (fh.len() % 4)) % 4;
-                            if pad > 0 { payload.get_mut().extend_from_slice(&[0u8;3][..pad]); }
-                            write_resop(&mut comp_res, NfsOp4::OpGetfh as u32, NFS4_OK, &payload.into_inner())?;
+                        res_count += 1;
+                        let Some(fh) = &current_fh else {
+                            overall_status = Nfs4Status::Nofilehandle as u32;
+                            write_resop(&mut comp_res, x, overall_status, &[]);
+                            return true;
+                        };
+                        write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                        comp_res.put(fh);
+                    }
+                    x if x == NfsOp4::OpLookup as u32 || x == NfsOp4::OpLookupp as u32 => {
+                        let Some(dir) = &current_fh else {
+                            overall_status = Nfs4Status::Nofilehandle as u32;
+                            write_resop(&mut comp_res, x, overall_status, &[]);
                             res_count += 1;
+                            return true;
+                        };
+                        let mut name = None;
+                        let found = if x == NfsOp4::OpLookup as u32 {
+                            match Bytes::xdr_decode(&mut op.opdata.clone()) {
+                                Ok(raw) => match component4(&raw) {
+                                    Ok(component) => {
+                                        name = Some(component.to_string());
+                                        match self.check_search(dir, caller).await {
+                                            Ok(()) => vfs.lookup(dir, component).await,
+                                            Err(e) => Err(e),
+                                        }
+                                    }
+                                    Err(e) => Err(e),
+                                },
+                                Err(e) => Err(NfsError::Xdr(e.to_string())),
+                            }
+                        } else if let Err(e) = self.check_search(dir, caller).await {
+                            Err(e)
                         } else {
-                            overall_status = NFS4ERR_NOFILEHANDLE;
-                            write_resop(&mut comp_res, NfsOp4::OpGetfh as u32, NFS4ERR_NOFILEHANDLE, &[])?;
-                            res_count += 1;
+                            match vfs.lookup_parent(dir).await {
+                                // the root of the namespace has no parent
+                                Ok(parent) if parent == *dir => Err(NfsError::NotFound),
+                                found => found,
+                            }
+                        };
+                        let found = match found {
+                            Err(NfsError::NotDir) => Err(self.notdir4(dir, args.minorversion).await),
+                            found => found,
+                        };
+                        // Crossing into another export brings its rules into play
+                        let found = match found {
+                            Ok(fh) => vfs.authorize_fh(&fh, caller).await.map(|_| fh),
+                            Err(e) => Err(e),
+                        };
+                        match found {
+                            Ok(fh) => {
+                                if let (Some(audit), Some(name)) = (&self.audit, &name) {
+                                    audit.learn(dir, name, &fh);
+                                }
+                                current_fh = Some(fh);
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                res_count += 1;
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                res_count += 1;
+                                return true;
+                            }
                         }
                     }
-                    x if x == NfsOp4::OpGetattr as u32 => {
-                        if let Some(_fh) = &current_fh {
-                            let req_bitmap: Vec<u32> = if !op.opdata.is_empty() {
-                                crate::xdr::deserialize_from_slice::<Vec<u32>>(&op.opdata).unwrap_or_else(|_| vec![])
-                            } else { vec![] };
-                            let fattr = vfs.getattr_root(&req_bitmap).await?;
-                            write_resop(&mut comp_res, NfsOp4::OpGetattr as u32, NFS4_OK, &fattr)?;
-                            res_count += 1;
+                    x if x == NfsOp4::OpSavefh as u32 || x == NfsOp4::OpRestorefh as u32 => {
+                        let (from, missing) = if x == NfsOp4::OpSavefh as u32 {
+                            (&current_fh, Nfs4Status::Nofilehandle)
                         } else {
-                            overall_status = NFS4ERR_NOFILEHANDLE;
-                            write_resop(&mut comp_res, NfsOp4::OpGetattr as u32, NFS4ERR_NOFILEHANDLE, &[])?;
-                            res_count += 1;
+                            (&saved_fh, Nfs4Status::Restorefh)
+                        };
+                        res_count += 1;
+                        let Some(fh) = from.clone() else {
+                            overall_status = missing as u32;
+                            write_resop(&mut comp_res, x, overall_status, &[]);
+                            return true;
+                        };
+                        if x == NfsOp4::OpSavefh as u32 {
+                            saved_fh = Some(fh);
+                        } else {
+                            current_fh = Some(fh);
+                        }
+                        write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                    }
+                    x if x == NfsOp4::OpGetattr as u32 => {
+                        let res = match (&current_fh, Vec::<u32>::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(req_bitmap)) => match vfs.getattr(fh).await {
+                                Ok(attr) => match vfs.mounted_on_fileid(fh).await {
+                                    Ok(mounted_on) => encode_file_fattr4(&attr, fh, mounted_on, vfs.acl_support(fh), vfs.clone_blksize(fh), &req_bitmap),
+                                    Err(e) => Err(e),
+                                },
+                                Err(e) => Err(e),
+                            },
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok(fattr) => write_resop(&mut comp_res, x, NFS4_OK, &fattr),
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpAccess as u32 => {
+                        let res = match (&current_fh, u32::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(requested)) => self.access4(fh, requested, caller).await,
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok((supported, access)) => {
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&supported);
+                                comp_res.put(&access);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpSetattr as u32 => {
+                        let res = match (&current_fh, Setattr4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(setattr)) => self.setattr4(fh, &setattr, caller).await,
+                        };
+                        res_count += 1;
+                        // the attributes set go back whatever the outcome
+                        match res {
+                            Ok(attrset) => {
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&attrset);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                comp_res.put(&Vec::<u32>::new());
+                                return true;
+                            }
                         }
                     }
                     x if x == NfsOp4::OpCreate as u32 => {
-                        // Minimal: create file with path from opdata, size 0
-                        let path = String::from_utf8_lossy(&op.opdata).to_string();
-                        let _ = vfs.create_file(&path, 0).await;
-                        write_resop(&mut comp_res, NfsOp4::OpCreate as u32, NFS4_OK, &[])?;
+                        let res = match (&current_fh, Create4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(dir), Ok(create)) => self.create4(dir, &create, caller, args.minorversion).await,
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok((fh, cinfo, attrset)) => {
+                                current_fh = Some(fh);
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&cinfo);
+                                comp_res.put(&attrset);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpRemove as u32 || x == NfsOp4::OpLink as u32 => {
+                        // LINK gives the saved object a name in the current directory
+                        let res = match (&current_fh, Bytes::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(dir), Ok(name)) if x == NfsOp4::OpRemove as u32 => self.remove4(dir, &name, caller, args.minorversion).await,
+                            (Some(dir), Ok(name)) => match &saved_fh {
+                                Some(fh) => self.link4(fh, dir, &name, caller, args.minorversion).await,
+                                None => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            },
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok(cinfo) => {
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&cinfo);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpReadlink as u32 => {
+                        let res = match &current_fh {
+                            None => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            Some(fh) => self.readlink4(fh, args.minorversion).await,
+                        };
                         res_count += 1;
+                        match res {
+                            Ok(target) => {
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&target);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpRead as u32 => {
+                        let res = match (&current_fh, Read4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(read)) => self.read4(fh, &read, caller, args.minorversion).await,
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok(res) => {
+                                #[cfg(feature = "metrics")]
+                                crate::metrics::record_read(res.data.len());
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                // file data goes out as its own segment
+                                res.encode_into(&mut comp_res);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpReadPlus as u32 => {
+                        let res = match (&current_fh, Read4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(read)) => self.read_plus4(fh, &read, caller, args.minorversion).await,
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok(res) => {
+                                // holes are not counted as read
+                                #[cfg(feature = "metrics")]
+                                for content in &res.contents {
+                                    if let ReadPlusContent4::Data { data, .. } = content {
+                                        crate::metrics::record_read(data.len());
+                                    }
+                                }
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&res);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
                     }
-                    x if x == NfsOp4::OpRemove as u32 => {
-                        let path = String::from_utf8_lossy(&op.opdata).to_string();
-                        let _ = vfs.remove_entry(&path).await;
-                        write_resop(&mut comp_res, NfsOp4::OpRemove as u32, NFS4_OK, &[])?;
+                    x if x == NfsOp4::OpSeek as u32 => {
+                        let res = match (&current_fh, Seek4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(seek)) => self.seek4(fh, &seek, caller, args.minorversion).await,
+                        };
                         res_count += 1;
+                        match res {
+                            Ok(res) => {
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&res);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
                     }
                     x if x == NfsOp4::OpWrite as u32 => {
-                        let path = String::from_utf8_lossy(&op.opdata).to_string();
-                        let _ = vfs.modify_file(&path, 0).await;
-                        write_resop(&mut comp_res, NfsOp4::OpWrite as u32, NFS4_OK, &[])?;
+                        let res = match (&current_fh, Write4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(write)) => {
+                                let range = format!("offset={} count={}", write.offset, write.data.len());
+                                let res = self.write4(fh, write, caller, args.minorversion).await;
+                                self.audit(caller, AuditEvent::new(AuditAction::Write, Target::Handle(fh)).detail(range), &res).await;
+                                res
+                            }
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok((n, committed)) => {
+                                #[cfg(feature = "metrics")]
+                                crate::metrics::record_write(n as usize);
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&n);
+                                comp_res.put(&committed);
+                                comp_res.put(&self.write_verf);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpCommit as u32 => {
+                        let res = match (&current_fh, Commit4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(commit)) => self.commit4(fh, &commit, args.minorversion).await,
+                        };
                         res_count += 1;
+                        match res {
+                            Ok(()) => {
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&self.write_verf);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpReaddir as u32 => {
+                        let res = match (&current_fh, Readdir4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(dir), Ok(readdir)) => self.readdir4(dir, &readdir, caller).await,
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok(resok) => write_resop(&mut comp_res, x, NFS4_OK, &resok),
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
                     }
                     x if x == NfsOp4::OpRename as u32 => {
-                        // For demo: remove old, create new
-                        let path = String::from_utf8_lossy(&op.opdata).to_string();
-                        let _ = vfs.remove_entry(&path).await;
-                        let _ = vfs.create_file(&format!("{}_renamed", path), 0).await;
-                        write_resop(&mut comp_res, NfsOp4::OpRename as u32, NFS4_OK, &[])?;
+                        // from the saved directory to the current one
+                        let res = match (&saved_fh, &current_fh, Rename4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _, _) | (_, None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (_, _, Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(from), Some(to), Ok(rename)) => self.rename4(from, to, &rename, caller, args.minorversion).await,
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok((source, target)) => {
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&source);
+                                comp_res.put(&target);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpCopy as u32 => {
+                        // from the saved file to the current one
+                        let res = match (&saved_fh, &current_fh, Copy4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _, _) | (_, None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (_, _, Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(src), Some(dst), Ok(copy)) => {
+                                let range = format!("src_offset={} dst_offset={} count={}", copy.src_offset, copy.dst_offset, copy.count);
+                                let res = self.copy4(src, dst, &copy, caller, args.minorversion).await;
+                                let event = AuditEvent::new(AuditAction::Copy, Target::Handle(src)).to(Target::Handle(dst)).detail(range);
+                                self.audit(caller, event, &res).await;
+                                res
+                            }
+                        };
+                        // the source server's handle was never checked here, so
+                        // it must not outlive the COPY it was saved for
+                        if foreign.is_some_and(|at| at + 3 == i) {
+                            saved_fh = None;
+                        }
+                        res_count += 1;
+                        match res {
+                            Ok(response) => {
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&response);
+                                // copy_requirements4: always consecutive and
+                                // synchronous
+                                comp_res.put(&true);
+                                comp_res.put(&true);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpClone as u32 => {
+                        // from the saved file to the current one
+                        let res = match (&saved_fh, &current_fh, Clone4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _, _) | (_, None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (_, _, Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(src), Some(dst), Ok(clone)) => {
+                                let range = format!("src_offset={} dst_offset={} count={}", clone.src_offset, clone.dst_offset, clone.count);
+                                let res = self.clone4(src, dst, &clone, caller, args.minorversion).await;
+                                let event = AuditEvent::new(AuditAction::Clone, Target::Handle(src)).to(Target::Handle(dst)).detail(range);
+                                self.audit(caller, event, &res).await;
+                                res
+                            }
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok(()) => write_resop(&mut comp_res, x, NFS4_OK, &[]),
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpAllocate as u32 || x == NfsOp4::OpDeallocate as u32 => {
+                        let punch = x == NfsOp4::OpDeallocate as u32;
+                        let res = match (&current_fh, Allocate4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(allocate)) => {
+                                let range = format!("offset={} length={}", allocate.offset, allocate.length);
+                                let res = self.allocate4(fh, &allocate, punch, caller, args.minorversion).await;
+                                let action = if punch { AuditAction::Deallocate } else { AuditAction::Allocate };
+                                self.audit(caller, AuditEvent::new(action, Target::Handle(fh)).detail(range), &res).await;
+                                res
+                            }
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok(()) => write_resop(&mut comp_res, x, NFS4_OK, &[]),
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpCopyNotify as u32 => {
+                        // the current file is the source
+                        let res = match (&current_fh, CopyNotify4args::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(notify)) => self.copy_notify4(fh, &notify, caller, args.minorversion).await,
+                        };
+                        res_count += 1;
+                        match res {
+                            Ok(res) => {
+                                write_resop(&mut comp_res, x, NFS4_OK, &[]);
+                                comp_res.put(&res);
+                            }
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    x if x == NfsOp4::OpOffloadStatus as u32 || x == NfsOp4::OpOffloadCancel as u32 => {
+                        // no copy runs in the background, so the only stateid
+                        // either names is a COPY_NOTIFY one for the current
+                        // file, which OFFLOAD_CANCEL withdraws
+                        let res = match (&current_fh, Stateid4::xdr_decode(&mut op.opdata.clone())) {
+                            (None, _) => Err(NfsError::Status(Nfs4Status::Nofilehandle)),
+                            (Some(_), Err(e)) => Err(NfsError::Xdr(e.to_string())),
+                            (Some(fh), Ok(stateid)) if x == NfsOp4::OpOffloadCancel as u32 => self.offloads.cancel(fh, &stateid),
+                            (Some(_), Ok(_)) => Err(NfsError::BadStateid),
+                        };
                         res_count += 1;
+                        match res {
+                            Ok(()) => write_resop(&mut comp_res, x, NFS4_OK, &[]),
+                            Err(e) => {
+                                overall_status = Nfs4Status::from(e) as u32;
+                                write_resop(&mut comp_res, x, overall_status, &[]);
+                                return true;
+                            }
+                        }
+                    }
+                    // Opcodes outside the protocol are answered as OP_ILLEGAL
+                    // and end the compound
+                    x if NfsOp4::from_u32(x).is_none() || x == NfsOp4::OpIllegal as u32 => {
+                        overall_status = Nfs4Status::OpIllegal as u32;
+                        write_resop(&mut comp_res, NfsOp4::OpIllegal as u32, overall_status, &[]);
+                        res_count += 1;
+                        return true;
                     }
                     _ => {
-                        let sts = 10004u32; // NFS4ERR_NOTSUPP
-                        overall_status = sts;
-                        write_resop(&mut comp_res, op.opcode, sts, &[])?;
+                        overall_status = Nfs4Status::Notsupp as u32;
+                        write_resop(&mut comp_res, op.opcode, overall_status, &[]);
                         res_count += 1;
-                        // Continue to next op instead of break
+                        return true;
+                    }
+                }
+                false
+            }
+            .instrument(span.clone())
+            .await;
+            if stop {
+                break;
+            }
+        }
+
+        // Now write Compound4res header and resarray
+        reply.put(&Compound4res { status: overall_status, tag: args.tag });
+        // resarray<>: count followed by entries (already encoded as opcode+status+payload)
+        reply.put(&res_count);
+        reply.append(comp_res);
+        reply
+    }
+}
+
+// Expose accept loop for tests/integration to run on a pre-bound listener
+pub async fn run_on_listener(listener: TcpListener, vfs: Arc<dyn Vfs>, shutdown: ShutdownHandle) -> NfsResult<()> {
+    serve_tcp(listener, Dispatcher::new(vfs), shutdown).await
+}
+
+/// Accept connections until `shutdown`, then wait for the requests in
+/// progress on them, up to the grace period.
+pub async fn serve_tcp(listener: TcpListener, dispatcher: Arc<Dispatcher>, shutdown: ShutdownHandle) -> NfsResult<()> {
+    let mut conns = JoinSet::new();
+    loop {
+        tokio::select! {
+            biased;
+            _ = shutdown.wait() => break,
+            // reap finished connections so the set does not grow forever
+            Some(_) = conns.join_next(), if !conns.is_empty() => {}
+            accepted = listener.accept() => {
+                let (mut sock, peer) = accepted?;
+                let Some(permit) = dispatcher.limits().connect(peer) else {
+                    warn!("too many connections, refusing {}", peer);
+                    continue;
+                };
+                info!("connection from {}", peer);
+                let dispatcher = dispatcher.clone();
+                let shutdown = shutdown.clone();
+                conns.spawn(async move {
+                    if let Err(e) = handle_conn(&mut sock, peer, dispatcher, &shutdown, permit.closer()).await {
+                        error!("conn error: {:?}", e);
                     }
+                });
+            }
+        }
+    }
+    drop(listener);
+    drain(conns, shutdown.grace(), "connections").await;
+    Ok(())
+}
+
+// Wait for spawned work, abandoning whatever is still running at the deadline
+async fn drain(mut tasks: JoinSet<()>, grace: Duration, what: &str) {
+    let done = async { while tasks.join_next().await.is_some() {} };
+    if tokio::time::timeout(grace, done).await.is_err() {
+        warn!("{} {} still busy after {:?}, dropping them", tasks.len(), what, grace);
+        tasks.shutdown().await;
+    }
+}
+
+/// Serve RPC over UDP until `shutdown`. Datagrams larger than
+/// `max_datagram` are dropped, as are replies that would not fit in one.
+pub async fn serve_udp(socket: UdpSocket, dispatcher: Arc<Dispatcher>, max_datagram: usize, shutdown: ShutdownHandle) -> NfsResult<()> {
+    let socket = Arc::new(socket);
+    // one spare byte lets us detect oversized datagrams that were truncated
+    let mut buf = vec![0u8; max_datagram + 1];
+    let mut calls = JoinSet::new();
+    loop {
+        let (n, peer) = tokio::select! {
+            biased;
+            _ = shutdown.wait() => break,
+            Some(_) = calls.join_next(), if !calls.is_empty() => continue,
+            received = socket.recv_from(&mut buf) => received?,
+        };
+        if n > max_datagram {
+            warn!("dropping oversized UDP datagram from {}", peer);
+            continue;
+        }
+        let msg = Bytes::copy_from_slice(&buf[..n]);
+        let dispatcher = dispatcher.clone();
+        let socket = socket.clone();
+        calls.spawn(async move {
+            let reply = match dispatcher.dispatch(msg, peer, Transport::Udp).await {
+                Ok(reply) => reply,
+                Err(e) => {
+                    error!("udp call from {} failed: {:?}", peer, e);
+                    return;
                 }
+            };
+            if reply.is_empty() {
+                return;
+            }
+            if reply.len() > max_datagram {
+                warn!("reply to {} exceeds UDP datagram limit, dropping", peer);
+                return;
+            }
+            let out: Vec<u8> = reply.into_segments().concat();
+            if let Err(e) = socket.send_to(&out, peer).await {
+                error!("udp send to {} failed: {:?}", peer, e);
             }
+        });
+    }
+    drain(calls, shutdown.grace(), "UDP calls").await;
+    Ok(())
+}
 
-            // Now write Compound4res header and resarray
-            let mut compound = std::io::Cursor::new(Vec::new());
-            let cres = Compound4res { status: overall_status, tag: args.tag };
-            cres.xdr_serialize(&mut compound)?;
-            // resarray<>: count followed by entries (already encoded as opcode+status+payload)
-            res_count.xdr_serialize(&mut compound)?;
-            compound.get_mut().extend_from_slice(&comp_res.into_inner());
-            reply_cur.get_mut().extend_from_slice(&compound.into_inner());
-        } else {
-            // Unknown proc
-            10004u32.xdr_serialize(&mut reply_cur)?; // NOTSUPP
-        }
-        let reply_payload = reply_cur.into_inner();
-        let mut framed = Vec::with_capacity(4 + reply_payload.len());
-        let last = 1u32 << 31;
-        let header = last | (reply_payload.len() as u32);
-        framed.extend_from_slice(&header.to_be_bytes());
-        framed.extend_from_slice(&reply_payload);
-        sock.write_all(&framed).await?;
+async fn handle_conn(
+    sock: &mut tokio::net::TcpStream,
+    peer: SocketAddr,
+    dispatcher: Arc<Dispatcher>,
+    shutdown: &ShutdownHandle,
+    closer: &ShutdownHandle,
+) -> NfsResult<()> {
+    let mut rbuf = BytesMut::with_capacity(64 * 1024);
+    let idle = Duration::from_secs(dispatcher.limits().config().idle_timeout);
+    let per_record = Duration::from_secs(dispatcher.limits().config().record_timeout);
+    loop {
+        // Read a record-marked RPC message; op arguments are sliced out of it.
+        // The idle timeout runs until a call starts, the record timeout from
+        // then until all of it is in. None is a connection to close.
+        let read = async {
+            let hdr = tokio::select! {
+                hdr = sock.read_u32() => hdr?,
+                _ = tokio::time::sleep(idle), if !idle.is_zero() => {
+                    info!("closing idle connection from {}", peer);
+                    return Ok(None);
+                }
+            };
+            tokio::select! {
+                msg = read_record_from(sock, hdr, &mut rbuf) => msg.map(Some),
+                _ = tokio::time::sleep(per_record), if !per_record.is_zero() => {
+                    info!("closing connection from {}: call not complete after {:?}", peer, per_record);
+                    Ok(None)
+                }
+            }
+        };
+        // A call already being read when we stop is abandoned unanswered.
+        let msg = tokio::select! {
+            biased;
+            _ = shutdown.wait() => return Ok(()),
+            _ = closer.wait() => {
+                info!("closing connection from {} on request", peer);
+                return Ok(());
+            }
+            msg = read => match msg? {
+                Some(msg) => msg,
+                None => return Ok(()),
+            },
+        };
+        let reply = dispatcher.dispatch(msg, peer, Transport::Tcp).await?;
+        if !reply.is_empty() {
+            write_record_vectored(sock, &reply.into_segments()).await?;
+        }
     }
 }
@@ -1,91 +1,898 @@
-
 use async_trait::async_trait;
-use crate::error::NfsResult;
+use crate::acl::{self, Acl};
+use crate::error::{Nfs4Status, NfsError, NfsResult};
 use crate::proto::nfs4::*;
 use crate::xdr::*;
-use std::sync::Arc;
+use bytes::{Bytes, BytesMut};
+use std::io::Write;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use dashmap::DashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    BlockDevice,
+    CharDevice,
+    Fifo,
+    Socket,
+}
+
 #[derive(Clone, Debug)]
 pub struct FileAttr {
     pub changeid: u64,
     pub size: u64,
     pub mtime: u64,
     pub ctime: u64,
+    pub atime: u64,
+    pub ftype: FileType,
+    /// Permission bits only; the type is in `ftype`
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub fileid: u64,
+    pub fsid: u64,
+    /// Bytes of storage actually allocated
+    pub used: u64,
+    /// Major/minor for device nodes
+    pub rdev: (u32, u32),
+    /// `None` if the object has no ACL beyond its mode
+    pub acl: Option<Acl>,
 }
 
-#[derive(Clone)]
-pub struct MemVfs {
-    root_fh: Vec<u8>,
-    attrs: Arc<DashMap<String, FileAttr>>,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetTime {
+    ServerNow,
+    Client(u64),
+}
+
+/// Attributes a client asked to change; `None` leaves the value alone
+#[derive(Clone, Debug, Default)]
+pub struct SetAttr {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub size: Option<u64>,
+    pub atime: Option<SetTime>,
+    pub mtime: Option<SetTime>,
+    /// Replaces the whole ACL, which then decides the permission bits
+    pub acl: Option<Acl>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CreateKind {
+    Regular,
+    Directory,
+    Symlink(String),
+    BlockDevice(u32, u32),
+    CharDevice(u32, u32),
+    Fifo,
+    Socket,
+}
+
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    /// Position to resume after this entry; stays valid while the entry exists
+    pub cookie: u64,
+    pub fh: Vec<u8>,
+    /// `None` if the entry's attributes could not be read
+    pub attr: Option<FileAttr>,
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct ReadDir {
+    pub entries: Vec<DirEntry>,
+    pub eof: bool,
+    /// Changes when cookies from an earlier listing may no longer resume at
+    /// the same place; 0 if cookies last as long as their entries
+    pub verifier: u64,
+}
+
+/// What a byte of a regular file holds, as SEEK and READ_PLUS tell them
+/// apart: data, or a hole that reads as zeros and takes no storage
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Content {
+    Data,
+    Hole,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FsStat {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub avail_bytes: u64,
+    pub total_files: u64,
+    pub free_files: u64,
+    pub avail_files: u64,
+}
+
+// Most the default `copy_range` holds in memory at once
+const COPY_CHUNK: u64 = 1024 * 1024;
+
 #[async_trait]
 pub trait Vfs: Send + Sync {
     async fn root_fh(&self) -> NfsResult<Vec<u8>>;
-    async fn getattr_root(&self, attr_request: &Vec<u32>) -> NfsResult<Vec<u8>>;
+    async fn getattr_root(&self, attr_request: &[u32]) -> NfsResult<Vec<u8>>;
     async fn create_file(&self, path: &str, size: u64) -> NfsResult<()>;
     async fn modify_file(&self, path: &str, new_size: u64) -> NfsResult<()>;
     async fn create_dir(&self, path: &str) -> NfsResult<()>;
     async fn remove_entry(&self, path: &str) -> NfsResult<()>;
+
+    // Filehandle based operations used by the protocol handlers. Handles are
+    // opaque to callers; a backend returns `BadHandle` for handles it could
+    // never have issued and `StaleHandle` for ones whose object is gone.
+
+    async fn getattr(&self, _fh: &[u8]) -> NfsResult<FileAttr> {
+        Err(NfsError::NotSupported)
+    }
+    async fn setattr(&self, _fh: &[u8], _attr: &SetAttr) -> NfsResult<FileAttr> {
+        Err(NfsError::NotSupported)
+    }
+    async fn lookup(&self, _dir: &[u8], _name: &str) -> NfsResult<Vec<u8>> {
+        Err(NfsError::NotSupported)
+    }
+    /// Handle of the directory containing `dir`; the root is its own parent
+    async fn lookup_parent(&self, _dir: &[u8]) -> NfsResult<Vec<u8>> {
+        Err(NfsError::NotSupported)
+    }
+    /// Read up to `count` bytes; the flag is true when the read reached EOF
+    async fn read(&self, _fh: &[u8], _offset: u64, _count: u32) -> NfsResult<(Bytes, bool)> {
+        Err(NfsError::NotSupported)
+    }
+    async fn write(&self, _fh: &[u8], _offset: u64, _data: Bytes) -> NfsResult<u32> {
+        Err(NfsError::NotSupported)
+    }
+    /// Make previously written data in the range durable
+    async fn commit(&self, _fh: &[u8], _offset: u64, _count: u64) -> NfsResult<()> {
+        Ok(())
+    }
+    /// Copy up to `count` bytes of `src` from `src_offset` to `dst` at
+    /// `dst_offset`, stopping at the end of `src`, and return how many were
+    /// copied. The default reads and writes a chunk at a time; a backend
+    /// that can copy without moving the data through us should.
+    async fn copy_range(&self, src: &[u8], src_offset: u64, dst: &[u8], dst_offset: u64, count: u64) -> NfsResult<u64> {
+        let mut done = 0;
+        while done < count {
+            let want = (count - done).min(COPY_CHUNK) as u32;
+            let (data, eof) = self.read(src, src_offset + done, want).await?;
+            let n = data.len() as u64;
+            if n > 0 {
+                self.write(dst, dst_offset + done, data).await?;
+            }
+            done += n;
+            if eof || n == 0 {
+                break;
+            }
+        }
+        Ok(done)
+    }
+    /// Make `count` bytes of `dst` from `dst_offset` share the storage of
+    /// `src` from `src_offset`. The offsets are multiples of
+    /// `clone_blksize`, as is `count` unless the range runs to the end of
+    /// `src`, which it does not pass.
+    async fn clone_range(&self, _src: &[u8], _src_offset: u64, _dst: &[u8], _dst_offset: u64, _count: u64) -> NfsResult<()> {
+        Err(NfsError::NotSupported)
+    }
+    /// Where the first `what` at or after `offset` starts; `None` if there
+    /// is none before the end of the file, or `offset` is not before it.
+    /// The end of the file starts a hole. The default knows of no holes.
+    async fn seek(&self, fh: &[u8], offset: u64, what: Content) -> NfsResult<Option<u64>> {
+        let size = self.getattr(fh).await?.size;
+        Ok(match what {
+            _ if offset >= size => None,
+            Content::Data => Some(offset),
+            Content::Hole => Some(size),
+        })
+    }
+    /// Read up to `count` bytes as the runs of data and holes `seek` finds
+    /// in them; the flag is true when the read reached EOF
+    async fn read_extents(&self, fh: &[u8], offset: u64, count: u32) -> NfsResult<ReadPlus4resok> {
+        let size = self.getattr(fh).await?.size;
+        let end = offset.saturating_add(count as u64).min(size);
+        let mut contents = Vec::new();
+        let mut at = offset;
+        while at < end {
+            let hole = self.seek(fh, at, Content::Hole).await?.unwrap_or(size).min(end);
+            if hole > at {
+                let (data, _) = self.read(fh, at, (hole - at) as u32).await?;
+                // the file shrank under us
+                if data.is_empty() {
+                    break;
+                }
+                let n = data.len() as u64;
+                contents.push(ReadPlusContent4::Data { offset: at, data });
+                at += n;
+            } else {
+                let data = self.seek(fh, at, Content::Data).await?.unwrap_or(size).min(end);
+                if data <= at {
+                    break;
+                }
+                contents.push(ReadPlusContent4::Hole { offset: at, length: data - at });
+                at = data;
+            }
+        }
+        Ok(ReadPlus4resok { eof: at >= size, contents })
+    }
+    /// Make sure `length` bytes from `offset` have storage, growing the
+    /// file if they run past its end
+    async fn allocate(&self, _fh: &[u8], _offset: u64, _length: u64) -> NfsResult<()> {
+        Err(NfsError::NotSupported)
+    }
+    /// Make `length` bytes from `offset` a hole, leaving the size alone
+    async fn deallocate(&self, _fh: &[u8], _offset: u64, _length: u64) -> NfsResult<()> {
+        Err(NfsError::NotSupported)
+    }
+    async fn create(&self, _dir: &[u8], _name: &str, _kind: CreateKind, _attr: &SetAttr) -> NfsResult<Vec<u8>> {
+        Err(NfsError::NotSupported)
+    }
+    async fn readlink(&self, _fh: &[u8]) -> NfsResult<String> {
+        Err(NfsError::NotSupported)
+    }
+    /// Remove a non-directory or an empty directory
+    async fn remove(&self, _dir: &[u8], _name: &str) -> NfsResult<()> {
+        Err(NfsError::NotSupported)
+    }
+    async fn rename(&self, _from_dir: &[u8], _from: &str, _to_dir: &[u8], _to: &str) -> NfsResult<()> {
+        Err(NfsError::NotSupported)
+    }
+    async fn link(&self, _fh: &[u8], _dir: &[u8], _name: &str) -> NfsResult<()> {
+        Err(NfsError::NotSupported)
+    }
+    /// List up to `max_entries` entries following `cookie` (0 = from the
+    /// start); a cookie never handed out is `Status(BadCookie)`
+    async fn readdir(&self, _dir: &[u8], _cookie: u64, _max_entries: usize) -> NfsResult<ReadDir> {
+        Err(NfsError::NotSupported)
+    }
+    async fn fsstat(&self, _fh: &[u8]) -> NfsResult<FsStat> {
+        Err(NfsError::NotSupported)
+    }
+    /// True if nothing but this server changes the backend, so directory
+    /// changes it makes one at a time are atomic as far as clients can tell
+    fn sole_writer(&self) -> bool {
+        false
+    }
+    /// The ACL4_SUPPORT_* entry types the backend stores, 0 if it keeps no
+    /// ACLs. One that does keeps each ACL and its mode in step, as
+    /// `acl::settle` and `acl::settle_new` do.
+    fn acl_support(&self) -> u32 {
+        0
+    }
+    /// The alignment `clone_range` needs, 0 if the backend cannot clone
+    fn clone_blksize(&self) -> u32 {
+        0
+    }
 }
 
+/// Encode the handful of fattr4 values `getattr_root` supports for a
+/// backend's root directory.
+pub fn encode_root_fattr4(root_attr: &FileAttr, root_fh: &[u8], acl_support: u32, clone_blksize: u32, attr_request: &[u32]) -> NfsResult<Vec<u8>> {
+    encode_file_fattr4(root_attr, root_fh, root_attr.fileid, acl_support, clone_blksize, attr_request)
+}
+
+fn nf4_type(ftype: FileType) -> u32 {
+    match ftype {
+        FileType::Regular => NF4REG,
+        FileType::Directory => NF4DIR,
+        FileType::BlockDevice => NF4BLK,
+        FileType::CharDevice => NF4CHR,
+        FileType::Symlink => NF4LNK,
+        FileType::Socket => NF4SOCK,
+        FileType::Fifo => NF4FIFO,
+    }
+}
+
+/// Encode the supported fattr4 values of any object. `mounted_on_fileid`
+/// differs from the fileid only at the root of an export, where it names
+/// the directory the export covers; `acl_support` and `clone_blksize` are
+/// what its backend reports. An object without an ACL shows the one its
+/// mode amounts to.
+pub fn encode_file_fattr4(attr: &FileAttr, fh: &[u8], mounted_on_fileid: u64, acl_support: u32, clone_blksize: u32, attr_request: &[u32]) -> NfsResult<Vec<u8>> {
+    let mut mask_bits: Vec<u32> = Vec::new();
+    let mut w = std::io::Cursor::new(Vec::new());
+
+    let req_has = |bit: u32| -> bool {
+        let idx = (bit / 32) as usize;
+        let off = bit % 32;
+        if idx >= attr_request.len() { return false; }
+        (attr_request[idx] & (1u32 << off)) != 0
+    };
+
+    if req_has(FATTR4_TYPE) {
+        mask_bits.push(FATTR4_TYPE);
+        nf4_type(attr.ftype).xdr_serialize(&mut w)?;
+    }
+    if req_has(FATTR4_FH_EXPIRE_TYPE) {
+        mask_bits.push(FATTR4_FH_EXPIRE_TYPE);
+        FH4_PERSISTENT.xdr_serialize(&mut w)?;
+    }
+    if req_has(FATTR4_CHANGE) {
+        mask_bits.push(FATTR4_CHANGE);
+        attr.changeid.xdr_serialize(&mut w)?;
+    }
+    if req_has(FATTR4_SIZE) {
+        mask_bits.push(FATTR4_SIZE);
+        attr.size.xdr_serialize(&mut w)?;
+    }
+    if req_has(FATTR4_FSID) {
+        // fsid4 { major, minor }
+        mask_bits.push(FATTR4_FSID);
+        attr.fsid.xdr_serialize(&mut w)?;
+        0u64.xdr_serialize(&mut w)?;
+    }
+    if req_has(FATTR4_RDATTR_ERROR) {
+        // only meaningful in READDIR, where it stands in for failed attributes
+        mask_bits.push(FATTR4_RDATTR_ERROR);
+        NFS4_OK.xdr_serialize(&mut w)?;
+    }
+    let acl = || attr.acl.clone().unwrap_or_else(|| Acl::from_mode(attr.mode, attr.ftype == FileType::Directory));
+    if req_has(FATTR4_ACL) {
+        mask_bits.push(FATTR4_ACL);
+        let mut buf = BytesMut::new();
+        acl().to_wire().xdr_encode(&mut buf);
+        w.write_all(&buf)?;
+    }
+    if req_has(FATTR4_ACLSUPPORT) {
+        mask_bits.push(FATTR4_ACLSUPPORT);
+        acl_support.xdr_serialize(&mut w)?;
+    }
+    // Note: time attributes not implemented in minimal proto set
+    if req_has(FATTR4_FILEHANDLE) {
+        mask_bits.push(FATTR4_FILEHANDLE);
+        fh.to_vec().xdr_serialize(&mut w)?;
+    }
+    if req_has(FATTR4_FILEID) {
+        mask_bits.push(FATTR4_FILEID);
+        attr.fileid.xdr_serialize(&mut w)?;
+    }
+    if req_has(FATTR4_MODE) {
+        mask_bits.push(FATTR4_MODE);
+        attr.mode.xdr_serialize(&mut w)?;
+    }
+    if req_has(FATTR4_MOUNTED_ON_FILEID) {
+        mask_bits.push(FATTR4_MOUNTED_ON_FILEID);
+        mounted_on_fileid.xdr_serialize(&mut w)?;
+    }
+    if req_has(FATTR4_DACL) {
+        mask_bits.push(FATTR4_DACL);
+        let mut buf = BytesMut::new();
+        acl().dacl().xdr_encode(&mut buf);
+        w.write_all(&buf)?;
+    }
+    if req_has(FATTR4_CLONE_BLKSIZE) && clone_blksize != 0 {
+        mask_bits.push(FATTR4_CLONE_BLKSIZE);
+        clone_blksize.xdr_serialize(&mut w)?;
+    }
+
+    let vals = w.into_inner();
+    let mut out = std::io::Cursor::new(Vec::new());
+    let bitmap = bitmap4_with(&mask_bits);
+    encode_fattr4(&mut out, &bitmap, &vals)?;
+    Ok(out.into_inner())
+}
+
+/// An owner, owner_group or ACL principal: a numeric id, optionally
+/// "@domain"; we map no names
+pub(crate) fn parse_owner(raw: &[u8]) -> NfsResult<u32> {
+    let owner = std::str::from_utf8(raw).map_err(|_| NfsError::Status(Nfs4Status::Badowner))?;
+    let id = owner.split_once('@').map_or(owner, |(id, _)| id);
+    id.parse().map_err(|_| NfsError::Status(Nfs4Status::Badowner))
+}
+
+fn decode_owner(buf: &mut Bytes) -> NfsResult<u32> {
+    parse_owner(&Bytes::xdr_decode(buf).map_err(|e| NfsError::Xdr(e.to_string()))?)
+}
+
+fn decode_settime4(buf: &mut Bytes) -> NfsResult<SetTime> {
+    let xdr = |e: std::io::Error| NfsError::Xdr(e.to_string());
+    match u32::xdr_decode(buf).map_err(xdr)? {
+        SET_TO_SERVER_TIME4 => Ok(SetTime::ServerNow),
+        SET_TO_CLIENT_TIME4 => {
+            // int64_t seconds; we keep whole seconds since the epoch
+            let secs = u64::xdr_decode(buf).map_err(xdr)? as i64;
+            let _nsecs = u32::xdr_decode(buf).map_err(xdr)?;
+            let secs = u64::try_from(secs).map_err(|_| NfsError::InvalidArgument("time before 1970".into()))?;
+            Ok(SetTime::Client(secs))
+        }
+        how => Err(NfsError::Xdr(format!("bad time_how4 {}", how))),
+    }
+}
+
+/// Decode the attributes a client asks to set, as CREATE and SETATTR carry
+/// them, and the bitmap of those set. Attributes we cannot set are
+/// `ATTRNOTSUPP`. A dacl comes back as the ACL; the caller, seeing
+/// FATTR4_DACL in the bitmap, keeps the entries it does not cover.
+pub fn decode_settable_fattr4(fattr: &Fattr4) -> NfsResult<(SetAttr, Vec<u32>)> {
+    let xdr = |e: std::io::Error| NfsError::Xdr(e.to_string());
+    let mut vals = fattr.attr_vals.clone();
+    let mut set = SetAttr::default();
+    let mut bits = Vec::new();
+    for bit in (0..fattr.attrmask.len() as u32 * 32).filter(|&b| bitmap4_has(&fattr.attrmask, b)) {
+        match bit {
+            FATTR4_SIZE => set.size = Some(u64::xdr_decode(&mut vals).map_err(xdr)?),
+            FATTR4_ACL => set.acl = Some(Acl::from_wire(&Vec::<Nfsace4>::xdr_decode(&mut vals).map_err(xdr)?)?),
+            FATTR4_MODE => set.mode = Some(u32::xdr_decode(&mut vals).map_err(xdr)? & 0o7777),
+            FATTR4_OWNER => set.uid = Some(decode_owner(&mut vals)?),
+            FATTR4_OWNER_GROUP => set.gid = Some(decode_owner(&mut vals)?),
+            FATTR4_TIME_ACCESS_SET => set.atime = Some(decode_settime4(&mut vals)?),
+            FATTR4_TIME_MODIFY_SET => set.mtime = Some(decode_settime4(&mut vals)?),
+            FATTR4_DACL if set.acl.is_some() => return Err(NfsError::InvalidArgument("both acl and dacl".into())),
+            FATTR4_DACL => {
+                let dacl = Nfsacl41::xdr_decode(&mut vals).map_err(xdr)?;
+                if dacl.aces.iter().any(|a| a.acetype > ACE4_ACCESS_DENIED_ACE_TYPE) {
+                    return Err(NfsError::InvalidArgument("dacl holds only ALLOW and DENY entries".into()));
+                }
+                set.acl = Some(Acl { flags: dacl.flag, ..Acl::from_wire(&dacl.aces)? });
+            }
+            _ => return Err(NfsError::Status(Nfs4Status::Attrnotsupp)),
+        }
+        bits.push(bit);
+    }
+    if !vals.is_empty() {
+        return Err(NfsError::Xdr("attribute values longer than the bitmap says".into()));
+    }
+    Ok((set, bitmap4_with(&bits)))
+}
+
+/// First cookie handed out for directory entries; NFSv4 reserves 1 and 2
+pub(crate) const FIRST_COOKIE: u64 = 3;
+const ROOT_FILEID: u64 = 1;
+const MEM_FH_MAGIC: &[u8; 4] = b"nfsm";
+const MEM_CAPACITY: u64 = 1 << 40;
+const MEM_MAX_FILES: u64 = 1 << 24;
+const MEM_MAX_FILESIZE: u64 = 1 << 32;
+// Files are kept in blocks of this size: the unit CLONE shares
+const MEM_BLOCK: u64 = 4096;
+
+type Block = Arc<[u8; MEM_BLOCK as usize]>;
+
+// The contents of a file. Blocks are shared between the files CLONE
+// links until one of them writes; one never written or allocated, or
+// since deallocated, is a hole. Bytes past
+// the end are kept zero so a file that grows reads zeros there.
+#[derive(Debug, Default, Clone)]
+struct FileData {
+    len: u64,
+    blocks: BTreeMap<u64, Block>,
+}
+
+impl FileData {
+    fn used(&self) -> u64 {
+        self.blocks.len() as u64 * MEM_BLOCK
+    }
+
+    fn read(&self, offset: u64, count: u32) -> (Bytes, bool) {
+        let start = offset.min(self.len);
+        let end = start.saturating_add(count as u64).min(self.len);
+        let mut out = vec![0u8; (end - start) as usize];
+        for (&i, block) in self.blocks.range(start / MEM_BLOCK..end.div_ceil(MEM_BLOCK)) {
+            let base = i * MEM_BLOCK;
+            let (from, to) = (base.max(start), (base + MEM_BLOCK).min(end));
+            out[(from - start) as usize..(to - start) as usize].copy_from_slice(&block[(from - base) as usize..(to - base) as usize]);
+        }
+        (out.into(), end == self.len)
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        let end = offset + data.len() as u64;
+        let mut at = offset;
+        while at < end {
+            let base = at / MEM_BLOCK * MEM_BLOCK;
+            let to = (base + MEM_BLOCK).min(end);
+            let block = self.blocks.entry(base / MEM_BLOCK).or_insert_with(|| Arc::new([0; MEM_BLOCK as usize]));
+            Arc::make_mut(block)[(at - base) as usize..(to - base) as usize].copy_from_slice(&data[(at - offset) as usize..(to - offset) as usize]);
+            at = to;
+        }
+        self.len = self.len.max(end);
+    }
+
+    fn resize(&mut self, size: u64) {
+        if size < self.len {
+            self.blocks.split_off(&size.div_ceil(MEM_BLOCK));
+            let tail = (size % MEM_BLOCK) as usize;
+            if let (true, Some(block)) = (tail != 0, self.blocks.get_mut(&(size / MEM_BLOCK))) {
+                Arc::make_mut(block)[tail..].fill(0);
+            }
+        }
+        self.len = size;
+    }
+
+    // Share `src`'s blocks for `count` bytes from `src_offset` at
+    // `dst_offset`, both block aligned. A partial last block, at the end
+    // of `src`, is copied.
+    fn clone_range(&mut self, src: &FileData, src_offset: u64, dst_offset: u64, count: u64) {
+        let (from, to) = (src_offset / MEM_BLOCK, dst_offset / MEM_BLOCK);
+        let whole = count / MEM_BLOCK;
+        for i in 0..whole {
+            match src.blocks.get(&(from + i)) {
+                Some(block) => self.blocks.insert(to + i, block.clone()),
+                None => self.blocks.remove(&(to + i)),
+            };
+        }
+        let done = whole * MEM_BLOCK;
+        if done < count {
+            let (tail, _) = src.read(src_offset + done, (count - done) as u32);
+            self.write(dst_offset + done, &tail);
+        }
+        self.len = self.len.max(dst_offset + count);
+    }
+
+    fn seek(&self, offset: u64, what: Content) -> Option<u64> {
+        if offset >= self.len {
+            return None;
+        }
+        let first = offset / MEM_BLOCK;
+        let block = match what {
+            Content::Data => *self.blocks.range(first..).next()?.0,
+            // the end of the run of blocks from `first`
+            Content::Hole => first + self.blocks.range(first..).zip(first..).take_while(|((&b, _), i)| b == *i).count() as u64,
+        };
+        let at = (block * MEM_BLOCK).max(offset);
+        match what {
+            Content::Data => (at < self.len).then_some(at),
+            Content::Hole => Some(at.min(self.len)),
+        }
+    }
+
+    fn allocate(&mut self, offset: u64, length: u64) {
+        let end = offset + length;
+        // the new blocks share one of zeros until written
+        let zeros: Block = Arc::new([0; MEM_BLOCK as usize]);
+        for i in offset / MEM_BLOCK..end.div_ceil(MEM_BLOCK) {
+            self.blocks.entry(i).or_insert_with(|| zeros.clone());
+        }
+        self.len = self.len.max(end);
+    }
+
+    fn deallocate(&mut self, offset: u64, length: u64) {
+        let end = offset.saturating_add(length).min(self.len);
+        let mut at = offset;
+        while at < end {
+            let base = at / MEM_BLOCK * MEM_BLOCK;
+            let to = (base + MEM_BLOCK).min(end);
+            // a block goes if nothing of the file is left in it
+            if at == base && (to == base + MEM_BLOCK || to == self.len) {
+                self.blocks.remove(&(base / MEM_BLOCK));
+            } else if let Some(block) = self.blocks.get_mut(&(base / MEM_BLOCK)) {
+                Arc::make_mut(block)[(at - base) as usize..(to - base) as usize].fill(0);
+            }
+            at = to;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Directory {
+    parent: u64,
+    // cookie -> (name, fileid); cookies are never reused so they stay stable
+    entries: BTreeMap<u64, (String, u64)>,
+    names: HashMap<String, u64>,
+    next_cookie: u64,
+}
+
+#[derive(Debug)]
+enum NodeData {
+    File(FileData),
+    Dir(Directory),
+    Symlink(String),
+    Special,
+}
+
+#[derive(Debug)]
+struct Node {
+    attr: FileAttr,
+    data: NodeData,
+}
+
+pub struct MemVfs {
+    // random per instance so handles from an earlier server are stale
+    instance: u32,
+    nodes: Arc<DashMap<u64, Node>>,
+    next_fileid: AtomicU64,
+    // Serialises namespace changes touching several nodes. DashMap entries
+    // are only ever borrowed one at a time so shard locks cannot deadlock.
+    ns_lock: Mutex<()>,
+}
+
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+fn touch(attr: &mut FileAttr) {
+    let t = now();
+    attr.mtime = t;
+    attr.ctime = t;
+    attr.changeid += 1;
+}
+
+fn apply_time(t: SetTime) -> u64 {
+    match t {
+        SetTime::ServerNow => now(),
+        SetTime::Client(v) => v,
+    }
+}
 
 impl MemVfs {
     /// Public accessor for tests to get file attributes
     pub fn get_attr(&self, path: &str) -> Option<FileAttr> {
-        self.attrs.get(path).map(|entry| entry.clone())
+        let id = self.resolve(path).ok()?;
+        self.nodes.get(&id).map(|n| n.attr.clone())
     }
     pub fn new() -> Arc<Self> {
-        let mut fh = Vec::new();
-        fh.extend_from_slice(b"nfsrs-root-fh");
-        let attrs = DashMap::new();
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        attrs.insert(
-            "/".to_string(),
-            FileAttr {
-                changeid: now,
-                size: 0,
-                mtime: now,
-                ctime: now,
+        let nodes = DashMap::new();
+        let t = now();
+        nodes.insert(
+            ROOT_FILEID,
+            Node {
+                attr: FileAttr {
+                    changeid: t,
+                    size: 0,
+                    mtime: t,
+                    ctime: t,
+                    atime: t,
+                    ftype: FileType::Directory,
+                    mode: 0o755,
+                    nlink: 2,
+                    uid: 0,
+                    gid: 0,
+                    fileid: ROOT_FILEID,
+                    fsid: 1,
+                    used: 0,
+                    rdev: (0, 0),
+                    acl: None,
+                },
+                data: NodeData::Dir(Directory { parent: ROOT_FILEID, next_cookie: FIRST_COOKIE, ..Default::default() }),
             },
         );
+        let instance = u32::from_be_bytes(uuid::Uuid::new_v4().as_bytes()[..4].try_into().unwrap());
         Arc::new(Self {
-            root_fh: fh,
-            attrs: Arc::new(attrs),
+            instance,
+            nodes: Arc::new(nodes),
+            next_fileid: AtomicU64::new(ROOT_FILEID + 1),
+            ns_lock: Mutex::new(()),
         })
     }
 
-    // Batch/coalesce: only update if size or mtime would change
-    fn update_attr(&self, path: &str, size: Option<u64>) {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        self.attrs.entry(path.to_string()).and_modify(|entry| {
-            let mut changed = false;
-            if let Some(s) = size {
-                if entry.size != s {
-                    entry.size = s;
-                    changed = true;
+    fn fh_for(&self, fileid: u64) -> Vec<u8> {
+        let mut fh = Vec::with_capacity(16);
+        fh.extend_from_slice(MEM_FH_MAGIC);
+        fh.extend_from_slice(&self.instance.to_be_bytes());
+        fh.extend_from_slice(&fileid.to_be_bytes());
+        fh
+    }
+
+    fn fileid_of(&self, fh: &[u8]) -> NfsResult<u64> {
+        if fh.len() != 16 || &fh[..4] != MEM_FH_MAGIC {
+            return Err(NfsError::BadHandle);
+        }
+        let instance = u32::from_be_bytes(fh[4..8].try_into().unwrap());
+        let fileid = u64::from_be_bytes(fh[8..16].try_into().unwrap());
+        if instance != self.instance || !self.nodes.contains_key(&fileid) {
+            return Err(NfsError::StaleHandle);
+        }
+        Ok(fileid)
+    }
+
+    fn attr_of(&self, id: u64) -> NfsResult<FileAttr> {
+        self.nodes.get(&id).map(|n| n.attr.clone()).ok_or(NfsError::StaleHandle)
+    }
+
+    fn dir_lookup(&self, dir: u64, name: &str) -> NfsResult<u64> {
+        let node = self.nodes.get(&dir).ok_or(NfsError::StaleHandle)?;
+        match &node.data {
+            NodeData::Dir(d) => {
+                let cookie = d.names.get(name).ok_or(NfsError::NotFound)?;
+                Ok(d.entries[cookie].1)
+            }
+            _ => Err(NfsError::NotDir),
+        }
+    }
+
+    fn resolve(&self, path: &str) -> NfsResult<u64> {
+        let mut id = ROOT_FILEID;
+        for comp in path.split('/').filter(|c| !c.is_empty()) {
+            id = self.dir_lookup(id, comp)?;
+        }
+        Ok(id)
+    }
+
+    fn resolve_parent(&self, path: &str) -> NfsResult<(u64, String)> {
+        let trimmed = path.trim_end_matches('/');
+        let (parent, name) = trimmed.rsplit_once('/').unwrap_or(("", trimmed));
+        if name.is_empty() {
+            return Err(NfsError::InvalidArgument(format!("no file name in {:?}", path)));
+        }
+        Ok((self.resolve(parent)?, name.to_string()))
+    }
+
+    // Add `name` -> `child` to a directory. Caller holds `ns_lock`.
+    fn link_entry(&self, dir: u64, name: &str, child: u64, child_is_dir: bool) -> NfsResult<()> {
+        let mut node = self.nodes.get_mut(&dir).ok_or(NfsError::StaleHandle)?;
+        let Node { attr, data } = &mut *node;
+        let NodeData::Dir(d) = data else { return Err(NfsError::NotDir) };
+        if d.names.contains_key(name) {
+            return Err(NfsError::AlreadyExists);
+        }
+        let cookie = d.next_cookie;
+        d.next_cookie += 1;
+        d.entries.insert(cookie, (name.to_string(), child));
+        d.names.insert(name.to_string(), cookie);
+        attr.size = d.entries.len() as u64;
+        if child_is_dir {
+            attr.nlink += 1;
+        }
+        touch(attr);
+        Ok(())
+    }
+
+    // Drop `name` from a directory and return the child it pointed to.
+    // Caller holds `ns_lock`.
+    fn unlink_entry(&self, dir: u64, name: &str) -> NfsResult<u64> {
+        let mut node = self.nodes.get_mut(&dir).ok_or(NfsError::StaleHandle)?;
+        let Node { attr, data } = &mut *node;
+        let NodeData::Dir(d) = data else { return Err(NfsError::NotDir) };
+        let cookie = d.names.remove(name).ok_or(NfsError::NotFound)?;
+        let (_, child) = d.entries.remove(&cookie).expect("dir index out of sync");
+        attr.size = d.entries.len() as u64;
+        touch(attr);
+        Ok(child)
+    }
+
+    fn alloc_node(&self, parent: u64, kind: &CreateKind, set: &SetAttr) -> u64 {
+        let fileid = self.next_fileid.fetch_add(1, Ordering::Relaxed);
+        let t = now();
+        let (ftype, data, rdev) = match kind {
+            CreateKind::Regular => (FileType::Regular, NodeData::File(FileData::default()), (0, 0)),
+            CreateKind::Directory => (
+                FileType::Directory,
+                NodeData::Dir(Directory { parent, next_cookie: FIRST_COOKIE, ..Default::default() }),
+                (0, 0),
+            ),
+            CreateKind::Symlink(target) => (FileType::Symlink, NodeData::Symlink(target.clone()), (0, 0)),
+            CreateKind::BlockDevice(ma, mi) => (FileType::BlockDevice, NodeData::Special, (*ma, *mi)),
+            CreateKind::CharDevice(ma, mi) => (FileType::CharDevice, NodeData::Special, (*ma, *mi)),
+            CreateKind::Fifo => (FileType::Fifo, NodeData::Special, (0, 0)),
+            CreateKind::Socket => (FileType::Socket, NodeData::Special, (0, 0)),
+        };
+        let default_mode = match ftype {
+            FileType::Directory => 0o755,
+            FileType::Symlink => 0o777,
+            _ => 0o644,
+        };
+        let size = match &data {
+            NodeData::Symlink(target) => target.len() as u64,
+            _ => 0,
+        };
+        // symlinks take no ACL from their directory
+        let parent_acl = match ftype {
+            FileType::Symlink => None,
+            _ => self.nodes.get(&parent).and_then(|n| n.attr.acl.clone()),
+        };
+        let dir = ftype == FileType::Directory;
+        let (mode, acl) = acl::settle_new(default_mode, parent_acl.as_ref(), set.mode, set.acl.as_ref(), dir);
+        let mut node = Node {
+            attr: FileAttr {
+                changeid: 1,
+                size,
+                mtime: t,
+                ctime: t,
+                atime: t,
+                ftype,
+                mode,
+                nlink: if dir { 2 } else { 1 },
+                uid: set.uid.unwrap_or(0),
+                gid: set.gid.unwrap_or(0),
+                fileid,
+                fsid: 1,
+                used: 0,
+                rdev,
+                acl,
+            },
+            data,
+        };
+        if let Some(size) = set.size {
+            Self::resize(&mut node, size);
+        }
+        self.nodes.insert(fileid, node);
+        fileid
+    }
+
+    fn resize(node: &mut Node, size: u64) {
+        if let NodeData::File(data) = &mut node.data {
+            data.resize(size);
+            node.attr.size = size;
+            node.attr.used = data.used();
+        }
+    }
+
+    // Create-or-update used by the path based API
+    fn update_attr(&self, path: &str, kind: CreateKind, size: Option<u64>) -> NfsResult<()> {
+        let _ns = self.ns_lock.lock().unwrap();
+        let (parent, name) = self.resolve_parent(path)?;
+        match self.dir_lookup(parent, &name) {
+            Ok(id) => {
+                let mut node = self.nodes.get_mut(&id).ok_or(NfsError::StaleHandle)?;
+                // Batch/coalesce: only bump change attributes if the size moves
+                if let Some(s) = size {
+                    if node.attr.size != s {
+                        Self::resize(&mut node, s);
+                        touch(&mut node.attr);
+                    }
                 }
+                Ok(())
             }
-            if entry.mtime != now {
-                entry.mtime = now;
-                changed = true;
+            Err(NfsError::NotFound) => {
+                let is_dir = kind == CreateKind::Directory;
+                let set = SetAttr { size, ..Default::default() };
+                let id = self.alloc_node(parent, &kind, &set);
+                self.link_entry(parent, &name, id, is_dir).inspect_err(|_| {
+                    self.nodes.remove(&id);
+                })
             }
-            if changed {
-                entry.changeid = now;
+            Err(e) => Err(e),
+        }
+    }
+
+    fn remove_attr(&self, dir: u64, name: &str) -> NfsResult<()> {
+        let _ns = self.ns_lock.lock().unwrap();
+        let child = self.dir_lookup(dir, name)?;
+        let child_is_dir = {
+            let node = self.nodes.get(&child).ok_or(NfsError::StaleHandle)?;
+            match &node.data {
+                NodeData::Dir(d) if !d.entries.is_empty() => return Err(NfsError::NotEmpty),
+                NodeData::Dir(_) => true,
+                _ => false,
+            }
+        };
+        self.unlink_entry(dir, name)?;
+        self.drop_link(child, child_is_dir);
+        if child_is_dir {
+            if let Some(mut parent) = self.nodes.get_mut(&dir) {
+                parent.attr.nlink -= 1;
             }
-        }).or_insert_with(|| FileAttr {
-            changeid: now,
-            size: size.unwrap_or(0),
-            mtime: now,
-            ctime: now,
-        });
+        }
+        Ok(())
+    }
+
+    // One name for `id` went away; free it once nothing refers to it
+    fn drop_link(&self, id: u64, is_dir: bool) {
+        let gone = match self.nodes.get_mut(&id) {
+            Some(mut node) => {
+                node.attr.nlink = node.attr.nlink.saturating_sub(1);
+                node.attr.ctime = now();
+                is_dir || node.attr.nlink == 0
+            }
+            None => false,
+        };
+        if gone {
+            self.nodes.remove(&id);
+        }
     }
 
-    fn remove_attr(&self, path: &str) {
-        self.attrs.remove(path);
+    fn is_ancestor(&self, ancestor: u64, mut id: u64) -> bool {
+        loop {
+            if id == ancestor {
+                return true;
+            }
+            let parent = match self.nodes.get(&id).map(|n| match &n.data {
+                NodeData::Dir(d) => d.parent,
+                _ => id,
+            }) {
+                Some(p) => p,
+                None => return false,
+            };
+            if parent == id {
+                return false;
+            }
+            id = parent;
+        }
     }
 }
 
@@ -93,74 +900,343 @@ impl MemVfs {
 #[async_trait]
 impl Vfs for MemVfs {
     async fn root_fh(&self) -> NfsResult<Vec<u8>> {
-        Ok(self.root_fh.clone())
+        Ok(self.fh_for(ROOT_FILEID))
     }
 
-    async fn getattr_root(&self, attr_request: &Vec<u32>) -> NfsResult<Vec<u8>> {
+    async fn getattr_root(&self, attr_request: &[u32]) -> NfsResult<Vec<u8>> {
         // DashMap read lock is very fast; no blocking for other ops
-        let root_attr = self.attrs.get("/").expect("root attr");
-        let mut mask_bits: Vec<u32> = Vec::new();
-        let mut w = std::io::Cursor::new(Vec::new());
-
-        let req_has = |bit: u32| -> bool {
-            let idx = (bit / 32) as usize;
-            let off = bit % 32;
-            if idx >= attr_request.len() { return false; }
-            (attr_request[idx] & (1u32 << off)) != 0
+        let root_attr = self.attr_of(ROOT_FILEID)?;
+        encode_root_fattr4(&root_attr, &self.fh_for(ROOT_FILEID), self.acl_support(), self.clone_blksize(), attr_request)
+    }
+
+    async fn create_file(&self, path: &str, size: u64) -> NfsResult<()> {
+        // Only update if needed (batch/coalesce)
+        self.update_attr(path, CreateKind::Regular, Some(size))
+    }
+
+    async fn modify_file(&self, path: &str, new_size: u64) -> NfsResult<()> {
+        self.update_attr(path, CreateKind::Regular, Some(new_size))
+    }
+
+    async fn create_dir(&self, path: &str) -> NfsResult<()> {
+        self.update_attr(path, CreateKind::Directory, None)
+    }
+
+    async fn remove_entry(&self, path: &str) -> NfsResult<()> {
+        let (parent, name) = self.resolve_parent(path)?;
+        self.remove_attr(parent, &name)
+    }
+
+    async fn getattr(&self, fh: &[u8]) -> NfsResult<FileAttr> {
+        self.attr_of(self.fileid_of(fh)?)
+    }
+
+    async fn setattr(&self, fh: &[u8], set: &SetAttr) -> NfsResult<FileAttr> {
+        let id = self.fileid_of(fh)?;
+        let mut node = self.nodes.get_mut(&id).ok_or(NfsError::StaleHandle)?;
+        if let Some(size) = set.size {
+            if size > MEM_MAX_FILESIZE {
+                return Err(NfsError::FileTooBig);
+            }
+            match node.attr.ftype {
+                FileType::Regular => Self::resize(&mut node, size),
+                FileType::Directory => return Err(NfsError::IsDir),
+                _ => return Err(NfsError::InvalidArgument("size of non-regular file".into())),
+            }
+            touch(&mut node.attr);
+        }
+        let attr = &mut node.attr;
+        if set.mode.is_some() || set.acl.is_some() {
+            let dir = attr.ftype == FileType::Directory;
+            (attr.mode, attr.acl) = acl::settle(attr.mode, attr.acl.as_ref(), set.mode, set.acl.as_ref(), dir);
+        }
+        if let Some(uid) = set.uid {
+            attr.uid = uid;
+        }
+        if let Some(gid) = set.gid {
+            attr.gid = gid;
+        }
+        if let Some(t) = set.atime {
+            attr.atime = apply_time(t);
+        }
+        if let Some(t) = set.mtime {
+            attr.mtime = apply_time(t);
+        }
+        attr.ctime = now();
+        attr.changeid += 1;
+        Ok(attr.clone())
+    }
+
+    async fn lookup(&self, dir: &[u8], name: &str) -> NfsResult<Vec<u8>> {
+        let dir = self.fileid_of(dir)?;
+        Ok(self.fh_for(self.dir_lookup(dir, name)?))
+    }
+
+    async fn lookup_parent(&self, dir: &[u8]) -> NfsResult<Vec<u8>> {
+        let id = self.fileid_of(dir)?;
+        let node = self.nodes.get(&id).ok_or(NfsError::StaleHandle)?;
+        match &node.data {
+            NodeData::Dir(d) => Ok(self.fh_for(d.parent)),
+            _ => Err(NfsError::NotDir),
+        }
+    }
+
+    async fn read(&self, fh: &[u8], offset: u64, count: u32) -> NfsResult<(Bytes, bool)> {
+        let id = self.fileid_of(fh)?;
+        let node = self.nodes.get(&id).ok_or(NfsError::StaleHandle)?;
+        match &node.data {
+            NodeData::File(data) => Ok(data.read(offset, count)),
+            NodeData::Dir(_) => Err(NfsError::IsDir),
+            _ => Err(NfsError::InvalidArgument("read of non-regular file".into())),
+        }
+    }
+
+    async fn write(&self, fh: &[u8], offset: u64, data: Bytes) -> NfsResult<u32> {
+        let id = self.fileid_of(fh)?;
+        let mut node = self.nodes.get_mut(&id).ok_or(NfsError::StaleHandle)?;
+        let Node { attr, data: contents } = &mut *node;
+        match contents {
+            NodeData::File(file) => {
+                if offset.saturating_add(data.len() as u64) > MEM_MAX_FILESIZE {
+                    return Err(NfsError::FileTooBig);
+                }
+                file.write(offset, &data);
+                attr.size = file.len;
+                attr.used = file.used();
+                touch(attr);
+                Ok(data.len() as u32)
+            }
+            NodeData::Dir(_) => Err(NfsError::IsDir),
+            _ => Err(NfsError::InvalidArgument("write to non-regular file".into())),
+        }
+    }
+
+    async fn clone_range(&self, src: &[u8], src_offset: u64, dst: &[u8], dst_offset: u64, count: u64) -> NfsResult<()> {
+        let (src, dst) = (self.fileid_of(src)?, self.fileid_of(dst)?);
+        if dst_offset.saturating_add(count) > MEM_MAX_FILESIZE {
+            return Err(NfsError::FileTooBig);
+        }
+        // a snapshot of the source only costs its block list, and leaves
+        // no entry borrowed while the destination is
+        let from = match &self.nodes.get(&src).ok_or(NfsError::StaleHandle)?.data {
+            NodeData::File(data) => data.clone(),
+            NodeData::Dir(_) => return Err(NfsError::IsDir),
+            _ => return Err(NfsError::InvalidArgument("clone of non-regular file".into())),
         };
+        let mut node = self.nodes.get_mut(&dst).ok_or(NfsError::StaleHandle)?;
+        let Node { attr, data } = &mut *node;
+        match data {
+            NodeData::File(file) => {
+                file.clone_range(&from, src_offset, dst_offset, count);
+                attr.size = file.len;
+                attr.used = file.used();
+                touch(attr);
+                Ok(())
+            }
+            NodeData::Dir(_) => Err(NfsError::IsDir),
+            _ => Err(NfsError::InvalidArgument("clone to non-regular file".into())),
+        }
+    }
 
-        if req_has(FATTR4_TYPE) {
-            mask_bits.push(FATTR4_TYPE);
-            (NF4DIR as u32).xdr_serialize(&mut w)?;
+    async fn seek(&self, fh: &[u8], offset: u64, what: Content) -> NfsResult<Option<u64>> {
+        let id = self.fileid_of(fh)?;
+        let node = self.nodes.get(&id).ok_or(NfsError::StaleHandle)?;
+        match &node.data {
+            NodeData::File(data) => Ok(data.seek(offset, what)),
+            NodeData::Dir(_) => Err(NfsError::IsDir),
+            _ => Err(NfsError::InvalidArgument("seek in non-regular file".into())),
         }
-        if req_has(FATTR4_FH_EXPIRE_TYPE) {
-            mask_bits.push(FATTR4_FH_EXPIRE_TYPE);
-            (FH4_PERSISTENT as u32).xdr_serialize(&mut w)?;
+    }
+
+    async fn allocate(&self, fh: &[u8], offset: u64, length: u64) -> NfsResult<()> {
+        let id = self.fileid_of(fh)?;
+        let mut node = self.nodes.get_mut(&id).ok_or(NfsError::StaleHandle)?;
+        let Node { attr, data } = &mut *node;
+        match data {
+            NodeData::File(file) => {
+                if offset.saturating_add(length) > MEM_MAX_FILESIZE {
+                    return Err(NfsError::FileTooBig);
+                }
+                file.allocate(offset, length);
+                attr.size = file.len;
+                attr.used = file.used();
+                touch(attr);
+                Ok(())
+            }
+            NodeData::Dir(_) => Err(NfsError::IsDir),
+            _ => Err(NfsError::InvalidArgument("allocate in non-regular file".into())),
         }
-        if req_has(FATTR4_CHANGE) {
-            mask_bits.push(FATTR4_CHANGE);
-            root_attr.changeid.xdr_serialize(&mut w)?;
+    }
+
+    async fn deallocate(&self, fh: &[u8], offset: u64, length: u64) -> NfsResult<()> {
+        let id = self.fileid_of(fh)?;
+        let mut node = self.nodes.get_mut(&id).ok_or(NfsError::StaleHandle)?;
+        let Node { attr, data } = &mut *node;
+        match data {
+            NodeData::File(file) => {
+                file.deallocate(offset, length);
+                attr.used = file.used();
+                touch(attr);
+                Ok(())
+            }
+            NodeData::Dir(_) => Err(NfsError::IsDir),
+            _ => Err(NfsError::InvalidArgument("deallocate in non-regular file".into())),
         }
-        if req_has(FATTR4_SIZE) {
-            mask_bits.push(FATTR4_SIZE);
-            root_attr.size.xdr_serialize(&mut w)?;
+    }
+
+    async fn create(&self, dir: &[u8], name: &str, kind: CreateKind, attr: &SetAttr) -> NfsResult<Vec<u8>> {
+        let dir = self.fileid_of(dir)?;
+        let _ns = self.ns_lock.lock().unwrap();
+        match self.dir_lookup(dir, name) {
+            Ok(_) => return Err(NfsError::AlreadyExists),
+            Err(NfsError::NotFound) => {}
+            Err(e) => return Err(e),
         }
-        // Note: time attributes not implemented in minimal proto set
-        if req_has(FATTR4_FILEHANDLE) {
-            mask_bits.push(FATTR4_FILEHANDLE);
-            let fh = self.root_fh.clone();
-            let len = fh.len() as u32;
-            len.xdr_serialize(&mut w)?;
-            w.get_mut().extend_from_slice(&fh);
-            let pad = (4 - (fh.len() % 4)) % 4;
-            if pad > 0 { w.get_mut().extend_from_slice(&[0u8;3][..pad]); }
+        let is_dir = kind == CreateKind::Directory;
+        let id = self.alloc_node(dir, &kind, attr);
+        if let Err(e) = self.link_entry(dir, name, id, is_dir) {
+            self.nodes.remove(&id);
+            return Err(e);
         }
+        Ok(self.fh_for(id))
+    }
 
-        let vals = w.into_inner();
-        let mut out = std::io::Cursor::new(Vec::new());
-        let bitmap = bitmap4_with(&mask_bits);
-        encode_fattr4(&mut out, &bitmap, &vals)?;
-        Ok(out.into_inner())
+    async fn readlink(&self, fh: &[u8]) -> NfsResult<String> {
+        let id = self.fileid_of(fh)?;
+        let node = self.nodes.get(&id).ok_or(NfsError::StaleHandle)?;
+        match &node.data {
+            NodeData::Symlink(target) => Ok(target.clone()),
+            _ => Err(NfsError::InvalidArgument("not a symlink".into())),
+        }
     }
 
-    async fn create_file(&self, path: &str, size: u64) -> NfsResult<()> {
-        // Only update if needed (batch/coalesce)
-        self.update_attr(path, Some(size));
-        Ok(())
+    async fn remove(&self, dir: &[u8], name: &str) -> NfsResult<()> {
+        let dir = self.fileid_of(dir)?;
+        self.remove_attr(dir, name)
     }
 
-    async fn modify_file(&self, path: &str, new_size: u64) -> NfsResult<()> {
-        self.update_attr(path, Some(new_size));
+    async fn rename(&self, from_dir: &[u8], from: &str, to_dir: &[u8], to: &str) -> NfsResult<()> {
+        let from_dir = self.fileid_of(from_dir)?;
+        let to_dir = self.fileid_of(to_dir)?;
+        let _ns = self.ns_lock.lock().unwrap();
+        let src = self.dir_lookup(from_dir, from)?;
+        let src_is_dir = self.attr_of(src)?.ftype == FileType::Directory;
+        if src_is_dir && self.is_ancestor(src, to_dir) {
+            return Err(NfsError::InvalidArgument("cannot move a directory into itself".into()));
+        }
+        match self.dir_lookup(to_dir, to) {
+            Ok(dst) if dst == src => return Ok(()),
+            Ok(dst) => {
+                let dst_is_dir = {
+                    let node = self.nodes.get(&dst).ok_or(NfsError::StaleHandle)?;
+                    match &node.data {
+                        NodeData::Dir(d) if !d.entries.is_empty() => return Err(NfsError::NotEmpty),
+                        NodeData::Dir(_) => true,
+                        _ => false,
+                    }
+                };
+                if src_is_dir != dst_is_dir {
+                    return Err(if dst_is_dir { NfsError::IsDir } else { NfsError::NotDir });
+                }
+                self.unlink_entry(to_dir, to)?;
+                self.drop_link(dst, dst_is_dir);
+                if dst_is_dir {
+                    if let Some(mut parent) = self.nodes.get_mut(&to_dir) {
+                        parent.attr.nlink -= 1;
+                    }
+                }
+            }
+            Err(NfsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        // Publish the new name before dropping the old one so a concurrent
+        // lookup sees the object under at least one of them
+        self.link_entry(to_dir, to, src, src_is_dir)?;
+        self.unlink_entry(from_dir, from)?;
+        if src_is_dir {
+            if let Some(mut parent) = self.nodes.get_mut(&from_dir) {
+                parent.attr.nlink -= 1;
+            }
+            if let Some(mut node) = self.nodes.get_mut(&src) {
+                if let NodeData::Dir(d) = &mut node.data {
+                    d.parent = to_dir;
+                }
+            }
+        }
+        if let Some(mut node) = self.nodes.get_mut(&src) {
+            node.attr.ctime = now();
+            node.attr.changeid += 1;
+        }
         Ok(())
     }
 
-    async fn create_dir(&self, path: &str) -> NfsResult<()> {
-        self.update_attr(path, Some(0));
+    async fn link(&self, fh: &[u8], dir: &[u8], name: &str) -> NfsResult<()> {
+        let id = self.fileid_of(fh)?;
+        let dir = self.fileid_of(dir)?;
+        let _ns = self.ns_lock.lock().unwrap();
+        if self.attr_of(id)?.ftype == FileType::Directory {
+            return Err(NfsError::IsDir);
+        }
+        self.link_entry(dir, name, id, false)?;
+        if let Some(mut node) = self.nodes.get_mut(&id) {
+            node.attr.nlink += 1;
+            node.attr.ctime = now();
+            node.attr.changeid += 1;
+        }
         Ok(())
     }
 
-    async fn remove_entry(&self, path: &str) -> NfsResult<()> {
-        self.remove_attr(path);
-        Ok(())
+    fn sole_writer(&self) -> bool {
+        true
+    }
+
+    fn acl_support(&self) -> u32 {
+        ACL4_SUPPORT_ALLOW_ACL | ACL4_SUPPORT_DENY_ACL | ACL4_SUPPORT_AUDIT_ACL | ACL4_SUPPORT_ALARM_ACL
+    }
+
+    fn clone_blksize(&self) -> u32 {
+        MEM_BLOCK as u32
+    }
+
+    async fn readdir(&self, dir: &[u8], cookie: u64, max_entries: usize) -> NfsResult<ReadDir> {
+        let id = self.fileid_of(dir)?;
+        let (page, eof): (Vec<(u64, String, u64)>, bool) = {
+            let node = self.nodes.get(&id).ok_or(NfsError::StaleHandle)?;
+            let NodeData::Dir(d) = &node.data else { return Err(NfsError::NotDir) };
+            if cookie != 0 && cookie >= d.next_cookie {
+                return Err(NfsError::Status(Nfs4Status::BadCookie));
+            }
+            let mut iter = d.entries.range(cookie.saturating_add(1)..);
+            let page = iter
+                .by_ref()
+                .take(max_entries)
+                .map(|(c, (name, child))| (*c, name.clone(), *child))
+                .collect();
+            (page, iter.next().is_none())
+        };
+        let entries = page
+            .into_iter()
+            .map(|(cookie, name, child)| DirEntry {
+                name,
+                cookie,
+                fh: self.fh_for(child),
+                attr: self.attr_of(child).ok(),
+            })
+            .collect();
+        Ok(ReadDir { entries, eof, verifier: 0 })
+    }
+
+    async fn fsstat(&self, fh: &[u8]) -> NfsResult<FsStat> {
+        self.fileid_of(fh)?;
+        let used: u64 = self.nodes.iter().map(|n| n.attr.used).sum();
+        let files = self.nodes.len() as u64;
+        Ok(FsStat {
+            total_bytes: MEM_CAPACITY,
+            free_bytes: MEM_CAPACITY.saturating_sub(used),
+            avail_bytes: MEM_CAPACITY.saturating_sub(used),
+            total_files: MEM_MAX_FILES,
+            free_files: MEM_MAX_FILES.saturating_sub(files),
+            avail_files: MEM_MAX_FILES.saturating_sub(files),
+        })
     }
 }
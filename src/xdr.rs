@@ -1,4 +1,5 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::io::{Cursor, Read, Write};
 
 pub trait XdrSerialize {
@@ -157,3 +158,228 @@ pub fn encode_fattr4<W: Write>(w: &mut W, attrmask: &Vec<u32>, attr_vals: &[u8])
     }
     Ok(())
 }
+
+// Zero-copy variants of the XDR traits operating on `bytes` buffers. Decoding
+// splits opaque data off the receive buffer instead of copying it, and
+// encoding appends straight into a `BytesMut`.
+
+pub trait XdrEncode {
+    fn xdr_encode(&self, buf: &mut BytesMut);
+}
+
+pub trait XdrDecode: Sized {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self>;
+}
+
+/// Number of zero bytes needed to pad `len` to a 4-byte boundary.
+pub fn xdr_pad(len: usize) -> usize {
+    (4 - (len % 4)) % 4
+}
+
+/// XDR size of a string or opaque<> of `len` bytes, length word included.
+pub fn opaque_size(len: usize) -> usize {
+    4 + len + xdr_pad(len)
+}
+
+fn ensure_remaining(buf: &Bytes, n: usize) -> std::io::Result<()> {
+    if buf.remaining() < n {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "short xdr buffer"));
+    }
+    Ok(())
+}
+
+impl XdrEncode for u32 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        buf.put_u32(*self);
+    }
+}
+impl XdrDecode for u32 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        ensure_remaining(buf, 4)?;
+        Ok(buf.get_u32())
+    }
+}
+
+impl XdrEncode for i32 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        buf.put_i32(*self);
+    }
+}
+impl XdrDecode for i32 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        ensure_remaining(buf, 4)?;
+        Ok(buf.get_i32())
+    }
+}
+
+impl XdrEncode for u64 {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        buf.put_u64(*self);
+    }
+}
+impl XdrDecode for u64 {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        ensure_remaining(buf, 8)?;
+        Ok(buf.get_u64())
+    }
+}
+
+impl XdrEncode for bool {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        buf.put_u32(if *self { 1 } else { 0 });
+    }
+}
+impl XdrDecode for bool {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        Ok(u32::xdr_decode(buf)? != 0)
+    }
+}
+
+// opaque<>: the decoded value shares the underlying receive buffer
+impl XdrEncode for Bytes {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self[..].xdr_encode(buf)
+    }
+}
+impl XdrDecode for Bytes {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let len = u32::xdr_decode(buf)? as usize;
+        let pad = xdr_pad(len);
+        ensure_remaining(buf, len + pad)?;
+        let data = buf.split_to(len);
+        buf.advance(pad);
+        Ok(data)
+    }
+}
+
+impl XdrEncode for [u8] {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.len() as u32);
+        buf.put_slice(self);
+        buf.put_bytes(0, xdr_pad(self.len()));
+    }
+}
+
+impl XdrEncode for Vec<u8> {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self[..].xdr_encode(buf)
+    }
+}
+impl XdrDecode for Vec<u8> {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        Ok(Bytes::xdr_decode(buf)?.to_vec())
+    }
+}
+
+impl XdrEncode for Vec<u32> {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.len() as u32);
+        for v in self {
+            buf.put_u32(*v);
+        }
+    }
+}
+impl XdrDecode for Vec<u32> {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        let len = u32::xdr_decode(buf)? as usize;
+        ensure_remaining(buf, len.saturating_mul(4))?;
+        Ok((0..len).map(|_| buf.get_u32()).collect())
+    }
+}
+
+impl XdrEncode for XdrString {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.0.xdr_encode(buf)
+    }
+}
+impl XdrDecode for XdrString {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        Ok(XdrString(Vec::<u8>::xdr_decode(buf)?))
+    }
+}
+
+impl XdrEncode for String {
+    fn xdr_encode(&self, buf: &mut BytesMut) {
+        self.as_bytes().xdr_encode(buf)
+    }
+}
+
+impl XdrDecode for String {
+    fn xdr_decode(buf: &mut Bytes) -> std::io::Result<Self> {
+        String::from_utf8(Vec::<u8>::xdr_decode(buf)?)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "xdr string is not UTF-8"))
+    }
+}
+
+/// Opaque payloads up to this size are copied inline rather than kept as
+/// separate segments; a tiny extra iovec costs more than the copy.
+const INLINE_OPAQUE_MAX: usize = 1024;
+
+/// An XDR output buffer made of segments. Small items are encoded into the
+/// current segment; large opaque payloads (READ data) are kept as their own
+/// `Bytes` so a reply can be written with vectored I/O without first
+/// concatenating header and file data.
+#[derive(Debug, Default)]
+pub struct XdrChain {
+    segs: Vec<Bytes>,
+    cur: BytesMut,
+    frozen_len: usize,
+}
+
+impl XdrChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put<T: XdrEncode + ?Sized>(&mut self, v: &T) {
+        v.xdr_encode(&mut self.cur);
+    }
+
+    /// Append already-encoded XDR bytes.
+    pub fn put_raw(&mut self, raw: &[u8]) {
+        self.cur.extend_from_slice(raw);
+    }
+
+    /// Append an opaque<> whose body is referenced rather than copied when large.
+    pub fn put_opaque(&mut self, data: Bytes) {
+        if data.len() <= INLINE_OPAQUE_MAX {
+            data.xdr_encode(&mut self.cur);
+            return;
+        }
+        self.cur.put_u32(data.len() as u32);
+        let pad = xdr_pad(data.len());
+        self.push_segment(data);
+        self.cur.put_bytes(0, pad);
+    }
+
+    pub fn append(&mut self, other: XdrChain) {
+        for seg in other.into_segments() {
+            self.push_segment(seg);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frozen_len + self.cur.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn into_segments(mut self) -> Vec<Bytes> {
+        if !self.cur.is_empty() {
+            self.segs.push(self.cur.freeze());
+        }
+        self.segs
+    }
+
+    fn push_segment(&mut self, seg: Bytes) {
+        if !self.cur.is_empty() {
+            let head = self.cur.split().freeze();
+            self.frozen_len += head.len();
+            self.segs.push(head);
+        }
+        self.frozen_len += seg.len();
+        self.segs.push(seg);
+    }
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use common::*;
+use nfs_rs::acl::{Ace, Acl, Who};
+use nfs_rs::auth::Credential;
+use nfs_rs::config::*;
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::{CreateKind, MemVfs, SetAttr, Vfs};
+use nfs_rs::xdr::*;
+
+fn setattr_op(attrs: Fattr4) -> Op {
+    Op::new(NfsOp4::OpSetattr).arg(&Setattr4args { stateid: Stateid4::ANONYMOUS, attrs })
+}
+
+fn ace(kind: u32, flags: u32, mask: u32, who: Who) -> Ace {
+    Ace { kind, flags, mask, who }
+}
+
+fn allow(mask: u32, who: Who) -> Ace {
+    ace(ACE4_ACCESS_ALLOWED_ACE_TYPE, 0, mask, who)
+}
+
+fn deny(mask: u32, who: Who) -> Ace {
+    ace(ACE4_ACCESS_DENIED_ACE_TYPE, 0, mask, who)
+}
+
+fn acl_attr(aces: Vec<Ace>) -> Fattr4 {
+    let mut vals = BytesMut::new();
+    Acl { flags: 0, aces }.to_wire().xdr_encode(&mut vals);
+    Fattr4 { attrmask: bitmap4_with(&[FATTR4_ACL]), attr_vals: vals.freeze() }
+}
+
+fn mode_attr(mode: u32) -> Fattr4 {
+    Fattr4 { attrmask: bitmap4_with(&[FATTR4_MODE]), attr_vals: Bytes::copy_from_slice(&mode.to_be_bytes()) }
+}
+
+// The ACL, as GETATTR reports it
+async fn getacl(d: &Dispatcher, fh: &[u8]) -> Acl {
+    let (res, status) = compound(d, sys(0, 0), 1, vec![putfh(fh), getattr(&[FATTR4_ACL])]).await;
+    assert_eq!(status, NFS4_OK);
+    let Res::Getattr(attrmask, vals) = &res[1] else { panic!("{:?}", res) };
+    assert_eq!(*attrmask, bitmap4_with(&[FATTR4_ACL]));
+    let mut vals = vals.clone();
+    Acl::from_wire(&Vec::<Nfsace4>::xdr_decode(&mut vals).unwrap()).unwrap()
+}
+
+async fn access(d: &Dispatcher, cred: Credential, fh: &[u8]) -> u32 {
+    let all = ACCESS4_READ | ACCESS4_LOOKUP | ACCESS4_MODIFY | ACCESS4_EXTEND | ACCESS4_DELETE | ACCESS4_EXECUTE;
+    match &compound(d, cred, 1, vec![putfh(fh), common::access(all)]).await.0[1] {
+        Res::Access(_, access) => *access,
+        res => panic!("{:?}", res),
+    }
+}
+
+async fn setattr(d: &Dispatcher, cred: Credential, fh: &[u8], attrs: Fattr4) -> u32 {
+    compound(d, cred, 1, vec![putfh(fh), setattr_op(attrs)]).await.1
+}
+
+// An object under the root owned by uid 1000, gid 100
+async fn make(d: &Dispatcher, name: &str, kind: CreateKind, mode: u32) -> Vec<u8> {
+    let exports = d.exports();
+    let root = exports.root_fh().await.unwrap();
+    let set = SetAttr { mode: Some(mode), uid: Some(1000), gid: Some(100), ..Default::default() };
+    exports.create(&root, name, kind, &set).await.unwrap()
+}
+
+const RW: u32 = ACE4_READ_DATA | ACE4_WRITE_DATA | ACE4_APPEND_DATA;
+
+#[tokio::test]
+async fn objects_without_an_acl_show_their_mode() {
+    let d = Dispatcher::new(MemVfs::new());
+    let f = make(&d, "f", CreateKind::Regular, 0o640).await;
+    let acl = getacl(&d, &f).await;
+    assert_eq!(acl, Acl::from_mode(0o640, false));
+    assert_eq!(acl.mode(), 0o640);
+    let (res, _) = compound(&d, sys(0, 0), 1, vec![putfh(&f), getattr(&[FATTR4_ACLSUPPORT, FATTR4_MODE])]).await;
+    let mut vals = BytesMut::new();
+    (ACL4_SUPPORT_ALLOW_ACL | ACL4_SUPPORT_DENY_ACL | ACL4_SUPPORT_AUDIT_ACL | ACL4_SUPPORT_ALARM_ACL).xdr_encode(&mut vals);
+    0o640u32.xdr_encode(&mut vals);
+    assert_eq!(res[1], Res::Getattr(bitmap4_with(&[FATTR4_ACLSUPPORT, FATTR4_MODE]), vals.freeze()));
+}
+
+#[tokio::test]
+async fn setting_an_acl_sets_the_mode() {
+    let d = Dispatcher::new(MemVfs::new());
+    let f = make(&d, "f", CreateKind::Regular, 0o600).await;
+    let aces = vec![allow(RW, Who::Owner), deny(ACE4_READ_DATA, Who::User(2000)), allow(ACE4_READ_DATA, Who::Everyone)];
+    let (res, status) = compound(&d, sys(1000, 100), 1, vec![putfh(&f), setattr_op(acl_attr(aces.clone()))]).await;
+    assert_eq!((res, status), (vec![Res::Ok, Res::Setattr(bitmap4_with(&[FATTR4_ACL]))], NFS4_OK));
+    assert_eq!(getacl(&d, &f).await.aces, aces);
+    assert_eq!(d.exports().getattr(&f).await.unwrap().mode, 0o644);
+
+    // entries are evaluated in order; the first to name a bit decides it
+    assert_eq!(access(&d, sys(1000, 100), &f).await, ACCESS4_READ | ACCESS4_MODIFY | ACCESS4_EXTEND);
+    assert_eq!(access(&d, sys(2000, 200), &f).await, 0);
+    assert_eq!(access(&d, sys(3000, 300), &f).await, ACCESS4_READ);
+}
+
+#[tokio::test]
+async fn named_groups_are_evaluated() {
+    let d = Dispatcher::new(MemVfs::new());
+    let dir = make(&d, "d", CreateKind::Directory, 0o700).await;
+    let list = ACE4_LIST_DIRECTORY | ACE4_EXECUTE;
+    let aces = vec![allow(RW | ACE4_EXECUTE | ACE4_DELETE_CHILD, Who::Owner), allow(list | ACE4_ADD_FILE | ACE4_ADD_SUBDIRECTORY, Who::GroupId(500))];
+    assert_eq!(setattr(&d, sys(1000, 100), &dir, acl_attr(aces)).await, NFS4_OK);
+    assert_eq!(access(&d, sys(2000, 500), &dir).await, ACCESS4_READ | ACCESS4_LOOKUP | ACCESS4_EXTEND);
+    assert_eq!(access(&d, sys(2000, 200), &dir).await, 0);
+}
+
+#[tokio::test]
+async fn chmod_rewrites_the_acl() {
+    let d = Dispatcher::new(MemVfs::new());
+    let f = make(&d, "f", CreateKind::Regular, 0o600).await;
+    let aces = vec![allow(RW, Who::Owner), allow(RW, Who::User(2000))];
+    assert_eq!(setattr(&d, sys(1000, 100), &f, acl_attr(aces)).await, NFS4_OK);
+    assert_eq!(access(&d, sys(2000, 200), &f).await, ACCESS4_READ | ACCESS4_MODIFY | ACCESS4_EXTEND);
+
+    // the group bits cap what named users were allowed
+    assert_eq!(setattr(&d, sys(1000, 100), &f, mode_attr(0o640)).await, NFS4_OK);
+    let acl = getacl(&d, &f).await;
+    assert_eq!(acl.mode(), 0o640);
+    assert!(acl.aces.contains(&allow(ACE4_READ_DATA, Who::User(2000))));
+    assert_eq!(access(&d, sys(2000, 200), &f).await, ACCESS4_READ);
+    assert_eq!(access(&d, sys(3000, 300), &f).await, 0);
+    assert_eq!(d.exports().getattr(&f).await.unwrap().mode, 0o640);
+}
+
+#[tokio::test]
+async fn only_owners_and_write_acl_holders_set_acls() {
+    let d = Dispatcher::new(MemVfs::new());
+    let f = make(&d, "f", CreateKind::Regular, 0o666).await;
+    let open = vec![allow(RW | ACE4_WRITE_ACL, Who::Everyone)];
+    assert_eq!(setattr(&d, sys(2000, 200), &f, acl_attr(open.clone())).await, Nfs4Status::Access as u32);
+    assert_eq!(setattr(&d, sys(1000, 100), &f, acl_attr(open.clone())).await, NFS4_OK);
+    assert_eq!(setattr(&d, sys(2000, 200), &f, acl_attr(open)).await, NFS4_OK);
+    // the mode stays the owner's to change
+    assert_eq!(setattr(&d, sys(2000, 200), &f, mode_attr(0o600)).await, Nfs4Status::Access as u32);
+}
+
+#[tokio::test]
+async fn delete_on_the_object_stands_in_for_the_directory() {
+    let d = Dispatcher::new(MemVfs::new());
+    let dir = make(&d, "d", CreateKind::Directory, 0o755).await;
+    let exports = d.exports();
+    let f = exports.create(&dir, "f", CreateKind::Regular, &SetAttr::default()).await.unwrap();
+    exports.setattr(&f, &SetAttr { acl: Some(Acl { flags: 0, aces: vec![allow(ACE4_DELETE, Who::User(2000))] }), ..Default::default() }).await.unwrap();
+    let remove = Op::new(NfsOp4::OpRemove).arg(b"f".as_slice());
+    assert_eq!(compound(&d, sys(2000, 200), 1, vec![putfh(&dir), remove]).await.1, NFS4_OK);
+    assert!(exports.lookup(&dir, "f").await.is_err());
+}
+
+#[tokio::test]
+async fn a_dacl_keeps_audit_entries() {
+    let d = Dispatcher::new(MemVfs::new());
+    let f = make(&d, "f", CreateKind::Regular, 0o600).await;
+    let audit = ace(ACE4_SYSTEM_AUDIT_ACE_TYPE, ACE4_FAILED_ACCESS_ACE_FLAG, ACE4_READ_DATA, Who::Everyone);
+    assert_eq!(setattr(&d, sys(1000, 100), &f, acl_attr(vec![allow(RW, Who::Owner), audit.clone()])).await, NFS4_OK);
+
+    let dacl = Nfsacl41 { flag: ACL4_PROTECTED, aces: vec![allow(ACE4_READ_DATA, Who::Owner).to_wire()] };
+    let mut vals = BytesMut::new();
+    dacl.xdr_encode(&mut vals);
+    let attrs = Fattr4 { attrmask: bitmap4_with(&[FATTR4_DACL]), attr_vals: vals.freeze() };
+    assert_eq!(setattr(&d, sys(1000, 100), &f, attrs).await, NFS4_OK);
+    assert_eq!(getacl(&d, &f).await.aces, vec![allow(ACE4_READ_DATA, Who::Owner), audit]);
+
+    let (res, _) = compound(&d, sys(1000, 100), 1, vec![putfh(&f), getattr(&[FATTR4_DACL])]).await;
+    let Res::Getattr(_, vals) = &res[1] else { panic!("{:?}", res) };
+    assert_eq!(Nfsacl41::xdr_decode(&mut vals.clone()).unwrap(), dacl);
+    assert_eq!(d.exports().getattr(&f).await.unwrap().mode, 0o400);
+
+    // a dacl holds no AUDIT or ALARM entries
+    let bad = Nfsacl41 { flag: 0, aces: vec![ace(ACE4_SYSTEM_ALARM_ACE_TYPE, 0, ACE4_READ_DATA, Who::Everyone).to_wire()] };
+    let mut vals = BytesMut::new();
+    bad.xdr_encode(&mut vals);
+    let attrs = Fattr4 { attrmask: bitmap4_with(&[FATTR4_DACL]), attr_vals: vals.freeze() };
+    assert_eq!(setattr(&d, sys(1000, 100), &f, attrs).await, Nfs4Status::Inval as u32);
+}
+
+#[tokio::test]
+async fn new_objects_inherit_from_their_directory() {
+    let d = Dispatcher::new(MemVfs::new());
+    let dir = make(&d, "d", CreateKind::Directory, 0o755).await;
+    let aces = vec![
+        allow(RW | ACE4_EXECUTE | ACE4_DELETE_CHILD, Who::Owner),
+        ace(ACE4_ACCESS_ALLOWED_ACE_TYPE, ACE4_FILE_INHERIT_ACE | ACE4_INHERIT_ONLY_ACE, RW, Who::User(2000)),
+        ace(ACE4_ACCESS_ALLOWED_ACE_TYPE, ACE4_DIRECTORY_INHERIT_ACE | ACE4_NO_PROPAGATE_INHERIT_ACE, ACE4_READ_DATA, Who::Everyone),
+    ];
+    assert_eq!(setattr(&d, sys(1000, 100), &dir, acl_attr(aces)).await, NFS4_OK);
+    let exports = d.exports();
+    let f = exports.create(&dir, "f", CreateKind::Regular, &SetAttr::default()).await.unwrap();
+    assert_eq!(getacl(&d, &f).await.aces, vec![ace(ACE4_ACCESS_ALLOWED_ACE_TYPE, ACE4_INHERITED_ACE, RW, Who::User(2000))]);
+    assert_eq!(access(&d, sys(2000, 200), &f).await, ACCESS4_READ | ACCESS4_MODIFY | ACCESS4_EXTEND);
+    // with no mode given, the inherited ACL decides it
+    assert_eq!(exports.getattr(&f).await.unwrap().mode, 0);
+
+    // a subdirectory passes the file entry on and stops the other
+    let sub = exports.create(&dir, "sub", CreateKind::Directory, &SetAttr::default()).await.unwrap();
+    let inherited = ACE4_INHERITED_ACE;
+    assert_eq!(
+        getacl(&d, &sub).await.aces,
+        vec![
+            ace(ACE4_ACCESS_ALLOWED_ACE_TYPE, ACE4_FILE_INHERIT_ACE | ACE4_INHERIT_ONLY_ACE | inherited, RW, Who::User(2000)),
+            ace(ACE4_ACCESS_ALLOWED_ACE_TYPE, inherited, ACE4_READ_DATA, Who::Everyone),
+        ]
+    );
+    let g = exports.create(&sub, "g", CreateKind::Regular, &SetAttr { mode: Some(0o640), ..Default::default() }).await.unwrap();
+    // a mode given at creation rewrites what was inherited
+    let acl = getacl(&d, &g).await;
+    assert_eq!(acl.mode(), 0o640);
+    assert!(acl.aces.contains(&ace(ACE4_ACCESS_ALLOWED_ACE_TYPE, inherited, ACE4_READ_DATA, Who::User(2000))));
+}
+
+#[tokio::test]
+async fn backends_without_acls_refuse_them() {
+    let dir = tempfile::tempdir().unwrap();
+    let export = ExportConfig { backend: BackendConfig::Local { root: dir.path().to_string_lossy().into_owned() }, ..Default::default() };
+    let d = Dispatcher::from_config(&NfsConfig { exports: vec![export], ..Default::default() }).unwrap();
+    let root = d.exports().root_fh().await.unwrap();
+    let (res, _) = compound(&d, sys(0, 0), 1, vec![putfh(&root), getattr(&[FATTR4_ACLSUPPORT])]).await;
+    assert_eq!(res[1], Res::Getattr(bitmap4_with(&[FATTR4_ACLSUPPORT]), Bytes::from_static(&[0; 4])));
+    let status = setattr(&d, sys(0, 0), &root, acl_attr(vec![allow(RW, Who::Everyone)])).await;
+    assert_eq!(status, Nfs4Status::Attrnotsupp as u32);
+    // the mode still reads as an ACL
+    assert_eq!(getacl(&d, &root).await.mode(), d.exports().getattr(&root).await.unwrap().mode & 0o777);
+}
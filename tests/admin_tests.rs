@@ -0,0 +1,71 @@
+#![cfg(unix)]
+
+use nfs_rs::admin::*;
+use nfs_rs::lock::LockOwner;
+use nfs_rs::server::{serve_tcp, ShutdownHandle};
+use nfs_rs::{NfsConfig, NfsServer};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UnixStream};
+
+#[tokio::test]
+async fn admin_socket_lists_and_expires_clients() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("admin.sock");
+    let server = NfsServer::new(NfsConfig { bind_addr: "127.0.0.1".into(), ..Default::default() }).await.unwrap();
+    let d = server.dispatcher().clone();
+    let shutdown = ShutdownHandle::new(Duration::from_secs(1));
+    let admin = Arc::new(AdminService::new(d.clone(), server.config().clone()));
+    let admin_task = tokio::spawn(serve(bind(&socket).unwrap(), admin, shutdown.clone()));
+
+    // one NFS connection, a mount and a lock from 127.0.0.1
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let nfs_task = tokio::spawn(serve_tcp(listener, d.clone(), shutdown.clone()));
+    let mut conn = TcpStream::connect(addr).await.unwrap();
+    while d.limits().connections() == 0 {
+        tokio::task::yield_now().await;
+    }
+    d.restore(&nfs_rs::recovery::RecoveryRecord { mounts: vec![("127.0.0.1".into(), "/".into())], ..Default::default() });
+    let owner = LockOwner { host: "127.0.0.1".into(), id: b"o".to_vec(), svid: 3 };
+    d.locks().lock(b"fh", &owner, 0, 10, true).unwrap();
+
+    let clients: Vec<ClientInfo> = serde_json::from_value(request(&socket, &AdminRequest::Clients).await.unwrap()).unwrap();
+    assert_eq!(clients.len(), 1);
+    assert_eq!((clients[0].client.as_str(), clients[0].connections.len(), clients[0].locks), ("127.0.0.1", 1, 1));
+    assert_eq!(clients[0].mounts, ["/"]);
+
+    let locks: Vec<FileLocks> = serde_json::from_value(request(&socket, &AdminRequest::Locks).await.unwrap()).unwrap();
+    assert_eq!(locks[0].fh, "6668");
+    assert_eq!((locks[0].locks[0].length, locks[0].locks[0].exclusive), (10, true));
+
+    let exports: Vec<ExportInfo> = serde_json::from_value(request(&socket, &AdminRequest::Exports).await.unwrap()).unwrap();
+    assert_eq!((exports[0].path.as_str(), exports[0].mounts), ("/", 1));
+
+    let expire = AdminRequest::ExpireClient { client: "127.0.0.1".into() };
+    let expired: Expired = serde_json::from_value(request(&socket, &expire).await.unwrap()).unwrap();
+    assert_eq!(expired, Expired { connections: 1, mounts: 1, locks: 1, monitored: false });
+    assert_eq!(conn.read(&mut [0u8; 4]).await.unwrap(), 0);
+    assert!(d.mount().mounts().is_empty());
+
+    // failures come back as errors, and the session survives bad input
+    let revoke = AdminRequest::RevokeDelegation { stateid: "00".into() };
+    assert!(request(&socket, &revoke).await.is_err());
+    assert!(request(&socket, &AdminRequest::Reload).await.is_err());
+    let grace: serde_json::Value = request(&socket, &AdminRequest::EndGrace).await.unwrap();
+    assert_eq!(grace["was_in_grace"], false);
+    let mut raw = BufReader::new(UnixStream::connect(&socket).await.unwrap());
+    raw.get_mut().write_all(b"{\"op\": \"frobnicate\"}\n{\"op\": \"locks\"}\n").await.unwrap();
+    let mut line = String::new();
+    raw.read_line(&mut line).await.unwrap();
+    assert!(line.contains("\"ok\":false"), "{}", line);
+    line.clear();
+    raw.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "{\"ok\":true,\"result\":[]}\n");
+
+    shutdown.shutdown();
+    admin_task.await.unwrap().unwrap();
+    nfs_task.await.unwrap().unwrap();
+    assert!(!socket.exists());
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use bytes::BytesMut;
+use common::*;
+use nfs_rs::audit::{hex, AuditAction, AuditLog, AuditRecord};
+use nfs_rs::export::ExportTable;
+use nfs_rs::proto::nfs3::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::{MemVfs, SetAttr, Vfs};
+use nfs_rs::xdr::*;
+use nfs_rs::NfsConfig;
+use std::sync::Arc;
+
+async fn status(d: &Dispatcher, proc: Nfs3Proc, args: BytesMut) -> u32 {
+    u32::xdr_decode(&mut call3(d, sys(1000, 100), proc, args).await).unwrap()
+}
+
+#[tokio::test]
+async fn changes_are_audited_with_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("audit.log");
+    let d = Dispatcher::from_config(&NfsConfig { audit_log: Some(log.to_str().unwrap().into()), ..Default::default() }).unwrap();
+    let root = d.exports().root_fh().await.unwrap();
+    // let the unprivileged caller make its directory at the top
+    let vfs = d.exports().exports()[0].vfs.clone();
+    vfs.setattr(&vfs.root_fh().await.unwrap(), &SetAttr { mode: Some(0o1777), ..Default::default() }).await.unwrap();
+
+    let docs = create3(&d, sys(1000, 100), Nfs3Proc::Mkdir, &root, "docs").await;
+    let file = create3(&d, sys(1000, 100), Nfs3Proc::Create, &docs, "a.txt").await;
+    let mut args = BytesMut::new();
+    file.xdr_encode(&mut args);
+    4u64.xdr_encode(&mut args);
+    5u32.xdr_encode(&mut args);
+    FILE_SYNC.xdr_encode(&mut args);
+    b"hello"[..].xdr_encode(&mut args);
+    assert_eq!(status(&d, Nfs3Proc::Write, args).await, NFS3_OK);
+
+    let mut args = diropargs(&docs, "a.txt");
+    args.extend_from_slice(&diropargs(&docs, "b.txt"));
+    assert_eq!(status(&d, Nfs3Proc::Rename, args).await, NFS3_OK);
+    // the handle is now known by its new name
+    let mut args = BytesMut::new();
+    file.xdr_encode(&mut args);
+    sattr3(&mut args, Some(0o600));
+    false.xdr_encode(&mut args);
+    assert_eq!(status(&d, Nfs3Proc::Setattr, args).await, NFS3_OK);
+    assert_ne!(status(&d, Nfs3Proc::Remove, diropargs(&docs, "missing")).await, NFS3_OK);
+    assert_eq!(status(&d, Nfs3Proc::Remove, diropargs(&docs, "b.txt")).await, NFS3_OK);
+    // reads and lookups are not changes
+    assert_eq!(status(&d, Nfs3Proc::Lookup, diropargs(&root, "docs")).await, NFS3_OK);
+
+    let records: Vec<AuditRecord> =
+        std::fs::read_to_string(&log).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    let got: Vec<_> = records.iter().map(|r| (r.action, r.path.as_str(), r.to.as_deref(), r.status.as_str())).collect();
+    assert_eq!(
+        got,
+        [
+            (AuditAction::Mkdir, "/docs", None, "ok"),
+            (AuditAction::Create, "/docs/a.txt", None, "ok"),
+            (AuditAction::Write, "/docs/a.txt", None, "ok"),
+            (AuditAction::Rename, "/docs/a.txt", Some("/docs/b.txt"), "ok"),
+            (AuditAction::Setattr, "/docs/b.txt", None, "ok"),
+            (AuditAction::Remove, "/docs/missing", None, "Not found"),
+            (AuditAction::Remove, "/docs/b.txt", None, "ok"),
+        ]
+    );
+    assert!(records.iter().all(|r| r.client == "127.0.0.1" && r.principal == "1000:100@client"));
+    assert_eq!(records[2].detail.as_deref(), Some("offset=4 count=5"));
+    assert_eq!(records[4].detail.as_deref(), Some("mode=0600"));
+}
+
+#[tokio::test]
+async fn paths_are_pieced_together_from_names_seen() {
+    let dir = tempfile::tempdir().unwrap();
+    let exports = Arc::new(ExportTable::single(MemVfs::new()));
+    let audit = AuditLog::open(&dir.path().join("audit.log"), exports.clone()).unwrap();
+    let root = exports.root_fh().await.unwrap();
+    let (a, b) = (exports.exports()[0].wrap(b"\x00a"), exports.exports()[0].wrap(b"\x00b"));
+
+    assert_eq!(audit.path(&root).await, "/");
+    assert_eq!(audit.path(&b).await, format!("fh:{}", hex(&b)));
+    audit.learn(&a, "b", &b);
+    assert_eq!(audit.path(&b).await, format!("fh:{}/b", hex(&a)));
+    audit.learn(&root, "a", &a);
+    assert_eq!(audit.path(&b).await, "/a/b");
+    // "." and ".." are not names of their own
+    audit.learn(&b, "..", &a);
+    assert_eq!(audit.path(&a).await, "/a");
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use bytes::Bytes;
+use common::*;
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::{MemVfs, Vfs};
+use nfs_rs::xdr::*;
+
+fn clone_op(args: Clone4args) -> Op {
+    Op::new(NfsOp4::OpClone).arg(&args)
+}
+
+fn clone_args(src_offset: u64, dst_offset: u64, count: u64) -> Clone4args {
+    Clone4args { src_stateid: Stateid4::ANONYMOUS, dst_stateid: Stateid4::ANONYMOUS, src_offset, dst_offset, count }
+}
+
+// CLONE from `src` to `dst`; the status
+async fn clone(d: &Dispatcher, src: &[u8], dst: &[u8], args: Clone4args) -> u32 {
+    compound(d, sys(0, 0), 2, vec![putfh(src), savefh(), putfh(dst), clone_op(args)]).await.1
+}
+
+// The clone_blksize attribute of `fh`, if it has one
+async fn clone_blksize(d: &Dispatcher, fh: &[u8]) -> Option<u32> {
+    let (res, _) = compound(d, sys(0, 0), 2, vec![putfh(fh), getattr(&[FATTR4_CLONE_BLKSIZE])]).await;
+    let Res::Getattr(attrmask, vals) = &res[1] else { panic!("{:?}", res) };
+    bitmap4_has(attrmask, FATTR4_CLONE_BLKSIZE).then(|| u32::xdr_decode(&mut vals.clone()).unwrap())
+}
+
+#[tokio::test]
+async fn memory_backend_clones_share_blocks_until_written() {
+    let d = Dispatcher::new(MemVfs::new());
+    let data = pattern(3 * 4096 + 100);
+    let src = file(&d, "src", &data).await;
+    let dst = file(&d, "dst", b"").await;
+    assert_eq!(clone_blksize(&d, &src).await, Some(4096));
+
+    // the whole file, its partial last block included
+    assert_eq!(clone(&d, &src, &dst, clone_args(0, 0, 0)).await, NFS4_OK);
+    assert_eq!(contents(&d, &dst).await, data);
+    // writing either copy leaves the other alone
+    d.exports().write(&dst, 10, Bytes::from_static(b"changed")).await.unwrap();
+    assert_eq!(contents(&d, &src).await, data);
+    d.exports().write(&src, 5000, Bytes::from_static(b"also")).await.unwrap();
+    let mut expected = data.clone();
+    expected[10..17].copy_from_slice(b"changed");
+    assert_eq!(contents(&d, &dst).await, expected);
+
+    // one block, into the middle of the destination
+    let src_now = contents(&d, &src).await;
+    assert_eq!(clone(&d, &src, &dst, clone_args(4096, 8192, 4096)).await, NFS4_OK);
+    expected[8192..12288].copy_from_slice(&src_now[4096..8192]);
+    assert_eq!(contents(&d, &dst).await, expected);
+
+    // holes stay holes, and read as zeros
+    let sparse = file(&d, "sparse", b"").await;
+    d.exports().write(&sparse, 3 * 4096, Bytes::from_static(b"end")).await.unwrap();
+    let copy = file(&d, "copy", &pattern(5 * 4096)).await;
+    assert_eq!(clone(&d, &sparse, &copy, clone_args(0, 0, 3 * 4096)).await, NFS4_OK);
+    let copied = contents(&d, &copy).await;
+    assert!(copied[..3 * 4096].iter().all(|&b| b == 0));
+    assert_eq!(copied[3 * 4096..], pattern(5 * 4096)[3 * 4096..]);
+}
+
+#[tokio::test]
+async fn clones_are_aligned_and_within_the_source() {
+    let d = Dispatcher::new(MemVfs::new());
+    let src = file(&d, "src", &pattern(2 * 4096 + 10)).await;
+    let dst = file(&d, "dst", &pattern(4 * 4096)).await;
+    let inval = Nfs4Status::Inval as u32;
+
+    assert_eq!(clone(&d, &src, &dst, clone_args(100, 0, 4096)).await, inval);
+    assert_eq!(clone(&d, &src, &dst, clone_args(0, 100, 4096)).await, inval);
+    assert_eq!(clone(&d, &src, &dst, clone_args(0, 0, 100)).await, inval);
+    // past the end of the source
+    assert_eq!(clone(&d, &src, &dst, clone_args(4096, 0, 2 * 4096)).await, inval);
+    // a count that is not whole blocks may end where the source does
+    assert_eq!(clone(&d, &src, &dst, clone_args(4096, 0, 4096 + 10)).await, NFS4_OK);
+    assert_eq!(contents(&d, &dst).await[..4096 + 10], pattern(2 * 4096 + 10)[4096..]);
+    // and within one file the ranges may not overlap
+    assert_eq!(clone(&d, &dst, &dst, clone_args(0, 4096, 2 * 4096)).await, inval);
+    assert_eq!(clone(&d, &dst, &dst, clone_args(0, 2 * 4096, 2 * 4096)).await, NFS4_OK);
+}
+
+#[tokio::test]
+async fn clones_check_handles_and_permissions() {
+    let d = Dispatcher::new(MemVfs::new());
+    let src = file(&d, "src", &pattern(4096)).await;
+    let dst = file(&d, "dst", b"").await;
+    let root = d.exports().root_fh().await.unwrap();
+
+    let (_, status) = compound(&d, sys(0, 0), 2, vec![putfh(&dst), clone_op(clone_args(0, 0, 0))]).await;
+    assert_eq!(status, Nfs4Status::Nofilehandle as u32);
+    assert_eq!(clone(&d, &root, &dst, clone_args(0, 0, 0)).await, Nfs4Status::Isdir as u32);
+    let mut args = clone_args(0, 0, 0);
+    args.src_stateid = Stateid4 { seqid: 1, other: [7; 12] };
+    assert_eq!(clone(&d, &src, &dst, args).await, Nfs4Status::BadStateid as u32);
+    // only the owner may write the 0644 destination
+    let ops = vec![putfh(&src), savefh(), putfh(&dst), clone_op(clone_args(0, 0, 0))];
+    assert_eq!(compound(&d, sys(1000, 1000), 2, ops).await.1, Nfs4Status::Access as u32);
+    assert!(contents(&d, &dst).await.is_empty());
+}
+
+#[tokio::test]
+async fn disk_backend_clones_where_the_filesystem_reflinks() {
+    let (_dir, d) = disk();
+    let data = pattern(64 * 1024);
+    let src = file(&d, "src", &data).await;
+    let dst = file(&d, "dst", b"").await;
+
+    let Some(blksize) = clone_blksize(&d, &src).await else {
+        // no FICLONERANGE on this system
+        assert_eq!(clone(&d, &src, &dst, clone_args(0, 0, 0)).await, Nfs4Status::Notsupp as u32);
+        return;
+    };
+    assert!(blksize.is_power_of_two());
+    match clone(&d, &src, &dst, clone_args(0, 0, 0)).await {
+        NFS4_OK => assert_eq!(contents(&d, &dst).await, data),
+        // a filesystem that cannot share blocks
+        status => assert_eq!(status, Nfs4Status::Notsupp as u32),
+    }
+    assert_eq!(clone(&d, &src, &dst, clone_args(1, 0, 0)).await, Nfs4Status::Inval as u32);
+}
@@ -0,0 +1,274 @@
+//! What the integration tests share: building and running COMPOUNDs,
+//! decoding their results, the NFSv3 calls, the credentials they run as,
+//! and the files they run on.
+#![allow(dead_code)]
+
+use bytes::{Bytes, BytesMut};
+use nfs_rs::auth::{AuthSys, Credential};
+use nfs_rs::config::{BackendConfig, ExportConfig, NfsConfig};
+use nfs_rs::proto::nfs3::{Nfs3Proc, DONT_CHANGE, GUARDED, NFS3_VERSION};
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::rpc::*;
+use nfs_rs::server::{Dispatcher, Transport};
+use nfs_rs::vfs::{CreateKind, SetAttr, Vfs};
+use nfs_rs::xdr::*;
+use num_traits::FromPrimitive;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+/// An AUTH_SYS credential for `uid` and `gid`
+pub fn sys(uid: u32, gid: u32) -> Credential {
+    Credential::Sys(AuthSys { stamp: 1, machinename: "client".into(), uid, gid, gids: vec![] })
+}
+
+/// Make one call of the NFS program as `cred` from 127.0.0.1 and return
+/// the body of its reply, which must have been accepted
+pub async fn call(d: &Dispatcher, cred: Credential, vers: u32, proc: u32, args: BytesMut) -> Bytes {
+    call_from(d, "127.0.0.1:700", cred, NFS4_PROGRAM, vers, proc, args).await
+}
+
+/// Make one call of `prog` as `cred` from `peer`
+pub async fn call_from(d: &Dispatcher, peer: &str, cred: Credential, prog: u32, vers: u32, proc: u32, args: BytesMut) -> Bytes {
+    let header = RpcCallHeader { xid: 3, msg_type: RpcMessageType::Call, rpcvers: 2, prog, vers, proc };
+    let mut msg = BytesMut::new();
+    RpcCall { header, cred }.xdr_encode(&mut msg);
+    msg.extend_from_slice(&args);
+    let reply = d.dispatch(msg.freeze(), peer.parse().unwrap(), Transport::Tcp).await.unwrap();
+    let mut out = Bytes::from(reply.into_segments().concat());
+    assert_eq!(RpcReplyHeader::xdr_decode(&mut out).unwrap().accept_state, ACCEPT_SUCCESS);
+    out
+}
+
+/// One operation of a COMPOUND: its opcode and its encoded arguments
+pub struct Op(u32, BytesMut);
+
+impl Op {
+    pub fn new(op: NfsOp4) -> Self {
+        Op(op as u32, BytesMut::new())
+    }
+
+    /// Append an argument
+    pub fn arg<A: XdrEncode + ?Sized>(mut self, arg: &A) -> Self {
+        arg.xdr_encode(&mut self.1);
+        self
+    }
+}
+
+pub fn putrootfh() -> Op {
+    Op::new(NfsOp4::OpPutrootfh)
+}
+
+pub fn putpubfh() -> Op {
+    Op::new(NfsOp4::OpPutpubfh)
+}
+
+pub fn putfh(fh: &[u8]) -> Op {
+    Op::new(NfsOp4::OpPutfh).arg(fh)
+}
+
+pub fn savefh() -> Op {
+    Op::new(NfsOp4::OpSavefh)
+}
+
+pub fn restorefh() -> Op {
+    Op::new(NfsOp4::OpRestorefh)
+}
+
+pub fn getfh() -> Op {
+    Op::new(NfsOp4::OpGetfh)
+}
+
+pub fn lookup(name: &[u8]) -> Op {
+    Op::new(NfsOp4::OpLookup).arg(name)
+}
+
+pub fn lookupp() -> Op {
+    Op::new(NfsOp4::OpLookupp)
+}
+
+pub fn access(mask: u32) -> Op {
+    Op::new(NfsOp4::OpAccess).arg(&mask)
+}
+
+pub fn getattr(bits: &[u32]) -> Op {
+    Op::new(NfsOp4::OpGetattr).arg(&bitmap4_with(bits))
+}
+
+/// The result of an operation that succeeded
+#[derive(Debug, PartialEq)]
+pub enum Res {
+    // of an operation that returns nothing but its status
+    Ok,
+    Getfh(Vec<u8>),
+    // change_info4 and attrset
+    Create(ChangeInfo4, Vec<u32>),
+    // REMOVE's and LINK's
+    Cinfo(ChangeInfo4),
+    // source and target directory
+    Rename(ChangeInfo4, ChangeInfo4),
+    Readlink(String),
+    // eof, data
+    Read(bool, Vec<u8>),
+    // count, committed, verifier
+    Write(u32, u32, u64),
+    Commit(u64),
+    // supported, access
+    Access(u32, u32),
+    // attrmask, attr_vals
+    Getattr(Vec<u32>, Bytes),
+    // attrsset
+    Setattr(Vec<u32>),
+    // response, consecutive, synchronous
+    Copy(WriteResponse4, bool, bool),
+    // count, complete
+    CopyNotify(CopyNotify4resok),
+    Seek(Seek4resok),
+    ReadPlus(ReadPlus4resok),
+}
+
+// Decode the result of `op` from the reply at its body
+fn decode(op: u32, r: &mut Bytes) -> Res {
+    let cinfo = |r: &mut Bytes| ChangeInfo4::xdr_decode(r).unwrap();
+    match NfsOp4::from_u32(op) {
+        Some(NfsOp4::OpGetfh) => Res::Getfh(Vec::<u8>::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpCreate) => Res::Create(cinfo(r), Vec::<u32>::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpRemove | NfsOp4::OpLink) => Res::Cinfo(cinfo(r)),
+        Some(NfsOp4::OpRename) => Res::Rename(cinfo(r), cinfo(r)),
+        Some(NfsOp4::OpReadlink) => Res::Readlink(String::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpRead) => Res::Read(bool::xdr_decode(r).unwrap(), Vec::<u8>::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpWrite) => Res::Write(u32::xdr_decode(r).unwrap(), u32::xdr_decode(r).unwrap(), u64::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpCommit) => Res::Commit(u64::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpAccess) => Res::Access(u32::xdr_decode(r).unwrap(), u32::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpGetattr) => {
+            let fattr = Fattr4::xdr_decode(r).unwrap();
+            Res::Getattr(fattr.attrmask, fattr.attr_vals)
+        }
+        Some(NfsOp4::OpSetattr) => Res::Setattr(Vec::<u32>::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpCopy) => Res::Copy(WriteResponse4::xdr_decode(r).unwrap(), bool::xdr_decode(r).unwrap(), bool::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpCopyNotify) => Res::CopyNotify(CopyNotify4resok::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpSeek) => Res::Seek(Seek4resok::xdr_decode(r).unwrap()),
+        Some(NfsOp4::OpReadPlus) => Res::ReadPlus(ReadPlus4resok::xdr_decode(r).unwrap()),
+        _ => Res::Ok,
+    }
+}
+
+/// Run a COMPOUND of `ops` as `cred` in minor version `minor`. Returns the
+/// result of each operation up to the first that failed, and the
+/// compound's status.
+pub async fn compound(d: &Dispatcher, cred: Credential, minor: u32, ops: Vec<Op>) -> (Vec<Res>, u32) {
+    compound_from(d, "127.0.0.1:700", cred, minor, ops).await
+}
+
+/// Run a COMPOUND as [`compound`] does, from `peer`
+pub async fn compound_from(d: &Dispatcher, peer: &str, cred: Credential, minor: u32, ops: Vec<Op>) -> (Vec<Res>, u32) {
+    let mut msg = BytesMut::new();
+    b"".as_slice().xdr_encode(&mut msg);
+    minor.xdr_encode(&mut msg);
+    (ops.len() as u32).xdr_encode(&mut msg);
+    for Op(opcode, args) in ops {
+        opcode.xdr_encode(&mut msg);
+        msg.extend_from_slice(&args);
+    }
+    let mut r = call_from(d, peer, cred, NFS4_PROGRAM, NFS4_VERSION, Nfs4Proc::Compound as u32, msg).await;
+    let status = u32::xdr_decode(&mut r).unwrap();
+    Vec::<u8>::xdr_decode(&mut r).unwrap();
+    let mut out = Vec::new();
+    for _ in 0..u32::xdr_decode(&mut r).unwrap() {
+        let op = u32::xdr_decode(&mut r).unwrap();
+        if u32::xdr_decode(&mut r).unwrap() != NFS4_OK {
+            // SETATTR reports the attributes it set whatever its status
+            if op == NfsOp4::OpSetattr as u32 {
+                Vec::<u32>::xdr_decode(&mut r).unwrap();
+            }
+            break;
+        }
+        out.push(decode(op, &mut r));
+    }
+    assert!(r.is_empty());
+    (out, status)
+}
+
+
+pub const NFS3_OK: u32 = 0;
+
+/// Make one NFSv3 call as `cred` and return the body of its reply
+pub async fn call3(d: &Dispatcher, cred: Credential, proc: Nfs3Proc, args: BytesMut) -> Bytes {
+    call(d, cred, NFS3_VERSION, proc as u32, args).await
+}
+
+pub fn diropargs(dir: &[u8], name: &str) -> BytesMut {
+    let mut b = BytesMut::new();
+    dir.xdr_encode(&mut b);
+    name.as_bytes().xdr_encode(&mut b);
+    b
+}
+
+/// A sattr3 setting only the mode, if any
+pub fn sattr3(b: &mut BytesMut, mode: Option<u32>) {
+    mode.is_some().xdr_encode(b);
+    if let Some(mode) = mode {
+        mode.xdr_encode(b);
+    }
+    for _ in 0..3 {
+        false.xdr_encode(b);
+    }
+    DONT_CHANGE.xdr_encode(b);
+    DONT_CHANGE.xdr_encode(b);
+}
+
+/// CREATE, guarded, or MKDIR `name` in `dir` as `cred` and return the new
+/// object's handle
+pub async fn create3(d: &Dispatcher, cred: Credential, proc: Nfs3Proc, dir: &[u8], name: &str) -> Vec<u8> {
+    let mut args = diropargs(dir, name);
+    if proc == Nfs3Proc::Create {
+        GUARDED.xdr_encode(&mut args);
+    }
+    sattr3(&mut args, None);
+    let mut r = call3(d, cred, proc, args).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NFS3_OK);
+    assert!(bool::xdr_decode(&mut r).unwrap());
+    Vec::<u8>::xdr_decode(&mut r).unwrap()
+}
+
+/// A dispatcher of one export of a fresh temporary directory, which lives
+/// as long as the returned `TempDir`
+pub fn disk() -> (TempDir, Arc<Dispatcher>) {
+    let dir = tempfile::tempdir().unwrap();
+    let export = ExportConfig { backend: BackendConfig::Local { root: dir.path().to_string_lossy().into_owned() }, ..Default::default() };
+    let d = Dispatcher::from_config(&NfsConfig { exports: vec![export], ..Default::default() }).unwrap();
+    (dir, d)
+}
+
+/// Create `name` in the export's root holding `data`, mode 0644
+pub async fn file(d: &Dispatcher, name: &str, data: &[u8]) -> Vec<u8> {
+    file_with_mode(d, name, data, 0o644).await
+}
+
+pub async fn file_with_mode(d: &Dispatcher, name: &str, data: &[u8], mode: u32) -> Vec<u8> {
+    let exports = d.exports();
+    let root = exports.root_fh().await.unwrap();
+    let set = SetAttr { mode: Some(mode), ..Default::default() };
+    let fh = exports.create(&root, name, CreateKind::Regular, &set).await.unwrap();
+    if !data.is_empty() {
+        exports.write(&fh, 0, Bytes::copy_from_slice(data)).await.unwrap();
+    }
+    fh
+}
+
+/// Everything `fh` holds
+pub async fn contents(d: &Dispatcher, fh: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let (data, eof) = d.exports().read(fh, out.len() as u64, 1 << 20).await.unwrap();
+        out.extend_from_slice(&data);
+        if eof {
+            return out;
+        }
+    }
+}
+
+/// `len` bytes that repeat at no power of two and are never zero, so data
+/// never passes for a hole
+pub fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8 + 1).collect()
+}
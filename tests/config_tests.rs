@@ -0,0 +1,99 @@
+use nfs_rs::config::*;
+use nfs_rs::NfsError;
+
+fn write(dir: &tempfile::TempDir, name: &str, text: &str) -> std::path::PathBuf {
+    let path = dir.path().join(name);
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn load_toml_and_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let toml = write(
+        &dir,
+        "nfs.toml",
+        r#"
+            bind_addr = "127.0.0.1"
+            port = 20490
+
+            [rpcbind]
+            mode = "serve"
+
+            [[exports]]
+            path = "/builds"
+            backend = { type = "local", root = "/srv/builds" }
+
+            [[exports.clients]]
+            host = "10.0.0.0/8"
+            access = "rw"
+        "#,
+    );
+    let cfg = NfsConfig::load(&toml).unwrap();
+    assert_eq!((cfg.bind_addr.as_str(), cfg.port), ("127.0.0.1", 20490));
+    assert_eq!(cfg.rpcbind.mode, RpcbindMode::Serve);
+    assert_eq!(cfg.exports[0].backend, BackendConfig::Local { root: "/srv/builds".into() });
+    assert_eq!(cfg.exports[0].clients[0].options.access, AccessMode::Rw);
+    cfg.validate().unwrap();
+
+    let json = write(&dir, "nfs.json", r#"{"bind_addr": "::1", "port": 2049, "udp": true}"#);
+    let cfg = NfsConfig::load(&json).unwrap();
+    assert!(cfg.udp);
+    assert_eq!(cfg.exports.len(), 1);
+
+    // typos are errors rather than silently ignored
+    let typo = write(&dir, "typo.toml", "bind_addr = \"0.0.0.0\"\nport = 2049\nprot = 1\n");
+    assert!(matches!(NfsConfig::load(&typo), Err(NfsError::Config(msg)) if msg.contains("prot")));
+}
+
+#[test]
+fn set_overrides_nested_settings() {
+    let mut cfg = NfsConfig::default();
+    cfg.set("port", "20490").unwrap();
+    cfg.set("rpcbind.mode", "register").unwrap();
+    cfg.set("exports.0.path", "/srv").unwrap();
+    cfg.set("exports.0.fsid", "7").unwrap();
+    cfg.set("exports.0.clients.0.sec", r#"["krb5"]"#).unwrap();
+    assert_eq!(cfg.port, 20490);
+    assert_eq!(cfg.rpcbind.mode, RpcbindMode::Register);
+    assert_eq!((cfg.exports[0].path.as_str(), cfg.exports[0].fsid), ("/srv", Some(7)));
+    assert_eq!(cfg.exports[0].clients[0].options.sec, vec![SecFlavor::Krb5]);
+
+    assert!(cfg.set("exports.3.path", "/x").is_err());
+    assert!(cfg.set("rpcbind.nope", "1").is_err());
+    assert!(cfg.set("port", "not a port").is_err());
+}
+
+#[test]
+fn validation_rejects_bad_settings() {
+    let invalid = |f: &dyn Fn(&mut NfsConfig)| {
+        let mut cfg = NfsConfig::default();
+        f(&mut cfg);
+        matches!(cfg.validate(), Err(NfsError::Config(_)))
+    };
+    assert!(!invalid(&|_| {}));
+    assert!(invalid(&|c| c.bind_addr = "localhost".into()));
+    assert!(invalid(&|c| c.max_udp_datagram = 100_000));
+    assert!(invalid(&|c| {
+        c.rpcbind.mode = RpcbindMode::Serve;
+        c.rpcbind.port = c.port;
+    }));
+    assert!(invalid(&|c| c.exports.clear()));
+    assert!(invalid(&|c| c.exports[0].path = "relative".into()));
+    assert!(invalid(&|c| c.exports[0].fsid = Some(0)));
+    assert!(invalid(&|c| c.exports[0].clients[0].host = "10.0.0.0/40".into()));
+    assert!(invalid(&|c| c.exports[0].clients[0].options.sec.clear()));
+    assert!(invalid(&|c| c.exports.push(ExportConfig::default())));
+    #[cfg(not(feature = "metrics"))]
+    assert!(invalid(&|c| c.metrics_addr = Some("127.0.0.1:9102".into())));
+}
+
+#[test]
+fn printed_config_loads_back() {
+    let mut cfg = NfsConfig::default();
+    cfg.set("exports.0.backend", r#"{ type = "local", root = "/srv" }"#).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = write(&dir, "printed.toml", &cfg.to_toml().unwrap());
+    let again = NfsConfig::load(&path).unwrap();
+    assert_eq!(serde_json::to_value(&again).unwrap(), serde_json::to_value(&cfg).unwrap());
+}
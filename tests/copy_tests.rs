@@ -0,0 +1,152 @@
+mod common;
+
+use bytes::Bytes;
+use common::*;
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::offload::COPY_CHUNK;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::{MemVfs, Vfs};
+
+fn copy_op(args: Copy4args) -> Op {
+    Op::new(NfsOp4::OpCopy).arg(&args)
+}
+
+fn offload_status(stateid: Stateid4) -> Op {
+    Op::new(NfsOp4::OpOffloadStatus).arg(&stateid)
+}
+
+fn offload_cancel(stateid: Stateid4) -> Op {
+    Op::new(NfsOp4::OpOffloadCancel).arg(&stateid)
+}
+
+fn copy_args(src_offset: u64, dst_offset: u64, count: u64, synchronous: bool) -> Copy4args {
+    let anon = Stateid4::ANONYMOUS;
+    Copy4args { src_stateid: anon, dst_stateid: anon, src_offset, dst_offset, count, consecutive: true, synchronous, source_server: vec![] }
+}
+
+// COPY from `src` to `dst`
+async fn copy(d: &Dispatcher, src: &[u8], dst: &[u8], args: Copy4args) -> (Vec<Res>, u32) {
+    compound(d, sys(0, 0), 2, vec![putfh(src), savefh(), putfh(dst), copy_op(args)]).await
+}
+
+async fn synchronous_copies(d: &Dispatcher) {
+    let src = file(d, "src", b"0123456789").await;
+    let dst = file(d, "dst", b"abcdefghij").await;
+    let (res, status) = copy(d, &src, &dst, copy_args(2, 4, 3, true)).await;
+    assert_eq!(status, NFS4_OK);
+    let Res::Copy(response, true, true) = res[3] else { panic!("{:?}", res) };
+    assert_eq!((response.callback_id, response.count, response.committed), (None, 3, FILE_SYNC4));
+    assert_eq!(contents(d, &dst).await, b"abcd234hij");
+
+    // a count of 0 runs to the end of the source, growing the destination
+    let (res, _) = copy(d, &src, &dst, copy_args(5, 8, 0, true)).await;
+    assert!(matches!(res[3], Res::Copy(WriteResponse4 { count: 5, .. }, true, true)));
+    assert_eq!(contents(d, &dst).await, b"abcd234h56789");
+
+    // within one file the ranges may not overlap
+    assert_eq!(copy(d, &src, &src, copy_args(0, 5, 5, true)).await.1, NFS4_OK);
+    assert_eq!(contents(d, &src).await, b"0123401234");
+    assert_eq!(copy(d, &src, &src, copy_args(0, 2, 5, true)).await.1, Nfs4Status::Inval as u32);
+}
+
+#[tokio::test]
+async fn memory_backend_copies() {
+    synchronous_copies(&Dispatcher::new(MemVfs::new())).await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn disk_backend_copies() {
+    let (dir, d) = disk();
+    synchronous_copies(&d).await;
+    assert_eq!(std::fs::read(dir.path().join("dst")).unwrap(), b"abcd234h56789");
+}
+
+#[tokio::test]
+async fn copies_stay_within_the_source() {
+    let d = Dispatcher::new(MemVfs::new());
+    let src = file(&d, "src", b"0123456789").await;
+    let dst = file(&d, "dst", b"").await;
+    let inval = Nfs4Status::Inval as u32;
+    assert_eq!(copy(&d, &src, &dst, copy_args(8, 0, 3, true)).await.1, inval);
+    assert_eq!(copy(&d, &src, &dst, copy_args(11, 0, 0, true)).await.1, inval);
+    assert_eq!(copy(&d, &src, &dst, copy_args(u64::MAX, 0, 2, true)).await.1, inval);
+    // copying nothing from the very end is fine
+    let (res, status) = copy(&d, &src, &dst, copy_args(10, 0, 0, true)).await;
+    assert_eq!(status, NFS4_OK);
+    assert!(matches!(res[3], Res::Copy(WriteResponse4 { count: 0, .. }, true, true)));
+}
+
+#[tokio::test]
+async fn copies_check_handles_and_permissions() {
+    let d = Dispatcher::new(MemVfs::new());
+    let src = file(&d, "src", b"data").await;
+    let dst = file(&d, "dst", b"").await;
+    let dir = d.exports().root_fh().await.unwrap();
+    // nothing saved to copy from
+    let (_, status) = compound(&d, sys(0, 0), 2, vec![putfh(&dst), copy_op(copy_args(0, 0, 0, true))]).await;
+    assert_eq!(status, Nfs4Status::Nofilehandle as u32);
+    assert_eq!(copy(&d, &dir, &dst, copy_args(0, 0, 0, true)).await.1, Nfs4Status::Isdir as u32);
+    assert_eq!(copy(&d, &src, &dir, copy_args(0, 0, 0, true)).await.1, Nfs4Status::Isdir as u32);
+
+    let mut args = copy_args(0, 0, 0, true);
+    args.dst_stateid = Stateid4 { seqid: 1, other: [7; 12] };
+    assert_eq!(copy(&d, &src, &dst, args).await.1, Nfs4Status::BadStateid as u32);
+
+    // only the owner may write the 0644 destination
+    let ops = vec![putfh(&src), savefh(), putfh(&dst), copy_op(copy_args(0, 0, 0, true))];
+    assert_eq!(compound(&d, sys(1000, 1000), 2, ops).await.1, Nfs4Status::Access as u32);
+
+    // a source server that cannot be reached
+    let mut args = copy_args(0, 0, 0, true);
+    args.source_server = vec![Netloc4::Netaddr { netid: Bytes::from_static(b"tcp"), addr: Bytes::from_static(b"127.0.0.1.0.1") }];
+    assert_eq!(copy(&d, &src, &dst, args).await.1, Nfs4Status::PartnerNotsupp as u32);
+}
+
+#[tokio::test]
+async fn small_copies_are_done_at_once() {
+    let d = Dispatcher::new(MemVfs::new());
+    let src = file(&d, "src", b"data").await;
+    let dst = file(&d, "dst", b"").await;
+    let (res, _) = copy(&d, &src, &dst, copy_args(0, 0, 0, false)).await;
+    assert!(matches!(res[3], Res::Copy(WriteResponse4 { callback_id: None, count: 4, .. }, true, true)));
+}
+
+async fn large_copy(d: &Dispatcher) {
+    let data = pattern(3 * COPY_CHUNK as usize + 100);
+    let src = file(d, "src", &data).await;
+    let dst = file(d, "dst", b"").await;
+    // there is no backchannel for CB_OFFLOAD, so the client waits anyway
+    let (res, status) = copy(d, &src, &dst, copy_args(0, 0, 0, false)).await;
+    assert_eq!(status, NFS4_OK);
+    let Res::Copy(response, true, true) = res[3] else { panic!("{:?}", res) };
+    assert_eq!((response.callback_id, response.count, response.committed), (None, data.len() as u64, FILE_SYNC4));
+    assert_eq!(contents(d, &dst).await, data);
+}
+
+#[tokio::test]
+async fn memory_backend_copies_large_files_at_once() {
+    large_copy(&Dispatcher::new(MemVfs::new())).await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn disk_backend_copies_large_files_at_once() {
+    let (dir, d) = disk();
+    large_copy(&d).await;
+    assert_eq!(std::fs::read(dir.path().join("dst")).unwrap(), pattern(3 * COPY_CHUNK as usize + 100));
+}
+
+#[tokio::test]
+async fn no_copy_is_left_to_offload() {
+    let d = Dispatcher::new(MemVfs::new());
+    let dst = file(&d, "dst", b"").await;
+    let stateid = Stateid4 { seqid: 1, other: [3; 12] };
+    let (_, status) = compound(&d, sys(0, 0), 2, vec![putfh(&dst), offload_status(stateid)]).await;
+    assert_eq!(status, Nfs4Status::BadStateid as u32);
+    let (_, status) = compound(&d, sys(0, 0), 2, vec![putfh(&dst), offload_cancel(stateid)]).await;
+    assert_eq!(status, Nfs4Status::BadStateid as u32);
+    let (_, status) = compound(&d, sys(0, 0), 2, vec![offload_status(stateid)]).await;
+    assert_eq!(status, Nfs4Status::Nofilehandle as u32);
+}
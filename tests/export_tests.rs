@@ -0,0 +1,187 @@
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use common::*;
+use nfs_rs::auth::Credential;
+use nfs_rs::config::*;
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::proto::mount::*;
+use nfs_rs::proto::nfs3::*;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::rpc::*;
+use nfs_rs::server::{Dispatcher, Transport};
+use nfs_rs::vfs::SetAttr;
+use nfs_rs::xdr::*;
+use std::sync::Arc;
+
+const NFS3ERR_ROFS: u32 = 30;
+
+fn rule(host: &str, access: AccessMode, squash: Squash, sec: Vec<SecFlavor>) -> ClientRule {
+    ClientRule { host: host.into(), options: ExportOptions { access, squash, sec, ..Default::default() } }
+}
+
+fn dispatcher(exports: Vec<ExportConfig>) -> Arc<Dispatcher> {
+    Dispatcher::from_config(&NfsConfig { exports, ..Default::default() }).unwrap()
+}
+
+// Status of the first operation of a PUTROOTFH, GETFH compound
+async fn root_status(d: &Dispatcher, peer: &str, cred: Credential) -> u32 {
+    match compound_from(d, peer, cred, 2, vec![putrootfh(), getfh()]).await {
+        (res, _) if !res.is_empty() => NFS4_OK,
+        (_, status) => status,
+    }
+}
+
+#[tokio::test]
+async fn client_rules_gate_putfh() {
+    let export = ExportConfig {
+        clients: vec![rule("10.0.0.0/8", AccessMode::Rw, Squash::Root, vec![SecFlavor::Sys])],
+        ..Default::default()
+    };
+    let d = dispatcher(vec![export]);
+    assert_eq!(root_status(&d, "10.1.2.3:900", sys(1000, 1000)).await, NFS4_OK);
+    assert_eq!(root_status(&d, "192.0.2.7:900", sys(1000, 1000)).await, Nfs4Status::Access as u32);
+    assert_eq!(root_status(&d, "10.1.2.3:900", Credential::None).await, Nfs4Status::Wrongsec as u32);
+}
+
+#[tokio::test]
+async fn unverified_gss_credentials_are_refused() {
+    let export = ExportConfig {
+        clients: vec![rule("*", AccessMode::Rw, Squash::Root, vec![SecFlavor::Krb5, SecFlavor::Krb5p])],
+        ..Default::default()
+    };
+    let d = dispatcher(vec![export]);
+    // a flavor 6 credential with no context behind it
+    let gss = Credential::Other(nfs_rs::auth::RPCSEC_GSS);
+    assert_eq!(root_status(&d, "10.1.2.3:900", gss.clone()).await, Nfs4Status::Wrongsec as u32);
+    assert_eq!(root_status(&d, "10.1.2.3:900", sys(0, 0)).await, Nfs4Status::Wrongsec as u32);
+
+    // everything else is denied at the RPC layer: MSG_DENIED, AUTH_ERROR, AUTH_TOOWEAK
+    for (prog, vers, proc) in [(MOUNT_PROGRAM, MOUNT_VERSION3, MountProc::Mnt as u32), (NFS4_PROGRAM, NFS3_VERSION, 0), (NFS4_PROGRAM, NFS4_VERSION, 0)] {
+        let header = RpcCallHeader { xid: 9, msg_type: RpcMessageType::Call, rpcvers: 2, prog, vers, proc };
+        let mut msg = BytesMut::new();
+        RpcCall { header, cred: gss.clone() }.xdr_encode(&mut msg);
+        "/".to_string().xdr_encode(&mut msg);
+        let reply = d.dispatch(msg.freeze(), "10.1.2.3:900".parse().unwrap(), Transport::Tcp).await.unwrap();
+        let mut r = Bytes::from(reply.into_segments().concat());
+        let words: Vec<u32> = std::iter::from_fn(|| u32::xdr_decode(&mut r).ok()).collect();
+        assert_eq!(words, [9, 1, 1, 1, AUTH_TOOWEAK]);
+    }
+}
+
+#[tokio::test]
+async fn unknown_flavors_are_never_anonymous() {
+    // the pseudo root above /a, then /a itself, both open to AUTH_NONE
+    let export = ExportConfig {
+        path: "/a".into(),
+        clients: vec![rule("*", AccessMode::Rw, Squash::Root, vec![SecFlavor::Sys, SecFlavor::None])],
+        ..Default::default()
+    };
+    let d = dispatcher(vec![export]);
+    assert_eq!(root_status(&d, "10.1.2.3:900", Credential::None).await, NFS4_OK);
+    for flavor in [nfs_rs::auth::RPCSEC_GSS, 7] {
+        assert_eq!(root_status(&d, "10.1.2.3:900", Credential::Other(flavor)).await, Nfs4Status::Wrongsec as u32);
+    }
+    let caller = nfs_rs::auth::Caller { addr: "10.1.2.3:900".parse().unwrap(), cred: Credential::Other(7) };
+    let (export, _) = d.exports().find("/a").unwrap();
+    assert!(matches!(d.exports().authorize(&export, &caller).await, Err(nfs_rs::NfsError::WrongSec)));
+}
+
+async fn mnt(d: &Dispatcher, cred: Credential, path: &str) -> Vec<u8> {
+    let mut args = BytesMut::new();
+    path.to_string().xdr_encode(&mut args);
+    let mut r = call_from(d, "10.1.2.3:900", cred, MOUNT_PROGRAM, MOUNT_VERSION3, MountProc::Mnt as u32, args).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), MNT3_OK);
+    Vec::<u8>::xdr_decode(&mut r).unwrap()
+}
+
+// CREATE `name` in `dir` and return the status and the new object's uid
+async fn create_owner(d: &Dispatcher, cred: Credential, dir: &[u8], name: &str) -> (u32, Option<u32>) {
+    let mut args = diropargs(dir, name);
+    UNCHECKED.xdr_encode(&mut args);
+    sattr3(&mut args, None);
+    let mut r = call_from(d, "10.1.2.3:900", cred, NFS4_PROGRAM, NFS3_VERSION, Nfs3Proc::Create as u32, args).await;
+    let status = u32::xdr_decode(&mut r).unwrap();
+    if status != NFS3_OK {
+        return (status, None);
+    }
+    assert!(bool::xdr_decode(&mut r).unwrap());
+    Vec::<u8>::xdr_decode(&mut r).unwrap();
+    assert!(bool::xdr_decode(&mut r).unwrap());
+    let _ = r.split_to(12); // type mode nlink
+    (status, Some(u32::xdr_decode(&mut r).unwrap()))
+}
+
+#[tokio::test]
+async fn squash_and_read_only_exports() {
+    let rw = ExportConfig {
+        clients: vec![rule("*", AccessMode::Rw, Squash::Root, vec![SecFlavor::Sys])],
+        ..Default::default()
+    };
+    let ro = ExportConfig {
+        path: "/ro".into(),
+        clients: vec![rule("*", AccessMode::Ro, Squash::None, vec![SecFlavor::Sys])],
+        ..Default::default()
+    };
+    let d = dispatcher(vec![rw, ro]);
+    // a shared directory anyone may create in
+    let vfs = d.exports().exports()[0].vfs.clone();
+    vfs.setattr(&vfs.root_fh().await.unwrap(), &SetAttr { mode: Some(0o1777), ..Default::default() }).await.unwrap();
+
+    // root is mapped to the anonymous user, others keep their identity
+    let root = mnt(&d, sys(0, 0), "/").await;
+    assert_eq!(create_owner(&d, sys(0, 0), &root, "a").await, (0, Some(65534)));
+    assert_eq!(create_owner(&d, sys(1000, 1000), &root, "b").await, (0, Some(1000)));
+
+    // handles of different exports differ in their fsid prefix
+    let ro_root = mnt(&d, sys(0, 0), "/ro").await;
+    assert_ne!(ro_root[..8], root[..8]);
+    assert_eq!(create_owner(&d, sys(0, 0), &ro_root, "c").await, (NFS3ERR_ROFS, None));
+}
+
+#[tokio::test]
+async fn local_backend_serves_a_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::set_permissions(dir.path(), std::os::unix::fs::PermissionsExt::from_mode(0o1777)).unwrap();
+    let export = ExportConfig {
+        backend: BackendConfig::Local { root: dir.path().to_string_lossy().into_owned() },
+        ..Default::default()
+    };
+    let d = dispatcher(vec![export]);
+    let root = mnt(&d, sys(1000, 1000), "/").await;
+    let (status, _) = create_owner(&d, sys(1000, 1000), &root, "hello").await;
+    assert_eq!(status, 0);
+    assert!(dir.path().join("hello").is_file());
+}
+
+#[test]
+fn exports_config_parses() {
+    let cfg: NfsConfig = serde_json::from_str(
+        r#"{
+            "bind_addr": "0.0.0.0",
+            "port": 2049,
+            "exports": [{
+                "path": "/srv",
+                "backend": { "type": "local", "root": "/srv/nfs" },
+                "fsid": 7,
+                "clients": [
+                    { "host": "192.168.0.0/16", "access": "rw", "squash": "all_squash", "anonuid": 1000 },
+                    { "host": "*.example.com", "sec": ["krb5", "sys"] }
+                ]
+            }]
+        }"#,
+    )
+    .unwrap();
+    let export = &cfg.exports[0];
+    assert_eq!(export.fsid, Some(7));
+    assert!(matches!(&export.backend, BackendConfig::Local { root } if root == "/srv/nfs"));
+    let opts = &export.clients[0].options;
+    assert_eq!((opts.access, opts.squash, opts.anonuid, opts.anongid), (AccessMode::Rw, Squash::All, 1000, 65534));
+    // unspecified options take the conservative defaults
+    let opts = &export.clients[1].options;
+    assert_eq!((opts.access, opts.squash), (AccessMode::Ro, Squash::Root));
+    assert_eq!(opts.sec, vec![SecFlavor::Krb5, SecFlavor::Sys]);
+
+    let missing: NfsConfig = serde_json::from_str(r#"{"bind_addr": "0.0.0.0", "port": 2049}"#).unwrap();
+    assert_eq!(missing.exports.len(), 1);
+}
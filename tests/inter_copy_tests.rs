@@ -0,0 +1,179 @@
+mod common;
+
+use bytes::Bytes;
+use common::*;
+use nfs_rs::config::*;
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::offload::COPY_CHUNK;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::proto::portmap::to_uaddr;
+use nfs_rs::server::{serve_tcp, Dispatcher, ShutdownHandle};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+fn copy_notify(args: CopyNotify4args) -> Op {
+    Op::new(NfsOp4::OpCopyNotify).arg(&args)
+}
+
+fn copy(args: Copy4args) -> Op {
+    Op::new(NfsOp4::OpCopy).arg(&args)
+}
+
+fn offload_cancel(stateid: Stateid4) -> Op {
+    Op::new(NfsOp4::OpOffloadCancel).arg(&stateid)
+}
+
+// A server of an in-memory export on an ephemeral port of 127.0.0.1
+async fn server() -> (Arc<Dispatcher>, SocketAddr, ShutdownHandle) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let cfg = NfsConfig { bind_addr: "127.0.0.1".into(), port: addr.port(), exports: vec![ExportConfig::default()], ..Default::default() };
+    let d = Dispatcher::from_config(&cfg).unwrap();
+    let shutdown = ShutdownHandle::new(Duration::from_secs(1));
+    tokio::spawn(serve_tcp(listener, d.clone(), shutdown.clone()));
+    (d, addr, shutdown)
+}
+
+fn netaddr(addr: SocketAddr) -> Netloc4 {
+    Netloc4::Netaddr { netid: Bytes::from_static(b"tcp"), addr: to_uaddr(&addr).into() }
+}
+
+// COPY_NOTIFY of `src` on the source server, for the server at `to`
+async fn notify(source: &Dispatcher, src: &[u8], to: Netloc4) -> Result<CopyNotify4resok, u32> {
+    let args = CopyNotify4args { src_stateid: Stateid4::ANONYMOUS, destination: to };
+    match compound(source, sys(0, 0), 2, vec![putfh(src), copy_notify(args)]).await {
+        (mut res, NFS4_OK) => match res.pop() {
+            Some(Res::CopyNotify(granted)) => Ok(granted),
+            other => panic!("{:?}", other),
+        },
+        (_, status) => Err(status),
+    }
+}
+
+// COPY on the destination server of `src` on the server at `from` to `dst`
+async fn pull(d: &Dispatcher, from: Vec<Netloc4>, src: &[u8], stateid: Stateid4, dst: &[u8], count: u64, synchronous: bool) -> Result<WriteResponse4, u32> {
+    let args = Copy4args {
+        src_stateid: stateid,
+        dst_stateid: Stateid4::ANONYMOUS,
+        src_offset: 0,
+        dst_offset: 0,
+        count,
+        consecutive: true,
+        synchronous,
+        source_server: from,
+    };
+    match compound(d, sys(0, 0), 2, vec![putfh(src), savefh(), putfh(dst), copy(args)]).await {
+        (mut res, NFS4_OK) => match res.pop() {
+            Some(Res::Copy(response, _, _)) => Ok(response),
+            other => panic!("{:?}", other),
+        },
+        (_, status) => Err(status),
+    }
+}
+
+#[tokio::test]
+async fn copy_between_servers() {
+    let (source, source_addr, source_down) = server().await;
+    let (dest, dest_addr, dest_down) = server().await;
+    // nobody but root may read it; the copy stateid is what lets the
+    // destination
+    let data = pattern(300_000);
+    let src = file_with_mode(&source, "src", &data, 0o600).await;
+    let dst = file(&dest, "dst", b"").await;
+
+    let granted = notify(&source, &src, netaddr(dest_addr)).await.unwrap();
+    assert_eq!(granted.lease_time, 90);
+    assert_eq!(granted.source_server, vec![netaddr(source_addr)]);
+    let response = pull(&dest, granted.source_server, &src, granted.stateid, &dst, 0, true).await.unwrap();
+    assert_eq!((response.callback_id, response.count, response.committed), (None, data.len() as u64, FILE_SYNC4));
+    assert_eq!(contents(&dest, &dst).await, data);
+
+    // a destination that cannot be found
+    let udp = Netloc4::Netaddr { netid: Bytes::from_static(b"udp"), addr: to_uaddr(&dest_addr).into() };
+    assert_eq!(notify(&source, &src, udp).await.unwrap_err(), Nfs4Status::Inval as u32);
+    // a server with no bind address cannot say where it is
+    let anywhere = Dispatcher::from_config(&NfsConfig { exports: vec![ExportConfig::default()], ..Default::default() }).unwrap();
+    let fh = file(&anywhere, "f", b"x").await;
+    assert!(notify(&anywhere, &fh, netaddr(dest_addr)).await.unwrap().source_server.is_empty());
+    source_down.shutdown();
+    dest_down.shutdown();
+}
+
+#[tokio::test]
+async fn large_copies_between_servers_are_done_at_once() {
+    let (source, source_addr, source_down) = server().await;
+    let (dest, dest_addr, dest_down) = server().await;
+    let data = pattern(2 * COPY_CHUNK as usize + 1000);
+    let src = file(&source, "src", &data).await;
+    let dst = file(&dest, "dst", b"").await;
+
+    let granted = notify(&source, &src, netaddr(dest_addr)).await.unwrap();
+    let response = pull(&dest, vec![netaddr(source_addr)], &src, granted.stateid, &dst, 0, false).await.unwrap();
+    assert_eq!((response.callback_id, response.count, response.committed), (None, data.len() as u64, FILE_SYNC4));
+    assert_eq!(contents(&dest, &dst).await, data);
+    source_down.shutdown();
+    dest_down.shutdown();
+}
+
+#[tokio::test]
+async fn the_source_handle_is_forgotten_after_the_copy() {
+    let (source, source_addr, source_down) = server().await;
+    let (dest, dest_addr, dest_down) = server().await;
+    let src = file(&source, "src", b"data").await;
+    let dst = file(&dest, "dst", b"").await;
+    let granted = notify(&source, &src, netaddr(dest_addr)).await.unwrap();
+    let args = Copy4args {
+        src_stateid: granted.stateid,
+        dst_stateid: Stateid4::ANONYMOUS,
+        src_offset: 0,
+        dst_offset: 0,
+        count: 0,
+        consecutive: true,
+        synchronous: true,
+        source_server: vec![netaddr(source_addr)],
+    };
+    // RESTOREFH would otherwise make the unchecked handle current
+    let ops = vec![putfh(&src), savefh(), putfh(&dst), copy(args), restorefh(), getfh()];
+    let (res, status) = compound(&dest, sys(0, 0), 2, ops).await;
+    assert_eq!((res.len(), status), (4, Nfs4Status::Restorefh as u32));
+    assert_eq!(contents(&dest, &dst).await, b"data");
+    source_down.shutdown();
+    dest_down.shutdown();
+}
+
+#[tokio::test]
+async fn partner_errors() {
+    let (source, source_addr, source_down) = server().await;
+    let (dest, dest_addr, dest_down) = server().await;
+    let src = file_with_mode(&source, "src", b"private", 0o600).await;
+    let dst = file(&dest, "dst", b"").await;
+    let from = || vec![netaddr(source_addr)];
+    let noauth = Err(Nfs4Status::PartnerNoAuth as u32);
+
+    // without COPY_NOTIFY the destination reads as anyone would
+    assert_eq!(pull(&dest, from(), &src, Stateid4::ANONYMOUS, &dst, 0, true).await, noauth);
+    // a stateid granted to another server
+    let elsewhere = "10.1.2.3:2049".parse().unwrap();
+    let granted = notify(&source, &src, netaddr(elsewhere)).await.unwrap();
+    assert_eq!(pull(&dest, from(), &src, granted.stateid, &dst, 0, true).await, noauth);
+    // or withdrawn by OFFLOAD_CANCEL on the source
+    let granted = notify(&source, &src, netaddr(dest_addr)).await.unwrap();
+    let cancel = vec![putfh(&src), offload_cancel(granted.stateid)];
+    assert_eq!(compound(&source, sys(0, 0), 2, cancel).await.1, NFS4_OK);
+    assert_eq!(pull(&dest, from(), &src, granted.stateid, &dst, 0, true).await, noauth);
+
+    // the range is checked against the source's size
+    let granted = notify(&source, &src, netaddr(dest_addr)).await.unwrap();
+    assert_eq!(pull(&dest, from(), &src, granted.stateid, &dst, 8, true).await, Err(Nfs4Status::Inval as u32));
+    assert_eq!(pull(&dest, from(), &src, granted.stateid, &dst, 7, true).await.unwrap().count, 7);
+    assert_eq!(contents(&dest, &dst).await, b"private");
+
+    // nothing listening where the source should be
+    source_down.shutdown();
+    let gone = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let status = pull(&dest, vec![netaddr(gone)], &src, granted.stateid, &dst, 0, true).await;
+    assert_eq!(status, Err(Nfs4Status::PartnerNotsupp as u32));
+    dest_down.shutdown();
+}
@@ -34,8 +34,10 @@ fn build_compound_putrootfh_getfh_getattr_lookup_setattr() -> Vec<u8> {
     let name: XdrString = "foo".into();
     name.xdr_serialize(&mut cur).unwrap();
 
-    // SETATTR (empty bitmap, no attrs)
+    // SETATTR (anonymous stateid, empty bitmap, no attrs)
     (NfsOp4::OpSetattr as u32).xdr_serialize(&mut cur).unwrap();
+    0u32.xdr_serialize(&mut cur).unwrap();
+    std::io::Write::write_all(&mut cur, &[0u8; 12]).unwrap();
     let empty_bm: Vec<u32> = vec![];
     empty_bm.xdr_serialize(&mut cur).unwrap();
     let empty_attrs: Vec<u8> = vec![];
@@ -50,11 +52,13 @@ async fn test_compound_roundtrip() {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
 
+    let shutdown = nfs_rs::server::ShutdownHandle::new(std::time::Duration::from_secs(5));
     let server_task = {
         let vfs_server = nfs_rs::vfs::MemVfs::new();
+        let shutdown = shutdown.clone();
         // Use the accept loop directly
         tokio::spawn(async move {
-            nfs_rs::server::run_on_listener(listener, vfs_server).await.unwrap();
+            nfs_rs::server::run_on_listener(listener, vfs_server, shutdown).await.unwrap();
         })
     };
 
@@ -80,20 +84,70 @@ async fn test_compound_roundtrip() {
     let _status = u32::xdr_deserialize(&mut cur).unwrap();
     let _tag = Vec::<u8>::xdr_deserialize(&mut cur).unwrap();
     let count = u32::xdr_deserialize(&mut cur).unwrap();
-    assert_eq!(count, 5);
+    // "foo" does not exist, and nothing after the failed LOOKUP runs
+    assert_eq!(count, 4);
     let expected = [
         (NfsOp4::OpPutrootfh as u32, NFS4_OK),
         (NfsOp4::OpGetfh as u32, NFS4_OK),
         (NfsOp4::OpGetattr as u32, NFS4_OK),
-        (NfsOp4::OpLookup as u32, 10004u32),
-        (NfsOp4::OpSetattr as u32, 10004u32),
+        (NfsOp4::OpLookup as u32, 2u32),
     ];
     for (i, (exp_op, exp_st)) in expected.iter().enumerate() {
         let op = u32::xdr_deserialize(&mut cur).unwrap();
         assert_eq!(op, *exp_op, "op {}: expected {}, got {}", i, exp_op, op);
         let st = u32::xdr_deserialize(&mut cur).unwrap();
         assert_eq!(st, *exp_st, "status {}: expected {}, got {}", i, exp_st, st);
+        // Skip result payloads: GETFH returns nfs_fh4, GETATTR returns fattr4
+        if op == NfsOp4::OpGetfh as u32 {
+            let _fh = Vec::<u8>::xdr_deserialize(&mut cur).unwrap();
+        } else if op == NfsOp4::OpGetattr as u32 {
+            let _mask = Vec::<u32>::xdr_deserialize(&mut cur).unwrap();
+            let _vals = Vec::<u8>::xdr_deserialize(&mut cur).unwrap();
+        }
     }
-    // Cleanup server task
-    server_task.abort();
+    // Stop the server; our idle connection is closed and the loop returns
+    shutdown.shutdown();
+    server_task.await.unwrap();
+    assert_eq!(stream.read(&mut hdr).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn malformed_calls_leave_the_connection_open() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = nfs_rs::server::ShutdownHandle::new(std::time::Duration::from_secs(5));
+    let server_task = {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            nfs_rs::server::run_on_listener(listener, nfs_rs::vfs::MemVfs::new(), shutdown).await.unwrap();
+        })
+    };
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+
+    // a COMPOUND cut short after its tag, then a NULL on the same connection
+    let call = |xid: u32, proc: Nfs4Proc| {
+        let hdr = RpcCallHeader { xid, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc: proc as u32 };
+        serialize_to_vec(&hdr).unwrap()
+    };
+    let mut truncated = call(1, Nfs4Proc::Compound);
+    truncated.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 2]);
+    let mut framed = Vec::new();
+    write_record_marked(&mut framed, &truncated).unwrap();
+    write_record_marked(&mut framed, &call(2, Nfs4Proc::Null)).unwrap();
+    stream.write_all(&framed).await.unwrap();
+
+    for (xid, status) in [(1, Some(nfs_rs::error::Nfs4Status::Badxdr as u32)), (2, None)] {
+        let mut hdr = [0u8; 4];
+        stream.read_exact(&mut hdr).await.unwrap();
+        let mut buf = vec![0u8; (u32::from_be_bytes(hdr) & 0x7fff_ffff) as usize];
+        stream.read_exact(&mut buf).await.unwrap();
+        let mut cur = std::io::Cursor::new(&buf);
+        let reply = RpcReplyHeader::xdr_deserialize(&mut cur).unwrap();
+        assert_eq!((reply.xid, reply.accept_state), (xid, ACCEPT_SUCCESS));
+        if let Some(status) = status {
+            assert_eq!(u32::xdr_deserialize(&mut cur).unwrap(), status);
+        }
+    }
+    shutdown.shutdown();
+    server_task.await.unwrap();
 }
@@ -0,0 +1,166 @@
+use bytes::{Bytes, BytesMut};
+use nfs_rs::config::{LimitsConfig, NfsConfig};
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::limits::Limits;
+use nfs_rs::proto::nfs3::NFS3_VERSION;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::rpc::*;
+use nfs_rs::server::{serve_tcp, Dispatcher, ShutdownHandle, Transport};
+use nfs_rs::xdr::*;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const A: &str = "10.0.0.1";
+const B: &str = "10.0.0.2";
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn sock(s: &str) -> SocketAddr {
+    SocketAddr::new(ip(s), 700)
+}
+
+fn unlimited() -> LimitsConfig {
+    LimitsConfig { max_connections: 0, max_connections_per_client: 0, max_in_flight: 0, max_in_flight_bytes: 0, idle_timeout: 0, record_timeout: 0, ..Default::default() }
+}
+
+#[test]
+fn connection_caps() {
+    let limits = Arc::new(Limits::new(LimitsConfig { max_connections: 3, max_connections_per_client: 2, ..unlimited() }));
+    let a1 = limits.connect(sock(A)).unwrap();
+    let _a2 = limits.connect(sock(A)).unwrap();
+    assert!(limits.connect(sock(A)).is_none());
+    let _b1 = limits.connect(sock(B)).unwrap();
+    assert!(limits.connect(sock(B)).is_none());
+    assert_eq!(limits.connections(), 3);
+    drop(a1);
+    assert!(limits.connect(sock(B)).is_some());
+}
+
+#[test]
+fn rate_and_budget() {
+    let limits = Limits::new(LimitsConfig { requests_per_second: 1, burst: 2, ..unlimited() });
+    assert!(limits.admit(ip(A), 100).is_some());
+    assert!(limits.admit(ip(A), 100).is_some());
+    assert!(limits.admit(ip(A), 100).is_none());
+    // other clients have their own allowance
+    assert!(limits.admit(ip(B), 100).is_some());
+
+    let limits = Limits::new(LimitsConfig { max_in_flight: 2, max_in_flight_bytes: 1000, ..unlimited() });
+    let first = limits.admit(ip(A), 600).unwrap();
+    assert!(limits.admit(ip(B), 600).is_none());
+    let second = limits.admit(ip(B), 400).unwrap();
+    assert!(limits.admit(ip(B), 1).is_none());
+    assert_eq!(limits.in_flight(), (2, 1000));
+    drop((first, second));
+    // a request larger than the whole budget still runs on its own
+    let big = limits.admit(ip(A), 5000).unwrap();
+    assert!(limits.admit(ip(B), 1).is_none());
+    drop(big);
+    assert_eq!(limits.in_flight(), (0, 0));
+}
+
+fn call(vers: u32, proc: u32) -> BytesMut {
+    let hdr = RpcCallHeader { xid: 9, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers, proc };
+    BytesMut::from(&serialize_to_vec(&hdr).unwrap()[..])
+}
+
+async fn dispatch(d: &Dispatcher, msg: BytesMut) -> Bytes {
+    let reply = d.dispatch(msg.freeze(), format!("{}:800", A).parse().unwrap(), Transport::Tcp).await.unwrap();
+    let mut r = Bytes::from(reply.into_segments().concat());
+    assert_eq!(RpcReplyHeader::xdr_decode(&mut r).unwrap().accept_state, ACCEPT_SUCCESS);
+    r
+}
+
+#[tokio::test]
+async fn over_rate_requests_are_delayed() {
+    let limits = LimitsConfig { requests_per_second: 1, burst: 1, ..unlimited() };
+    let d = Dispatcher::from_config(&NfsConfig { limits, ..Default::default() }).unwrap();
+    let compound = || {
+        let mut msg = call(NFS4_VERSION, Nfs4Proc::Compound as u32);
+        b"t".as_slice().xdr_encode(&mut msg);
+        0u32.xdr_encode(&mut msg);
+        1u32.xdr_encode(&mut msg);
+        (NfsOp4::OpPutrootfh as u32).xdr_encode(&mut msg);
+        msg
+    };
+
+    let mut r = dispatch(&d, compound()).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NFS4_OK);
+
+    let mut r = dispatch(&d, compound()).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), Nfs4Status::Delay as u32);
+    assert_eq!(Vec::<u8>::xdr_decode(&mut r).unwrap(), b"t");
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), 1);
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NfsOp4::OpPutrootfh as u32);
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), Nfs4Status::Delay as u32);
+
+    // NFSv3 gets JUKEBOX with an empty post-op attribute
+    let mut msg = call(NFS3_VERSION, 4);
+    Vec::<u8>::new().xdr_encode(&mut msg);
+    let mut r = dispatch(&d, msg).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), 10008);
+    assert!(!bool::xdr_decode(&mut r).unwrap());
+
+    // NULL is never refused
+    assert!(dispatch(&d, call(NFS4_VERSION, Nfs4Proc::Null as u32)).await.is_empty());
+}
+
+#[tokio::test]
+async fn connections_over_the_cap_and_idle_ones_are_closed() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let limits = LimitsConfig { max_connections_per_client: 1, idle_timeout: 1, ..unlimited() };
+    let d = Dispatcher::from_config(&NfsConfig { limits, ..Default::default() }).unwrap();
+    let shutdown = ShutdownHandle::new(Duration::from_secs(1));
+    let server = tokio::spawn(serve_tcp(listener, d.clone(), shutdown.clone()));
+
+    let mut first = TcpStream::connect(addr).await.unwrap();
+    let mut second = TcpStream::connect(addr).await.unwrap();
+    let closed = tokio::time::timeout(Duration::from_millis(900), second.read(&mut [0u8; 4])).await;
+    assert_eq!(closed.unwrap().unwrap(), 0);
+    assert_eq!(d.limits().connections(), 1);
+
+    // the first connection sends nothing and is closed as idle
+    let closed = tokio::time::timeout(Duration::from_secs(5), first.read(&mut [0u8; 4])).await;
+    assert_eq!(closed.unwrap().unwrap(), 0);
+    shutdown.shutdown();
+    server.await.unwrap().unwrap();
+    assert_eq!(d.limits().connections(), 0);
+}
+
+#[tokio::test]
+async fn slow_calls_are_bounded_by_the_record_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let limits = LimitsConfig { idle_timeout: 1, record_timeout: 3, ..unlimited() };
+    let d = Dispatcher::from_config(&NfsConfig { limits, ..Default::default() }).unwrap();
+    let shutdown = ShutdownHandle::new(Duration::from_secs(1));
+    let server = tokio::spawn(serve_tcp(listener, d.clone(), shutdown.clone()));
+    let null = call(NFS4_VERSION, Nfs4Proc::Null as u32);
+    let mut framed = Vec::new();
+    write_record_marked(&mut framed, &null).unwrap();
+
+    // a call that takes longer than the idle timeout to arrive, once
+    // started, is still answered
+    let mut slow = TcpStream::connect(addr).await.unwrap();
+    slow.write_all(&framed[..10]).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    slow.write_all(&framed[10..]).await.unwrap();
+    let reply = tokio::time::timeout(Duration::from_secs(1), slow.read(&mut [0u8; 64])).await;
+    assert!(reply.unwrap().unwrap() > 0);
+
+    // one that never finishes is closed once the record timeout is up
+    let mut stalled = TcpStream::connect(addr).await.unwrap();
+    stalled.write_all(&framed[..10]).await.unwrap();
+    let open = tokio::time::timeout(Duration::from_millis(2000), stalled.read(&mut [0u8; 4])).await;
+    assert!(open.is_err());
+    let closed = tokio::time::timeout(Duration::from_secs(3), stalled.read(&mut [0u8; 4])).await;
+    assert_eq!(closed.unwrap().unwrap(), 0);
+    shutdown.shutdown();
+    server.await.unwrap().unwrap();
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "metrics")]
+
+use bytes::BytesMut;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::rpc::*;
+use nfs_rs::server::{Dispatcher, ShutdownHandle, Transport};
+use nfs_rs::xdr::*;
+use nfs_rs::NfsConfig;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn get(addr: SocketAddr, path: &str) -> String {
+    let mut sock = TcpStream::connect(addr).await.unwrap();
+    sock.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await.unwrap();
+    let mut reply = String::new();
+    sock.read_to_string(&mut reply).await.unwrap();
+    reply
+}
+
+#[tokio::test]
+async fn scrape_reports_ops_errors_and_state() {
+    let d = Dispatcher::new(nfs_rs::vfs::MemVfs::new());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = ShutdownHandle::new(Duration::from_secs(1));
+    let server = tokio::spawn(nfs_rs::metrics::serve(listener, d.clone(), shutdown.clone()));
+
+    // PUTROOTFH then a LOOKUP of something missing
+    let hdr = RpcCallHeader { xid: 1, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc: Nfs4Proc::Compound as u32 };
+    let mut msg = BytesMut::from(&serialize_to_vec(&hdr).unwrap()[..]);
+    b"".as_slice().xdr_encode(&mut msg);
+    2u32.xdr_encode(&mut msg);
+    2u32.xdr_encode(&mut msg);
+    (NfsOp4::OpPutrootfh as u32).xdr_encode(&mut msg);
+    (NfsOp4::OpLookup as u32).xdr_encode(&mut msg);
+    "missing".to_string().xdr_encode(&mut msg);
+    d.dispatch(msg.freeze(), "127.0.0.1:900".parse().unwrap(), Transport::Tcp).await.unwrap();
+
+    let reply = get(addr, "/metrics").await;
+    assert!(reply.starts_with("HTTP/1.1 200 OK\r\n"), "{}", reply);
+    for expected in [
+        "nfs_ops_total{op=\"PUTROOTFH\"}",
+        "nfs_op_duration_seconds_bucket{op=\"LOOKUP\",le=\"0.001\"}",
+        "nfs_errors_total{status=\"Noent\"}",
+        "nfs_exports 1",
+        "nfs_locks 0",
+        "nfs_connections 0",
+    ] {
+        assert!(reply.contains(expected), "missing {} in\n{}", expected, reply);
+    }
+
+    assert!(get(addr, "/").await.starts_with("HTTP/1.1 404"));
+    shutdown.shutdown();
+    server.await.unwrap().unwrap();
+}
+
+#[test]
+fn metrics_addr_is_validated() {
+    let cfg = NfsConfig { metrics_addr: Some("127.0.0.1:9102".into()), ..Default::default() };
+    cfg.validate().unwrap();
+    let cfg = NfsConfig { metrics_addr: Some("localhost".into()), ..Default::default() };
+    assert!(cfg.validate().is_err());
+}
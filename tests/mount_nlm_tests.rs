@@ -0,0 +1,173 @@
+use bytes::{Bytes, BytesMut};
+use nfs_rs::lock::{LockManager, LockOwner};
+use nfs_rs::proto::mount::*;
+use nfs_rs::proto::nlm::*;
+use nfs_rs::rpc::*;
+use nfs_rs::server::{Dispatcher, Transport};
+use nfs_rs::vfs::{MemVfs, Vfs};
+use nfs_rs::xdr::*;
+use std::sync::Arc;
+
+const PEER: &str = "192.0.2.7:800";
+
+async fn call(d: &Dispatcher, prog: u32, vers: u32, proc: u32, args: BytesMut) -> Bytes {
+    let hdr = RpcCallHeader { xid: 9, msg_type: RpcMessageType::Call, rpcvers: 2, prog, vers, proc };
+    let mut msg = BytesMut::from(&serialize_to_vec(&hdr).unwrap()[..]);
+    msg.extend_from_slice(&args);
+    let reply = d.dispatch(msg.freeze(), PEER.parse().unwrap(), Transport::Tcp).await.unwrap();
+    let mut out = Bytes::from(reply.into_segments().concat());
+    let rh = RpcReplyHeader::xdr_decode(&mut out).unwrap();
+    assert_eq!(rh.accept_state, ACCEPT_SUCCESS);
+    out
+}
+
+fn string_arg(s: &str) -> BytesMut {
+    let mut b = BytesMut::new();
+    s.to_string().xdr_encode(&mut b);
+    b
+}
+
+#[tokio::test]
+async fn mount_export_mnt_dump_umnt() {
+    let vfs = MemVfs::new();
+    vfs.create_dir("/data").await.unwrap();
+    vfs.create_file("/notes", 1).await.unwrap();
+    let d = Dispatcher::new(vfs.clone());
+
+    let mut r = call(&d, MOUNT_PROGRAM, MOUNT_VERSION3, MountProc::Export as u32, BytesMut::new()).await;
+    assert!(bool::xdr_decode(&mut r).unwrap());
+    assert_eq!(String::xdr_decode(&mut r).unwrap(), "/");
+    assert!(!bool::xdr_decode(&mut r).unwrap()); // no groups
+    assert!(!bool::xdr_decode(&mut r).unwrap());
+
+    // a directory below the export resolves to its own handle
+    let mut r = call(&d, MOUNT_PROGRAM, MOUNT_VERSION3, MountProc::Mnt as u32, string_arg("/data")).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), MNT3_OK);
+    let fh = Vec::<u8>::xdr_decode(&mut r).unwrap();
+    let root = d.exports().root_fh().await.unwrap();
+    assert_eq!(fh, d.exports().lookup(&root, "data").await.unwrap());
+    // the default export accepts AUTH_SYS and AUTH_NONE
+    assert_eq!(Vec::<u32>::xdr_decode(&mut r).unwrap(), vec![1, 0]);
+
+    let mut r = call(&d, MOUNT_PROGRAM, MOUNT_VERSION3, MountProc::Mnt as u32, string_arg("/notes")).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), MNT3ERR_NOTDIR);
+    let mut r = call(&d, MOUNT_PROGRAM, MOUNT_VERSION3, MountProc::Mnt as u32, string_arg("/missing")).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), MNT3ERR_NOENT);
+
+    let mut r = call(&d, MOUNT_PROGRAM, 1, MountProc::Dump as u32, BytesMut::new()).await;
+    assert!(bool::xdr_decode(&mut r).unwrap());
+    assert_eq!(String::xdr_decode(&mut r).unwrap(), "192.0.2.7");
+    assert_eq!(String::xdr_decode(&mut r).unwrap(), "/data");
+    assert!(!bool::xdr_decode(&mut r).unwrap());
+
+    call(&d, MOUNT_PROGRAM, MOUNT_VERSION3, MountProc::Umnt as u32, string_arg("/data")).await;
+    assert!(d.mount().mounts().is_empty());
+}
+
+fn lock_args(lock: &Nlm4Lock, exclusive: bool) -> BytesMut {
+    lock_request(lock, exclusive, false)
+}
+
+fn lock_request(lock: &Nlm4Lock, exclusive: bool, reclaim: bool) -> BytesMut {
+    let mut b = BytesMut::new();
+    b"c1".as_slice().xdr_encode(&mut b);
+    false.xdr_encode(&mut b); // block
+    exclusive.xdr_encode(&mut b);
+    lock.xdr_encode(&mut b);
+    reclaim.xdr_encode(&mut b);
+    1i32.xdr_encode(&mut b); // state
+    b
+}
+
+fn nlm_status(r: &mut Bytes) -> u32 {
+    assert_eq!(Vec::<u8>::xdr_decode(r).unwrap(), b"c1");
+    u32::xdr_decode(r).unwrap()
+}
+
+#[tokio::test]
+async fn nlm_lock_test_unlock_and_notify() {
+    let vfs = MemVfs::new();
+    vfs.create_file("/f", 100).await.unwrap();
+    let d = Dispatcher::new(vfs);
+    let root = d.exports().root_fh().await.unwrap();
+    let fh = d.exports().lookup(&root, "f").await.unwrap();
+
+    let a = Nlm4Lock { caller_name: "hosta".into(), fh: fh.clone(), oh: b"oa".to_vec(), svid: 11, l_offset: 0, l_len: 50 };
+    let b = Nlm4Lock { caller_name: "hostb".into(), fh: fh.clone(), oh: b"ob".to_vec(), svid: 22, l_offset: 40, l_len: 0 };
+
+    let mut r = call(&d, NLM_PROGRAM, NLM_VERSION4, NlmProc::Lock as u32, lock_args(&a, true)).await;
+    assert_eq!(nlm_status(&mut r), NLM4_GRANTED);
+    let mut r = call(&d, NLM_PROGRAM, NLM_VERSION4, NlmProc::Lock as u32, lock_args(&b, false)).await;
+    assert_eq!(nlm_status(&mut r), NLM4_DENIED);
+
+    // TEST reports the conflicting holder
+    let mut args = BytesMut::new();
+    b"c1".as_slice().xdr_encode(&mut args);
+    false.xdr_encode(&mut args);
+    b.xdr_encode(&mut args);
+    let mut r = call(&d, NLM_PROGRAM, NLM_VERSION4, NlmProc::Test as u32, args).await;
+    assert_eq!(nlm_status(&mut r), NLM4_DENIED);
+    let holder = Nlm4Holder::xdr_decode(&mut r).unwrap();
+    assert_eq!(holder, Nlm4Holder { exclusive: true, svid: 11, oh: b"oa".to_vec(), l_offset: 0, l_len: 50 });
+
+    // hosta reboots: its statd notifies ours and the lock goes away
+    let mut args = string_arg("hosta");
+    3i32.xdr_encode(&mut args);
+    call(&d, NSM_PROGRAM, NSM_VERSION, NsmProc::Notify as u32, args).await;
+    let mut r = call(&d, NLM_PROGRAM, NLM_VERSION4, NlmProc::Lock as u32, lock_args(&b, false)).await;
+    assert_eq!(nlm_status(&mut r), NLM4_GRANTED);
+
+    let mut args = BytesMut::new();
+    b"c1".as_slice().xdr_encode(&mut args);
+    b.xdr_encode(&mut args);
+    let mut r = call(&d, NLM_PROGRAM, NLM_VERSION4, NlmProc::Unlock as u32, args).await;
+    assert_eq!(nlm_status(&mut r), NLM4_GRANTED);
+    assert!(d.locks().locks(&fh).is_empty());
+
+    let stale = Nlm4Lock { fh: b"nope".to_vec(), ..a };
+    let mut r = call(&d, NLM_PROGRAM, NLM_VERSION4, NlmProc::Lock as u32, lock_args(&stale, true)).await;
+    assert_eq!(nlm_status(&mut r), NLM4_STALE_FH);
+}
+
+#[tokio::test]
+async fn grace_period_admits_only_reclaims() {
+    let vfs = MemVfs::new();
+    vfs.create_file("/f", 100).await.unwrap();
+    let d = Dispatcher::new(vfs);
+    let root = d.exports().root_fh().await.unwrap();
+    let fh = d.exports().lookup(&root, "f").await.unwrap();
+    let lock = |host: &str, offset| Nlm4Lock { caller_name: host.into(), fh: fh.clone(), oh: b"o".to_vec(), svid: 1, l_offset: offset, l_len: 10 };
+
+    d.locks().start_grace(std::time::Duration::from_secs(60));
+    let mut r = call(&d, NLM_PROGRAM, NLM_VERSION4, NlmProc::Lock as u32, lock_args(&lock("new", 0), true)).await;
+    assert_eq!(nlm_status(&mut r), NLM4_DENIED_GRACE_PERIOD);
+    let mut r = call(&d, NLM_PROGRAM, NLM_VERSION4, NlmProc::Lock as u32, lock_request(&lock("old", 0), true, true)).await;
+    assert_eq!(nlm_status(&mut r), NLM4_GRANTED);
+
+    assert!(d.locks().end_grace());
+    assert!(!d.locks().end_grace());
+    let mut r = call(&d, NLM_PROGRAM, NLM_VERSION4, NlmProc::Lock as u32, lock_args(&lock("new", 20), true)).await;
+    assert_eq!(nlm_status(&mut r), NLM4_GRANTED);
+}
+
+#[test]
+fn lock_manager_splits_and_upgrades() {
+    let lm = Arc::new(LockManager::new());
+    let a = LockOwner { host: "a".into(), id: vec![1], svid: 0 };
+    let b = LockOwner { host: "b".into(), id: vec![2], svid: 0 };
+    lm.lock(b"f", &a, 0, 100, false).unwrap();
+    // shared locks coexist, but not with an exclusive one
+    lm.lock(b"f", &b, 50, 10, false).unwrap();
+    assert_eq!(lm.lock(b"f", &b, 0, 10, true).unwrap_err().owner, a);
+    // unlocking the middle leaves two pieces
+    lm.unlock(b"f", &a, 20, 10);
+    let mut mine: Vec<_> = lm.locks(b"f").into_iter().filter(|l| l.owner == a).map(|l| (l.offset, l.end)).collect();
+    mine.sort();
+    assert_eq!(mine, vec![(0, 19), (30, 99)]);
+    lm.lock(b"f", &b, 20, 10, true).unwrap();
+    // NFSv4-style all-ones length runs to end of file
+    lm.unlock(b"f", &b, 0, u64::MAX);
+    assert!(lm.test(b"f", &b, 0, 0, true).is_some());
+    assert_eq!(lm.release_host("a"), 2);
+    assert!(lm.locks(b"f").is_empty());
+}
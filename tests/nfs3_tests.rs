@@ -0,0 +1,129 @@
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use common::*;
+use nfs_rs::proto::nfs3::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::{MemVfs, Vfs};
+use nfs_rs::xdr::*;
+use std::sync::Arc;
+
+fn skip_post_op_attr(r: &mut Bytes) -> Option<(u32, u64)> {
+    if !bool::xdr_decode(r).unwrap() {
+        return None;
+    }
+    let ftype = u32::xdr_decode(r).unwrap();
+    for _ in 0..4 {
+        u32::xdr_decode(r).unwrap(); // mode nlink uid gid
+    }
+    let size = u64::xdr_decode(r).unwrap();
+    let _ = r.split_to(8 + 8 + 8 + 8 + 24); // used rdev fsid fileid times
+    Some((ftype, size))
+}
+
+fn skip_wcc(r: &mut Bytes) {
+    if bool::xdr_decode(r).unwrap() {
+        let _ = r.split_to(24);
+    }
+    skip_post_op_attr(r);
+}
+
+async fn setup() -> (Arc<Dispatcher>, Vec<u8>) {
+    let d = Dispatcher::new(MemVfs::new());
+    let root = d.exports().root_fh().await.unwrap();
+    (d, root)
+}
+
+#[tokio::test]
+async fn test_nfs3_create_write_read_remove() {
+    let (d, root) = setup().await;
+    let fh = create3(&d, sys(0, 0), Nfs3Proc::Create, &root, "hello.txt").await;
+
+    let mut args = BytesMut::new();
+    fh.xdr_encode(&mut args);
+    0u64.xdr_encode(&mut args);
+    11u32.xdr_encode(&mut args);
+    FILE_SYNC.xdr_encode(&mut args);
+    b"hello world"[..].xdr_encode(&mut args);
+    let mut r = call3(&d, sys(0, 0), Nfs3Proc::Write, args).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NFS3_OK);
+    skip_wcc(&mut r);
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), 11);
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), FILE_SYNC);
+
+    let mut args = BytesMut::new();
+    fh.xdr_encode(&mut args);
+    6u64.xdr_encode(&mut args);
+    100u32.xdr_encode(&mut args);
+    let mut r = call3(&d, sys(0, 0), Nfs3Proc::Read, args).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NFS3_OK);
+    assert_eq!(skip_post_op_attr(&mut r), Some((NF3REG, 11)));
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), 5);
+    assert!(bool::xdr_decode(&mut r).unwrap());
+    assert_eq!(&Bytes::xdr_decode(&mut r).unwrap()[..], b"world");
+
+    let mut r = call3(&d, sys(0, 0), Nfs3Proc::Remove, diropargs(&root, "hello.txt")).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NFS3_OK);
+    let mut r = call3(&d, sys(0, 0), Nfs3Proc::Lookup, diropargs(&root, "hello.txt")).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), 2); // NFS3ERR_NOENT
+}
+
+#[tokio::test]
+async fn test_nfs3_readdir_pages_with_cookies() {
+    let (d, root) = setup().await;
+    let dir = create3(&d, sys(0, 0), Nfs3Proc::Mkdir, &root, "d").await;
+    for i in 0..20 {
+        create3(&d, sys(0, 0), Nfs3Proc::Create, &dir, &format!("f{:02}", i)).await;
+    }
+
+    let mut names = Vec::new();
+    let mut cookie = 0u64;
+    let mut calls = 0;
+    loop {
+        let mut args = BytesMut::new();
+        dir.xdr_encode(&mut args);
+        cookie.xdr_encode(&mut args);
+        0u64.xdr_encode(&mut args);
+        300u32.xdr_encode(&mut args);
+        let mut r = call3(&d, sys(0, 0), Nfs3Proc::Readdir, args).await;
+        calls += 1;
+        assert_eq!(u32::xdr_decode(&mut r).unwrap(), NFS3_OK);
+        skip_post_op_attr(&mut r);
+        u64::xdr_decode(&mut r).unwrap();
+        while bool::xdr_decode(&mut r).unwrap() {
+            u64::xdr_decode(&mut r).unwrap();
+            names.push(String::from_utf8(Vec::<u8>::xdr_decode(&mut r).unwrap()).unwrap());
+            cookie = u64::xdr_decode(&mut r).unwrap();
+        }
+        if bool::xdr_decode(&mut r).unwrap() {
+            break;
+        }
+    }
+    assert!(calls > 1, "small count should force paging");
+    let expected: Vec<String> = (0..20).map(|i| format!("f{:02}", i)).collect();
+    assert_eq!(names, expected);
+}
+
+#[tokio::test]
+async fn test_nfs3_rename_and_rmdir() {
+    let (d, root) = setup().await;
+    let a = create3(&d, sys(0, 0), Nfs3Proc::Mkdir, &root, "a").await;
+    let b = create3(&d, sys(0, 0), Nfs3Proc::Mkdir, &root, "b").await;
+    let f = create3(&d, sys(0, 0), Nfs3Proc::Create, &a, "f").await;
+
+    let mut r = call3(&d, sys(0, 0), Nfs3Proc::Rmdir, diropargs(&root, "a")).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), 66); // NFS3ERR_NOTEMPTY
+
+    let mut args = diropargs(&a, "f");
+    args.extend_from_slice(&diropargs(&b, "g"));
+    let mut r = call3(&d, sys(0, 0), Nfs3Proc::Rename, args).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NFS3_OK);
+
+    // Same object, same handle, new name
+    let mut r = call3(&d, sys(0, 0), Nfs3Proc::Lookup, diropargs(&b, "g")).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NFS3_OK);
+    assert_eq!(Vec::<u8>::xdr_decode(&mut r).unwrap(), f);
+
+    let mut r = call3(&d, sys(0, 0), Nfs3Proc::Rmdir, diropargs(&root, "a")).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NFS3_OK);
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use bytes::Bytes;
+use common::*;
+use nfs_rs::config::*;
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::{CreateKind, MemVfs, SetAttr, Vfs};
+use std::sync::Arc;
+
+fn read(stateid: Stateid4, offset: u64, count: u32) -> Op {
+    Op::new(NfsOp4::OpRead).arg(&Read4args { stateid, offset, count })
+}
+
+fn write(stateid: Stateid4, offset: u64, stable: u32, data: &'static [u8]) -> Op {
+    Op::new(NfsOp4::OpWrite).arg(&Write4args { stateid, offset, stable, data: Bytes::from_static(data) })
+}
+
+fn commit(offset: u64, count: u32) -> Op {
+    Op::new(NfsOp4::OpCommit).arg(&Commit4args { offset, count })
+}
+
+async fn create(d: &Dispatcher, name: &str, kind: CreateKind) -> Vec<u8> {
+    let exports = d.exports();
+    let root = exports.root_fh().await.unwrap();
+    exports.create(&root, name, kind, &SetAttr::default()).await.unwrap()
+}
+
+// Writes in each stability, a short read at EOF and a COMMIT
+async fn read_write_commit(d: &Dispatcher) {
+    let fh = create(d, "data", CreateKind::Regular).await;
+    let anon = Stateid4::ANONYMOUS;
+    let (res, status) = compound(
+        d,
+        sys(0, 0),
+        2,
+        vec![
+            putfh(&fh),
+            write(anon, 0, UNSTABLE4, b"hello "),
+            write(anon, 6, DATA_SYNC4, b"wor"),
+            write(anon, 9, FILE_SYNC4, b"ld"),
+            commit(0, 0),
+        ],
+    )
+    .await;
+    assert_eq!(status, NFS4_OK);
+    let Res::Write(_, _, verf) = res[1] else { panic!("{:?}", res) };
+    assert_eq!(res[1..], [Res::Write(6, UNSTABLE4, verf), Res::Write(3, FILE_SYNC4, verf), Res::Write(2, FILE_SYNC4, verf), Res::Commit(verf)]);
+
+    let (res, status) = compound(
+        d,
+        sys(0, 0),
+        2,
+        vec![
+            putfh(&fh),
+            read(anon, 0, 5),
+            read(Stateid4::READ_BYPASS, 6, 100),
+            read(anon, 11, 4),
+            read(anon, 50, 4),
+        ],
+    )
+    .await;
+    assert_eq!(status, NFS4_OK);
+    assert_eq!(
+        res[1..],
+        [Res::Read(false, b"hello".to_vec()), Res::Read(true, b"world".to_vec()), Res::Read(true, vec![]), Res::Read(true, vec![])]
+    );
+}
+
+#[tokio::test]
+async fn memory_backend_reads_and_writes() {
+    read_write_commit(&Dispatcher::new(MemVfs::new())).await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn disk_backend_reads_and_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let export = ExportConfig {
+        backend: BackendConfig::Local { root: dir.path().to_string_lossy().into_owned() },
+        ..Default::default()
+    };
+    let d = Dispatcher::from_config(&NfsConfig { exports: vec![export], ..Default::default() }).unwrap();
+    read_write_commit(&d).await;
+    assert_eq!(std::fs::read(dir.path().join("data")).unwrap(), b"hello world");
+}
+
+#[tokio::test]
+async fn write_verifier_lasts_until_restart() {
+    let d = Dispatcher::new(MemVfs::new());
+    let fh = create(&d, "f", CreateKind::Regular).await;
+    let commit = |d: Arc<Dispatcher>, fh: Vec<u8>| async move {
+        let (res, _) = compound(&d, sys(0, 0), 2, vec![putfh(&fh), commit(0, 0)]).await;
+        res
+    };
+    let first = commit(d.clone(), fh.clone()).await;
+    assert_eq!(first, commit(d.clone(), fh.clone()).await);
+    // a new server instance is a reboot as far as clients can tell
+    let rebooted = Dispatcher::new(MemVfs::new());
+    let fh = create(&rebooted, "f", CreateKind::Regular).await;
+    assert_ne!(first, commit(rebooted, fh).await);
+}
+
+#[tokio::test]
+async fn only_special_stateids_are_accepted() {
+    let d = Dispatcher::new(MemVfs::new());
+    let fh = create(&d, "f", CreateKind::Regular).await;
+    let made_up = Stateid4 { seqid: 1, other: [7; 12] };
+    let bad = Nfs4Status::BadStateid as u32;
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&fh), read(made_up, 0, 1)]).await, (vec![Res::Ok], bad));
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&fh), write(made_up, 0, UNSTABLE4, b"x")]).await, (vec![Res::Ok], bad));
+    // the bypass stateid is for reading only
+    let (res, status) = compound(&d, sys(0, 0), 2, vec![putfh(&fh), write(Stateid4::READ_BYPASS, 0, UNSTABLE4, b"x"), read(Stateid4::ANONYMOUS, 0, 1)]).await;
+    assert_eq!((res, status), (vec![Res::Ok], bad));
+}
+
+#[tokio::test]
+async fn io_needs_a_regular_file() {
+    let d = Dispatcher::new(MemVfs::new());
+    let dir = create(&d, "dir", CreateKind::Directory).await;
+    let link = create(&d, "link", CreateKind::Symlink("dir".into())).await;
+    let anon = Stateid4::ANONYMOUS;
+    let status = |d: &Arc<Dispatcher>, minor, ops| {
+        let d = d.clone();
+        async move { compound(&d, sys(0, 0), minor, ops).await.1 }
+    };
+    assert_eq!(status(&d, 2, vec![putfh(&dir), read(anon, 0, 1)]).await, Nfs4Status::Isdir as u32);
+    assert_eq!(status(&d, 2, vec![putfh(&dir), write(anon, 0, UNSTABLE4, b"x")]).await, Nfs4Status::Isdir as u32);
+    assert_eq!(status(&d, 2, vec![putfh(&dir), commit(0, 0)]).await, Nfs4Status::Isdir as u32);
+    assert_eq!(status(&d, 2, vec![putfh(&link), read(anon, 0, 1)]).await, Nfs4Status::Symlink as u32);
+    // NFSv4.0 predates NFS4ERR_SYMLINK for I/O
+    assert_eq!(status(&d, 0, vec![putfh(&link), read(anon, 0, 1)]).await, Nfs4Status::Inval as u32);
+    assert_eq!(status(&d, 2, vec![read(anon, 0, 1)]).await, Nfs4Status::Nofilehandle as u32);
+}
+
+#[tokio::test]
+async fn read_only_exports_refuse_writes() {
+    let export = ExportConfig {
+        clients: vec![ClientRule {
+            host: "*".into(),
+            options: ExportOptions { access: AccessMode::Ro, sec: vec![SecFlavor::Sys], ..Default::default() },
+        }],
+        ..Default::default()
+    };
+    let d = Dispatcher::from_config(&NfsConfig { exports: vec![export], ..Default::default() }).unwrap();
+    let fh = create(&d, "f", CreateKind::Regular).await;
+    let (res, status) = compound(&d, sys(0, 0), 2, vec![putfh(&fh), write(Stateid4::ANONYMOUS, 0, FILE_SYNC4, b"x")]).await;
+    assert_eq!((res, status), (vec![Res::Ok], Nfs4Status::Rofs as u32));
+    let (res, _) = compound(&d, sys(0, 0), 2, vec![putfh(&fh), read(Stateid4::ANONYMOUS, 0, 1)]).await;
+    assert_eq!(res[1], Res::Read(true, vec![]));
+}
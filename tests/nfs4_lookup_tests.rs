@@ -0,0 +1,95 @@
+mod common;
+
+use common::*;
+use nfs_rs::auth::Credential;
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::{CreateKind, MemVfs, SetAttr, Vfs};
+
+async fn status(d: &Dispatcher, ops: Vec<Op>) -> u32 {
+    compound(d, Credential::None, 2, ops).await.1
+}
+
+// A root with a directory "dir", a file "file" and a symlink "link"
+async fn tree() -> (std::sync::Arc<Dispatcher>, Vec<u8>, Vec<u8>) {
+    let d = Dispatcher::new(MemVfs::new());
+    let exports = d.exports();
+    let root = exports.root_fh().await.unwrap();
+    let dir = exports.create(&root, "dir", CreateKind::Directory, &SetAttr::default()).await.unwrap();
+    exports.create(&root, "file", CreateKind::Regular, &SetAttr::default()).await.unwrap();
+    exports.create(&root, "link", CreateKind::Symlink("dir".into()), &SetAttr::default()).await.unwrap();
+    (d, root, dir)
+}
+
+#[tokio::test]
+async fn putfh_checks_the_handle() {
+    let (d, root, _) = tree().await;
+    let file = d.exports().lookup(&root, "file").await.unwrap();
+    assert_eq!(compound(&d, Credential::None, 2, vec![putfh(&file), getfh()]).await, (vec![Res::Ok, Res::Getfh(file.clone())], NFS4_OK));
+
+    let badhandle = Nfs4Status::Badhandle as u32;
+    assert_eq!(status(&d, vec![putfh(b"")]).await, badhandle);
+    assert_eq!(status(&d, vec![putfh(&[1; 129])]).await, badhandle);
+    // the export is known but the rest is not a handle its backend made
+    let mut garbled = root[..8].to_vec();
+    garbled.extend_from_slice(b"not a handle");
+    assert_eq!(status(&d, vec![putfh(&garbled)]).await, badhandle);
+
+    let stale = Nfs4Status::Stale as u32;
+    // no export has this fsid
+    assert_eq!(status(&d, vec![putfh(&[0xee; 24])]).await, stale);
+    d.exports().remove(&root, "file").await.unwrap();
+    assert_eq!(status(&d, vec![putfh(&file)]).await, stale);
+    // a handle from before a restart
+    let (rebooted, _, _) = tree().await;
+    let old = d.exports().lookup(&root, "dir").await.unwrap();
+    assert_eq!(status(&rebooted, vec![putfh(&old)]).await, stale);
+}
+
+#[tokio::test]
+async fn lookup_checks_the_name() {
+    let (d, _, dir) = tree().await;
+    let find = |name: &'static [u8]| {
+        let d = d.clone();
+        async move { status(&d, vec![putrootfh(), lookup(name)]).await }
+    };
+    assert_eq!(find(b"dir").await, NFS4_OK);
+    assert_eq!(find(b"missing").await, Nfs4Status::Noent as u32);
+    assert_eq!(find(b"").await, Nfs4Status::Inval as u32);
+    assert_eq!(find(b"\xff\xfe").await, Nfs4Status::Inval as u32);
+    for name in [&b"."[..], b"..", b"dir/file", b"a\0b"] {
+        assert_eq!(status(&d, vec![putrootfh(), lookup(name)]).await, Nfs4Status::Badname as u32, "{:?}", name);
+    }
+    let long = vec![b'x'; 256];
+    assert_eq!(status(&d, vec![putrootfh(), lookup(&long)]).await, Nfs4Status::Nametoolong as u32);
+    assert_eq!(status(&d, vec![putfh(&dir), lookup(&long[..255])]).await, Nfs4Status::Noent as u32);
+}
+
+#[tokio::test]
+async fn lookup_needs_a_directory() {
+    let (d, _, _) = tree().await;
+    assert_eq!(status(&d, vec![putrootfh(), lookup(b"file"), lookup(b"x")]).await, Nfs4Status::Notdir as u32);
+    assert_eq!(status(&d, vec![putrootfh(), lookup(b"link"), lookup(b"x")]).await, Nfs4Status::Symlink as u32);
+    // NFSv4.0 has no SYMLINK error
+    assert_eq!(compound(&d, Credential::None, 0, vec![putrootfh(), lookup(b"link"), lookup(b"x")]).await.1, Nfs4Status::Notdir as u32);
+    assert_eq!(status(&d, vec![lookup(b"dir")]).await, Nfs4Status::Nofilehandle as u32);
+}
+
+#[tokio::test]
+async fn lookupp_climbs_to_the_root_and_stops() {
+    let (d, root, dir) = tree().await;
+    let res = vec![Res::Ok, Res::Ok, Res::Getfh(root)];
+    assert_eq!(compound(&d, Credential::None, 2, vec![putfh(&dir), lookupp(), getfh()]).await, (res, NFS4_OK));
+    assert_eq!(status(&d, vec![putrootfh(), lookupp()]).await, Nfs4Status::Noent as u32);
+}
+
+#[tokio::test]
+async fn savefh_and_restorefh() {
+    let (d, root, dir) = tree().await;
+    let ops = vec![putrootfh(), lookup(b"dir"), savefh(), lookupp(), getfh(), restorefh(), getfh()];
+    let (res, _) = compound(&d, Credential::None, 2, ops).await;
+    assert_eq!(res, [Res::Ok, Res::Ok, Res::Ok, Res::Ok, Res::Getfh(root), Res::Ok, Res::Getfh(dir)]);
+    assert_eq!(status(&d, vec![putrootfh(), restorefh()]).await, Nfs4Status::Restorefh as u32);
+    assert_eq!(status(&d, vec![savefh()]).await, Nfs4Status::Nofilehandle as u32);
+}
@@ -0,0 +1,242 @@
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use common::*;
+use nfs_rs::config::*;
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::{CreateKind, FileType, MemVfs, SetAttr, Vfs};
+use nfs_rs::xdr::*;
+
+fn create_op(args: Create4args) -> Op {
+    Op::new(NfsOp4::OpCreate).arg(&args)
+}
+
+fn remove(name: &str) -> Op {
+    Op::new(NfsOp4::OpRemove).arg(name.as_bytes())
+}
+
+fn rename_op(from: &str, to: &str) -> Op {
+    Op::new(NfsOp4::OpRename).arg(&Rename4args { oldname: from.to_string().into(), newname: to.to_string().into() })
+}
+
+fn link_op(name: &str) -> Op {
+    Op::new(NfsOp4::OpLink).arg(name.as_bytes())
+}
+
+fn readlink() -> Op {
+    Op::new(NfsOp4::OpReadlink)
+}
+
+fn create(objtype: u32, name: &str) -> Create4args {
+    Create4args {
+        objtype,
+        linkdata: Bytes::new(),
+        devdata: (0, 0),
+        objname: name.to_string().into(),
+        createattrs: Fattr4::default(),
+    }
+}
+
+// A root with directories "a" and "b", each holding a file "f"
+async fn tree(d: &Dispatcher) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let exports = d.exports();
+    let root = exports.root_fh().await.unwrap();
+    let a = exports.create(&root, "a", CreateKind::Directory, &SetAttr::default()).await.unwrap();
+    let b = exports.create(&root, "b", CreateKind::Directory, &SetAttr::default()).await.unwrap();
+    for dir in [&a, &b] {
+        exports.create(dir, "f", CreateKind::Regular, &SetAttr::default()).await.unwrap();
+    }
+    (root, a, b)
+}
+
+async fn changeid(d: &Dispatcher, fh: &[u8]) -> u64 {
+    d.exports().getattr(fh).await.unwrap().changeid
+}
+
+#[tokio::test]
+async fn create_makes_every_kind_but_regular_files() {
+    let d = Dispatcher::new(MemVfs::new());
+    let (_, a, _) = tree(&d).await;
+    let link = Create4args { linkdata: Bytes::from_static(b"../b/f"), ..create(NF4LNK, "link") };
+    let dev = Create4args { devdata: (8, 1), ..create(NF4BLK, "sda1") };
+    let kinds = [
+        (create(NF4DIR, "dir"), FileType::Directory),
+        (link, FileType::Symlink),
+        (dev, FileType::BlockDevice),
+        (create(NF4CHR, "tty"), FileType::CharDevice),
+        (create(NF4SOCK, "sock"), FileType::Socket),
+        (create(NF4FIFO, "fifo"), FileType::Fifo),
+    ];
+    for (args, ftype) in kinds {
+        let before = changeid(&d, &a).await;
+        let (res, status) = compound(&d, sys(0, 0), 2, vec![putfh(&a), create_op(args), getfh()]).await;
+        assert_eq!(status, NFS4_OK);
+        let after = changeid(&d, &a).await;
+        assert_ne!(before, after);
+        assert_eq!(res[1], Res::Create(ChangeInfo4 { atomic: true, before, after }, vec![0]));
+        // the new object becomes the current filehandle
+        let Res::Getfh(fh) = &res[2] else { panic!("{:?}", res) };
+        assert_eq!(d.exports().getattr(fh).await.unwrap().ftype, ftype);
+    }
+    let sda1 = d.exports().lookup(&a, "sda1").await.unwrap();
+    assert_eq!(d.exports().getattr(&sda1).await.unwrap().rdev, (8, 1));
+    let link = d.exports().lookup(&a, "link").await.unwrap();
+    let (res, _) = compound(&d, sys(0, 0), 2, vec![putfh(&link), readlink()]).await;
+    assert_eq!(res[1], Res::Readlink("../b/f".into()));
+
+    let status = |args| {
+        let (d, a) = (d.clone(), a.clone());
+        async move { compound(&d, sys(0, 0), 2, vec![putfh(&a), create_op(args)]).await.1 }
+    };
+    assert_eq!(status(create(NF4REG, "file")).await, Nfs4Status::Badtype as u32);
+    assert_eq!(status(create(NF4DIR, "f")).await, Nfs4Status::Exist as u32);
+    assert_eq!(status(create(NF4DIR, "..")).await, Nfs4Status::Badname as u32);
+    let f = d.exports().lookup(&a, "f").await.unwrap();
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&f), create_op(create(NF4DIR, "x"))]).await.1, Nfs4Status::Notdir as u32);
+}
+
+#[tokio::test]
+async fn create_applies_the_attributes_it_reports() {
+    let d = Dispatcher::new(MemVfs::new());
+    let (_, a, _) = tree(&d).await;
+    let mut vals = BytesMut::new();
+    0o750u32.xdr_encode(&mut vals);
+    b"1234@example.com".as_slice().xdr_encode(&mut vals);
+    b"99".as_slice().xdr_encode(&mut vals);
+    let attrmask = bitmap4_with(&[FATTR4_MODE, FATTR4_OWNER, FATTR4_OWNER_GROUP]);
+    let createattrs = Fattr4 { attrmask: attrmask.clone(), attr_vals: vals.freeze() };
+    let (res, status) = compound(&d, sys(0, 0), 2, vec![putfh(&a), create_op(Create4args { createattrs, ..create(NF4DIR, "d") }), getfh()]).await;
+    assert_eq!(status, NFS4_OK);
+    let Res::Create(_, attrset) = &res[1] else { panic!("{:?}", res) };
+    assert_eq!(*attrset, attrmask);
+    let Res::Getfh(fh) = &res[2] else { panic!("{:?}", res) };
+    let attr = d.exports().getattr(fh).await.unwrap();
+    assert_eq!((attr.mode, attr.uid, attr.gid), (0o750, 1234, 99));
+
+    let with = |bit, vals: &'static [u8], name| {
+        let createattrs = Fattr4 { attrmask: bitmap4_with(&[bit]), attr_vals: Bytes::from_static(vals) };
+        Create4args { createattrs, ..create(NF4DIR, name) }
+    };
+    let status = |args| {
+        let (d, a) = (d.clone(), a.clone());
+        async move { compound(&d, sys(0, 0), 2, vec![putfh(&a), create_op(args)]).await.1 }
+    };
+    assert_eq!(status(with(FATTR4_OWNER, b"\0\0\0\x04root", "e")).await, Nfs4Status::Badowner as u32);
+    assert_eq!(status(with(FATTR4_FILEID, b"\0\0\0\0\0\0\0\x07", "e")).await, Nfs4Status::Attrnotsupp as u32);
+    // values left over once the bitmap's attributes are read
+    assert_eq!(status(with(FATTR4_MODE, b"\0\0\x01\xed\0\0\0\0", "e")).await, Nfs4Status::Badxdr as u32);
+    assert!(d.exports().lookup(&a, "e").await.is_err());
+}
+
+#[tokio::test]
+async fn remove_reports_the_change_and_keeps_full_directories() {
+    let d = Dispatcher::new(MemVfs::new());
+    let (root, a, _) = tree(&d).await;
+    let before = changeid(&d, &root).await;
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&root), remove("a")]).await.1, Nfs4Status::Notempty as u32);
+    assert_eq!(changeid(&d, &root).await, before);
+
+    let (res, status) = compound(&d, sys(0, 0), 2, vec![putfh(&a), remove("f")]).await;
+    assert_eq!(status, NFS4_OK);
+    let Res::Cinfo(cinfo) = res[1] else { panic!("{:?}", res) };
+    assert!(cinfo.atomic && cinfo.after == changeid(&d, &a).await && cinfo.before < cinfo.after);
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&a), remove("f")]).await.1, Nfs4Status::Noent as u32);
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&root), remove("a")]).await.1, NFS4_OK);
+}
+
+#[tokio::test]
+async fn rename_moves_across_directories_and_replaces_the_target() {
+    let d = Dispatcher::new(MemVfs::new());
+    let (root, a, b) = tree(&d).await;
+    let moved = d.exports().lookup(&a, "f").await.unwrap();
+    let (before_a, before_b) = (changeid(&d, &a).await, changeid(&d, &b).await);
+    let (res, status) = compound(&d, sys(0, 0), 2, vec![putfh(&a), savefh(), putfh(&b), rename_op("f", "f")]).await;
+    assert_eq!(status, NFS4_OK);
+    let (after_a, after_b) = (changeid(&d, &a).await, changeid(&d, &b).await);
+    assert_eq!(
+        res[3],
+        Res::Rename(ChangeInfo4 { atomic: true, before: before_a, after: after_a }, ChangeInfo4 { atomic: true, before: before_b, after: after_b })
+    );
+    assert_eq!(d.exports().lookup(&b, "f").await.unwrap(), moved);
+    assert!(d.exports().lookup(&a, "f").await.is_err());
+
+    // only an object of the same kind may be replaced
+    d.exports().create(&b, "d", CreateKind::Directory, &SetAttr::default()).await.unwrap();
+    let rename = |from, to| compound(&d, sys(0, 0), 2, vec![putfh(&b), savefh(), rename_op(from, to)]);
+    assert_eq!(rename("f", "d").await.1, Nfs4Status::Exist as u32);
+    assert_eq!(rename("d", "f").await.1, Nfs4Status::Exist as u32);
+    // two names for one file: nothing happens
+    d.exports().link(&moved, &b, "g").await.unwrap();
+    let before = changeid(&d, &b).await;
+    let (res, status) = rename("f", "g").await;
+    assert_eq!(status, NFS4_OK);
+    let same = ChangeInfo4 { atomic: true, before, after: before };
+    assert_eq!(res[2], Res::Rename(same, same));
+    assert!(d.exports().lookup(&b, "f").await.is_ok());
+
+    // a directory cannot move below itself
+    let ops = vec![putfh(&root), savefh(), putfh(&b), rename_op("b", "sub")];
+    assert_eq!(compound(&d, sys(0, 0), 2, ops).await.1, Nfs4Status::Inval as u32);
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&b), rename_op("f", "h")]).await.1, Nfs4Status::Nofilehandle as u32);
+}
+
+#[tokio::test]
+async fn link_and_readlink() {
+    let d = Dispatcher::new(MemVfs::new());
+    let (root, a, b) = tree(&d).await;
+    let f = d.exports().lookup(&a, "f").await.unwrap();
+    let before = changeid(&d, &b).await;
+    let (res, status) = compound(&d, sys(0, 0), 2, vec![putfh(&f), savefh(), putfh(&b), link_op("g")]).await;
+    assert_eq!(status, NFS4_OK);
+    assert_eq!(res[3], Res::Cinfo(ChangeInfo4 { atomic: true, before, after: changeid(&d, &b).await }));
+    assert_eq!(d.exports().lookup(&b, "g").await.unwrap(), f);
+    assert_eq!(d.exports().getattr(&f).await.unwrap().nlink, 2);
+    let link = |name| compound(&d, sys(0, 0), 2, vec![putfh(&f), savefh(), putfh(&b), link_op(name)]);
+    assert_eq!(link("g").await.1, Nfs4Status::Exist as u32);
+    let ops = vec![putfh(&a), savefh(), putfh(&root), link_op("a2")];
+    assert_eq!(compound(&d, sys(0, 0), 2, ops).await.1, Nfs4Status::Isdir as u32);
+
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&f), readlink()]).await.1, Nfs4Status::WrongType as u32);
+    assert_eq!(compound(&d, sys(0, 0), 0, vec![putfh(&f), readlink()]).await.1, Nfs4Status::Inval as u32);
+}
+
+#[tokio::test]
+async fn read_only_exports_refuse_changes() {
+    let export = ExportConfig {
+        clients: vec![ClientRule {
+            host: "*".into(),
+            options: ExportOptions { access: AccessMode::Ro, sec: vec![SecFlavor::Sys], ..Default::default() },
+        }],
+        ..Default::default()
+    };
+    let d = Dispatcher::from_config(&NfsConfig { exports: vec![export], ..Default::default() }).unwrap();
+    let (_, a, b) = tree(&d).await;
+    let rofs = Nfs4Status::Rofs as u32;
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&a), create_op(create(NF4DIR, "d"))]).await.1, rofs);
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&a), remove("f")]).await.1, rofs);
+    assert_eq!(compound(&d, sys(0, 0), 2, vec![putfh(&a), savefh(), putfh(&b), rename_op("f", "g")]).await.1, rofs);
+    assert!(d.exports().lookup(&a, "f").await.is_ok());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn disk_changes_are_not_atomic() {
+    let dir = tempfile::tempdir().unwrap();
+    let export = ExportConfig {
+        backend: BackendConfig::Local { root: dir.path().to_string_lossy().into_owned() },
+        ..Default::default()
+    };
+    let d = Dispatcher::from_config(&NfsConfig { exports: vec![export], ..Default::default() }).unwrap();
+    let (_, a, b) = tree(&d).await;
+    // other processes may change the directory too
+    let (res, status) = compound(&d, sys(0, 0), 2, vec![putfh(&a), create_op(create(NF4DIR, "d"))]).await;
+    assert_eq!(status, NFS4_OK);
+    assert!(matches!(res[1], Res::Create(ChangeInfo4 { atomic: false, .. }, _)));
+    let (res, status) = compound(&d, sys(0, 0), 2, vec![putfh(&a), savefh(), putfh(&b), rename_op("d", "e")]).await;
+    assert_eq!(status, NFS4_OK);
+    let Res::Rename(source, target) = res[3] else { panic!("{:?}", res) };
+    assert!(!source.atomic && !target.atomic);
+    assert!(dir.path().join("b/e").is_dir() && !dir.path().join("a/d").exists());
+}
@@ -28,3 +28,253 @@ fn test_compound_getattr_parse() {
     assert!((parsed_bm[0] & (1 << (FATTR4_TYPE % 32))) != 0);
     assert!((parsed_bm[0] & (1 << (FATTR4_FILEHANDLE % 32))) != 0);
 }
+
+#[test]
+fn status_table_is_complete() {
+    use nfs_rs::error::Nfs4Status;
+    use num_traits::FromPrimitive;
+    // every code from BADHANDLE to OFFLOAD_NO_REQS but the two holes
+    for code in (10001..=10094).filter(|c| ![10002, 10073].contains(c)) {
+        assert!(Nfs4Status::from_u32(code).is_some(), "missing {}", code);
+    }
+    assert_eq!(Nfs4Status::from_u32(10002), None);
+    assert_eq!(Nfs4Status::from_u32(19), None);
+}
+
+#[test]
+fn errors_map_to_their_status() {
+    use nfs_rs::error::{Nfs3Status, Nfs4Status};
+    use nfs_rs::NfsError;
+    assert_eq!(Nfs4Status::from(NfsError::BadStateid), Nfs4Status::BadStateid);
+    assert_eq!(Nfs4Status::from(NfsError::Grace), Nfs4Status::Grace);
+    assert_eq!(Nfs4Status::from(NfsError::Xdr("short".into())), Nfs4Status::Badxdr);
+    assert_eq!(Nfs4Status::from(NfsError::NameTooLong), Nfs4Status::Nametoolong);
+    assert_eq!(Nfs4Status::from(NfsError::Status(Nfs4Status::Denied)), Nfs4Status::Denied);
+    assert_eq!(Nfs4Status::from(NfsError::Config("x".into())), Nfs4Status::Serverfault);
+    // NFSv3 keeps the codes the versions share and has no others
+    assert_eq!(Nfs3Status::from(NfsError::Status(Nfs4Status::Delay)), Nfs3Status::Jukebox);
+    assert_eq!(Nfs3Status::from(NfsError::Status(Nfs4Status::Expired)), Nfs3Status::Serverfault);
+}
+
+#[test]
+fn op_names_follow_the_rfc() {
+    assert_eq!(op_name(NfsOp4::OpPutrootfh as u32), "PUTROOTFH");
+    assert_eq!(op_name(NfsOp4::OpReadPlus as u32), "READ_PLUS");
+    assert_eq!(op_name(NfsOp4::OpSetclientidConfirm as u32), "SETCLIENTID_CONFIRM");
+    assert_eq!(op_name(9999), "9999");
+}
+
+#[tokio::test]
+async fn illegal_opcodes_and_minor_versions_are_refused() {
+    use bytes::{Bytes, BytesMut};
+    use nfs_rs::error::Nfs4Status;
+    use nfs_rs::rpc::*;
+    use nfs_rs::server::{Dispatcher, Transport};
+
+    let d = Dispatcher::new(nfs_rs::vfs::MemVfs::new());
+    let compound = |minor: u32, ops: &[u32]| {
+        let hdr = RpcCallHeader { xid: 3, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc: Nfs4Proc::Compound as u32 };
+        let mut msg = BytesMut::from(&serialize_to_vec(&hdr).unwrap()[..]);
+        b"".as_slice().xdr_encode(&mut msg);
+        minor.xdr_encode(&mut msg);
+        (ops.len() as u32).xdr_encode(&mut msg);
+        for op in ops {
+            op.xdr_encode(&mut msg);
+        }
+        msg.freeze()
+    };
+    let run = |msg: Bytes| {
+        let d = d.clone();
+        async move {
+            let reply = d.dispatch(msg, "127.0.0.1:900".parse().unwrap(), Transport::Tcp).await.unwrap();
+            let mut r = Bytes::from(reply.into_segments().concat());
+            RpcReplyHeader::xdr_decode(&mut r).unwrap();
+            let status = u32::xdr_decode(&mut r).unwrap();
+            let _tag = Vec::<u8>::xdr_decode(&mut r).unwrap();
+            let results: Vec<(u32, u32)> =
+                (0..u32::xdr_decode(&mut r).unwrap()).map(|_| (u32::xdr_decode(&mut r).unwrap(), u32::xdr_decode(&mut r).unwrap())).collect();
+            (status, results)
+        }
+    };
+
+    let illegal = Nfs4Status::OpIllegal as u32;
+    let (status, results) = run(compound(2, &[NfsOp4::OpPutrootfh as u32, 2, NfsOp4::OpGetfh as u32])).await;
+    assert_eq!(status, illegal);
+    assert_eq!(results, [(NfsOp4::OpPutrootfh as u32, NFS4_OK), (NfsOp4::OpIllegal as u32, illegal)]);
+
+    let (status, results) = run(compound(3, &[NfsOp4::OpPutrootfh as u32])).await;
+    assert_eq!((status, results.len()), (Nfs4Status::MinorVersMismatch as u32, 0));
+}
+
+#[tokio::test]
+async fn nothing_runs_after_an_op_that_fails() {
+    use bytes::{Bytes, BytesMut};
+    use nfs_rs::error::Nfs4Status;
+    use nfs_rs::rpc::*;
+    use nfs_rs::server::{Dispatcher, Transport};
+    use nfs_rs::vfs::{CreateKind, SetAttr, Vfs};
+
+    let d = Dispatcher::new(nfs_rs::vfs::MemVfs::new());
+    let root = d.exports().root_fh().await.unwrap();
+    d.exports().create(&root, "keep", CreateKind::Regular, &SetAttr::default()).await.unwrap();
+    // `numops` ops: PUTROOTFH, then `opcode` and the raw `rest`
+    let run = |numops: u32, opcode: u32, rest: BytesMut| {
+        let d = d.clone();
+        async move {
+            let hdr = RpcCallHeader { xid: 4, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc: Nfs4Proc::Compound as u32 };
+            let mut msg = BytesMut::from(&serialize_to_vec(&hdr).unwrap()[..]);
+            b"".as_slice().xdr_encode(&mut msg);
+            2u32.xdr_encode(&mut msg);
+            numops.xdr_encode(&mut msg);
+            (NfsOp4::OpPutrootfh as u32).xdr_encode(&mut msg);
+            opcode.xdr_encode(&mut msg);
+            msg.extend_from_slice(&rest);
+            let reply = d.dispatch(msg.freeze(), "127.0.0.1:900".parse().unwrap(), Transport::Tcp).await.unwrap();
+            let mut r = Bytes::from(reply.into_segments().concat());
+            RpcReplyHeader::xdr_decode(&mut r).unwrap();
+            let status = u32::xdr_decode(&mut r).unwrap();
+            let _tag = Vec::<u8>::xdr_decode(&mut r).unwrap();
+            let results: Vec<(u32, u32)> =
+                (0..u32::xdr_decode(&mut r).unwrap()).map(|_| (u32::xdr_decode(&mut r).unwrap(), u32::xdr_decode(&mut r).unwrap())).collect();
+            assert!(r.is_empty());
+            (status, results)
+        }
+    };
+    // what follows the op would be a REMOVE of "keep", were it read as ops
+    let remove = || {
+        let mut rest = BytesMut::new();
+        (NfsOp4::OpRemove as u32).xdr_encode(&mut rest);
+        b"keep".as_slice().xdr_encode(&mut rest);
+        rest
+    };
+    let putrootfh = (NfsOp4::OpPutrootfh as u32, NFS4_OK);
+
+    // OPEN's arguments are not ones we know
+    let notsupp = Nfs4Status::Notsupp as u32;
+    let (status, results) = run(3, NfsOp4::OpOpen as u32, remove()).await;
+    assert_eq!((status, results), (notsupp, vec![putrootfh, (NfsOp4::OpOpen as u32, notsupp)]));
+    let illegal = Nfs4Status::OpIllegal as u32;
+    let (status, results) = run(3, 9999, remove()).await;
+    assert_eq!((status, results), (illegal, vec![putrootfh, (NfsOp4::OpIllegal as u32, illegal)]));
+    // arguments cut short
+    let badxdr = Nfs4Status::Badxdr as u32;
+    let (status, results) = run(2, NfsOp4::OpRead as u32, BytesMut::from(&[0u8; 8][..])).await;
+    assert_eq!((status, results), (badxdr, vec![putrootfh, (NfsOp4::OpRead as u32, badxdr)]));
+    assert!(d.exports().lookup(&root, "keep").await.is_ok());
+}
+
+#[tokio::test]
+async fn malformed_calls_are_answered() {
+    use bytes::{Bytes, BytesMut};
+    use nfs_rs::error::Nfs4Status;
+    use nfs_rs::rpc::*;
+    use nfs_rs::server::{Dispatcher, Transport};
+
+    let d = Dispatcher::new(nfs_rs::vfs::MemVfs::new());
+    let run = |msg: BytesMut| {
+        let d = d.clone();
+        async move {
+            let reply = d.dispatch(msg.freeze(), "127.0.0.1:900".parse().unwrap(), Transport::Tcp).await.unwrap();
+            Bytes::from(reply.into_segments().concat())
+        }
+    };
+    let hdr = RpcCallHeader { xid: 8, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc: Nfs4Proc::Compound as u32 };
+    let call = BytesMut::from(&serialize_to_vec(&hdr).unwrap()[..]);
+
+    // a COMPOUND whose tag runs past the end of the call
+    let mut msg = call.clone();
+    100u32.xdr_encode(&mut msg);
+    msg.extend_from_slice(b"short");
+    let mut r = run(msg).await;
+    let reply = RpcReplyHeader::xdr_decode(&mut r).unwrap();
+    assert_eq!((reply.xid, reply.accept_state), (8, ACCEPT_SUCCESS));
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), Nfs4Status::Badxdr as u32);
+    assert!(Vec::<u8>::xdr_decode(&mut r).unwrap().is_empty());
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), 0);
+    assert!(r.is_empty());
+
+    // a header cut off in its credential
+    let mut r = run(BytesMut::from(&call[..30])).await;
+    let reply = RpcReplyHeader::xdr_decode(&mut r).unwrap();
+    assert_eq!((reply.xid, reply.accept_state), (8, GARBAGE_ARGS));
+    // too little to say which call it was
+    assert!(run(BytesMut::from(&call[..3])).await.is_empty());
+}
+
+#[tokio::test]
+async fn only_rpc_version_2_calls_are_served() {
+    use bytes::{Bytes, BytesMut};
+    use nfs_rs::rpc::*;
+    use nfs_rs::server::{Dispatcher, Transport};
+
+    let d = Dispatcher::new(nfs_rs::vfs::MemVfs::new());
+    let run = |hdr: RpcCallHeader| {
+        let d = d.clone();
+        async move {
+            let mut msg = BytesMut::new();
+            RpcCall { header: hdr, cred: nfs_rs::auth::Credential::None }.xdr_encode(&mut msg);
+            let reply = d.dispatch(msg.freeze(), "127.0.0.1:900".parse().unwrap(), Transport::Tcp).await.unwrap();
+            let mut r = Bytes::from(reply.into_segments().concat());
+            std::iter::from_fn(|| u32::xdr_decode(&mut r).ok()).collect::<Vec<u32>>()
+        }
+    };
+    let null = RpcCallHeader { xid: 4, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc: 0 };
+    assert_eq!(run(null.clone()).await[..4], [4, 1, 0, 0]);
+    // MSG_DENIED, RPC_MISMATCH, from 2 to 2
+    assert_eq!(run(RpcCallHeader { rpcvers: 3, ..null.clone() }).await, [4, 1, 1, 0, 2, 2]);
+    // a reply sent to the server is not answered at all
+    assert!(run(RpcCallHeader { msg_type: RpcMessageType::Reply, ..null }).await.is_empty());
+}
+
+#[tokio::test]
+async fn getfh_and_getattr_failures_end_the_compound() {
+    use bytes::{Bytes, BytesMut};
+    use nfs_rs::error::Nfs4Status;
+    use nfs_rs::rpc::*;
+    use nfs_rs::server::{Dispatcher, Transport};
+
+    let d = Dispatcher::new(nfs_rs::vfs::MemVfs::new());
+    // `numops` ops encoded in `ops`
+    let run = |numops: u32, ops: BytesMut| {
+        let d = d.clone();
+        async move {
+            let hdr = RpcCallHeader { xid: 6, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc: Nfs4Proc::Compound as u32 };
+            let mut msg = BytesMut::from(&serialize_to_vec(&hdr).unwrap()[..]);
+            b"".as_slice().xdr_encode(&mut msg);
+            2u32.xdr_encode(&mut msg);
+            numops.xdr_encode(&mut msg);
+            msg.extend_from_slice(&ops);
+            let reply = d.dispatch(msg.freeze(), "127.0.0.1:900".parse().unwrap(), Transport::Tcp).await.unwrap();
+            let mut r = Bytes::from(reply.into_segments().concat());
+            RpcReplyHeader::xdr_decode(&mut r).unwrap();
+            let status = u32::xdr_decode(&mut r).unwrap();
+            let _tag = Vec::<u8>::xdr_decode(&mut r).unwrap();
+            let results: Vec<(u32, u32)> =
+                (0..u32::xdr_decode(&mut r).unwrap()).map(|_| (u32::xdr_decode(&mut r).unwrap(), u32::xdr_decode(&mut r).unwrap())).collect();
+            assert!(r.is_empty());
+            (status, results)
+        }
+    };
+    let nofh = Nfs4Status::Nofilehandle as u32;
+    let (getfh, getattr, putrootfh) = (NfsOp4::OpGetfh as u32, NfsOp4::OpGetattr as u32, NfsOp4::OpPutrootfh as u32);
+
+    // with no current filehandle neither returns one, and PUTROOTFH never runs
+    let mut ops = BytesMut::new();
+    getfh.xdr_encode(&mut ops);
+    putrootfh.xdr_encode(&mut ops);
+    assert_eq!(run(2, ops).await, (nofh, vec![(getfh, nofh)]));
+    let mut ops = BytesMut::new();
+    getattr.xdr_encode(&mut ops);
+    bitmap4_with(&[FATTR4_TYPE]).xdr_encode(&mut ops);
+    putrootfh.xdr_encode(&mut ops);
+    assert_eq!(run(2, ops).await, (nofh, vec![(getattr, nofh)]));
+
+    // a bitmap claiming more words than the call holds
+    let badxdr = Nfs4Status::Badxdr as u32;
+    let mut ops = BytesMut::new();
+    putrootfh.xdr_encode(&mut ops);
+    getattr.xdr_encode(&mut ops);
+    5u32.xdr_encode(&mut ops);
+    1u32.xdr_encode(&mut ops);
+    assert_eq!(run(2, ops).await, (badxdr, vec![(putrootfh, NFS4_OK), (getattr, badxdr)]));
+}
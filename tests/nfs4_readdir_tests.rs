@@ -0,0 +1,232 @@
+use bytes::{Bytes, BytesMut};
+use nfs_rs::config::*;
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::rpc::*;
+use nfs_rs::server::{Dispatcher, Transport};
+use nfs_rs::vfs::{CreateKind, MemVfs, SetAttr, Vfs};
+use nfs_rs::xdr::*;
+use std::collections::HashSet;
+
+#[derive(Debug)]
+struct Entry {
+    cookie: u64,
+    name: String,
+    // type and fileid, or the rdattr_error
+    attrs: Result<(u32, u64), u32>,
+}
+
+#[derive(Debug)]
+struct Page {
+    verifier: u64,
+    entries: Vec<Entry>,
+    eof: bool,
+}
+
+// PUTFH, READDIR asking for the type and fileid (and rdattr_error)
+async fn readdir(d: &Dispatcher, dir: &[u8], cookie: u64, cookieverf: u64, dircount: u32, maxcount: u32) -> Result<Page, u32> {
+    let hdr = RpcCallHeader { xid: 6, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc: Nfs4Proc::Compound as u32 };
+    let mut msg = BytesMut::from(&serialize_to_vec(&hdr).unwrap()[..]);
+    b"".as_slice().xdr_encode(&mut msg);
+    2u32.xdr_encode(&mut msg);
+    2u32.xdr_encode(&mut msg);
+    (NfsOp4::OpPutfh as u32).xdr_encode(&mut msg);
+    dir.xdr_encode(&mut msg);
+    (NfsOp4::OpReaddir as u32).xdr_encode(&mut msg);
+    let attr_request = bitmap4_with(&[FATTR4_TYPE, FATTR4_RDATTR_ERROR, FATTR4_FILEID]);
+    Readdir4args { cookie, cookieverf, dircount, maxcount, attr_request }.xdr_encode(&mut msg);
+    let reply = d.dispatch(msg.freeze(), "127.0.0.1:700".parse().unwrap(), Transport::Tcp).await.unwrap();
+    let mut r = Bytes::from(reply.into_segments().concat());
+    RpcReplyHeader::xdr_decode(&mut r).unwrap();
+    let status = u32::xdr_decode(&mut r).unwrap();
+    Vec::<u8>::xdr_decode(&mut r).unwrap();
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), 2);
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NfsOp4::OpPutfh as u32);
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NFS4_OK);
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), NfsOp4::OpReaddir as u32);
+    if u32::xdr_decode(&mut r).unwrap() != NFS4_OK {
+        return Err(status);
+    }
+    let verifier = u64::xdr_decode(&mut r).unwrap();
+    let mut entries = Vec::new();
+    while bool::xdr_decode(&mut r).unwrap() {
+        let cookie = u64::xdr_decode(&mut r).unwrap();
+        let name = String::xdr_decode(&mut r).unwrap();
+        let bitmap = Vec::<u32>::xdr_decode(&mut r).unwrap();
+        let mut vals = Bytes::from(Vec::<u8>::xdr_decode(&mut r).unwrap());
+        let attrs = if bitmap4_has(&bitmap, FATTR4_TYPE) {
+            let ftype = u32::xdr_decode(&mut vals).unwrap();
+            assert_eq!(u32::xdr_decode(&mut vals).unwrap(), NFS4_OK);
+            Ok((ftype, u64::xdr_decode(&mut vals).unwrap()))
+        } else {
+            Err(u32::xdr_decode(&mut vals).unwrap())
+        };
+        entries.push(Entry { cookie, name, attrs });
+    }
+    let eof = bool::xdr_decode(&mut r).unwrap();
+    assert!(r.is_empty());
+    Ok(Page { verifier, entries, eof })
+}
+
+// Page through a whole directory, following cookies
+async fn list_all(d: &Dispatcher, dir: &[u8], maxcount: u32) -> (Vec<String>, usize) {
+    let (mut cookie, mut verifier, mut names, mut calls) = (0, 0, Vec::new(), 0);
+    loop {
+        let page = readdir(d, dir, cookie, verifier, 0, maxcount).await.unwrap();
+        calls += 1;
+        verifier = page.verifier;
+        if let Some(last) = page.entries.last() {
+            cookie = last.cookie;
+        }
+        names.extend(page.entries.into_iter().map(|e| e.name));
+        if page.eof {
+            return (names, calls);
+        }
+    }
+}
+
+async fn mkfiles(d: &Dispatcher, n: usize) -> Vec<u8> {
+    let exports = d.exports();
+    let root = exports.root_fh().await.unwrap();
+    for i in 0..n {
+        exports.create(&root, &format!("file{:06}", i), CreateKind::Regular, &SetAttr::default()).await.unwrap();
+    }
+    root
+}
+
+#[tokio::test]
+async fn large_directories_page_through_every_entry() {
+    let d = Dispatcher::new(MemVfs::new());
+    let root = mkfiles(&d, 20_000).await;
+    let (names, calls) = list_all(&d, &root, 32 * 1024).await;
+    assert_eq!(names.len(), 20_000);
+    assert_eq!(names.iter().collect::<HashSet<_>>().len(), 20_000);
+    // each entry takes 56 bytes here, so a page holds hundreds
+    assert!(calls < 40, "{} calls", calls);
+
+    let page = readdir(&d, &root, 0, 0, 0, 4096).await.unwrap();
+    assert!(!page.eof);
+    assert_eq!(page.entries[0].name, "file000000");
+    assert!(matches!(page.entries[0].attrs, Ok((NF4REG, _))));
+    // dircount limits the cookies and names alone
+    let page = readdir(&d, &root, 0, 0, 3 * 24, 4096).await.unwrap();
+    assert_eq!(page.entries.len(), 3);
+}
+
+#[tokio::test]
+async fn cookies_survive_changes_where_the_backend_keeps_them() {
+    let d = Dispatcher::new(MemVfs::new());
+    let root = mkfiles(&d, 10).await;
+    let first = readdir(&d, &root, 0, 0, 0, 200).await.unwrap();
+    let last = first.entries.last().unwrap();
+    d.exports().remove(&root, &last.name).await.unwrap();
+    let rest = readdir(&d, &root, last.cookie, first.verifier, 0, 4096).await.unwrap();
+    assert_eq!(first.entries.len() + rest.entries.len(), 10);
+    assert!(rest.eof);
+}
+
+#[tokio::test]
+async fn bad_cookies_and_small_budgets_are_refused() {
+    let d = Dispatcher::new(MemVfs::new());
+    let root = mkfiles(&d, 3).await;
+    let status = |cookie, maxcount| readdir(&d, &root, cookie, 0, 0, maxcount);
+    assert_eq!(status(1, 4096).await.unwrap_err(), Nfs4Status::BadCookie as u32);
+    assert_eq!(status(2, 4096).await.unwrap_err(), Nfs4Status::BadCookie as u32);
+    assert_eq!(status(1000, 4096).await.unwrap_err(), Nfs4Status::BadCookie as u32);
+    assert_eq!(status(0, 40).await.unwrap_err(), Nfs4Status::Toosmall as u32);
+    let file = d.exports().lookup(&root, "file000000").await.unwrap();
+    assert_eq!(readdir(&d, &file, 0, 0, 0, 4096).await.unwrap_err(), Nfs4Status::Notdir as u32);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn disk_listings_change_their_verifier() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..500 {
+        std::fs::write(dir.path().join(format!("f{:03}", i)), b"").unwrap();
+    }
+    let export = ExportConfig {
+        backend: BackendConfig::Local { root: dir.path().to_string_lossy().into_owned() },
+        ..Default::default()
+    };
+    let d = Dispatcher::from_config(&NfsConfig { exports: vec![export], ..Default::default() }).unwrap();
+    let root = d.exports().root_fh().await.unwrap();
+    let (names, _) = list_all(&d, &root, 2048).await;
+    assert_eq!(names.len(), 500);
+    assert!(names.windows(2).all(|w| w[0] < w[1]));
+
+    let first = readdir(&d, &root, 0, 0, 0, 1024).await.unwrap();
+    let cookie = first.entries.last().unwrap().cookie;
+    assert!(readdir(&d, &root, cookie, first.verifier, 0, 1024).await.is_ok());
+    // a new name shifts the positions cookies stand for
+    std::fs::write(dir.path().join("e000"), b"").unwrap();
+    let err = readdir(&d, &root, cookie, first.verifier, 0, 1024).await.unwrap_err();
+    assert_eq!(err, Nfs4Status::NotSame as u32);
+    let again = readdir(&d, &root, 0, 0, 0, 1024).await.unwrap();
+    assert_ne!(again.verifier, first.verifier);
+    assert_eq!(again.entries[0].name, "e000");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn disk_paging_survives_many_other_listings() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("big")).unwrap();
+    for i in 0..300 {
+        std::fs::write(dir.path().join("big").join(format!("f{:03}", i)), b"").unwrap();
+    }
+    // more directories than the backend keeps listings for
+    for i in 0..40 {
+        std::fs::create_dir(dir.path().join(format!("d{:02}", i))).unwrap();
+        std::fs::write(dir.path().join(format!("d{:02}", i)).join("x"), b"").unwrap();
+    }
+    let export = ExportConfig {
+        backend: BackendConfig::Local { root: dir.path().to_string_lossy().into_owned() },
+        ..Default::default()
+    };
+    let d = Dispatcher::from_config(&NfsConfig { exports: vec![export], ..Default::default() }).unwrap();
+    let root = d.exports().root_fh().await.unwrap();
+    let big = d.exports().lookup(&root, "big").await.unwrap();
+    let mut others = Vec::new();
+    for i in 0..40 {
+        others.push(d.exports().lookup(&root, &format!("d{:02}", i)).await.unwrap());
+    }
+
+    let (mut cookie, mut verifier, mut names) = (0, 0, Vec::new());
+    loop {
+        let page = readdir(&d, &big, cookie, verifier, 0, 1024).await.unwrap();
+        assert!(verifier == 0 || page.verifier == verifier);
+        verifier = page.verifier;
+        cookie = page.entries.last().unwrap().cookie;
+        names.extend(page.entries.into_iter().map(|e| e.name));
+        if page.eof {
+            break;
+        }
+        // list every other directory between pages
+        for other in &others {
+            assert_eq!(readdir(&d, other, 0, 0, 0, 1024).await.unwrap().entries.len(), 1);
+        }
+    }
+    assert_eq!(names, (0..300).map(|i| format!("f{:03}", i)).collect::<Vec<_>>());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unreadable_entries_carry_rdattr_error() {
+    let gone = tempfile::tempdir().unwrap();
+    let exports = vec![
+        ExportConfig { path: "/a".into(), ..Default::default() },
+        ExportConfig {
+            path: "/b".into(),
+            backend: BackendConfig::Local { root: gone.path().to_string_lossy().into_owned() },
+            ..Default::default()
+        },
+    ];
+    let d = Dispatcher::from_config(&NfsConfig { exports, ..Default::default() }).unwrap();
+    let root = d.exports().root_fh().await.unwrap();
+    // the export's directory disappears from under it
+    std::fs::remove_dir(gone.path()).unwrap();
+    let page = readdir(&d, &root, 0, 0, 0, 4096).await.unwrap();
+    let got: Vec<_> = page.entries.iter().map(|e| (e.name.as_str(), e.attrs.map(|a| a.0))).collect();
+    assert_eq!(got, [("a", Ok(NF4DIR)), ("b", Err(Nfs4Status::Stale as u32))]);
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use common::*;
+use nfs_rs::auth::Credential;
+use nfs_rs::config::*;
+use nfs_rs::error::{Nfs3Status, Nfs4Status};
+use nfs_rs::proto::nfs3::{Nfs3Proc, ACCESS3_READ, DONT_CHANGE, NFS3_VERSION};
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::{CreateKind, MemVfs, SetAttr, Vfs};
+use nfs_rs::xdr::*;
+
+// CREATE of a directory
+fn mkdir(name: &str) -> Op {
+    let args = Create4args {
+        objtype: NF4DIR,
+        linkdata: Bytes::new(),
+        devdata: (0, 0),
+        objname: name.to_string().into(),
+        createattrs: Fattr4::default(),
+    };
+    Op::new(NfsOp4::OpCreate).arg(&args)
+}
+
+fn remove(name: &str) -> Op {
+    Op::new(NfsOp4::OpRemove).arg(name.as_bytes())
+}
+
+fn read() -> Op {
+    Op::new(NfsOp4::OpRead).arg(&Read4args { stateid: Stateid4::ANONYMOUS, offset: 0, count: 16 })
+}
+
+fn write(data: &'static [u8]) -> Op {
+    Op::new(NfsOp4::OpWrite).arg(&Write4args { stateid: Stateid4::ANONYMOUS, offset: 0, stable: FILE_SYNC4, data: Bytes::from_static(data) })
+}
+
+// An object under the root made by root, with the given owner and mode
+async fn make(d: &Dispatcher, name: &str, kind: CreateKind, uid: u32, gid: u32, mode: u32) -> Vec<u8> {
+    let exports = d.exports();
+    let root = exports.root_fh().await.unwrap();
+    let set = SetAttr { mode: Some(mode), uid: Some(uid), gid: Some(gid), ..Default::default() };
+    exports.create(&root, name, kind, &set).await.unwrap()
+}
+
+const ALL: u32 = ACCESS4_READ | ACCESS4_LOOKUP | ACCESS4_MODIFY | ACCESS4_EXTEND | ACCESS4_DELETE | ACCESS4_EXECUTE;
+const FILE_WRITE: u32 = ACCESS4_MODIFY | ACCESS4_EXTEND;
+
+#[tokio::test]
+async fn access_follows_the_mode_bits() {
+    let d = Dispatcher::new(MemVfs::new());
+    let f = make(&d, "f", CreateKind::Regular, 1000, 100, 0o640).await;
+    let x = make(&d, "x", CreateKind::Regular, 1000, 100, 0o750).await;
+    let dir = make(&d, "d", CreateKind::Directory, 1000, 100, 0o755).await;
+    let file_bits = ACCESS4_READ | FILE_WRITE | ACCESS4_EXECUTE;
+    let dir_bits = ACCESS4_READ | ACCESS4_LOOKUP | FILE_WRITE | ACCESS4_DELETE;
+    let cases = [
+        // owner, group member, anyone else
+        (&f, sys(1000, 100), file_bits, ACCESS4_READ | FILE_WRITE),
+        (&f, sys(2000, 100), file_bits, ACCESS4_READ),
+        (&f, sys(3000, 300), file_bits, 0),
+        // root may not run what nobody may
+        (&f, sys(0, 0), file_bits, ACCESS4_READ | FILE_WRITE),
+        (&x, sys(0, 0), file_bits, file_bits),
+        (&x, sys(2000, 100), file_bits, ACCESS4_READ | ACCESS4_EXECUTE),
+        (&dir, sys(1000, 100), dir_bits, dir_bits),
+        (&dir, sys(3000, 300), dir_bits, ACCESS4_READ | ACCESS4_LOOKUP),
+    ];
+    for (fh, cred, supported, allowed) in cases {
+        let (res, status) = compound(&d, cred, 2, vec![putfh(fh), access(ALL)]).await;
+        assert_eq!((res, status), (vec![Res::Ok, Res::Access(supported, allowed)], NFS4_OK));
+    }
+    // only what was asked about is answered
+    let (res, _) = compound(&d, sys(1000, 100), 2, vec![putfh(&f), access(ACCESS4_READ)]).await;
+    assert_eq!(res[1], Res::Access(ACCESS4_READ, ACCESS4_READ));
+}
+
+#[tokio::test]
+async fn read_only_exports_grant_no_writes() {
+    let export = ExportConfig {
+        clients: vec![ClientRule {
+            host: "*".into(),
+            options: ExportOptions { access: AccessMode::Ro, sec: vec![SecFlavor::Sys], ..Default::default() },
+        }],
+        ..Default::default()
+    };
+    let d = Dispatcher::from_config(&NfsConfig { exports: vec![export], ..Default::default() }).unwrap();
+    let f = make(&d, "f", CreateKind::Regular, 1000, 100, 0o644).await;
+    let (res, _) = compound(&d, sys(1000, 100), 2, vec![putfh(&f), access(ALL)]).await;
+    assert_eq!(res[1], Res::Access(ACCESS4_READ | FILE_WRITE | ACCESS4_EXECUTE, ACCESS4_READ));
+}
+
+#[tokio::test]
+async fn changes_need_write_permission_on_the_directory() {
+    let d = Dispatcher::new(MemVfs::new());
+    let dir = make(&d, "d", CreateKind::Directory, 1000, 100, 0o755).await;
+    d.exports().create(&dir, "f", CreateKind::Regular, &SetAttr::default()).await.unwrap();
+    let denied = Nfs4Status::Access as u32;
+    assert_eq!(compound(&d, sys(2000, 100), 2, vec![putfh(&dir), mkdir("sub")]).await.1, denied);
+    assert_eq!(compound(&d, sys(2000, 100), 2, vec![putfh(&dir), remove("f")]).await.1, denied);
+    assert!(d.exports().lookup(&dir, "f").await.is_ok());
+    assert_eq!(compound(&d, sys(1000, 100), 2, vec![putfh(&dir), mkdir("sub")]).await.1, NFS4_OK);
+    assert_eq!(compound(&d, sys(1000, 100), 2, vec![putfh(&dir), remove("f")]).await.1, NFS4_OK);
+    // a new object belongs to whoever made it
+    let sub = d.exports().lookup(&dir, "sub").await.unwrap();
+    assert_eq!(d.exports().getattr(&sub).await.unwrap().uid, 1000);
+}
+
+#[tokio::test]
+async fn sticky_directories_keep_others_entries() {
+    let d = Dispatcher::new(MemVfs::new());
+    let tmp = make(&d, "tmp", CreateKind::Directory, 0, 0, 0o1777).await;
+    let mine = SetAttr { uid: Some(1000), gid: Some(100), ..Default::default() };
+    d.exports().create(&tmp, "f", CreateKind::Regular, &mine).await.unwrap();
+    let (_, status) = compound(&d, sys(2000, 100), 2, vec![putfh(&tmp), remove("f")]).await;
+    assert_eq!(status, Nfs4Status::Access as u32);
+    let (_, status) = compound(&d, sys(1000, 100), 2, vec![putfh(&tmp), remove("f")]).await;
+    assert_eq!(status, NFS4_OK);
+}
+
+#[tokio::test]
+async fn data_needs_read_or_write_permission() {
+    let d = Dispatcher::new(MemVfs::new());
+    let f = make(&d, "f", CreateKind::Regular, 1000, 100, 0o604).await;
+    let denied = Nfs4Status::Access as u32;
+    assert_eq!(compound(&d, sys(2000, 100), 2, vec![putfh(&f), read()]).await.1, denied);
+    assert_eq!(compound(&d, sys(3000, 300), 2, vec![putfh(&f), read()]).await.1, NFS4_OK);
+    assert_eq!(compound(&d, sys(3000, 300), 2, vec![putfh(&f), write(b"x")]).await.1, denied);
+    // the owner may write a file it opened before making it read-only
+    let ro = SetAttr { mode: Some(0o400), ..Default::default() };
+    d.exports().setattr(&f, &ro).await.unwrap();
+    assert_eq!(compound(&d, sys(1000, 100), 2, vec![putfh(&f), write(b"x")]).await.1, NFS4_OK);
+}
+
+// SETATTR3 of the mode only
+async fn chmod3(d: &Dispatcher, cred: Credential, fh: &[u8], mode: u32) -> u32 {
+    let mut args = BytesMut::new();
+    fh.xdr_encode(&mut args);
+    true.xdr_encode(&mut args);
+    mode.xdr_encode(&mut args);
+    for _ in 0..3 {
+        false.xdr_encode(&mut args);
+    }
+    DONT_CHANGE.xdr_encode(&mut args);
+    DONT_CHANGE.xdr_encode(&mut args);
+    false.xdr_encode(&mut args);
+    u32::xdr_decode(&mut call(d, cred, NFS3_VERSION, Nfs3Proc::Setattr as u32, args).await).unwrap()
+}
+
+#[tokio::test]
+async fn nfs3_checks_the_same_permissions() {
+    let d = Dispatcher::new(MemVfs::new());
+    let f = make(&d, "f", CreateKind::Regular, 1000, 100, 0o600).await;
+    assert_eq!(chmod3(&d, sys(2000, 100), &f, 0o666).await, Nfs3Status::Acces as u32);
+    assert_eq!(chmod3(&d, sys(1000, 100), &f, 0o640).await, 0);
+    assert_eq!(d.exports().getattr(&f).await.unwrap().mode & 0o777, 0o640);
+
+    // ACCESS3 shares its bits with ACCESS4
+    let mut args = BytesMut::new();
+    f.xdr_encode(&mut args);
+    ACCESS3_READ.xdr_encode(&mut args);
+    let mut r = call(&d, sys(2000, 100), NFS3_VERSION, Nfs3Proc::Access as u32, args).await;
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), 0);
+    assert!(bool::xdr_decode(&mut r).unwrap());
+    let _ = r.split_to(84);
+    assert_eq!(u32::xdr_decode(&mut r).unwrap(), ACCESS3_READ);
+}
@@ -0,0 +1,103 @@
+use bytes::{Bytes, BytesMut};
+use nfs_rs::config::{NfsConfig, RpcbindMode};
+use nfs_rs::portmap::{register_with_rpcbind, served_services};
+use nfs_rs::proto::nfs4::NFS4_PROGRAM;
+use nfs_rs::proto::portmap::*;
+use nfs_rs::rpc::*;
+use nfs_rs::server::{Dispatcher, Transport};
+use nfs_rs::xdr::*;
+
+const PEER: &str = "127.0.0.1:700";
+
+fn serving_dispatcher() -> std::sync::Arc<Dispatcher> {
+    let mut cfg = NfsConfig { bind_addr: "127.0.0.1".into(), ..Default::default() };
+    cfg.rpcbind.mode = RpcbindMode::Serve;
+    Dispatcher::from_config(&cfg).unwrap()
+}
+
+fn encode<T: XdrEncode>(v: &T) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    v.xdr_encode(&mut buf);
+    buf.to_vec()
+}
+
+async fn pmap_call(d: &Dispatcher, vers: u32, proc: u32, args: &[u8]) -> Bytes {
+    let hdr = RpcCallHeader { xid: 1, msg_type: RpcMessageType::Call, rpcvers: 2, prog: PMAP_PROGRAM, vers, proc };
+    let mut msg = BytesMut::from(&serialize_to_vec(&hdr).unwrap()[..]);
+    msg.extend_from_slice(args);
+    let reply = d.dispatch(msg.freeze(), PEER.parse().unwrap(), Transport::Udp).await.unwrap();
+    let mut out = Bytes::from(reply.into_segments().concat());
+    let rh = RpcReplyHeader::xdr_decode(&mut out).unwrap();
+    assert_eq!(rh.accept_state, ACCEPT_SUCCESS);
+    out
+}
+
+#[tokio::test]
+async fn test_pmap_getport_and_dump() {
+    let d = serving_dispatcher();
+    let want = Mapping { prog: NFS4_PROGRAM, vers: 4, prot: IPPROTO_TCP, port: 0 };
+    let mut body = pmap_call(&d, PMAP_VERSION, PMAPPROC_GETPORT, &encode(&want)).await;
+    assert_eq!(u32::xdr_decode(&mut body).unwrap(), 2049);
+
+    let mut body = pmap_call(&d, PMAP_VERSION, PMAPPROC_DUMP, &[]).await;
+    let mut maps = Vec::new();
+    while bool::xdr_decode(&mut body).unwrap() {
+        maps.push(Mapping::xdr_decode(&mut body).unwrap());
+    }
+    assert!(maps.contains(&Mapping { prog: NFS4_PROGRAM, vers: 4, prot: IPPROTO_TCP, port: 2049 }));
+    assert!(maps.contains(&Mapping { prog: PMAP_PROGRAM, vers: 2, prot: IPPROTO_UDP, port: 111 }));
+}
+
+#[tokio::test]
+async fn test_rpcbind_getaddr() {
+    let d = serving_dispatcher();
+    let want = Rpcb { prog: NFS4_PROGRAM, vers: 4, netid: "tcp".into(), ..Default::default() };
+    let mut body = pmap_call(&d, RPCBIND_VERSION4, RPCBPROC_GETADDR, &encode(&want)).await;
+    let uaddr = String::from_utf8(Vec::<u8>::xdr_decode(&mut body).unwrap()).unwrap();
+    assert_eq!(uaddr, "127.0.0.1.8.1");
+    assert_eq!(from_uaddr(&uaddr).unwrap().port(), 2049);
+}
+
+#[tokio::test]
+async fn test_portmap_disabled_is_prog_unavail() {
+    let d = Dispatcher::new(nfs_rs::vfs::MemVfs::new());
+    let hdr = RpcCallHeader { xid: 3, msg_type: RpcMessageType::Call, rpcvers: 2, prog: PMAP_PROGRAM, vers: 2, proc: 0 };
+    let reply = d.dispatch(Bytes::from(serialize_to_vec(&hdr).unwrap()), PEER.parse().unwrap(), Transport::Tcp).await.unwrap();
+    let rh = RpcReplyHeader::xdr_decode(&mut Bytes::from(reply.into_segments().concat())).unwrap();
+    assert_eq!(rh.accept_state, PROG_UNAVAIL);
+}
+
+#[tokio::test]
+async fn test_register_with_rpcbind_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("rpcbind.sock");
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    let cfg = NfsConfig { bind_addr: "127.0.0.1".into(), ..Default::default() };
+    let services = served_services(&cfg).unwrap();
+    let expected = services.len() * 2;
+
+    // Fake rpcbind: accept every UNSET/SET and record the SETs
+    let fake = tokio::spawn(async move {
+        let (mut sock, _) = listener.accept().await.unwrap();
+        let mut rbuf = BytesMut::new();
+        let mut sets = Vec::new();
+        for _ in 0..expected {
+            let mut msg = read_record(&mut sock, &mut rbuf).await.unwrap();
+            let call = RpcCallHeader::xdr_decode(&mut msg).unwrap();
+            assert_eq!((call.prog, call.vers), (PMAP_PROGRAM, RPCBIND_VERSION4));
+            let rpcb = Rpcb::xdr_decode(&mut msg).unwrap();
+            if call.proc == RPCBPROC_SET {
+                sets.push(rpcb);
+            }
+            let mut reply = XdrChain::new();
+            reply.put(&RpcReplyHeader::success(call.xid));
+            reply.put(&true);
+            write_record_vectored(&mut sock, &reply.into_segments()).await.unwrap();
+        }
+        sets
+    });
+
+    register_with_rpcbind(&path, &services).await.unwrap();
+    let sets = fake.await.unwrap();
+    assert_eq!(sets, services);
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::*;
+use nfs_rs::auth::Credential;
+use nfs_rs::config::*;
+use nfs_rs::export::PSEUDO_FSID;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::Vfs;
+use nfs_rs::xdr::*;
+use std::sync::Arc;
+
+fn dispatcher(paths: &[&str]) -> Arc<Dispatcher> {
+    let exports = paths.iter().map(|p| ExportConfig { path: p.to_string(), ..Default::default() }).collect();
+    Dispatcher::from_config(&NfsConfig { exports, ..Default::default() }).unwrap()
+}
+
+// GETATTR of what the tests look at
+fn attrs() -> Op {
+    getattr(&[FATTR4_TYPE, FATTR4_FSID, FATTR4_FILEID, FATTR4_MOUNTED_ON_FILEID])
+}
+
+// The type, fsid major, fileid and mounted_on_fileid that attrs() got
+fn attr(res: &Res) -> (u32, u64, u64, u64) {
+    let Res::Getattr(_, vals) = res else { panic!("expected attributes, got {:?}", res) };
+    let mut vals = vals.clone();
+    let ftype = u32::xdr_decode(&mut vals).unwrap();
+    let major = u64::xdr_decode(&mut vals).unwrap();
+    let _minor = u64::xdr_decode(&mut vals).unwrap();
+    let fileid = u64::xdr_decode(&mut vals).unwrap();
+    (ftype, major, fileid, u64::xdr_decode(&mut vals).unwrap())
+}
+
+#[tokio::test]
+async fn pseudo_root_joins_exports() {
+    let d = dispatcher(&["/builds", "/artifacts", "/scratch/ci"]);
+
+    let (res, status) = compound(&d, Credential::None, 2, vec![putrootfh(), getfh(), attrs(), putpubfh(), getfh()]).await;
+    assert_eq!(status, NFS4_OK);
+    let Res::Getfh(root) = &res[1] else { panic!() };
+    assert_eq!(res[4], Res::Getfh(root.clone()));
+    let (ftype, fsid, root_id, _) = attr(&res[2]);
+    assert_eq!((ftype, fsid), (NF4DIR, PSEUDO_FSID));
+
+    // crossing into an export changes fsid; its root is mounted on a
+    // different fileid than its own
+    let (res, _) = compound(&d, Credential::None, 2, vec![putrootfh(), lookup(b"builds"), attrs(), lookupp(), getfh()]).await;
+    let (_, builds_fsid, fileid, mounted_on) = attr(&res[2]);
+    assert_ne!(builds_fsid, PSEUDO_FSID);
+    assert_ne!(fileid, mounted_on);
+    assert_eq!(res[4], Res::Getfh(root.clone()));
+
+    let (res, _) = compound(&d, Credential::None, 2, vec![putrootfh(), lookup(b"artifacts"), attrs()]).await;
+    let (_, artifacts_fsid, _, _) = attr(&res[2]);
+    assert_ne!(artifacts_fsid, builds_fsid);
+
+    // an intermediate pseudo directory, then back up through it
+    let (res, _) = compound(&d, Credential::None, 2, vec![putrootfh(), lookup(b"scratch"), attrs(), lookup(b"ci"), lookupp(), lookupp(), attrs()]).await;
+    let (_, fsid, scratch_id, _) = attr(&res[2]);
+    assert_eq!(fsid, PSEUDO_FSID);
+    assert_ne!(scratch_id, root_id);
+    assert_eq!(attr(&res[6]).2, root_id);
+
+    let (res, status) = compound(&d, Credential::None, 2, vec![putrootfh(), lookup(b"missing"), getfh()]).await;
+    assert_eq!((res.len(), status), (1, 2));
+
+    let names: Vec<String> = d.exports().readdir(root, 0, 10).await.unwrap().entries.into_iter().map(|e| e.name).collect();
+    assert_eq!(names, ["artifacts", "builds", "scratch"]);
+}
+
+#[tokio::test]
+async fn nested_export_is_crossed() {
+    let d = dispatcher(&["/", "/srv"]);
+    d.exports().create_dir("/srv").await.unwrap();
+    d.exports().create_dir("/etc").await.unwrap();
+
+    let (res, _) = compound(&d, Credential::None, 2, vec![putrootfh(), attrs(), lookup(b"etc"), attrs(), lookupp(), lookup(b"srv"), attrs(), lookupp(), attrs()]).await;
+    let (_, root_fsid, root_id, _) = attr(&res[1]);
+    assert_eq!(attr(&res[3]).1, root_fsid);
+    let (_, srv_fsid, srv_id, mounted_on) = attr(&res[6]);
+    assert_ne!(srv_fsid, root_fsid);
+    // lookups through the Vfs interface cross as well
+    let covered = d.exports().lookup(&d.exports().root_fh().await.unwrap(), "srv").await.unwrap();
+    assert_eq!(d.exports().getattr(&covered).await.unwrap().fsid, srv_fsid);
+    assert_ne!(mounted_on, srv_id);
+    assert_eq!(attr(&res[8]).2, root_id);
+}
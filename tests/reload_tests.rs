@@ -0,0 +1,64 @@
+use nfs_rs::auth::{AuthSys, Caller, Credential};
+use nfs_rs::config::*;
+use nfs_rs::export::ExportTable;
+use nfs_rs::vfs::Vfs;
+use nfs_rs::{NfsError, NfsServer};
+
+fn caller(addr: &str) -> Caller {
+    let sys = AuthSys { stamp: 0, machinename: "c".into(), uid: 1000, gid: 1000, gids: vec![] };
+    Caller { addr: addr.parse().unwrap(), cred: Credential::Sys(sys) }
+}
+
+fn export(path: &str, host: &str) -> ExportConfig {
+    let options = ExportOptions { access: AccessMode::Rw, ..Default::default() };
+    ExportConfig { path: path.into(), clients: vec![ClientRule { host: host.into(), options }], ..Default::default() }
+}
+
+#[tokio::test]
+async fn reload_keeps_backends_and_applies_rules() {
+    let table = ExportTable::from_config(&[export("/builds", "10.0.0.0/8")]).unwrap();
+    let root = table.exports()[0].root_fh().await.unwrap();
+    let dir = table.lookup(&table.root_fh().await.unwrap(), "builds").await.unwrap();
+    assert_eq!(dir, root);
+    let fh = table.create(&root, "log", nfs_rs::vfs::CreateKind::Regular, &Default::default()).await.unwrap();
+    table.authorize_fh(&fh, &caller("10.1.1.1:1")).await.unwrap();
+
+    // new rules apply at once; the file and its handle are untouched
+    table.reload(&[export("/builds", "192.0.2.0/24"), export("/scratch", "*")]).unwrap();
+    assert!(matches!(table.authorize_fh(&fh, &caller("10.1.1.1:1")).await, Err(NfsError::PermissionDenied)));
+    table.authorize_fh(&fh, &caller("192.0.2.9:1")).await.unwrap();
+    assert!(table.getattr(&fh).await.is_ok());
+    let names: Vec<_> = table.readdir(&table.root_fh().await.unwrap(), 0, 10).await.unwrap().entries.into_iter().map(|e| e.name).collect();
+    assert_eq!(names, ["builds", "scratch"]);
+
+    // an invalid configuration changes nothing
+    assert!(table.reload(&[export("/builds", "10.0.0.0/99")]).is_err());
+    table.authorize_fh(&fh, &caller("192.0.2.9:1")).await.unwrap();
+
+    // a different fsid is a different filesystem: old handles go stale
+    let moved = ExportConfig { fsid: Some(99), ..export("/builds", "*") };
+    table.reload(&[moved]).unwrap();
+    assert!(matches!(table.getattr(&fh).await, Err(NfsError::StaleHandle)));
+}
+
+#[tokio::test]
+async fn config_handle_reloads_from_source() {
+    let cfg = NfsConfig { bind_addr: "127.0.0.1".into(), ..Default::default() };
+    let server = NfsServer::new(cfg).await.unwrap();
+    assert!(matches!(server.config().reload_from_source(), Err(NfsError::Config(_))));
+
+    let server = server.with_config_source(|| {
+        let mut cfg = NfsConfig { bind_addr: "127.0.0.1".into(), port: 1, ..Default::default() };
+        cfg.exports = vec![export("/a", "*")];
+        Ok(cfg)
+    });
+    server.config().reload_from_source().unwrap();
+    let current = server.config().current();
+    assert_eq!(current.exports[0].path, "/a");
+    // the port cannot change without a restart
+    assert_eq!(current.port, 2049);
+
+    let bad = NfsConfig { log: Some("nfs_rs=loud".into()), ..Default::default() };
+    assert!(server.config().reload(bad).is_err());
+    assert_eq!(server.config().current().exports[0].path, "/a");
+}
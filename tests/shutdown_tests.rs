@@ -0,0 +1,77 @@
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::recovery::RecoveryRecord;
+use nfs_rs::rpc::*;
+use nfs_rs::server::{serve_tcp, Dispatcher, ShutdownHandle};
+use nfs_rs::xdr::*;
+use nfs_rs::{NfsConfig, NfsServer};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn null_call(stream: &mut TcpStream, xid: u32) -> RpcReplyHeader {
+    let hdr = RpcCallHeader { xid, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc: Nfs4Proc::Null as u32 };
+    let mut framed = Vec::new();
+    write_record_marked(&mut framed, &serialize_to_vec(&hdr).unwrap()).unwrap();
+    stream.write_all(&framed).await.unwrap();
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await.unwrap();
+    let mut buf = vec![0u8; (u32::from_be_bytes(len) & 0x7fff_ffff) as usize];
+    stream.read_exact(&mut buf).await.unwrap();
+    deserialize_from_slice(&buf).unwrap()
+}
+
+#[tokio::test]
+async fn shutdown_closes_connections_and_returns() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = ShutdownHandle::new(Duration::from_secs(5));
+    let server = tokio::spawn(serve_tcp(listener, Dispatcher::new(nfs_rs::vfs::MemVfs::new()), shutdown.clone()));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    assert_eq!(null_call(&mut stream, 1).await.xid, 1);
+    assert!(!shutdown.is_shutdown());
+
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap().unwrap();
+    // the idle connection was closed and nothing accepts new ones
+    assert_eq!(stream.read(&mut [0u8; 4]).await.unwrap(), 0);
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn recovery_record_survives_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let cfg = NfsConfig { bind_addr: "127.0.0.1".into(), port: 0, state_dir: Some(dir.path().to_str().unwrap().into()), ..Default::default() };
+
+    let server = NfsServer::new(cfg.clone()).await.unwrap();
+    let shutdown = server.shutdown_handle();
+    let run = tokio::spawn(server.run());
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), run).await.unwrap().unwrap().unwrap();
+    let first = RecoveryRecord::load(dir.path()).unwrap().unwrap();
+    assert_eq!(first.nsm_state % 2, 1);
+
+    // pretend a client had mounted and locked before the restart
+    let saved = RecoveryRecord { monitored: vec!["client1".into()], mounts: vec![("10.0.0.7".into(), "/".into())], ..first.clone() };
+    saved.save(dir.path()).unwrap();
+    let server = NfsServer::new(cfg).await.unwrap();
+    let shutdown = server.shutdown_handle();
+    shutdown.shutdown();
+    server.run().await.unwrap();
+
+    let second = RecoveryRecord::load(dir.path()).unwrap().unwrap();
+    assert!(second.nsm_state > first.nsm_state);
+    assert_eq!(second.nsm_state % 2, 1);
+    assert_eq!((second.monitored, second.mounts), (saved.monitored, saved.mounts));
+}
+
+#[tokio::test]
+async fn dispatcher_restores_record() {
+    let d = Dispatcher::new(nfs_rs::vfs::MemVfs::new());
+    assert_eq!(RecoveryRecord::load(tempfile::tempdir().unwrap().path()).unwrap(), None);
+    d.restore(&RecoveryRecord { nsm_state: i32::MAX - 1, monitored: vec![], mounts: vec![("h".into(), "/".into())] });
+    let record = d.recovery_record();
+    // a state that would overflow is left alone rather than going backwards
+    assert!(record.nsm_state > 0);
+    assert_eq!(record.mounts, [("h".to_string(), "/".to_string())]);
+}
@@ -0,0 +1,185 @@
+mod common;
+
+use bytes::Bytes;
+use common::*;
+use nfs_rs::error::Nfs4Status;
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::server::Dispatcher;
+use nfs_rs::vfs::{MemVfs, Vfs};
+
+fn seek_op(offset: u64, what: u32) -> Op {
+    Op::new(NfsOp4::OpSeek).arg(&Seek4args { stateid: Stateid4::ANONYMOUS, offset, what })
+}
+
+fn allocate(offset: u64, length: u64) -> Op {
+    Op::new(NfsOp4::OpAllocate).arg(&Allocate4args { stateid: Stateid4::ANONYMOUS, offset, length })
+}
+
+fn deallocate(offset: u64, length: u64) -> Op {
+    Op::new(NfsOp4::OpDeallocate).arg(&Allocate4args { stateid: Stateid4::ANONYMOUS, offset, length })
+}
+
+fn read_plus_op(offset: u64, count: u32) -> Op {
+    Op::new(NfsOp4::OpReadPlus).arg(&Read4args { stateid: Stateid4::ANONYMOUS, offset, count })
+}
+
+async fn seek(d: &Dispatcher, fh: &[u8], offset: u64, what: u32) -> Result<Seek4resok, u32> {
+    match compound(d, sys(0, 0), 2, vec![putfh(fh), seek_op(offset, what)]).await {
+        (mut res, NFS4_OK) => match res.pop() {
+            Some(Res::Seek(found)) => Ok(found),
+            other => panic!("{:?}", other),
+        },
+        (_, status) => Err(status),
+    }
+}
+
+async fn read_plus(d: &Dispatcher, fh: &[u8], offset: u64, count: u32) -> ReadPlus4resok {
+    match compound(d, sys(0, 0), 2, vec![putfh(fh), read_plus_op(offset, count)]).await {
+        (mut res, NFS4_OK) => match res.pop() {
+            Some(Res::ReadPlus(read)) => read,
+            other => panic!("{:?}", other),
+        },
+        (_, status) => panic!("READ_PLUS failed with {}", status),
+    }
+}
+
+// ALLOCATE or DEALLOCATE; the status
+async fn change(d: &Dispatcher, uid: u32, fh: &[u8], op: Op) -> u32 {
+    compound(d, sys(uid, uid), 2, vec![putfh(fh), op]).await.1
+}
+
+// The bytes READ_PLUS returned from `offset`, holes filled in with zeros
+fn flatten(offset: u64, read: &ReadPlus4resok) -> Vec<u8> {
+    let mut out = Vec::new();
+    for content in &read.contents {
+        let (at, bytes) = match content {
+            ReadPlusContent4::Data { offset, data } => (*offset, data.to_vec()),
+            ReadPlusContent4::Hole { offset, length } => (*offset, vec![0; *length as usize]),
+        };
+        assert_eq!(at, offset + out.len() as u64, "{:?}", read.contents);
+        out.extend_from_slice(&bytes);
+    }
+    out
+}
+
+#[tokio::test]
+async fn memory_backend_seeks_and_reads_holes() {
+    let d = Dispatcher::new(MemVfs::new());
+    let fh = file(&d, "sparse", b"").await;
+    d.exports().write(&fh, 0, Bytes::from_static(b"head")).await.unwrap();
+    d.exports().write(&fh, 3 * 4096, Bytes::from_static(b"tail")).await.unwrap();
+    let size = 3 * 4096 + 4;
+
+    let found = |eof, offset| Ok(Seek4resok { eof, offset });
+    assert_eq!(seek(&d, &fh, 0, NFS4_CONTENT_DATA).await, found(false, 0));
+    assert_eq!(seek(&d, &fh, 0, NFS4_CONTENT_HOLE).await, found(false, 4096));
+    assert_eq!(seek(&d, &fh, 5000, NFS4_CONTENT_HOLE).await, found(false, 5000));
+    assert_eq!(seek(&d, &fh, 4096, NFS4_CONTENT_DATA).await, found(false, 3 * 4096));
+    // the end of the file is a hole
+    assert_eq!(seek(&d, &fh, 3 * 4096, NFS4_CONTENT_HOLE).await, found(true, size));
+    assert_eq!(seek(&d, &fh, size, NFS4_CONTENT_DATA).await, Err(Nfs4Status::Nxio as u32));
+    assert_eq!(seek(&d, &fh, 0, 7).await, Err(Nfs4Status::UnionNotsupp as u32));
+
+    let read = read_plus(&d, &fh, 0, 1 << 20).await;
+    assert!(read.eof);
+    let mut head = b"head".to_vec();
+    head.resize(4096, 0);
+    let expected = vec![
+        ReadPlusContent4::Data { offset: 0, data: head.into() },
+        ReadPlusContent4::Hole { offset: 4096, length: 2 * 4096 },
+        ReadPlusContent4::Data { offset: 3 * 4096, data: Bytes::from_static(b"tail") },
+    ];
+    assert_eq!(read.contents, expected);
+    assert_eq!(flatten(0, &read), contents(&d, &fh).await);
+    // a read within a hole
+    let read = read_plus(&d, &fh, 5000, 100).await;
+    assert_eq!((read.eof, read.contents), (false, vec![ReadPlusContent4::Hole { offset: 5000, length: 100 }]));
+    let read = read_plus(&d, &fh, size, 100).await;
+    assert_eq!((read.eof, read.contents), (true, vec![]));
+}
+
+#[tokio::test]
+async fn memory_backend_allocates_and_punches_holes() {
+    let d = Dispatcher::new(MemVfs::new());
+    let fh = file(&d, "f", b"").await;
+    let data = pattern(4 * 4096);
+    d.exports().write(&fh, 0, Bytes::from(data.clone())).await.unwrap();
+
+    // whole blocks go, partial ones are zeroed
+    assert_eq!(change(&d, 0, &fh, deallocate(100, 2 * 4096)).await, NFS4_OK);
+    let attr = d.exports().getattr(&fh).await.unwrap();
+    assert_eq!((attr.size, attr.used), (4 * 4096, 3 * 4096));
+    let mut expected = data.clone();
+    expected[100..100 + 2 * 4096].fill(0);
+    assert_eq!(contents(&d, &fh).await, expected);
+    assert_eq!(seek(&d, &fh, 0, NFS4_CONTENT_HOLE).await.unwrap().offset, 4096);
+    assert_eq!(seek(&d, &fh, 4096, NFS4_CONTENT_DATA).await.unwrap().offset, 2 * 4096);
+    // past the end nothing changes
+    assert_eq!(change(&d, 0, &fh, deallocate(8 * 4096, 4096)).await, NFS4_OK);
+    assert_eq!(d.exports().getattr(&fh).await.unwrap().size, 4 * 4096);
+
+    // allocating fills the hole and grows the file
+    assert_eq!(change(&d, 0, &fh, allocate(4096, 5 * 4096)).await, NFS4_OK);
+    let attr = d.exports().getattr(&fh).await.unwrap();
+    assert_eq!((attr.size, attr.used), (6 * 4096, 6 * 4096));
+    assert_eq!(seek(&d, &fh, 0, NFS4_CONTENT_HOLE).await.unwrap(), Seek4resok { eof: true, offset: 6 * 4096 });
+    expected.resize(6 * 4096, 0);
+    assert_eq!(contents(&d, &fh).await, expected);
+    assert_eq!(flatten(0, &read_plus(&d, &fh, 0, 1 << 20).await), expected);
+    // allocated blocks are written like any other
+    d.exports().write(&fh, 5 * 4096, Bytes::from_static(b"new")).await.unwrap();
+    expected[5 * 4096..5 * 4096 + 3].copy_from_slice(b"new");
+    assert_eq!(contents(&d, &fh).await, expected);
+}
+
+#[tokio::test]
+async fn allocation_checks_ranges_and_permissions() {
+    let d = Dispatcher::new(MemVfs::new());
+    let fh = file(&d, "f", b"").await;
+    let root = d.exports().root_fh().await.unwrap();
+    let inval = Nfs4Status::Inval as u32;
+
+    assert_eq!(change(&d, 0, &fh, allocate(0, 0)).await, inval);
+    assert_eq!(change(&d, 0, &fh, deallocate(u64::MAX, 2)).await, inval);
+    assert_eq!(change(&d, 0, &fh, allocate(u64::MAX / 2, 4096)).await, Nfs4Status::Fbig as u32);
+    assert_eq!(change(&d, 0, &root, allocate(0, 4096)).await, Nfs4Status::Isdir as u32);
+    assert_eq!(seek(&d, &root, 0, NFS4_CONTENT_DATA).await, Err(Nfs4Status::Isdir as u32));
+    // only the owner may write the 0644 file
+    assert_eq!(change(&d, 1000, &fh, allocate(0, 4096)).await, Nfs4Status::Access as u32);
+    assert_eq!(change(&d, 1000, &fh, deallocate(0, 4096)).await, Nfs4Status::Access as u32);
+    assert_eq!(d.exports().getattr(&fh).await.unwrap().size, 0);
+    let (_, status) = compound(&d, sys(0, 0), 2, vec![seek_op(0, NFS4_CONTENT_DATA)]).await;
+    assert_eq!(status, Nfs4Status::Nofilehandle as u32);
+}
+
+#[tokio::test]
+async fn disk_backend_punches_and_finds_holes() {
+    let (_dir, d) = disk();
+    let fh = file(&d, "image", b"").await;
+    let data = pattern(256 * 1024);
+    d.exports().write(&fh, 0, Bytes::from(data.clone())).await.unwrap();
+    assert_eq!(seek(&d, &fh, 0, NFS4_CONTENT_DATA).await.unwrap().offset, 0);
+    assert_eq!(seek(&d, &fh, 256 * 1024, NFS4_CONTENT_DATA).await, Err(Nfs4Status::Nxio as u32));
+
+    match change(&d, 0, &fh, deallocate(64 * 1024, 64 * 1024)).await {
+        NFS4_OK => {}
+        // a filesystem that cannot punch holes
+        status => return assert_eq!(status, Nfs4Status::Notsupp as u32),
+    }
+    let mut expected = data.clone();
+    expected[64 * 1024..128 * 1024].fill(0);
+    assert_eq!(contents(&d, &fh).await, expected);
+    assert_eq!(d.exports().getattr(&fh).await.unwrap().size, 256 * 1024);
+    // where the filesystem keeps the hole, SEEK finds it
+    let hole = seek(&d, &fh, 0, NFS4_CONTENT_HOLE).await.unwrap();
+    assert!(hole.offset == 64 * 1024 || hole == Seek4resok { eof: true, offset: 256 * 1024 }, "{:?}", hole);
+    let read = read_plus(&d, &fh, 0, 1 << 20).await;
+    assert!(read.eof);
+    assert_eq!(flatten(0, &read), expected);
+
+    assert_eq!(change(&d, 0, &fh, allocate(256 * 1024, 256 * 1024)).await, NFS4_OK);
+    let attr = d.exports().getattr(&fh).await.unwrap();
+    assert_eq!(attr.size, 512 * 1024);
+    expected.resize(512 * 1024, 0);
+    assert_eq!(flatten(0, &read_plus(&d, &fh, 0, 1 << 20).await), expected);
+}
@@ -0,0 +1,61 @@
+use bytes::BytesMut;
+use nfs_rs::auth::{AuthSys, Credential};
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::rpc::*;
+use nfs_rs::server::{Dispatcher, Transport};
+use nfs_rs::vfs::MemVfs;
+use nfs_rs::xdr::*;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tracing::Level;
+
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn compound_ops_are_spans_inside_the_rpc_span() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(Level::DEBUG)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let d = Dispatcher::new(MemVfs::new());
+    let header = RpcCallHeader { xid: 77, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc: Nfs4Proc::Compound as u32 };
+    let cred = Credential::Sys(AuthSys { stamp: 1, machinename: "ws1".into(), uid: 501, gid: 20, gids: vec![] });
+    let mut msg = BytesMut::new();
+    RpcCall { header, cred }.xdr_encode(&mut msg);
+    b"".as_slice().xdr_encode(&mut msg);
+    2u32.xdr_encode(&mut msg);
+    2u32.xdr_encode(&mut msg);
+    (NfsOp4::OpPutrootfh as u32).xdr_encode(&mut msg);
+    (NfsOp4::OpLookup as u32).xdr_encode(&mut msg);
+    "missing".to_string().xdr_encode(&mut msg);
+    d.dispatch(msg.freeze(), "127.0.0.1:900".parse().unwrap(), Transport::Tcp).await.unwrap();
+
+    let out = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = out.lines().filter(|l| l.contains("op done")).collect();
+    assert_eq!(lines.len(), 2, "{}", out);
+    for line in &lines {
+        assert!(line.contains("rpc{xid=77 peer=127.0.0.1:900 prog=100003 vers=4 proc=1 principal=501:20@ws1}:op{"), "{}", line);
+        assert!(line.contains("elapsed_us="), "{}", line);
+    }
+    assert!(lines[0].contains("op{op=PUTROOTFH status=0"), "{}", lines[0]);
+    // LOOKUP acts on the root's handle and fails with NOENT
+    assert!(lines[1].contains("op{op=LOOKUP fh="), "{}", lines[1]);
+    assert!(lines[1].contains("status=2 "), "{}", lines[1]);
+}
@@ -0,0 +1,65 @@
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::rpc::*;
+use nfs_rs::server::{serve_udp, Dispatcher, ShutdownHandle};
+use nfs_rs::xdr::*;
+use tokio::net::UdpSocket;
+
+fn call(xid: u32, proc: u32) -> Vec<u8> {
+    let hdr = RpcCallHeader { xid, msg_type: RpcMessageType::Call, rpcvers: 2, prog: NFS4_PROGRAM, vers: NFS4_VERSION, proc };
+    serialize_to_vec(&hdr).unwrap()
+}
+
+async fn start_udp_server(max_datagram: usize) -> (UdpSocket, tokio::task::JoinHandle<()>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let dispatcher = Dispatcher::new(nfs_rs::vfs::MemVfs::new());
+    let task = tokio::spawn(async move {
+        serve_udp(socket, dispatcher, max_datagram, ShutdownHandle::new(std::time::Duration::from_secs(1))).await.unwrap();
+    });
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(addr).await.unwrap();
+    (client, task)
+}
+
+async fn recv_reply(client: &UdpSocket) -> RpcReplyHeader {
+    let mut buf = vec![0u8; 65536];
+    let n = client.recv(&mut buf).await.unwrap();
+    deserialize_from_slice::<RpcReplyHeader>(&buf[..n]).unwrap()
+}
+
+#[tokio::test]
+async fn test_udp_null_succeeds() {
+    let (client, task) = start_udp_server(8192).await;
+    client.send(&call(7, Nfs4Proc::Null as u32)).await.unwrap();
+    let reply = recv_reply(&client).await;
+    assert_eq!(reply.xid, 7);
+    assert_eq!(reply.accept_state, ACCEPT_SUCCESS);
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_udp_compound_rejected() {
+    let (client, task) = start_udp_server(8192).await;
+    let mut msg = call(8, Nfs4Proc::Compound as u32);
+    msg.extend(serialize_to_vec(&XdrString::from("t")).unwrap());
+    msg.extend(serialize_to_vec(&2u32).unwrap());
+    msg.extend(serialize_to_vec(&0u32).unwrap());
+    client.send(&msg).await.unwrap();
+    let reply = recv_reply(&client).await;
+    assert_eq!(reply.xid, 8);
+    assert_eq!(reply.accept_state, PROC_UNAVAIL);
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_udp_oversized_datagram_dropped() {
+    let (client, task) = start_udp_server(512).await;
+    let mut big = call(9, Nfs4Proc::Null as u32);
+    big.resize(2048, 0);
+    client.send(&big).await.unwrap();
+    client.send(&call(10, Nfs4Proc::Null as u32)).await.unwrap();
+    // Only the in-limit call is answered
+    let reply = recv_reply(&client).await;
+    assert_eq!(reply.xid, 10);
+    task.abort();
+}
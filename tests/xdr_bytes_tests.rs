@@ -0,0 +1,73 @@
+use bytes::{Bytes, BytesMut};
+use nfs_rs::proto::nfs4::*;
+use nfs_rs::rpc::*;
+use nfs_rs::xdr::*;
+
+#[test]
+fn test_write_args_data_is_slice_of_receive_buffer() {
+    let payload = vec![7u8; 4096];
+    let args = Write4args {
+        stateid: Stateid4::default(),
+        offset: 8192,
+        stable: 0,
+        data: Bytes::from(payload.clone()),
+    };
+    let mut raw = BytesMut::new();
+    (NfsOp4::OpWrite as u32).xdr_encode(&mut raw);
+    args.xdr_encode(&mut raw);
+    let recv = raw.freeze();
+
+    let mut buf = recv.clone();
+    let _opcode = u32::xdr_decode(&mut buf).unwrap();
+    let back = Write4args::xdr_decode(&mut buf).unwrap();
+    assert_eq!(back.offset, 8192);
+    assert_eq!(&back.data[..], &payload[..]);
+    // The decoded data must point into the original buffer, not a copy
+    let base = recv.as_ptr() as usize;
+    let data = back.data.as_ptr() as usize;
+    assert!(data >= base && data + back.data.len() <= base + recv.len());
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn test_xdr_chain_keeps_large_payload_as_segment() {
+    let data = Bytes::from(vec![1u8; 10_001]);
+    let res = Read4resok { eof: true, data: data.clone() };
+    let mut chain = XdrChain::new();
+    chain.put(&0u32);
+    res.encode_into(&mut chain);
+    let total = chain.len();
+    let segs = chain.into_segments();
+    assert!(segs.iter().any(|s| s.as_ptr() == data.as_ptr()));
+
+    // Flattened output must match the stream encoder byte for byte
+    let flat: Vec<u8> = segs.iter().flat_map(|s| s.iter().copied()).collect();
+    assert_eq!(flat.len(), total);
+    let mut expected = serialize_to_vec(&0u32).unwrap();
+    expected.extend(serialize_to_vec(&true).unwrap());
+    expected.extend(serialize_to_vec(&data.to_vec()).unwrap());
+    assert_eq!(flat, expected);
+}
+
+#[tokio::test]
+async fn test_vectored_record_roundtrip_with_fragments() {
+    let (mut client, mut server) = tokio::io::duplex(1 << 20);
+    let segs = vec![Bytes::from_static(b"head"), Bytes::from(vec![9u8; 70_000]), Bytes::from_static(b"tail")];
+    write_record_vectored(&mut client, &segs).await.unwrap();
+
+    // A second record split into two fragments must be reassembled
+    let mut frag = Vec::new();
+    frag.extend_from_slice(&3u32.to_be_bytes());
+    frag.extend_from_slice(b"abc");
+    frag.extend_from_slice(&((1u32 << 31) | 2).to_be_bytes());
+    frag.extend_from_slice(b"de");
+    tokio::io::AsyncWriteExt::write_all(&mut client, &frag).await.unwrap();
+
+    let mut rbuf = BytesMut::new();
+    let first = read_record(&mut server, &mut rbuf).await.unwrap();
+    assert_eq!(first.len(), 4 + 70_000 + 4);
+    assert_eq!(&first[..4], b"head");
+    assert_eq!(&first[first.len() - 4..], b"tail");
+    let second = read_record(&mut server, &mut rbuf).await.unwrap();
+    assert_eq!(&second[..], b"abcde");
+}
@@ -45,7 +45,7 @@ fn test_rpc_headers_roundtrip() {
     let mut rd = Cursor::new(cur.into_inner());
     let back = RpcCallHeader::xdr_deserialize(&mut rd).unwrap();
     assert_eq!(back.xid, 42);
-    assert_eq!(matches!(back.msg_type, RpcMessageType::Call), true);
+    assert!(matches!(back.msg_type, RpcMessageType::Call));
     assert_eq!(back.rpcvers, 2);
     assert_eq!(back.prog, 100003);
     assert_eq!(back.vers, 4);